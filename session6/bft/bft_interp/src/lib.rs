@@ -578,7 +578,7 @@ impl<'a, T: std::fmt::Debug + CellKind + std::clone::Clone + std::default::Defau
             let inst = self.program.instructions()[self.program_pointer];
             let cmd = inst.command();
             self.program_pointer = match cmd {
-                bft_types::BfCommand::Comment => todo!(), // Do nothing
+                bft_types::BfCommand::Comment => self.program_pointer + 1, // Do nothing
                 bft_types::BfCommand::IncDataPointer => self.command_move_pointer_forward()?,
                 bft_types::BfCommand::DecDataPointer => self.command_move_pointer_back()?,
                 bft_types::BfCommand::IncValue => self.command_inc_value()?,
@@ -858,4 +858,33 @@ mod tests {
         let result = tape.move_program_pointer_forward();
         assert!(result.is_err());
     }
+
+    /// Test that comment instructions do nothing when the program is run
+    #[test]
+    fn comments_are_skipped() {
+        use bft_types::{BfCommand, BfInstruction};
+        let mut program = BfProgram::from_instructions(
+            "comments.bf",
+            vec![
+                BfInstruction::new(BfCommand::Comment, 1, 1),
+                BfInstruction::new(BfCommand::IncValue, 1, 2),
+                BfInstruction::new(BfCommand::JumpForward, 1, 3),
+                BfInstruction::new(BfCommand::Comment, 1, 4),
+                BfInstruction::new(BfCommand::OutputValue, 1, 5),
+                BfInstruction::new(BfCommand::DecValue, 1, 6),
+                BfInstruction::new(BfCommand::JumpBackward, 1, 7),
+                BfInstruction::new(BfCommand::Comment, 1, 8),
+            ],
+        );
+        program.validate().unwrap();
+        let tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = Vec::new();
+        assert!(tape.interpreter(&mut std::io::empty(), &mut writer).is_ok());
+        assert_eq!(writer, b"1,");
+    }
 }
@@ -292,6 +292,30 @@ impl BfProgram {
         Ok(program)
     }
 
+    /// Create a new Brain Fuck program from instructions that have already been parsed, or
+    /// built by hand. The instructions can include comments, which do nothing when run.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use bft_types::{BfCommand, BfInstruction};
+    /// let program = bft_types::BfProgram::from_instructions(
+    ///     &"built.bf",
+    ///     vec![
+    ///         BfInstruction::new(BfCommand::IncValue, 1, 1),
+    ///         BfInstruction::new(BfCommand::Comment, 1, 2),
+    ///     ],
+    /// );
+    /// assert_eq!(program.size(), 2);
+    /// ```
+    pub fn from_instructions(filename: impl AsRef<Path>, instructions: Vec<BfInstruction>) -> Self {
+        Self {
+            filename: filename.as_ref().to_path_buf(),
+            instructions,
+            location_map: BiMap::new(),
+        }
+    }
+
     /// Read a BrainFuck program from a file. The program will be returned in a Result<>.
     /// If the file is not found or there are issues with reading it an error will be returned.
     ///
@@ -379,6 +403,22 @@ mod tests {
         assert_eq!(program.size(), 5);
     }
 
+    // Test that a program can be built from instructions, including comments
+    #[test]
+    fn program_from_instructions() {
+        let mut program = BfProgram::from_instructions(
+            "built.bf",
+            vec![
+                BfInstruction::new(BfCommand::JumpForward, 1, 1),
+                BfInstruction::new(BfCommand::Comment, 1, 2),
+                BfInstruction::new(BfCommand::JumpBackward, 1, 3),
+            ],
+        );
+        assert_eq!(program.size(), 3);
+        program.validate().unwrap();
+        assert_eq!(program.location_map().len(), 1);
+    }
+
     // Test that commands in a BF program have been read correctly.
     #[test]
     fn check_commands_in_minimal_program() {
@@ -0,0 +1,196 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// Nested loops that execute a lot of instructions without much I/O. The program finishes by
+/// outputting a newline so that the interpreter doesn't add one of its own on stdout.
+const NESTED_LOOPS: &str = "++++++++++[>++++++++++[>++++++++++[>+<-]<-]<-]>>>>>++++++++++.";
+
+/// Run a program with the given debug level, with any debug output thrown away
+fn run(program: &bft_types::BfProgram, debug: cli::DebugLevelType) {
+    let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTape::new(
+        program,
+        100,
+        cli::AllocStrategy::TapeIsFixed,
+        cli::OutputFormat::AsciiOutput,
+    );
+    tape.set_debug(debug);
+    tape.set_debug_writer(Box::new(std::io::sink()));
+    tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+        .unwrap();
+}
+
+/// Compare the loop without debug against the same loop with debug output enabled
+fn debug_overhead(c: &mut Criterion) {
+    let mut program = bft_types::BfProgram::new("nested.bf", NESTED_LOOPS).unwrap();
+    program.validate().unwrap();
+
+    let mut group = c.benchmark_group("debug_overhead");
+    group.bench_function("none", |b| {
+        b.iter(|| run(&program, cli::DebugLevelType::None))
+    });
+    group.bench_function("information", |b| {
+        b.iter(|| run(&program, cli::DebugLevelType::Information))
+    });
+    group.finish();
+}
+
+/// A loop with a long body so that finding the matching bracket is expensive if the program
+/// has to be searched
+fn long_loop_body(c: &mut Criterion) {
+    let source = format!("++++++++++[>{}<-]++++++++++.", "+-".repeat(500));
+    let mut program = bft_types::BfProgram::new("long.bf", &source).unwrap();
+    program.validate().unwrap();
+
+    c.bench_function("long_loop_body", |b| {
+        b.iter(|| run(&program, cli::DebugLevelType::None))
+    });
+}
+
+/// A long run of moves to the right on a tape that starts with one cell and has to grow, compared
+/// with a tape that is big enough to start with
+fn tape_growth(c: &mut Criterion) {
+    let source = ">".repeat(100_000);
+    let program = bft_types::BfProgram::new("scan.bf", &source).unwrap();
+
+    let mut group = c.benchmark_group("tape_growth");
+    for (name, size) in [("grown", 1), ("preallocated", 100_001)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTape::new(
+                    &program,
+                    size,
+                    cli::AllocStrategy::TapeCanGrow,
+                    cli::OutputFormat::AsciiOutput,
+                );
+                tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// A tight loop that moves a large count from one cell to the next, using wide cells so that the
+/// count can be bigger than a byte
+fn counting_loop(c: &mut Criterion) {
+    let mut program = bft_types::BfProgram::new("count.bf", "[->+<]").unwrap();
+    program.validate().unwrap();
+
+    c.bench_function("counting_loop", |b| {
+        b.iter(|| {
+            let mut tape: bft_interp::BfTape<u32> = bft_interp::BfTapeBuilder::new()
+                .tape_size(2)
+                .build(&program);
+            tape.set_cell_at(0, 1_000_000).unwrap();
+            tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+                .unwrap();
+        })
+    });
+}
+
+/// Loops nested six deep, so that most of the time is spent jumping between brackets
+fn deeply_nested_loops(c: &mut Criterion) {
+    let depth = 6;
+    let source = format!("{}+{}", "++++++[>".repeat(depth), "<-]".repeat(depth));
+    let mut program = bft_types::BfProgram::new("deep.bf", &source).unwrap();
+    program.validate().unwrap();
+
+    c.bench_function("deeply_nested_loops", |b| {
+        b.iter(|| run(&program, cli::DebugLevelType::None))
+    });
+}
+
+/// A scan of 10,000 cells to the right on a fixed tape and on a tape that grows
+fn long_scan(c: &mut Criterion) {
+    let source = ">".repeat(10_000);
+    let program = bft_types::BfProgram::new("scan.bf", &source).unwrap();
+
+    let mut group = c.benchmark_group("long_scan");
+    for (name, strategy) in [
+        ("fixed", cli::AllocStrategy::TapeIsFixed),
+        ("can_grow", cli::AllocStrategy::TapeCanGrow),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new()
+                    .tape_size(10_001)
+                    .alloc_strategy(strategy)
+                    .build(&program);
+                tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Copy 1 MB from input to output through in memory buffers
+fn cat(c: &mut Criterion) {
+    let mut program = bft_types::BfProgram::new("cat.bf", ",[.,]").unwrap();
+    program.validate().unwrap();
+    // Zero ends the program so the input mustn't contain any
+    let input: Vec<u8> = (0..1024 * 1024).map(|i| (i % 255 + 1) as u8).collect();
+
+    let mut group = c.benchmark_group("cat");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("1MB", |b| {
+        b.iter(|| {
+            let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new()
+                .eof_behavior(cli::EofBehavior::Zero)
+                .build(&program);
+            let mut output = Vec::with_capacity(input.len());
+            tape.interpreter(&mut input.as_slice(), &mut output)
+                .unwrap();
+            output
+        })
+    });
+    group.finish();
+}
+
+/// Compare the interpreter loop, which runs the program lowered to the function for each
+/// instruction, against stepping through it an instruction at a time, which finds the command
+/// of each instruction as it goes. Stepping doesn't check the step limit or interrupt flag, so
+/// it has less to do for each instruction than the interpreter loop.
+fn dispatch(c: &mut Criterion) {
+    let examples = [
+        ("nested_loops", NESTED_LOOPS.to_string()),
+        (
+            "deeply_nested_loops",
+            format!("{}+{}", "++++++[>".repeat(6), "<-]".repeat(6)),
+        ),
+        (
+            "long_loop_body",
+            format!("++++++++++[>{}<-]++++++++++.", "+-".repeat(500)),
+        ),
+    ];
+    let mut group = c.benchmark_group("dispatch");
+    for (name, source) in examples {
+        let mut program = bft_types::BfProgram::new("dispatch.bf", &source).unwrap();
+        program.validate().unwrap();
+        group.bench_function(format!("{}/lowered", name), |b| {
+            b.iter(|| run(&program, cli::DebugLevelType::None))
+        });
+        group.bench_function(format!("{}/stepped", name), |b| {
+            b.iter(|| {
+                let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new()
+                    .tape_size(100)
+                    .build(&program);
+                let mut io = bft_interp::StdIo::new(std::io::empty(), std::io::sink());
+                while tape.step_io(&mut io).unwrap().is_none() {}
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    debug_overhead,
+    long_loop_body,
+    tape_growth,
+    counting_loop,
+    deeply_nested_loops,
+    long_scan,
+    cat,
+    dispatch
+);
+criterion_main!(benches);
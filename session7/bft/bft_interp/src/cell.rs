@@ -0,0 +1,212 @@
+//! The cells that make up a tape.
+//!
+//! Nothing here needs the standard library, only `core` and `alloc`, so it can be built for
+//! targets without an operating system.
+
+use alloc::format;
+use alloc::string::String;
+
+/// Trait for the cells in the tape that allows them to be incremented/decremented and for
+/// the value held in the cell to be set or extracted.
+///
+/// Cells are implemented for u8, u16, u32, u64, i8, i16 and i32. For all of them:
+/// * Incrementing and decrementing wraps around at the limits of the type.
+/// * Converting to a u8 keeps the low byte, so an i8 of -1 is output as 0xFF and a u16 of 0x141
+///   is output as 0x41.
+/// * Converting from a u8 is an `as` cast, so unsigned types are zero extended, an i8 takes
+///   the same bit pattern (0xFF becomes -1) and the wider signed types are positive.
+///
+pub trait CellKind: Default + Clone + Copy + PartialEq + core::fmt::Debug {
+    /// Increment a data cell's value
+    fn inc(&mut self) -> Self
+    where
+        Self: core::marker::Sized;
+    /// Decrement a data cell's value
+    fn dec(&mut self) -> Self
+    where
+        Self: core::marker::Sized;
+
+    /// Convert the value of a data cell to a u8
+    fn to_u8(&self) -> u8
+    where
+        Self: core::marker::Sized;
+
+    /// Convert the value of a data cell from u8
+    fn from_u8(value: u8) -> Self
+    where
+        Self: core::marker::Sized;
+
+    /// Check if a data cell's value is zero. The whole value is checked, not just the low byte.
+    fn is_zero(&self) -> bool;
+
+    /// Increment a data cell's value, None if it would overflow
+    fn checked_inc(&self) -> Option<Self>
+    where
+        Self: core::marker::Sized;
+
+    /// Decrement a data cell's value, None if it would overflow
+    fn checked_dec(&self) -> Option<Self>
+    where
+        Self: core::marker::Sized;
+
+    /// Increment a data cell's value, staying at the maximum rather than overflowing
+    fn saturating_inc(&self) -> Self
+    where
+        Self: core::marker::Sized;
+
+    /// Decrement a data cell's value, staying at the minimum rather than overflowing
+    fn saturating_dec(&self) -> Self
+    where
+        Self: core::marker::Sized;
+
+    /// Parse a decimal number into a data cell's value, None if it isn't a number that fits
+    fn parse(text: &str) -> Option<Self>
+    where
+        Self: core::marker::Sized;
+
+    /// Convert a data cell's value to a u32, keeping the low 32 bits. Signed values keep their
+    /// two's complement bit pattern, so negative values become large numbers.
+    fn to_u32(&self) -> u32;
+
+    /// The whole of a data cell's value as lowercase hex, two digits per byte of the cell.
+    /// Signed values are shown as their two's complement bit pattern.
+    fn to_hex(&self) -> String;
+
+    /// Convert a data cell's value to an i64. Unsigned values keep their bit pattern, so a u64
+    /// above the maximum i64 becomes negative.
+    fn to_i64(&self) -> i64;
+
+    /// Convert a data cell's value to an i128, which holds every value of every cell type
+    fn to_i128(&self) -> i128;
+
+    /// Add an amount to a data cell's value, wrapping around as many times as needed
+    fn wrapping_add_i64(&self, amount: i64) -> Self
+    where
+        Self: core::marker::Sized;
+
+    /// Add a delta, which may be negative, to a data cell's value, wrapping around at the
+    /// limits of the type
+    fn wrapping_add_n(&self, delta: i16) -> Self
+    where
+        Self: core::marker::Sized;
+
+    /// Add a delta to a data cell's value, None if it would overflow
+    fn checked_add_n(&self, delta: i16) -> Option<Self>
+    where
+        Self: core::marker::Sized;
+
+    /// Add a delta to a data cell's value, staying at the maximum or minimum rather than
+    /// overflowing
+    fn saturating_add_n(&self, delta: i16) -> Self
+    where
+        Self: core::marker::Sized;
+}
+
+/// Implementation of the Trait for the cells using integers. Values wrap around when
+/// incremented past the maximum or decremented past the minimum. Conversions to and from u8
+/// are `as` casts.
+///
+macro_rules! impl_cell_kind {
+    ($($cell:ty),*) => {
+        $(
+            impl CellKind for $cell {
+                /// Increment a data cell's value
+                fn inc(&mut self) -> Self {
+                    self.wrapping_add(1)
+                }
+
+                /// Decrement a data cell's value
+                fn dec(&mut self) -> Self {
+                    self.wrapping_sub(1)
+                }
+
+                /// Convert the value of a data cell to a u8
+                #[allow(clippy::unnecessary_cast)]
+                fn to_u8(&self) -> u8 {
+                    *self as u8
+                }
+
+                /// Set the value of a data cell from u8
+                #[allow(clippy::unnecessary_cast)]
+                fn from_u8(value: u8) -> Self {
+                    value as $cell
+                }
+
+                /// Check if a data cell's value is zero
+                fn is_zero(&self) -> bool {
+                    *self == 0
+                }
+
+                /// Increment a data cell's value, None if it would overflow
+                fn checked_inc(&self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+
+                /// Decrement a data cell's value, None if it would overflow
+                fn checked_dec(&self) -> Option<Self> {
+                    self.checked_sub(1)
+                }
+
+                /// Increment a data cell's value, staying at the maximum
+                fn saturating_inc(&self) -> Self {
+                    self.saturating_add(1)
+                }
+
+                /// Decrement a data cell's value, staying at the minimum
+                fn saturating_dec(&self) -> Self {
+                    self.saturating_sub(1)
+                }
+
+                /// Parse a decimal number into a data cell's value
+                fn parse(text: &str) -> Option<Self> {
+                    text.parse::<$cell>().ok()
+                }
+
+                /// Convert a data cell's value to a u32
+                fn to_u32(&self) -> u32 {
+                    *self as u32
+                }
+
+                /// The whole of a data cell's value as lowercase hex
+                fn to_hex(&self) -> String {
+                    format!("{:01$x}", self, core::mem::size_of::<$cell>() * 2)
+                }
+
+                /// Convert a data cell's value to an i64
+                fn to_i64(&self) -> i64 {
+                    *self as i64
+                }
+
+                /// Convert a data cell's value to an i128
+                fn to_i128(&self) -> i128 {
+                    *self as i128
+                }
+
+                /// Add an amount to a data cell's value, wrapping around
+                #[allow(clippy::unnecessary_cast)]
+                fn wrapping_add_i64(&self, amount: i64) -> Self {
+                    (*self as i64).wrapping_add(amount) as $cell
+                }
+
+                /// Add a delta to a data cell's value, wrapping around
+                fn wrapping_add_n(&self, delta: i16) -> Self {
+                    self.wrapping_add_i64(delta.into())
+                }
+
+                /// Add a delta to a data cell's value, None if it would overflow. Every cell
+                /// value and delta fits in an i128 so the sum can't overflow there.
+                fn checked_add_n(&self, delta: i16) -> Option<Self> {
+                    <$cell>::try_from(i128::from(*self) + i128::from(delta)).ok()
+                }
+
+                /// Add a delta to a data cell's value, staying at the maximum or minimum
+                fn saturating_add_n(&self, delta: i16) -> Self {
+                    let sum = i128::from(*self) + i128::from(delta);
+                    sum.clamp(<$cell>::MIN.into(), <$cell>::MAX.into()) as $cell
+                }
+            }
+        )*
+    };
+}
+
+impl_cell_kind!(u8, u16, u32, u64, i8, i16, i32);
@@ -0,0 +1,383 @@
+//! Interactive debugger for BF programs.
+//!
+//! The debugger reads its commands from one stream and writes its responses to another,
+//! which are kept apart from the program's own input and output. This means a program that
+//! reads input, such as cat, can be debugged with its input coming from stdin while the
+//! debugger is driven from the terminal.
+//!
+//! The commands are
+//! * s          - Step, execute the next instruction.
+//! * c          - Continue, run until a breakpoint is reached or the program finishes.
+//! * b line:col - Set a breakpoint on the instruction at a line and column of the source.
+//! * p          - Print the cells around the data pointer.
+//! * d N        - Display the value of cell N.
+//! * q          - Quit.
+//!
+//! Interrupting the program, such as with Ctrl-C while it is being continued, stops it and
+//! returns to the prompt rather than ending the debugger. The step and time limits of the tape
+//! apply as usual.
+
+use crate::{BfError, BfIo, BfTape, CellKind, StopReason};
+use bft_types::{BfCommand, BfLocation};
+use std::io::{BufRead, Write};
+use std::sync::atomic::Ordering;
+
+/// Number of cells either side of the data pointer shown by the print command
+const WINDOW_RADIUS: usize = 8;
+
+/// Prompt output before each command is read
+const PROMPT: &str = "(bft) ";
+
+/// Run a program under the debugger. The program's input and output go through io, the
+/// debugger's commands are read from commands and its responses written to out.
+///
+/// Returns StopReason::Halted if the program ran to completion or StopReason::Aborted if the
+/// debugger was quit, or the commands ran out, before it did.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"tiny.bf", "+\n>+").unwrap();
+///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+///     let mut io = bft_interp::VecIo::new(Vec::new());
+///     let mut out = Vec::new();
+///     let reason =
+///         bft_interp::debugger::run(&mut tape, &mut io, "b 2:2\nc\nq\n".as_bytes(), &mut out).unwrap();
+///     assert_eq!(reason, bft_interp::StopReason::Aborted { program_pointer: 2 });
+///     assert_eq!(tape.data_pointer(), 1);
+/// ```
+pub fn run<T, I, R, W>(
+    tape: &mut BfTape<'_, T>,
+    io: &mut I,
+    commands: R,
+    out: &mut W,
+) -> Result<StopReason<T>, BfError>
+where
+    T: CellKind,
+    I: BfIo,
+    R: BufRead,
+    W: Write,
+{
+    let mut debugger = Debugger {
+        breakpoints: Vec::new(),
+        out,
+    };
+    debugger.show_position(tape)?;
+    let mut lines = commands.lines();
+    loop {
+        debugger.write(tape, format_args!("{}", PROMPT))?;
+        debugger.out.flush().map_err(|e| tape.io_error(e))?;
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| tape.io_error(e))?,
+            None => {
+                return Ok(StopReason::Aborted {
+                    program_pointer: tape.program_pointer(),
+                })
+            }
+        };
+
+        let mut words = line.split_whitespace();
+        let reason = match (words.next(), words.next(), words.next()) {
+            (None, _, _) => None,
+            (Some("s"), None, _) => debugger.step(tape, io)?,
+            (Some("c"), None, _) => debugger.continue_running(tape, io)?,
+            (Some("b"), Some(location), None) => {
+                debugger.set_breakpoint(tape, location)?;
+                None
+            }
+            (Some("p"), None, _) => {
+                let window = tape.render_window(WINDOW_RADIUS);
+                debugger.write(tape, format_args!("{}\n", window))?;
+                None
+            }
+            (Some("d"), Some(cell), None) => {
+                debugger.display_cell(tape, cell)?;
+                None
+            }
+            (Some("q"), None, _) => {
+                return Ok(StopReason::Aborted {
+                    program_pointer: tape.program_pointer(),
+                })
+            }
+            _ => {
+                debugger.write(
+                    tape,
+                    format_args!("Unknown command, use s, c, b line:col, p, d N or q\n"),
+                )?;
+                None
+            }
+        };
+
+        match reason {
+            Some(StopReason::Halted) => {
+                debugger.write(tape, format_args!("Program finished\n"))?;
+                return Ok(StopReason::Halted);
+            }
+            Some(reason @ StopReason::Aborted { .. }) => return Ok(reason),
+            Some(reason) => {
+                debugger.write(tape, format_args!("Stopped: {:?}\n", reason))?;
+                debugger.show_position(tape)?;
+            }
+            None => {}
+        }
+    }
+}
+
+/// State of the debugger between commands
+struct Debugger<'w, W: Write> {
+    /// Locations in the source of the instructions to stop at
+    breakpoints: Vec<BfLocation>,
+    /// Where the debugger's responses are written
+    out: &'w mut W,
+}
+
+impl<W: Write> Debugger<'_, W> {
+    /// Execute one instruction and show where the program has got to
+    fn step<T: CellKind, I: BfIo>(
+        &mut self,
+        tape: &mut BfTape<'_, T>,
+        io: &mut I,
+    ) -> Result<Option<StopReason<T>>, BfError> {
+        let reason = match tape.step_io(io) {
+            Err(BfError::Interrupted { .. }) => return self.interrupted(tape),
+            result => result?,
+        };
+        if reason.is_none() {
+            self.show_position(tape)?;
+        }
+        Ok(reason)
+    }
+
+    /// Run until the next instruction is at a breakpoint, or the program stops. The instruction
+    /// at the program pointer is always executed so that a breakpoint that was stopped at can
+    /// be continued from.
+    fn continue_running<T: CellKind, I: BfIo>(
+        &mut self,
+        tape: &mut BfTape<'_, T>,
+        io: &mut I,
+    ) -> Result<Option<StopReason<T>>, BfError> {
+        loop {
+            let reason = match tape.step_io(io) {
+                Err(BfError::Interrupted { .. }) => return self.interrupted(tape),
+                result => result?,
+            };
+            if let Some(reason) = reason {
+                return Ok(Some(reason));
+            }
+            if let Some(inst) = tape.current_instruction() {
+                if self.breakpoints.contains(&inst.location()) {
+                    self.write(tape, format_args!("Breakpoint at {}\n", inst.location()))?;
+                    self.show_position(tape)?;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Report that the program was interrupted and go back to the prompt. The interrupt flag
+    /// is cleared so that the program can be carried on with.
+    fn interrupted<T: CellKind>(
+        &mut self,
+        tape: &BfTape<'_, T>,
+    ) -> Result<Option<StopReason<T>>, BfError> {
+        if let Some(flag) = &tape.interrupt {
+            flag.store(false, Ordering::Relaxed);
+        }
+        self.write(tape, format_args!("Interrupted\n"))?;
+        self.show_position(tape)?;
+        Ok(None)
+    }
+
+    /// Add a breakpoint given as line:col, which must be the location of an instruction
+    fn set_breakpoint<T: CellKind>(
+        &mut self,
+        tape: &BfTape<'_, T>,
+        location: &str,
+    ) -> Result<(), BfError> {
+        let parsed = location
+            .split_once(':')
+            .and_then(|(line, col)| Some(BfLocation::new(line.parse().ok()?, col.parse().ok()?)));
+        match parsed {
+            Some(location)
+                if tape
+                    .program
+                    .instructions()
+                    .iter()
+                    .any(|inst| inst.location() == location) =>
+            {
+                if !self.breakpoints.contains(&location) {
+                    self.breakpoints.push(location);
+                }
+                self.write(tape, format_args!("Breakpoint set at {}\n", location))
+            }
+            Some(location) => self.write(tape, format_args!("No instruction at {}\n", location)),
+            None => self.write(
+                tape,
+                format_args!("Breakpoints are given as line:col, not {}\n", location),
+            ),
+        }
+    }
+
+    /// Show the value of a cell
+    fn display_cell<T: CellKind>(
+        &mut self,
+        tape: &BfTape<'_, T>,
+        cell: &str,
+    ) -> Result<(), BfError> {
+        match cell.parse::<usize>() {
+            Ok(index) => match tape.get_cell_at(index) {
+                Ok(value) => self.write(tape, format_args!("Cell {} is {:?}\n", index, value)),
+                Err(e) => self.write(tape, format_args!("{}\n", e)),
+            },
+            Err(_) => self.write(tape, format_args!("{} is not a cell number\n", cell)),
+        }
+    }
+
+    /// Show the instruction the program will execute next
+    fn show_position<T: CellKind>(&mut self, tape: &BfTape<'_, T>) -> Result<(), BfError> {
+        match tape.current_instruction() {
+            Some(inst) => self.write(
+                tape,
+                format_args!(
+                    "At {} {}\n",
+                    inst.location(),
+                    BfCommand::to_char(inst.command())
+                ),
+            ),
+            None => self.write(tape, format_args!("At end of program\n")),
+        }
+    }
+
+    /// Write a response from the debugger
+    fn write<T: CellKind>(
+        &mut self,
+        tape: &BfTape<'_, T>,
+        args: std::fmt::Arguments,
+    ) -> Result<(), BfError> {
+        self.out.write_fmt(args).map_err(|e| tape.io_error(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BfTapeBuilder, VecIo};
+    use bft_types::BfProgram;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    /// Run the debugger with scripted commands, returning the reason and what it output
+    fn debug(tape: &mut BfTape<u8>, io: &mut VecIo, commands: &str) -> (StopReason<u8>, String) {
+        let mut out = Vec::new();
+        let reason = run(tape, io, commands.as_bytes(), &mut out).unwrap();
+        (reason, String::from_utf8(out).unwrap())
+    }
+
+    /// Test stepping, printing and displaying cells
+    #[test]
+    fn step_and_inspect() {
+        let program = BfProgram::new("debug.bf", "++>+").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().tape_size(4).build(&program);
+        let mut io = VecIo::default();
+        let (reason, out) = debug(&mut tape, &mut io, "s\ns\np\nd 0\nd 9\nx\nq\n");
+        assert_eq!(reason, StopReason::Aborted { program_pointer: 2 });
+        assert_eq!(
+            out,
+            "At 1:1 +\n\
+             (bft) At 1:2 +\n\
+             (bft) At 1:3 >\n\
+             (bft) [0] >2< 0 0 0 [3]\n\
+             (bft) Cell 0 is 2\n\
+             (bft) [E_CELL_OUT_OF_RANGE] Cell 9 is outside of the tape of 4 cells\n\
+             (bft) Unknown command, use s, c, b line:col, p, d N or q\n\
+             (bft) "
+        );
+        assert_eq!(tape.get_data_value(), 2);
+    }
+
+    /// Test continuing to breakpoints and on to the end of the program
+    #[test]
+    fn breakpoints() {
+        let mut program = BfProgram::new("debug.bf", "+[>+<-]\n>.").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let mut io = VecIo::default();
+        let (reason, out) = debug(&mut tape, &mut io, "b 1:9\nb 1:4\nb 2:2\nc\nc\nq\n");
+        assert_eq!(reason, StopReason::Aborted { program_pointer: 8 });
+        assert_eq!(
+            out,
+            "At 1:1 +\n\
+             (bft) No instruction at 1:9\n\
+             (bft) Breakpoint set at 1:4\n\
+             (bft) Breakpoint set at 2:2\n\
+             (bft) Breakpoint at 1:4\n\
+             At 1:4 +\n\
+             (bft) Breakpoint at 2:2\n\
+             At 2:2 .\n\
+             (bft) "
+        );
+        assert_eq!(tape.data_pointer(), 1);
+
+        // Continuing from the last breakpoint runs the program to the end
+        let (reason, out) = debug(&mut tape, &mut io, "c\n");
+        assert_eq!(reason, StopReason::Halted);
+        assert_eq!(out, "At 2:2 .\n(bft) Program finished\n");
+        assert_eq!(io.output(), &[1]);
+    }
+
+    /// Test that the program's input is kept apart from the debugger's commands
+    #[test]
+    fn program_input_is_separate() {
+        let mut program = BfProgram::new("cat.bf", ",[.,]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .eof_behavior(cli::EofBehavior::Zero)
+            .build(&program);
+        let mut io = VecIo::new(b"hi".to_vec());
+        let (reason, _) = debug(&mut tape, &mut io, "s\nd 0\nc\n");
+        assert_eq!(reason, StopReason::Halted);
+        assert_eq!(io.output(), b"hi");
+    }
+
+    /// Test that interrupting an infinite loop that is being continued goes back to the prompt
+    #[test]
+    fn interrupt_while_continuing() {
+        let mut program = BfProgram::new("forever.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let flag = Arc::new(AtomicBool::new(false));
+        tape.set_interrupt_flag(Arc::clone(&flag));
+
+        let setter = {
+            let flag = Arc::clone(&flag);
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                flag.store(true, Ordering::Relaxed);
+            })
+        };
+        let mut io = VecIo::default();
+        let (reason, out) = debug(&mut tape, &mut io, "c\nd 0\nq\n");
+        setter.join().unwrap();
+        assert!(matches!(reason, StopReason::Aborted { .. }));
+        assert!(
+            out.starts_with("At 1:1 +\n(bft) Interrupted\nAt 1:"),
+            "{out}"
+        );
+        assert!(out.ends_with("(bft) Cell 0 is 1\n(bft) "), "{out}");
+        assert!(!flag.load(Ordering::Relaxed));
+    }
+
+    /// Test that the step limit stops a program that is being continued
+    #[test]
+    fn step_limit_while_continuing() {
+        let mut program = BfProgram::new("forever.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().step_limit(Some(100)).build(&program);
+        let mut io = VecIo::default();
+        let mut out = Vec::new();
+        let result = run(&mut tape, &mut io, "c\nq\n".as_bytes(), &mut out);
+        assert!(matches!(
+            result,
+            Err(BfError::StepLimitExceeded { steps: 100, .. })
+        ));
+    }
+}
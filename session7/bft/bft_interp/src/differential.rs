@@ -0,0 +1,576 @@
+//! Differential testing of the ways a program can be run.
+//!
+//! The same program and input are run by two backends, such as the interpreter and the
+//! compiled ops, and what they leave behind is compared: the output, whether they failed and
+//! where, the cells of the tape and the data pointer. All of these should be identical, so
+//! the first difference is reported as a Divergence along with the place in the source that
+//! it came from.
+
+use crate::{BfError, BfIo, BfTape, BfTapeBuilder, HookAction, HookEvent, StdIo};
+use bft_types::{BfCommand, BfLocation, BfProgram};
+use std::fmt;
+
+/// A way of running a program.
+///
+/// * Interpreter
+/// * Stepped
+/// * Ir
+/// * Jit, with the jit feature
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backend {
+    /// The interpreter, see BfTape::interpreter
+    Interpreter,
+    /// One instruction at a time, see BfTape::step
+    Stepped,
+    /// The ops compiled from the program, see BfTape::run_ir
+    Ir,
+    /// Native code, see BfTape::run_jit
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Backend::Interpreter => "interpreter",
+            Backend::Stepped => "stepped",
+            Backend::Ir => "ir",
+            #[cfg(feature = "jit")]
+            Backend::Jit => "jit",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An error that stopped a program, kept as its code and where it happened so that the
+/// errors from different backends can be compared.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ErrorOutcome {
+    /// The error's code, see BfError::code
+    pub code: &'static str,
+    /// Where the error happened, see BfError::location
+    pub location: Option<BfLocation>,
+}
+
+impl From<&BfError> for ErrorOutcome {
+    fn from(error: &BfError) -> Self {
+        Self {
+            code: error.code(),
+            location: error.location(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "{} at {}", self.code, location),
+            None => write!(f, "{}", self.code),
+        }
+    }
+}
+
+/// Everything that a program left behind after being run by a backend.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    /// The bytes that the program output
+    pub output: Vec<u8>,
+    /// The error that stopped the program, None if it ran to completion
+    pub error: Option<ErrorOutcome>,
+    /// The cells of the tape
+    pub cells: Vec<u8>,
+    /// The data pointer
+    pub data_pointer: usize,
+}
+
+/// What was different between the outcomes of two backends. The values are the first
+/// backend's followed by the second's.
+///
+/// * Output
+/// * Error
+/// * Cell
+/// * TapeLength
+/// * DataPointer
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DivergenceKind {
+    /// A byte of the output differs, None where a backend output fewer bytes
+    Output {
+        offset: usize,
+        left: Option<u8>,
+        right: Option<u8>,
+    },
+    /// One backend failed and the other didn't, or they failed differently
+    Error {
+        left: Option<ErrorOutcome>,
+        right: Option<ErrorOutcome>,
+    },
+    /// A cell of the tape differs
+    Cell { index: usize, left: u8, right: u8 },
+    /// The tapes have a different number of cells
+    TapeLength { left: usize, right: usize },
+    /// The data pointers differ
+    DataPointer { left: usize, right: usize },
+}
+
+/// The first difference found between two backends running the same program.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The first backend
+    pub left: Backend,
+    /// The second backend
+    pub right: Backend,
+    /// What was different
+    pub kind: DivergenceKind,
+    /// The instruction that the difference came from, as far as it can be told. This is the
+    /// instruction that output the byte, that last changed the cell, or that last moved the
+    /// data pointer, when the program is run an instruction at a time.
+    pub location: Option<BfLocation>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (left, right) = (self.left, self.right);
+        match self.kind {
+            DivergenceKind::Output {
+                offset,
+                left: left_byte,
+                right: right_byte,
+            } => {
+                let byte = |byte: Option<u8>| byte.map_or("nothing".to_string(), |b| b.to_string());
+                write!(
+                    f,
+                    "Output byte {} is {} from {} but {} from {}",
+                    offset,
+                    byte(left_byte),
+                    left,
+                    byte(right_byte),
+                    right
+                )?;
+            }
+            DivergenceKind::Error {
+                left: left_error,
+                right: right_error,
+            } => {
+                let error = |error: Option<ErrorOutcome>| {
+                    error.map_or("no error".to_string(), |e| e.to_string())
+                };
+                write!(
+                    f,
+                    "The {} gave {} but the {} gave {}",
+                    left,
+                    error(left_error),
+                    right,
+                    error(right_error)
+                )?;
+            }
+            DivergenceKind::Cell {
+                index,
+                left: left_value,
+                right: right_value,
+            } => write!(
+                f,
+                "Cell {} is {} from {} but {} from {}",
+                index, left_value, left, right_value, right
+            )?,
+            DivergenceKind::TapeLength {
+                left: left_length,
+                right: right_length,
+            } => write!(
+                f,
+                "The tape has {} cells from {} but {} from {}",
+                left_length, left, right_length, right
+            )?,
+            DivergenceKind::DataPointer {
+                left: left_pointer,
+                right: right_pointer,
+            } => write!(
+                f,
+                "The data pointer is {} from {} but {} from {}",
+                left_pointer, left, right_pointer, right
+            )?,
+        }
+        if let Some(location) = self.location {
+            write!(f, " (from the instruction at {})", location)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a program with a backend on a tape set up by the builder, collecting what the program
+/// left behind. The program must have been validated.
+///
+/// Example usage:
+/// ```
+///     use bft_interp::differential::{run, Backend};
+///     let mut program = bft_types::BfProgram::new(&"tiny.bf", ",+.").unwrap();
+///     program.validate().unwrap();
+///     let outcome = run(Backend::Ir, &program, &bft_interp::BfTapeBuilder::new(), b"A");
+///     assert_eq!(outcome.output, b"B");
+///     assert_eq!(outcome.error, None);
+/// ```
+pub fn run(
+    backend: Backend,
+    program: &BfProgram,
+    builder: &BfTapeBuilder,
+    input: &[u8],
+) -> Outcome {
+    let mut tape: BfTape<u8> = builder.build(program);
+    let mut reader = input;
+    let mut output = Vec::new();
+    let mut io = StdIo::new(&mut reader, &mut output);
+    let result = match backend {
+        Backend::Interpreter => tape.interpreter_io(&mut io).map(|_| ()),
+        Backend::Stepped => run_stepped(&mut tape, &mut io),
+        Backend::Ir => compile(program).and_then(|ir| tape.run_ir_io(&ir, &mut io).map(|_| ())),
+        #[cfg(feature = "jit")]
+        Backend::Jit => {
+            compile(program).and_then(|ir| tape.run_jit(&ir, &mut reader, &mut output).map(|_| ()))
+        }
+    };
+    Outcome {
+        output,
+        error: result.as_ref().err().map(ErrorOutcome::from),
+        cells: tape.cells().into_owned(),
+        data_pointer: tape.data_pointer(),
+    }
+}
+
+/// Run a program an instruction at a time
+fn run_stepped<I: BfIo>(tape: &mut BfTape<u8>, io: &mut I) -> Result<(), BfError> {
+    while tape.current_instruction().is_some() {
+        tape.step_io(io)?;
+    }
+    Ok(())
+}
+
+/// Compile a program for the backends that run ops
+fn compile(program: &BfProgram) -> Result<bft_types::BfIr, BfError> {
+    program
+        .compile()
+        .map_err(|e| BfError::InvalidProgram { error_msg: e })
+}
+
+/// Run a program and its input with two backends and return the first difference between
+/// them, None if there isn't one. The output is compared first, then the errors, the cells,
+/// the size of the tape and lastly the data pointer.
+///
+/// Example usage:
+/// ```
+///     use bft_interp::differential::{compare, Backend};
+///     let mut program = bft_types::BfProgram::new(&"echo.bf", "+[,+[-[>+<-]>.[-]<]<]").unwrap();
+///     program.validate().unwrap();
+///     let builder = bft_interp::BfTapeBuilder::new();
+///     assert_eq!(compare(&program, &builder, b"abc", Backend::Interpreter, Backend::Ir), None);
+/// ```
+pub fn compare(
+    program: &BfProgram,
+    builder: &BfTapeBuilder,
+    input: &[u8],
+    left: Backend,
+    right: Backend,
+) -> Option<Divergence> {
+    let left_outcome = run(left, program, builder, input);
+    let right_outcome = run(right, program, builder, input);
+    let kind = first_difference(&left_outcome, &right_outcome)?;
+    Some(Divergence {
+        left,
+        right,
+        kind,
+        location: locate(program, builder, input, kind),
+    })
+}
+
+/// The first difference between two outcomes, in the order described by compare
+pub fn first_difference(left: &Outcome, right: &Outcome) -> Option<DivergenceKind> {
+    let length = left.output.len().max(right.output.len());
+    if let Some(offset) = (0..length).find(|&i| left.output.get(i) != right.output.get(i)) {
+        return Some(DivergenceKind::Output {
+            offset,
+            left: left.output.get(offset).copied(),
+            right: right.output.get(offset).copied(),
+        });
+    }
+    if left.error != right.error {
+        return Some(DivergenceKind::Error {
+            left: left.error,
+            right: right.error,
+        });
+    }
+    let cells = left.cells.iter().zip(&right.cells);
+    if let Some((index, (&l, &r))) = cells.enumerate().find(|(_, (l, r))| l != r) {
+        return Some(DivergenceKind::Cell {
+            index,
+            left: l,
+            right: r,
+        });
+    }
+    if left.cells.len() != right.cells.len() {
+        return Some(DivergenceKind::TapeLength {
+            left: left.cells.len(),
+            right: right.cells.len(),
+        });
+    }
+    if left.data_pointer != right.data_pointer {
+        return Some(DivergenceKind::DataPointer {
+            left: left.data_pointer,
+            right: right.data_pointer,
+        });
+    }
+    None
+}
+
+/// Picks out the instructions that a difference could have come from
+type EventMatcher = dyn FnMut(&HookEvent<u8>) -> bool + Send;
+
+/// Find the instruction that a difference came from by running the program an instruction
+/// at a time with a hook that watches for it. The cell indexes are those of the tape as it
+/// is when the hook is called, so they only match the final tape if it hasn't grown to the
+/// left.
+fn locate(
+    program: &BfProgram,
+    builder: &BfTapeBuilder,
+    input: &[u8],
+    kind: DivergenceKind,
+) -> Option<BfLocation> {
+    let mut matches: Box<EventMatcher> = match kind {
+        DivergenceKind::Error { left, right } => {
+            return left.or(right).and_then(|error| error.location)
+        }
+        DivergenceKind::Output { offset, .. } => {
+            let mut outputs = 0;
+            Box::new(move |event| {
+                if event.instruction.command() != BfCommand::OutputValue {
+                    return false;
+                }
+                outputs += 1;
+                outputs == offset + 1
+            })
+        }
+        DivergenceKind::Cell { index, .. } => Box::new(move |event| {
+            event.data_pointer == index
+                && matches!(
+                    event.instruction.command(),
+                    BfCommand::IncValue | BfCommand::DecValue | BfCommand::InputValue
+                )
+        }),
+        DivergenceKind::TapeLength { .. } | DivergenceKind::DataPointer { .. } => {
+            Box::new(|event| {
+                matches!(
+                    event.instruction.command(),
+                    BfCommand::IncDataPointer | BfCommand::DecDataPointer
+                )
+            })
+        }
+    };
+    let mut location = None;
+    {
+        let mut tape: BfTape<u8> = builder.build(program);
+        tape.set_hook(|event| {
+            if matches(event) {
+                location = Some(event.instruction.location());
+            }
+            HookAction::Continue
+        });
+        let mut io = StdIo::new(input, Vec::new());
+        // The run only has to get as far as the difference, so an error doesn't matter
+        let _ = tape.interpreter_io(&mut io);
+    }
+    location
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::ProgramGenerator;
+
+    /// Programs in the workspace with input for them
+    const EXAMPLES: [(&str, &str, &[u8]); 7] = [
+        ("hello.bf", include_str!("../../hello-world.bf"), b""),
+        (
+            "rot13.bf",
+            include_str!("../../rot13.bf"),
+            b"Hello, World!\n",
+        ),
+        ("prob2.bf", include_str!("../../prob2.bf"), b""),
+        ("loop.bf", include_str!("../../loop-three-times.bf"), b""),
+        ("seven.bf", include_str!("../../output-seven.bf"), b""),
+        (
+            "three.bf",
+            include_str!("../../input-and-output-three-values.bf"),
+            b"abc",
+        ),
+        ("inc.bf", include_str!("../../inc-and-output.bf"), b""),
+    ];
+
+    /// The backends compared with the interpreter
+    fn backends() -> Vec<Backend> {
+        vec![
+            Backend::Stepped,
+            Backend::Ir,
+            #[cfg(feature = "jit")]
+            Backend::Jit,
+        ]
+    }
+
+    /// Test that every backend agrees with the interpreter on the example programs
+    #[test]
+    fn examples_agree() {
+        let builder = BfTapeBuilder::new();
+        for (name, source, input) in EXAMPLES {
+            let mut program = BfProgram::new(name, source).unwrap();
+            program.validate().unwrap();
+            for backend in backends() {
+                let divergence = compare(&program, &builder, input, Backend::Interpreter, backend);
+                assert_eq!(divergence, None, "{}", name);
+            }
+        }
+    }
+
+    /// Test that every backend agrees with the interpreter on generated programs, with a tape
+    /// that is small enough for the data pointer to run off either end of it. The step limit
+    /// is checked per instruction by the interpreter but per op by the compiled backends, so
+    /// the programs that reach it are left out.
+    #[test]
+    fn generated_programs_agree() {
+        let builder = BfTapeBuilder::new()
+            .tape_size(8)
+            .step_limit(Some(2000))
+            .eof_behavior(cli::EofBehavior::NegOne);
+        let mut compared = 0;
+        for seed in 0..500 {
+            let source = ProgramGenerator::new(seed).program();
+            let mut program = BfProgram::new("generated.bf", &source).unwrap();
+            program.validate().unwrap();
+            let reference = run(Backend::Interpreter, &program, &builder, b"xyz");
+            if reference.error.map(|e| e.code) == Some("E_STEP_LIMIT") {
+                continue;
+            }
+            compared += 1;
+            for backend in backends() {
+                let divergence = compare(&program, &builder, b"xyz", Backend::Interpreter, backend);
+                assert_eq!(divergence, None, "{}", source);
+            }
+        }
+        assert!(compared > 250, "only {} programs finished", compared);
+    }
+
+    /// Test that a difference is found and reported with where it came from. The difference
+    /// is brought in by running one backend with a different end of file behaviour, standing
+    /// in for a backend that handled the end of the input wrongly, and goes once they match.
+    #[test]
+    fn divergence_reported() {
+        let mut program = BfProgram::new("eof.bf", "+>,\n,.").unwrap();
+        program.validate().unwrap();
+        let builder = BfTapeBuilder::new().eof_behavior(cli::EofBehavior::Zero);
+        let broken = builder.clone().eof_behavior(cli::EofBehavior::NegOne);
+        let left = run(Backend::Interpreter, &program, &builder, b"A");
+        let right = run(Backend::Ir, &program, &broken, b"A");
+        let kind = first_difference(&left, &right).unwrap();
+        assert_eq!(
+            kind,
+            DivergenceKind::Output {
+                offset: 0,
+                left: Some(0),
+                right: Some(255)
+            }
+        );
+        let divergence = Divergence {
+            left: Backend::Interpreter,
+            right: Backend::Ir,
+            kind,
+            location: locate(&program, &builder, b"A", kind),
+        };
+        assert_eq!(divergence.location, Some(BfLocation::new(2, 2)));
+        assert_eq!(
+            divergence.to_string(),
+            "Output byte 0 is 0 from interpreter but 255 from ir (from the instruction at 2:2)"
+        );
+
+        // Fixed, the backends agree once the end of file is handled the same way
+        let right = run(Backend::Ir, &program, &builder, b"A");
+        assert_eq!(first_difference(&left, &right), None);
+
+        // The other differences are found in order, with where the cell was last changed
+        let mut right = left.clone();
+        right.cells[1] = 7;
+        right.data_pointer = 0;
+        let kind = first_difference(&left, &right).unwrap();
+        assert_eq!(
+            kind,
+            DivergenceKind::Cell {
+                index: 1,
+                left: 0,
+                right: 7
+            }
+        );
+        assert_eq!(
+            locate(&program, &builder, b"A", kind),
+            Some(BfLocation::new(2, 1))
+        );
+        right.cells[1] = 0;
+        let kind = first_difference(&left, &right).unwrap();
+        assert_eq!(kind, DivergenceKind::DataPointer { left: 1, right: 0 });
+        assert_eq!(
+            locate(&program, &builder, b"A", kind),
+            Some(BfLocation::new(1, 2))
+        );
+    }
+
+    /// Test that errors are compared by their code and location
+    #[test]
+    fn errors_compared() {
+        let mut program = BfProgram::new("underrun.bf", "+\n<").unwrap();
+        program.validate().unwrap();
+        let builder = BfTapeBuilder::new();
+        for backend in backends() {
+            assert_eq!(
+                compare(&program, &builder, b"", Backend::Interpreter, backend),
+                None
+            );
+        }
+        let outcome = run(Backend::Ir, &program, &builder, b"");
+        let error = outcome.error.unwrap();
+        assert_eq!(error.code, "E_TAPE_UNDERRUN");
+        assert_eq!(error.location, Some(BfLocation::new(2, 1)));
+
+        let mut right = outcome.clone();
+        right.error = None;
+        let kind = first_difference(&outcome, &right).unwrap();
+        assert_eq!(
+            kind,
+            DivergenceKind::Error {
+                left: Some(error),
+                right: None
+            }
+        );
+        assert_eq!(
+            locate(&program, &builder, b"", kind),
+            Some(BfLocation::new(2, 1))
+        );
+    }
+
+    /// Test for a difference that the generated programs found. The ops that replaced a loop
+    /// such as [-><] left out its moves because they cancel out, so on a fixed tape the
+    /// compiled program cleared the cell where the interpreter went off the end of the tape.
+    /// Replaced loops now keep how far they move and are run an instruction at a time when
+    /// they would go off the end.
+    #[test]
+    fn replaced_loop_off_tape() {
+        let builder = BfTapeBuilder::new().tape_size(1);
+        for source in ["+[-><]", "+[->+<]", "+[-<+>]"] {
+            let mut program = BfProgram::new("reach.bf", source).unwrap();
+            program.validate().unwrap();
+            for backend in backends() {
+                let divergence = compare(&program, &builder, b"", Backend::Interpreter, backend);
+                assert_eq!(divergence, None, "{}", source);
+            }
+            let error = run(Backend::Ir, &program, &builder, b"").error.unwrap();
+            assert_eq!(error.location, Some(BfLocation::new(1, 4)), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Random programs for testing.
+//!
+//! ProgramGenerator makes valid programs, with their brackets balanced, and input for them
+//! from a seed, so that the same seed always gives the same programs. When a generated program
+//! shows up a problem, shrink cuts it down to the smallest program that still shows it.
+
+/// Makes random programs with balanced brackets, and random input for them.
+///
+/// Example usage:
+/// ```
+///     let mut generator = bft_interp::generate::ProgramGenerator::new(7).max_length(20);
+///     let source = generator.program();
+///     assert!(source.len() <= 20);
+///     let mut program = bft_types::BfProgram::new(&"random.bf", &source).unwrap();
+///     assert!(program.validate().is_ok());
+/// ```
+#[derive(Clone, Debug)]
+pub struct ProgramGenerator {
+    /// State of the xorshift random number generator, which is never zero
+    state: u64,
+    /// Most commands in a program
+    max_length: usize,
+}
+
+impl ProgramGenerator {
+    /// Create a generator from a seed, making programs of up to 40 commands
+    pub fn new(seed: u64) -> Self {
+        Self {
+            // Spread the seed out so that seeds close together give different programs
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            max_length: 40,
+        }
+    }
+
+    /// Set the most commands in a program
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Next random number
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Random number below a limit, which must not be zero
+    fn below(&mut self, limit: usize) -> usize {
+        (self.next() % limit as u64) as usize
+    }
+
+    /// Make a program of up to max_length commands. Each loop is closed before the program
+    /// ends, and space is kept for the ] of the loops still open, so the brackets are always
+    /// balanced. Loops are short so that most of the programs finish.
+    pub fn program(&mut self) -> String {
+        let length = self.below(self.max_length + 1);
+        let mut source = String::with_capacity(length);
+        let mut open = 0;
+        while source.len() + open < length {
+            let ch = match self.below(10) {
+                0 | 1 => '+',
+                2 => '-',
+                3 | 4 => '>',
+                5 => '<',
+                6 => '.',
+                7 => ',',
+                8 if source.len() + open + 2 <= length => {
+                    open += 1;
+                    '['
+                }
+                _ if open > 0 => {
+                    open -= 1;
+                    ']'
+                }
+                _ => '-',
+            };
+            source.push(ch);
+        }
+        source.extend(std::iter::repeat_n(']', open));
+        source
+    }
+
+    /// Make up to max_length bytes of input
+    pub fn input(&mut self, max_length: usize) -> Vec<u8> {
+        let length = self.below(max_length + 1);
+        (0..length).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Cut a program down to the smallest one that still fails. A loop is taken out whole, then
+/// the brackets of a loop leaving its body, then single commands, as long as the program still
+/// fails, until nothing more can be taken out. The brackets stay balanced, so if the program
+/// was valid the smaller one is too.
+///
+/// Example usage:
+/// ```
+///     use bft_interp::generate::shrink;
+///     // The smallest program that moves the data pointer before the start of the tape
+///     let fails = |source: &str| bft_interp::run_with_io(source, b"", bft_interp::BfTapeBuilder::new()).is_err();
+///     assert_eq!(shrink("++[->+<]>.<<,", fails), "<");
+/// ```
+pub fn shrink(source: &str, mut fails: impl FnMut(&str) -> bool) -> String {
+    let mut commands: Vec<char> = source.chars().collect();
+    'shrinking: loop {
+        for candidate in smaller(&commands) {
+            let text: String = candidate.iter().collect();
+            if fails(&text) {
+                commands = candidate;
+                continue 'shrinking;
+            }
+        }
+        return commands.into_iter().collect();
+    }
+}
+
+/// The programs one step smaller than a program, largest cut first
+fn smaller(commands: &[char]) -> Vec<Vec<char>> {
+    let mut loops = Vec::new();
+    let mut open = Vec::new();
+    for (index, &ch) in commands.iter().enumerate() {
+        match ch {
+            '[' => open.push(index),
+            ']' => {
+                if let Some(start) = open.pop() {
+                    loops.push((start, index));
+                }
+            }
+            _ => {}
+        }
+    }
+    let without = |cut: &[usize]| -> Vec<char> {
+        commands
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !cut.contains(index))
+            .map(|(_, &ch)| ch)
+            .collect()
+    };
+
+    let mut candidates = Vec::new();
+    for &(start, end) in &loops {
+        candidates.push(without(&(start..=end).collect::<Vec<usize>>()));
+    }
+    for &(start, end) in &loops {
+        candidates.push(without(&[start, end]));
+    }
+    for (index, &ch) in commands.iter().enumerate() {
+        if ch != '[' && ch != ']' {
+            candidates.push(without(&[index]));
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that the generated programs are valid, short enough and the same for a seed
+    #[test]
+    fn programs() {
+        for seed in 0..200 {
+            let source = ProgramGenerator::new(seed).max_length(30).program();
+            assert!(source.len() <= 30, "{}", source);
+            let mut program = bft_types::BfProgram::new("random.bf", &source).unwrap();
+            assert!(program.validate().is_ok(), "{}", source);
+            assert_eq!(ProgramGenerator::new(seed).max_length(30).program(), source);
+        }
+        let mut generator = ProgramGenerator::new(1);
+        let lengths: Vec<usize> = (0..50).map(|_| generator.input(5).len()).collect();
+        assert!(lengths.iter().all(|&length| length <= 5));
+        assert!(lengths.contains(&0) && lengths.contains(&5));
+    }
+
+    /// Test that a failing program is cut down to the smallest one that still fails
+    #[test]
+    fn shrinking() {
+        // Outputs something
+        assert_eq!(shrink("+[>,.<-]>>+", |s| s.contains('.')), ".");
+        // Has a loop inside a loop
+        assert_eq!(shrink("+[>[-]<[+>]-]", |s| s.contains("[[")), "[[]]");
+        // A program that doesn't fail is left as it is
+        assert_eq!(shrink("+[-]", |_| false), "+[-]");
+    }
+}
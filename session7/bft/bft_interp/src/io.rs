@@ -0,0 +1,132 @@
+//! Byte level input and output used by the interpreter.
+//!
+//! Nothing here needs the standard library. With the std feature the errors are
+//! std::io::Error, so that the std Read and Write adapters pass their errors on as they are.
+//! Without it they are an IoError of this module, which only needs `core`.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Error from a BfIo, std::io::Error when the standard library is available
+#[cfg(feature = "std")]
+pub type IoError = std::io::Error;
+
+/// Error from a BfIo without the standard library, which is a message saying what failed
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoError {
+    message: &'static str,
+}
+
+#[cfg(not(feature = "std"))]
+impl IoError {
+    /// Create an error with a message saying what failed
+    pub const fn new(message: &'static str) -> Self {
+        Self { message }
+    }
+
+    /// What failed
+    pub fn message(&self) -> &'static str {
+        self.message
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for IoError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Byte level input and output used by the interpreter. The `,` command reads a byte and the
+/// `.` command writes one, so anything that can supply and accept bytes can be used to run a
+/// program, not just a pair of `Read` and `Write` objects.
+///
+/// Example usage:
+/// ```
+///     use bft_interp::BfIo;
+///     let mut io = bft_interp::VecIo::new(vec![7]);
+///     assert_eq!(io.read_byte().unwrap(), Some(7));
+///     assert_eq!(io.read_byte().unwrap(), None);
+///     io.write_byte(42).unwrap();
+///     assert_eq!(io.output(), &[42]);
+/// ```
+pub trait BfIo {
+    /// Read a single byte, None at end of file
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError>;
+
+    /// Write a single byte
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError>;
+
+    /// Write several bytes, such as a number or an encoded character. By default each byte
+    /// is written in turn.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        for byte in bytes {
+            self.write_byte(*byte)?;
+        }
+        Ok(())
+    }
+
+    /// Make sure everything written so far has reached its destination
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
+
+/// In memory BfIo that serves input from a buffer and captures the output, which is handy
+/// for tests and for running programs as part of a larger application.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"tiny.bf", ",+.").unwrap();
+///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+///     let mut io = bft_interp::VecIo::new(b"A".to_vec());
+///     assert!(tape.interpreter_io(&mut io).is_ok());
+///     assert_eq!(io.into_output(), b"B");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VecIo {
+    pub(crate) input: VecDeque<u8>,
+    pub(crate) output: Vec<u8>,
+}
+
+impl VecIo {
+    /// Create with the bytes that will be served as input
+    pub fn new(input: Vec<u8>) -> Self {
+        Self {
+            input: input.into(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Input that has not been read yet
+    pub fn remaining_input(&self) -> &VecDeque<u8> {
+        &self.input
+    }
+
+    /// Everything written so far
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Take the output, consuming the VecIo
+    pub fn into_output(self) -> Vec<u8> {
+        self.output
+    }
+}
+
+impl BfIo for VecIo {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError> {
+        self.output.push(byte);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        self.output.extend_from_slice(bytes);
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+//! Compiling programs to native code with cranelift, enabled by the `jit` feature.
+//!
+//! The ops of a compiled program are turned into a native function that works on the cells of
+//! a dense tape. Input and output call back into the tape so that they behave exactly as they
+//! do when the program is interpreted. Anything the native code can't do itself, such as
+//! moving off the end of the tape or stopping when interrupted, makes it stop before the op
+//! and return the op's index, so that BfTape::run_ir can carry on from there, growing the tape
+//! or reporting the error as it always would.
+
+use crate::machine::TapeStorage;
+use crate::{BfError, BfIo, BfTape, CellKind};
+use bft_types::{BfIr, BfOp};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlagsData, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use std::sync::atomic::AtomicBool;
+
+/// Status returned by the native code when it has run every op
+const FINISHED: i64 = 0;
+
+/// Status returned by the native code when it has stopped before an op that has to be run by
+/// run_ir
+const STOPPED: i64 = 1;
+
+/// Status returned by the native code, or an I/O function, when input or output failed
+const FAILED: i64 = 2;
+
+/// Interrupt flag for tapes that don't have one, which is never set
+static NOT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Native function made from the ops. It is passed the state, the cells, the length of the
+/// tape, the data pointer and the interrupt flag.
+type NativeFunction = extern "C" fn(*mut u8, *mut u8, usize, usize, *const u8) -> i64;
+
+/// What the native code and the I/O functions it calls share. The native code writes the
+/// first two fields when it returns, so they must stay first.
+#[repr(C)]
+struct JitState<'s, 'a, T, I> {
+    /// Data pointer when the native code returned
+    data_pointer: usize,
+    /// Index of the op the native code stopped before, or the number of ops if it finished
+    op: usize,
+    /// Tape the program is running on
+    tape: &'s mut BfTape<'a, T>,
+    /// Where input comes from and output goes to
+    io: &'s mut I,
+    /// Index of the instruction each op was made from
+    source_map: &'s [usize],
+    /// Error from input or output
+    error: Option<BfError>,
+}
+
+/// Run as many of the ops as possible as native code, starting at the first. The index of the
+/// op to carry on from is returned, which is the number of ops if they have all been run, or
+/// zero if the ops couldn't be made into native code.
+pub(crate) fn run<T: CellKind, I: BfIo>(
+    tape: &mut BfTape<'_, T>,
+    ir: &BfIr,
+    io: &mut I,
+) -> Result<usize, BfError> {
+    let cells = match &mut tape.machine.tape {
+        TapeStorage::Dense(cells) => cells.as_mut_ptr() as *mut u8,
+        TapeStorage::Sparse(_) => return Ok(0),
+    };
+    let Some((module, code)) = compile::<T, I>(ir) else {
+        return Ok(0);
+    };
+    let interrupt = match &tape.interrupt {
+        Some(flag) => flag.as_ptr() as *const u8,
+        None => NOT_INTERRUPTED.as_ptr() as *const u8,
+    };
+    let length = tape.machine.length;
+    let data_pointer = tape.machine.data_pointer;
+    let mut state = JitState {
+        data_pointer,
+        op: 0,
+        tape,
+        io,
+        source_map: ir.source_map(),
+        error: None,
+    };
+
+    // SAFETY: The code was compiled with the signature of a NativeFunction. It only touches
+    // the cells below the length of the tape, which input doesn't move as the tape can't grow
+    // until the native code has returned.
+    let function = unsafe { std::mem::transmute::<*const u8, NativeFunction>(code) };
+    let status = function(
+        &mut state as *mut JitState<T, I> as *mut u8,
+        cells,
+        length,
+        data_pointer,
+        interrupt,
+    );
+    // SAFETY: The function has returned and isn't called again
+    unsafe { module.free_memory() };
+
+    state.tape.machine.data_pointer = state.data_pointer;
+    state.tape.machine.high_water = state.tape.machine.high_water.max(state.data_pointer);
+    match (status, state.error.take()) {
+        (FAILED, Some(e)) => Err(e),
+        _ => Ok(state.op),
+    }
+}
+
+/// Make the ops into a native function, None if that isn't possible on this machine
+fn compile<T: CellKind, I: BfIo>(ir: &BfIr) -> Option<(JITModule, *const u8)> {
+    let mut flags = settings::builder();
+    flags.set("use_colocated_libcalls", "false").ok()?;
+    flags.set("is_pic", "false").ok()?;
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let frontend_config = module.target_config();
+    let pointer = frontend_config.pointer_type();
+
+    let mut context = module.make_context();
+    for param in [pointer, pointer, pointer, pointer, pointer] {
+        context.func.signature.params.push(AbiParam::new(param));
+    }
+    context
+        .func
+        .signature
+        .returns
+        .push(AbiParam::new(types::I64));
+    let mut io_signature = module.make_signature();
+    for param in [pointer, pointer, pointer] {
+        io_signature.params.push(AbiParam::new(param));
+    }
+    io_signature.returns.push(AbiParam::new(types::I64));
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let io_signature = builder.import_signature(io_signature);
+    let mut lowering = Lowering::<T>::new(&mut builder, pointer);
+    let source_map = ir.source_map();
+    for (index, op) in ir.ops().iter().enumerate() {
+        let source = source_map[index];
+        if index == 0 || source_map[index - 1] != source {
+            // A replaced loop is run as a whole or not at all, so every cell it reaches is
+            // checked before the first of its ops
+            let loop_ops = ir.ops()[index..]
+                .iter()
+                .zip(&source_map[index..])
+                .take_while(|&(_, &op_source)| op_source == source);
+            for (loop_op, _) in loop_ops {
+                if let BfOp::MulAdd { offset, .. } = *loop_op {
+                    lowering.check_offset(offset, index);
+                }
+            }
+        }
+        match *op {
+            BfOp::Add(amount) => lowering.add(amount),
+            BfOp::Move(amount) => lowering.move_by(amount, index),
+            BfOp::ClearCell => lowering.clear_cell(),
+            BfOp::MulAdd { offset, factor } => lowering.mul_add(offset, factor),
+            BfOp::ScanRight => lowering.scan(1, index),
+            BfOp::ScanLeft => lowering.scan(-1, index),
+            BfOp::Output => lowering.call_io(io_signature, output::<T, I> as *const u8, index),
+            BfOp::Input => lowering.call_io(io_signature, input::<T, I> as *const u8, index),
+            BfOp::LoopStart(_) => lowering.loop_start(),
+            BfOp::LoopEnd(_) => lowering.loop_end(index),
+        }
+    }
+    lowering.finish(ir.ops().len());
+    builder.seal_all_blocks();
+    builder.finalize(frontend_config);
+
+    let id = module
+        .declare_function("bf_program", Linkage::Local, &context.func.signature)
+        .ok()?;
+    module.define_function(id, &mut context).ok()?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+    let code = module.get_finalized_function(id);
+    Some((module, code))
+}
+
+/// Turns ops into cranelift instructions
+struct Lowering<'b, 'f, T> {
+    builder: &'b mut FunctionBuilder<'f>,
+    /// Type of a pointer, which is also used for the data pointer and the tape's length
+    pointer: Type,
+    /// Type of a cell
+    cell: Type,
+    /// The function's parameters
+    state: Value,
+    cells: Value,
+    length: Value,
+    interrupt: Value,
+    /// The data pointer
+    data_pointer: Variable,
+    /// The block at the start and the block after the end of each loop that is open
+    loops: Vec<(cranelift_codegen::ir::Block, cranelift_codegen::ir::Block)>,
+    cell_kind: std::marker::PhantomData<T>,
+}
+
+impl<'b, 'f, T: CellKind> Lowering<'b, 'f, T> {
+    /// Start the function, with the parameters in variables
+    fn new(builder: &'b mut FunctionBuilder<'f>, pointer: Type) -> Self {
+        let cell = match std::mem::size_of::<T>() {
+            1 => types::I8,
+            2 => types::I16,
+            4 => types::I32,
+            _ => types::I64,
+        };
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let params = builder.block_params(entry).to_vec();
+        let data_pointer = builder.declare_var(pointer);
+        builder.def_var(data_pointer, params[3]);
+        Self {
+            builder,
+            pointer,
+            cell,
+            state: params[0],
+            cells: params[1],
+            length: params[2],
+            interrupt: params[4],
+            data_pointer,
+            loops: Vec::new(),
+            cell_kind: std::marker::PhantomData,
+        }
+    }
+
+    /// A constant cell value. Cranelift wants the bits of narrow constants, so negative
+    /// amounts have the bits above the cell cleared.
+    fn cell_constant(&mut self, value: i64) -> Value {
+        let bits = self.cell.bits();
+        let value = if bits < 64 {
+            value & ((1 << bits) - 1)
+        } else {
+            value
+        };
+        self.builder.ins().iconst(self.cell, value)
+    }
+
+    /// Address of a cell
+    fn cell_address(&mut self, index: Value) -> Value {
+        // Cells are 1, 2, 4 or 8 bytes
+        let shift = std::mem::size_of::<T>().trailing_zeros() as i64;
+        let offset = if shift == 0 {
+            index
+        } else {
+            self.builder.ins().ishl_imm_u(index, shift)
+        };
+        self.builder.ins().iadd(self.cells, offset)
+    }
+
+    fn load_cell(&mut self, index: Value) -> Value {
+        let address = self.cell_address(index);
+        self.builder
+            .ins()
+            .load(self.cell, MemFlagsData::trusted(), address, 0)
+    }
+
+    fn store_cell(&mut self, index: Value, value: Value) {
+        let address = self.cell_address(index);
+        self.builder
+            .ins()
+            .store(MemFlagsData::trusted(), value, address, 0);
+    }
+
+    /// Return from the function, noting where it got to
+    fn exit(&mut self, status: i64, op: usize) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        self.builder
+            .ins()
+            .store(MemFlagsData::trusted(), data_pointer, self.state, 0);
+        let op = self.builder.ins().iconst(self.pointer, op as i64);
+        let offset = self.pointer.bytes() as i32;
+        self.builder
+            .ins()
+            .store(MemFlagsData::trusted(), op, self.state, offset);
+        let status = self.builder.ins().iconst(types::I64, status);
+        self.builder.ins().return_(&[status]);
+    }
+
+    /// Carry on if a condition isn't zero, otherwise return with a status before the op
+    fn exit_unless(&mut self, condition: Value, status: i64, op: usize) {
+        let carry_on = self.builder.create_block();
+        let stop = self.builder.create_block();
+        self.builder.ins().brif(condition, carry_on, &[], stop, &[]);
+        self.builder.switch_to_block(stop);
+        self.exit(status, op);
+        self.builder.switch_to_block(carry_on);
+    }
+
+    /// Stop before the op if a cell index is off the end of the tape. Indexes before the start
+    /// of the tape are huge when treated as unsigned so are off the end as well.
+    fn check_bounds(&mut self, index: Value, op: usize) {
+        let in_bounds = self
+            .builder
+            .ins()
+            .icmp(IntCC::UnsignedLessThan, index, self.length);
+        self.exit_unless(in_bounds, STOPPED, op);
+    }
+
+    fn add(&mut self, amount: i64) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let value = self.load_cell(data_pointer);
+        let amount = self.cell_constant(amount);
+        let value = self.builder.ins().iadd(value, amount);
+        self.store_cell(data_pointer, value);
+    }
+
+    fn move_by(&mut self, amount: isize, op: usize) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let moved = self.builder.ins().iadd_imm_s(data_pointer, amount as i64);
+        self.check_bounds(moved, op);
+        self.builder.def_var(self.data_pointer, moved);
+    }
+
+    fn clear_cell(&mut self) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let zero = self.cell_constant(0);
+        self.store_cell(data_pointer, zero);
+    }
+
+    /// Stop before the op if the cell at an offset from the data pointer is off the end of
+    /// the tape
+    fn check_offset(&mut self, offset: isize, op: usize) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let target = self.builder.ins().iadd_imm_s(data_pointer, offset as i64);
+        self.check_bounds(target, op);
+    }
+
+    fn mul_add(&mut self, offset: isize, factor: i64) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let target = self.builder.ins().iadd_imm_s(data_pointer, offset as i64);
+        let value = self.load_cell(data_pointer);
+        let factor = self.cell_constant(factor);
+        let amount = self.builder.ins().imul(value, factor);
+        let total = self.load_cell(target);
+        let total = self.builder.ins().iadd(total, amount);
+        self.store_cell(target, total);
+    }
+
+    fn scan(&mut self, step: i64, op: usize) {
+        let header = self.builder.create_block();
+        let body = self.builder.create_block();
+        let done = self.builder.create_block();
+        self.builder.ins().jump(header, &[]);
+        self.builder.switch_to_block(header);
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let value = self.load_cell(data_pointer);
+        self.builder.ins().brif(value, body, &[], done, &[]);
+        self.builder.switch_to_block(body);
+        let moved = self.builder.ins().iadd_imm_s(data_pointer, step);
+        self.check_bounds(moved, op);
+        self.builder.def_var(self.data_pointer, moved);
+        self.builder.ins().jump(header, &[]);
+        self.builder.switch_to_block(done);
+    }
+
+    /// Call an I/O function, returning if it failed
+    fn call_io(
+        &mut self,
+        signature: cranelift_codegen::ir::SigRef,
+        function: *const u8,
+        op: usize,
+    ) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let function = self.builder.ins().iconst(self.pointer, function as i64);
+        let op_index = self.builder.ins().iconst(self.pointer, op as i64);
+        let call = self.builder.ins().call_indirect(
+            signature,
+            function,
+            &[self.state, data_pointer, op_index],
+        );
+        let status = self.builder.inst_results(call)[0];
+        let succeeded = self
+            .builder
+            .ins()
+            .icmp_imm_s(IntCC::Equal, status, FINISHED);
+        self.exit_unless(succeeded, FAILED, op);
+    }
+
+    fn loop_start(&mut self) {
+        let header = self.builder.create_block();
+        let body = self.builder.create_block();
+        let after = self.builder.create_block();
+        self.builder.ins().jump(header, &[]);
+        self.builder.switch_to_block(header);
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let value = self.load_cell(data_pointer);
+        self.builder.ins().brif(value, body, &[], after, &[]);
+        self.builder.switch_to_block(body);
+        self.loops.push((header, after));
+    }
+
+    /// Go round the loop again, unless the program has been interrupted in which case stop
+    /// before the end of the loop so that the interrupt is reported there
+    fn loop_end(&mut self, op: usize) {
+        let (header, after) = self.loops.pop().expect("ops have matching loops");
+        let interrupted =
+            self.builder
+                .ins()
+                .load(types::I8, MemFlagsData::trusted(), self.interrupt, 0);
+        let running = self.builder.ins().icmp_imm_s(IntCC::Equal, interrupted, 0);
+        self.exit_unless(running, STOPPED, op);
+        self.builder.ins().jump(header, &[]);
+        self.builder.switch_to_block(after);
+    }
+
+    /// Return once every op has been run
+    fn finish(&mut self, ops: usize) {
+        self.exit(FINISHED, ops);
+    }
+}
+
+/// Output the cell at the data pointer for the native code
+extern "C" fn output<T: CellKind, I: BfIo>(state: *mut u8, data_pointer: usize, op: usize) -> i64 {
+    // SAFETY: The native code passes back the state that it was given, which outlives it
+    let state = unsafe { &mut *(state as *mut JitState<T, I>) };
+    state.tape.machine.data_pointer = data_pointer;
+    state.tape.machine.program_pointer = state.source_map[op];
+    match state.tape.output_io(state.io) {
+        Ok(()) => FINISHED,
+        Err(e) => {
+            state.error = Some(e);
+            FAILED
+        }
+    }
+}
+
+/// Input a value into the cell at the data pointer for the native code
+extern "C" fn input<T: CellKind, I: BfIo>(state: *mut u8, data_pointer: usize, op: usize) -> i64 {
+    // SAFETY: The native code passes back the state that it was given, which outlives it
+    let state = unsafe { &mut *(state as *mut JitState<T, I>) };
+    state.tape.machine.data_pointer = data_pointer;
+    state.tape.machine.program_pointer = state.source_map[op];
+    match state.tape.input_io(state.io) {
+        Ok(()) => FINISHED,
+        Err(e) => {
+            state.error = Some(e);
+            FAILED
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Listings of a program that don't run it.
+//!
+//! format pretty-prints a program's source, with the body of each loop indented. dump writes an
+//! annotated listing of its instructions, showing where each jump goes. ProgramStats counts the
+//! commands and loops in it.
+
+use bft_types::{BfCommand, BfProgram};
+use std::io::Write;
+
+/// The commands in the order they are counted by ProgramStats
+const COMMANDS: [BfCommand; 8] = [
+    BfCommand::IncDataPointer,
+    BfCommand::DecDataPointer,
+    BfCommand::IncValue,
+    BfCommand::DecValue,
+    BfCommand::OutputValue,
+    BfCommand::InputValue,
+    BfCommand::JumpForward,
+    BfCommand::JumpBackward,
+];
+
+/// Pretty-print a program's commands, leaving out the comments. Each [ and ] is on a line of
+/// its own, with the commands between them indented by the number of spaces given for each
+/// loop they are in. Runs of other commands are wrapped so that lines are no longer than the
+/// width, other than by the indent.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"mul.bf", "+++ three [->++<] double >.").unwrap();
+///     assert_eq!(
+///         bft_interp::listing::format(&program, 2, 80),
+///         "+++\n[\n  ->++<\n]\n>.\n"
+///     );
+/// ```
+pub fn format(program: &BfProgram, indent: usize, width: usize) -> String {
+    let mut text = String::new();
+    let mut line = String::new();
+    let mut depth: usize = 0;
+    for inst in program.instructions() {
+        match inst.command() {
+            BfCommand::Comment => continue,
+            BfCommand::JumpForward => {
+                end_line(&mut text, &mut line);
+                push_command(&mut line, depth * indent, '[');
+                end_line(&mut text, &mut line);
+                depth += 1;
+            }
+            BfCommand::JumpBackward => {
+                end_line(&mut text, &mut line);
+                depth = depth.saturating_sub(1);
+                push_command(&mut line, depth * indent, ']');
+                end_line(&mut text, &mut line);
+            }
+            command => {
+                if line.trim_start().len() >= width.max(1) {
+                    end_line(&mut text, &mut line);
+                }
+                push_command(&mut line, depth * indent, BfCommand::to_char(command));
+            }
+        }
+    }
+    end_line(&mut text, &mut line);
+    text
+}
+
+/// Add a command to a line, indenting it first if it is the first on the line
+fn push_command(line: &mut String, indent: usize, command: char) {
+    if line.is_empty() {
+        line.push_str(&" ".repeat(indent));
+    }
+    line.push(command);
+}
+
+/// Add a line to the text if there is anything on it, leaving it empty
+fn end_line(text: &mut String, line: &mut String) {
+    if !line.is_empty() {
+        text.push_str(line);
+        text.push('\n');
+        line.clear();
+    }
+}
+
+/// Write an annotated listing of a program, one line for each command giving its index, its
+/// location in the source, the command and what it does. The brackets also give the index and
+/// location of the matching bracket, so the program must have been validated.
+///
+/// Example usage:
+/// ```
+///     let mut program = bft_types::BfProgram::new(&"loop.bf", "+[-]").unwrap();
+///     program.validate().unwrap();
+///     let mut listing = Vec::new();
+///     bft_interp::listing::dump(&program, &mut listing).unwrap();
+///     let listing = String::from_utf8(listing).unwrap();
+///     assert_eq!(listing.lines().count(), 4);
+///     assert!(listing.lines().nth(1).unwrap().ends_with("jumps to 3 at 1:4"));
+/// ```
+pub fn dump(program: &BfProgram, writer: &mut impl Write) -> std::io::Result<()> {
+    let filename = program.filename().to_string_lossy();
+    let instructions = program.instructions();
+    let width = instructions.len().saturating_sub(1).to_string().len();
+    for (index, inst) in instructions.iter().enumerate() {
+        if inst.command() == BfCommand::Comment {
+            continue;
+        }
+        write!(
+            writer,
+            "{:>width$} {}:{} {} {}",
+            index,
+            filename,
+            inst.location(),
+            BfCommand::to_char(inst.command()),
+            inst.command(),
+            width = width
+        )?;
+        if let Some(&target) = program.jump_table().get(index).filter(|_| {
+            matches!(
+                inst.command(),
+                BfCommand::JumpForward | BfCommand::JumpBackward
+            )
+        }) {
+            write!(
+                writer,
+                ", jumps to {} at {}",
+                target,
+                instructions[target].location()
+            )?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Statistics about a program, worked out from its source without running it.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"stats.bf", "++[>+[-]<-] a comment").unwrap();
+///     let stats = bft_interp::listing::ProgramStats::new(&program);
+///     assert_eq!(stats.commands, 11);
+///     assert_eq!(stats.loops, 2);
+///     assert_eq!(stats.max_depth, 2);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgramStats {
+    /// Name of the file the program came from
+    pub filename: std::path::PathBuf,
+    /// Number of commands, not counting comments
+    pub commands: usize,
+    /// Number of times each command is used, in the order of COMMANDS
+    pub command_counts: Vec<(BfCommand, usize)>,
+    /// Number of loops, which is the number of [
+    pub loops: usize,
+    /// Most loops inside each other, 0 if there are none
+    pub max_depth: usize,
+    /// Number of lines of source the commands are on
+    pub lines: usize,
+}
+
+impl ProgramStats {
+    /// Count the commands and loops in a program
+    pub fn new(program: &BfProgram) -> Self {
+        let mut stats = ProgramStats {
+            filename: program.filename().to_path_buf(),
+            commands: 0,
+            command_counts: COMMANDS.iter().map(|&command| (command, 0)).collect(),
+            loops: 0,
+            max_depth: 0,
+            lines: 0,
+        };
+        let mut depth: usize = 0;
+        let mut last_line = 0;
+        for inst in program.instructions() {
+            let Some(count) = stats
+                .command_counts
+                .iter_mut()
+                .find(|(command, _)| *command == inst.command())
+            else {
+                continue;
+            };
+            count.1 += 1;
+            stats.commands += 1;
+            // The instructions are in the order they are in the source
+            if inst.location().line() != last_line {
+                last_line = inst.location().line();
+                stats.lines += 1;
+            }
+            match inst.command() {
+                BfCommand::JumpForward => {
+                    stats.loops += 1;
+                    depth += 1;
+                    stats.max_depth = stats.max_depth.max(depth);
+                }
+                BfCommand::JumpBackward => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// The statistics as text to be shown to the user
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{}: {} commands on {} lines, {} loops nested up to {} deep\n",
+            self.filename.to_string_lossy(),
+            self.commands,
+            self.lines,
+            self.loops,
+            self.max_depth
+        );
+        for (command, count) in &self.command_counts {
+            report += &format!(
+                "  {} {:?}: {}\n",
+                BfCommand::to_char(*command),
+                command,
+                count
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that nested loops are indented and long runs are wrapped, without changing the
+    /// commands
+    #[test]
+    fn format_program() {
+        let source = "read, then [copy [>+<-] # and\nmove >>>>>>>>]\n.";
+        let program = BfProgram::new("nested.bf", source).unwrap();
+        let text = format(&program, 4, 4);
+        assert_eq!(
+            text,
+            ",\n[\n    [\n        >+<-\n    ]\n    >>>>\n    >>>>\n]\n.\n"
+        );
+        let formatted = BfProgram::new("formatted.bf", &text).unwrap();
+        let commands = |program: &BfProgram| -> Vec<BfCommand> {
+            program
+                .instructions()
+                .iter()
+                .map(|inst| inst.command())
+                .collect()
+        };
+        assert_eq!(commands(&formatted), commands(&program));
+
+        // A program that is all comments has nothing to format
+        let program = BfProgram::new("empty.bf", "just words").unwrap();
+        assert_eq!(format(&program, 2, 80), "");
+    }
+
+    /// Test the listing gives the location of each command and the matching brackets
+    #[test]
+    fn dump_program() {
+        let mut program = BfProgram::new("loop.bf", "+\n[->+<]").unwrap();
+        program.validate().unwrap();
+        let mut listing = Vec::new();
+        dump(&program, &mut listing).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].starts_with("0 loop.bf:1:1 + "), "{}", lines[0]);
+        assert!(lines[1].starts_with("1 loop.bf:2:1 [ "), "{}", lines[1]);
+        assert!(lines[1].ends_with(", jumps to 6 at 2:6"), "{}", lines[1]);
+        assert!(lines[6].ends_with(", jumps to 1 at 2:1"), "{}", lines[6]);
+        assert!(!lines[2].contains("jumps"));
+    }
+
+    /// Test the counts of commands and loops
+    #[test]
+    fn program_stats() {
+        let program = BfProgram::new("stats.bf", "+[>[<]]\n\n,.").unwrap();
+        let stats = ProgramStats::new(&program);
+        assert_eq!(stats.commands, 9);
+        assert_eq!(stats.loops, 2);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.lines, 2);
+        assert_eq!(
+            stats.command_counts,
+            vec![
+                (BfCommand::IncDataPointer, 1),
+                (BfCommand::DecDataPointer, 1),
+                (BfCommand::IncValue, 1),
+                (BfCommand::DecValue, 0),
+                (BfCommand::OutputValue, 1),
+                (BfCommand::InputValue, 1),
+                (BfCommand::JumpForward, 2),
+                (BfCommand::JumpBackward, 2),
+            ]
+        );
+        let report = stats.report();
+        assert!(
+            report.starts_with("stats.bf: 9 commands on 2 lines, 2 loops nested up to 2 deep\n")
+        );
+        assert!(report.contains("  [ JumpForward: 2\n"));
+
+        let stats = ProgramStats::new(&BfProgram::new("empty.bf", "").unwrap());
+        assert_eq!(stats.commands, 0);
+        assert_eq!(stats.lines, 0);
+        assert_eq!(stats.max_depth, 0);
+    }
+}
@@ -0,0 +1,825 @@
+//! The tape and execution core that BfTape is built on.
+//!
+//! Nothing here needs the standard library, only `core` and `alloc`, so a program can be run
+//! on an embedded target with the std feature turned off. BfMachine holds the cells and the
+//! data and program pointers, moves the data pointer, changes the cells and executes commands,
+//! doing their input and output a byte at a time through a BfIo. BfTape adds the program
+//! itself and everything around it, such as the output formats, hooks, tracing and limits.
+
+use crate::{BfIo, CellKind, IoError};
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, TryReserveError};
+use alloc::vec::Vec;
+
+/// Number of cells added each time a tape that can grow runs out of cells
+pub(crate) const TAPE_GROWTH_CHUNK: usize = 4096;
+
+/// Whether the tape can change size, the same as cli::AllocStrategy, which needs std.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AllocStrategy {
+    /// Allows more memory to be allocated when required.
+    TapeCanGrow,
+    /// The amount of memory used to store the tape cannot be changed.
+    TapeIsFixed,
+    /// Allows more memory to be allocated when required, on the left of the first cell as well
+    /// as the right of the last. Growing a sparse tape to the left moves the index of every
+    /// cell in its BTreeMap along.
+    TapeCanGrowBothWays,
+    /// Allows more memory to be allocated when required, until the tape has this many cells.
+    TapeCanGrowWithLimit(usize),
+    /// The tape is fixed in size but moving the data pointer off one end of it moves it to the
+    /// other end.
+    TapeWraps,
+}
+
+/// What happens when a cell is incremented or decremented beyond its limits, the same as
+/// cli::OverflowBehavior.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverflowBehavior {
+    /// The value wraps around, so incrementing 255 in a u8 cell gives 0
+    Wrap,
+    /// The value stays at the limit, so incrementing 255 in a u8 cell gives 255
+    Saturate,
+    /// The command fails with a CellOverflow error
+    Error,
+}
+
+/// What is stored in the current cell when an input command reaches the end of the input, the
+/// same as cli::EofBehavior.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EofBehavior {
+    /// The cell is set to 0
+    Zero,
+    /// The cell is set to -1, which is 255 in a u8 cell
+    NegOne,
+    /// The cell keeps whatever value it had before the input command
+    Unchanged,
+}
+
+/// A command executed by a BfMachine, the same as bft_types::BfCommand.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// `>`
+    IncDataPointer,
+    /// `<`
+    DecDataPointer,
+    /// `+`
+    IncValue,
+    /// `-`
+    DecValue,
+    /// `.`
+    OutputValue,
+    /// `,`
+    InputValue,
+    /// `[`
+    JumpForward,
+    /// `]`
+    JumpBackward,
+    /// Anything else, which does nothing
+    Comment,
+}
+
+/// Errors from a BfMachine. They don't say where in the program they happened, the program
+/// pointer is left at the command that failed. BfTape turns each into the BfError of the same
+/// name, with the instruction and source line added.
+#[derive(Debug)]
+pub enum MachineError {
+    /// The data pointer was moved before the start of the tape
+    DataPtrMovedBeforeStart,
+    /// The data pointer was moved after the end of a tape that can't grow
+    DataPtrMovedAfterEnd,
+    /// A tape that can grow would have grown beyond its maximum length
+    TapeLimitExceeded { limit: usize },
+    /// A tape can't have no cells
+    InvalidTapeSize { requested_cells: usize },
+    /// There isn't enough memory for the cells of the tape
+    TapeAllocationFailed { requested_cells: usize },
+    /// The current cell was incremented or decremented beyond its limits, and is unchanged
+    CellOverflow,
+    /// A bracket that has to be jumped from has no matching bracket to jump to
+    BracketNotFound,
+    /// Reading or writing a byte failed
+    IOError { error: IoError },
+}
+
+impl core::fmt::Display for MachineError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            MachineError::DataPtrMovedBeforeStart => {
+                write!(
+                    f,
+                    "[E_TAPE_UNDERRUN] Data pointer moved before start of tape"
+                )
+            }
+            MachineError::DataPtrMovedAfterEnd => {
+                write!(f, "[E_TAPE_OVERRUN] Data pointer moved after end of tape")
+            }
+            MachineError::TapeLimitExceeded { limit } => {
+                write!(f, "[E_TAPE_LIMIT] Tape limit of {} cells exceeded", limit)
+            }
+            MachineError::InvalidTapeSize { requested_cells } => write!(
+                f,
+                "[E_TAPE_SIZE] A tape of {} cells can't be created",
+                requested_cells
+            ),
+            MachineError::TapeAllocationFailed { requested_cells } => write!(
+                f,
+                "[E_TAPE_ALLOCATION] Not enough memory for a tape of {} cells",
+                requested_cells
+            ),
+            MachineError::CellOverflow => write!(f, "[E_CELL_OVERFLOW] Cell overflow"),
+            MachineError::BracketNotFound => write!(f, "[E_BRACKET] No matching bracket"),
+            MachineError::IOError { error } => write!(f, "[E_IO] I/O error {}", error),
+        }
+    }
+}
+
+/// Where the cells of a tape are stored.
+///
+/// A dense tape holds every cell in a Vec, which is fastest. A sparse tape only holds the cells
+/// that aren't zero in a BTreeMap, which suits programs that use a few cells spread across a
+/// huge tape. The rest of the tape doesn't know which is in use.
+///
+/// The sparse tape is a BTreeMap rather than a HashMap because it is in `alloc`, so it builds
+/// without the standard library, and it keeps the cells in order of their index, so non_zero
+/// doesn't have to sort them.
+#[derive(Debug)]
+pub(crate) enum TapeStorage<T> {
+    Dense(Vec<T>),
+    Sparse(BTreeMap<usize, T>),
+}
+
+impl<T: CellKind> TapeStorage<T> {
+    /// The value of a cell. Cells that haven't been written to are zero.
+    pub(crate) fn get(&self, index: usize) -> T {
+        match self {
+            TapeStorage::Dense(cells) => cells.get(index).copied().unwrap_or_default(),
+            TapeStorage::Sparse(cells) => cells.get(&index).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Set the value of a cell, which must be allocated for a dense tape. A sparse tape drops
+    /// cells that are set back to zero so that it only holds the cells in use.
+    pub(crate) fn set(&mut self, index: usize, value: T) {
+        match self {
+            TapeStorage::Dense(cells) => cells[index] = value,
+            TapeStorage::Sparse(cells) => {
+                if value == T::default() {
+                    cells.remove(&index);
+                } else {
+                    cells.insert(index, value);
+                }
+            }
+        }
+    }
+
+    /// Number of cells allocated. Every cell of a sparse tape is available without allocating.
+    pub(crate) fn allocated(&self) -> usize {
+        match self {
+            TapeStorage::Dense(cells) => cells.len(),
+            TapeStorage::Sparse(_) => usize::MAX,
+        }
+    }
+
+    /// Add cells to the start of the tape, moving every existing cell along
+    fn prepend(&mut self, count: usize) -> Result<(), TryReserveError> {
+        match self {
+            TapeStorage::Dense(cells) => {
+                cells.try_reserve(count)?;
+                cells.splice(0..0, core::iter::repeat_n(T::default(), count));
+            }
+            TapeStorage::Sparse(cells) => {
+                *cells = core::mem::take(cells)
+                    .into_iter()
+                    .map(|(i, v)| (i + count, v))
+                    .collect();
+            }
+        }
+        Ok(())
+    }
+
+    /// Set every cell back to zero, keeping the cells allocated
+    fn clear(&mut self) {
+        match self {
+            TapeStorage::Dense(cells) => cells.fill(T::default()),
+            TapeStorage::Sparse(cells) => cells.clear(),
+        }
+    }
+
+    /// Dense storage of a number of cells. The memory is reserved first so that running out
+    /// of it is an error rather than an abort.
+    fn dense(length: usize) -> Result<Self, TryReserveError> {
+        let mut cells = Vec::new();
+        cells.try_reserve_exact(length)?;
+        cells.resize(length, T::default());
+        Ok(TapeStorage::Dense(cells))
+    }
+
+    /// Allocate cells up to the given length
+    fn resize(&mut self, length: usize) -> Result<(), TryReserveError> {
+        if let TapeStorage::Dense(cells) = self {
+            cells.try_reserve_exact(length.saturating_sub(cells.len()))?;
+            cells.resize(length, T::default());
+        }
+        Ok(())
+    }
+
+    /// The cells in a range, borrowed if possible
+    pub(crate) fn range(&self, start: usize, end: usize) -> Cow<'_, [T]> {
+        match self {
+            TapeStorage::Dense(cells) => Cow::Borrowed(&cells[start..end]),
+            TapeStorage::Sparse(_) => Cow::Owned((start..end).map(|i| self.get(i)).collect()),
+        }
+    }
+}
+
+/// The cells of a tape and the pointers into it and into the program, which only needs `core`
+/// and `alloc`.
+///
+/// The program itself isn't held here, each command is passed to execute along with, for a
+/// bracket, the index of its matching bracket. The tape starts with the number of cells given,
+/// is fixed in size unless the allocation strategy says otherwise, and at the end of the input
+/// sets the cell to -1 unless the EOF behaviour says otherwise.
+///
+/// Example usage:
+/// ```
+///     use bft_interp::machine::{BfMachine, Command, EofBehavior};
+///     let mut machine: BfMachine<u8> = BfMachine::new(10).unwrap();
+///     machine.set_eof_behavior(EofBehavior::Zero);
+///     // ,[.-] with the index of each bracket's match
+///     let program = [
+///         Command::InputValue,
+///         Command::JumpForward,
+///         Command::OutputValue,
+///         Command::DecValue,
+///         Command::JumpBackward,
+///     ];
+///     let jumps = [0, 4, 0, 0, 1];
+///     let mut io = bft_interp::VecIo::new(vec![3]);
+///     machine.run(&program, &jumps, &mut io).unwrap();
+///     assert_eq!(io.output(), &[3, 2, 1]);
+/// ```
+#[derive(Debug)]
+pub struct BfMachine<T> {
+    /// The cells of the tape
+    pub(crate) tape: TapeStorage<T>,
+    /// Number of cells in use. A tape that can grow allocates cells in chunks, so there can be
+    /// more cells allocated than are in use.
+    pub(crate) length: usize,
+    /// Index of the current cell
+    pub(crate) data_pointer: usize,
+    /// Index of the cell that the program started on. Cells added to the left of it have a
+    /// negative position.
+    pub(crate) origin: usize,
+    /// Index of the next command to be executed
+    pub(crate) program_pointer: usize,
+    /// Number of loops the program pointer is inside
+    pub(crate) depth: usize,
+    /// Highest cell the data pointer has reached
+    pub(crate) high_water: usize,
+    /// Number of times the tape has grown
+    pub(crate) growth_events: u64,
+    /// Whether the tape can change size
+    alloc_strategy: AllocStrategy,
+    /// What happens when a cell is incremented or decremented beyond its limits
+    overflow_behavior: OverflowBehavior,
+    /// What is stored in the cell when the input reaches end of file
+    eof_behavior: EofBehavior,
+}
+
+impl<T: CellKind> BfMachine<T> {
+    /// Create a machine with a fixed tape of the number of cells given, all held in memory. An
+    /// InvalidTapeSize error is returned for a tape of no cells, and a TapeAllocationFailed
+    /// error if there isn't enough memory for the cells.
+    pub fn new(cells: usize) -> Result<Self, MachineError> {
+        if cells == 0 {
+            return Err(MachineError::InvalidTapeSize {
+                requested_cells: cells,
+            });
+        }
+        let tape = TapeStorage::dense(cells).map_err(|_| MachineError::TapeAllocationFailed {
+            requested_cells: cells,
+        })?;
+        Ok(Self::with_storage(tape, cells))
+    }
+
+    /// Create a machine with a fixed tape of the number of cells given, which only holds the
+    /// cells that aren't zero. See new.
+    pub fn new_sparse(cells: usize) -> Result<Self, MachineError> {
+        if cells == 0 {
+            return Err(MachineError::InvalidTapeSize {
+                requested_cells: cells,
+            });
+        }
+        Ok(Self::with_storage(
+            TapeStorage::Sparse(BTreeMap::new()),
+            cells,
+        ))
+    }
+
+    /// Create a machine with the given storage and number of cells in use
+    fn with_storage(tape: TapeStorage<T>, length: usize) -> Self {
+        Self {
+            tape,
+            length,
+            data_pointer: 0,
+            origin: 0,
+            program_pointer: 0,
+            depth: 0,
+            high_water: 0,
+            growth_events: 0,
+            alloc_strategy: AllocStrategy::TapeIsFixed,
+            overflow_behavior: OverflowBehavior::Wrap,
+            eof_behavior: EofBehavior::NegOne,
+        }
+    }
+
+    /// Set every cell back to zero, with the tape back to the number of cells given, and the
+    /// pointers back to the start. The cells stay allocated.
+    pub fn reset(&mut self, length: usize) {
+        self.tape.clear();
+        self.length = length;
+        self.data_pointer = 0;
+        self.origin = 0;
+        self.program_pointer = 0;
+        self.depth = 0;
+        self.high_water = 0;
+        self.growth_events = 0;
+    }
+
+    /// Whether the tape can change size
+    pub fn alloc_strategy(&self) -> AllocStrategy {
+        self.alloc_strategy
+    }
+
+    /// Default is for the tape to be fixed in size, but it can grow or wrap around instead
+    pub fn set_alloc_strategy(&mut self, alloc_strategy: AllocStrategy) {
+        self.alloc_strategy = alloc_strategy;
+    }
+
+    /// What happens when a cell is incremented or decremented beyond its limits
+    pub fn overflow_behavior(&self) -> OverflowBehavior {
+        self.overflow_behavior
+    }
+
+    /// Default is for cells to wrap around, but they can saturate or cause an error instead
+    pub fn set_overflow_behavior(&mut self, overflow_behavior: OverflowBehavior) {
+        self.overflow_behavior = overflow_behavior;
+    }
+
+    /// What is stored in the cell when the input reaches end of file
+    pub fn eof_behavior(&self) -> EofBehavior {
+        self.eof_behavior
+    }
+
+    /// Default is for the cell to be set to -1, but it can be set to zero or left unchanged
+    pub fn set_eof_behavior(&mut self, eof_behavior: EofBehavior) {
+        self.eof_behavior = eof_behavior;
+    }
+
+    /// Index of the current cell
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// Index of the next command to be executed
+    pub fn program_pointer(&self) -> usize {
+        self.program_pointer
+    }
+
+    /// Number of cells in use
+    pub fn data_length(&self) -> usize {
+        self.length
+    }
+
+    /// The cells in use
+    pub fn cells(&self) -> Cow<'_, [T]> {
+        self.tape.range(0, self.length)
+    }
+
+    /// The value of the current cell
+    pub fn get_cell(&self) -> T {
+        self.tape.get(self.data_pointer)
+    }
+
+    /// Set the value of the current cell
+    pub fn set_cell(&mut self, value: T) {
+        self.tape.set(self.data_pointer, value);
+    }
+
+    /// The most cells a tape that can grow is allowed to have, None if there is no limit
+    pub fn max_tape(&self) -> Option<usize> {
+        match self.alloc_strategy {
+            AllocStrategy::TapeCanGrowWithLimit(limit) => Some(limit),
+            _ => None,
+        }
+    }
+
+    /// Allocate cells up to the given length, or a TapeAllocationFailed error if there isn't
+    /// enough memory for them
+    pub(crate) fn grow_to(&mut self, length: usize) -> Result<(), MachineError> {
+        self.tape
+            .resize(length)
+            .map_err(|_| MachineError::TapeAllocationFailed {
+                requested_cells: length,
+            })
+    }
+
+    /// Moves the data pointer forward
+    pub fn move_data_pointer_forward(&mut self) -> Result<(), MachineError> {
+        if self.data_pointer + 1 == self.length {
+            // The data pointer is at the end of the tape, we can either abort the BF program
+            // or extend the tape.
+            match self.alloc_strategy {
+                AllocStrategy::TapeIsFixed => return Err(MachineError::DataPtrMovedAfterEnd),
+                AllocStrategy::TapeWraps => {
+                    // Going off the end of the tape comes back round to the first cell
+                    self.data_pointer = 0;
+                    return Ok(());
+                }
+                AllocStrategy::TapeCanGrow
+                | AllocStrategy::TapeCanGrowBothWays
+                | AllocStrategy::TapeCanGrowWithLimit(_) => {
+                    // Gone past end of tape, but tape can be extended. Cells are added a chunk
+                    // at a time so that scanning along the tape doesn't reallocate every move
+                    let limit = self.max_tape().unwrap_or(usize::MAX);
+                    if self.length >= limit {
+                        return Err(MachineError::TapeLimitExceeded { limit });
+                    }
+                    if self.length == self.tape.allocated() {
+                        let size = self.length.saturating_add(TAPE_GROWTH_CHUNK).min(limit);
+                        self.grow_to(size)?;
+                        self.growth_events += 1;
+                    }
+                    self.length += 1;
+                }
+            }
+        }
+        self.data_pointer += 1;
+        self.high_water = self.high_water.max(self.data_pointer);
+        Ok(())
+    }
+
+    /// Moves the data pointer backward. A tape that grows both ways has cells added on the
+    /// left, which moves every cell along so the origin moves by the number added.
+    pub fn move_data_pointer_back(&mut self) -> Result<(), MachineError> {
+        if self.data_pointer == 0 {
+            // Going off the start of the tape comes back round to the last cell
+            if self.alloc_strategy == AllocStrategy::TapeWraps {
+                self.data_pointer = self.length - 1;
+                return Ok(());
+            }
+            if self.alloc_strategy != AllocStrategy::TapeCanGrowBothWays {
+                return Err(MachineError::DataPtrMovedBeforeStart);
+            }
+            // Add a chunk of cells on the left so that the cells only have to be moved along
+            // once per chunk rather than on every move
+            let count = TAPE_GROWTH_CHUNK;
+            self.tape
+                .prepend(count)
+                .map_err(|_| MachineError::TapeAllocationFailed {
+                    requested_cells: self.length + count,
+                })?;
+            self.length += count;
+            self.origin += count;
+            self.data_pointer += count;
+            self.high_water += count;
+            self.growth_events += 1;
+        }
+        self.data_pointer -= 1;
+        Ok(())
+    }
+
+    /// Increment the value of the current cell
+    pub fn increment_data_value(&mut self) -> Result<(), MachineError> {
+        let mut cell = self.get_cell();
+        let value = match self.overflow_behavior {
+            OverflowBehavior::Wrap => cell.inc(),
+            OverflowBehavior::Saturate => cell.saturating_inc(),
+            OverflowBehavior::Error => cell.checked_inc().ok_or(MachineError::CellOverflow)?,
+        };
+        self.set_cell(value);
+        Ok(())
+    }
+
+    /// Decrement the value of the current cell
+    pub fn decrement_data_value(&mut self) -> Result<(), MachineError> {
+        let mut cell = self.get_cell();
+        let value = match self.overflow_behavior {
+            OverflowBehavior::Wrap => cell.dec(),
+            OverflowBehavior::Saturate => cell.saturating_dec(),
+            OverflowBehavior::Error => cell.checked_dec().ok_or(MachineError::CellOverflow)?,
+        };
+        self.set_cell(value);
+        Ok(())
+    }
+
+    /// Add a delta, which may be negative, to the value of the current cell in one go. What
+    /// happens at the limits of the cell is the same as for incrementing or decrementing it.
+    pub fn add_to_data_value(&mut self, delta: i16) -> Result<(), MachineError> {
+        let cell = self.get_cell();
+        let value = match self.overflow_behavior {
+            OverflowBehavior::Wrap => cell.wrapping_add_n(delta),
+            OverflowBehavior::Saturate => cell.saturating_add_n(delta),
+            OverflowBehavior::Error => cell
+                .checked_add_n(delta)
+                .ok_or(MachineError::CellOverflow)?,
+        };
+        self.set_cell(value);
+        Ok(())
+    }
+
+    /// Store a value that was input in the current cell. None is the end of the input, where
+    /// what is stored depends on the EOF behaviour.
+    pub fn store_input(&mut self, value: Option<T>) {
+        match value {
+            Some(value) => self.set_cell(value),
+            // End of file. The default of -1 is how rot13.bf program knows when to terminate
+            None => match self.eof_behavior {
+                EofBehavior::Zero => self.set_cell(T::from_u8(0)),
+                EofBehavior::NegOne => self.set_cell(T::from_u8(u8::MAX)),
+                EofBehavior::Unchanged => {}
+            },
+        }
+    }
+
+    /// Output the low byte of the current cell
+    pub fn output_value<I: BfIo>(&mut self, io: &mut I) -> Result<(), MachineError> {
+        io.write_byte(self.get_cell().to_u8())
+            .map_err(|error| MachineError::IOError { error })
+    }
+
+    /// Input a byte into the current cell
+    pub fn input_value<I: BfIo>(&mut self, io: &mut I) -> Result<(), MachineError> {
+        let byte = io
+            .read_byte()
+            .map_err(|error| MachineError::IOError { error })?;
+        self.store_input(byte.map(T::from_u8));
+        Ok(())
+    }
+
+    /// Jump to the matching bracket if the current cell is zero, otherwise the loop is entered
+    pub fn jump_forward(&mut self, jump: Option<usize>) -> Result<(), MachineError> {
+        if self.get_cell().is_zero() {
+            self.program_pointer = jump.ok_or(MachineError::BracketNotFound)?;
+        } else {
+            self.depth += 1;
+        }
+        Ok(())
+    }
+
+    /// Jump back to the matching bracket if the current cell isn't zero, otherwise the loop
+    /// is left
+    pub fn jump_backward(&mut self, jump: Option<usize>) -> Result<(), MachineError> {
+        if !self.get_cell().is_zero() {
+            self.program_pointer = jump.ok_or(MachineError::BracketNotFound)?;
+        } else {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    /// Execute the command at the program pointer. For a bracket, jump is the index of its
+    /// matching bracket, which is only needed if the jump is made. The return value is the
+    /// updated program pointer, which is left at the command if it fails.
+    pub fn execute<I: BfIo>(
+        &mut self,
+        command: Command,
+        jump: Option<usize>,
+        io: &mut I,
+    ) -> Result<usize, MachineError> {
+        match command {
+            Command::OutputValue => self.output_value(io)?,
+            Command::InputValue => self.input_value(io)?,
+            _ => return self.execute_command(command, jump),
+        }
+        self.program_pointer += 1;
+        Ok(self.program_pointer)
+    }
+
+    /// Execute a command that doesn't need input or output, see execute. Input and output are
+    /// done by the caller so that they can be synchronous or asynchronous, or formatted.
+    pub fn execute_command(
+        &mut self,
+        command: Command,
+        jump: Option<usize>,
+    ) -> Result<usize, MachineError> {
+        match command {
+            Command::Comment => {} // Do nothing
+            Command::IncDataPointer => self.move_data_pointer_forward()?,
+            Command::DecDataPointer => self.move_data_pointer_back()?,
+            Command::IncValue => self.increment_data_value()?,
+            Command::DecValue => self.decrement_data_value()?,
+            Command::OutputValue | Command::InputValue => {
+                unreachable!("Input and output are executed by the caller")
+            }
+            Command::JumpForward => self.jump_forward(jump)?,
+            Command::JumpBackward => self.jump_backward(jump)?,
+        }
+        self.program_pointer += 1;
+        Ok(self.program_pointer)
+    }
+
+    /// Run a program from the program pointer until it finishes, or there is an error. jumps
+    /// has the index of the matching bracket of each bracket in the program, as in
+    /// bft_types::BfProgram::jump_table. The output is flushed at the end.
+    pub fn run<I: BfIo>(
+        &mut self,
+        program: &[Command],
+        jumps: &[usize],
+        io: &mut I,
+    ) -> Result<(), MachineError> {
+        while let Some(&command) = program.get(self.program_pointer) {
+            self.execute(command, jumps.get(self.program_pointer).copied(), io)?;
+        }
+        io.flush().map_err(|error| MachineError::IOError { error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VecIo;
+    use alloc::string::ToString;
+
+    /// The commands of a program's source and the index of each bracket's match. The brackets
+    /// must match.
+    fn program(source: &str) -> (Vec<Command>, Vec<usize>) {
+        let commands: Vec<Command> = source
+            .chars()
+            .map(|command| match command {
+                '>' => Command::IncDataPointer,
+                '<' => Command::DecDataPointer,
+                '+' => Command::IncValue,
+                '-' => Command::DecValue,
+                '.' => Command::OutputValue,
+                ',' => Command::InputValue,
+                '[' => Command::JumpForward,
+                ']' => Command::JumpBackward,
+                _ => Command::Comment,
+            })
+            .collect();
+        let mut jumps: Vec<usize> = (0..commands.len()).collect();
+        let mut open = Vec::new();
+        for (index, command) in commands.iter().enumerate() {
+            match command {
+                Command::JumpForward => open.push(index),
+                Command::JumpBackward => {
+                    let start = open.pop().unwrap();
+                    jumps[start] = index;
+                    jumps[index] = start;
+                }
+                _ => {}
+            }
+        }
+        (commands, jumps)
+    }
+
+    /// Run a program on a fixed tape of 8 bit cells, returning its output
+    fn run(source: &str, cells: usize, input: &[u8]) -> Result<Vec<u8>, MachineError> {
+        let mut machine: BfMachine<u8> = BfMachine::new(cells)?;
+        machine.set_eof_behavior(EofBehavior::Zero);
+        let (commands, jumps) = program(source);
+        let mut io = VecIo::new(input.to_vec());
+        machine.run(&commands, &jumps, &mut io)?;
+        Ok(io.into_output())
+    }
+
+    /// Test that the commands do what they should
+    #[test]
+    fn commands() {
+        assert_eq!(run("+++.", 1, b"").unwrap(), [3]);
+        assert_eq!(run("-.", 1, b"").unwrap(), [255]);
+        assert_eq!(run(">+.<.", 2, b"").unwrap(), [1, 0]);
+        assert_eq!(run(",[.,] a cat", 1, b"cat").unwrap(), b"cat");
+        assert_eq!(run("++[>+++<-]>.", 2, b"").unwrap(), [6]);
+        // Skipped when the cell is zero, with comments in between
+        assert_eq!(run("[ -. ]+.", 1, b"").unwrap(), [1]);
+        let hello = include_str!("../../hello-world.bf");
+        assert_eq!(run(hello, 100, b"").unwrap(), b"Hello World!\n");
+    }
+
+    /// Test the options of the tape, which are the same for cells of any size
+    #[test]
+    fn options() {
+        let (commands, jumps) = program(",.");
+        let mut machine: BfMachine<u16> = BfMachine::new(1).unwrap();
+        let mut io = VecIo::new(Vec::new());
+        machine.execute(commands[0], None, &mut io).unwrap();
+        assert_eq!(machine.get_cell(), 255);
+        machine.set_cell(300);
+        machine.run(&commands, &jumps, &mut io).unwrap();
+        assert_eq!(io.output(), &[44]);
+        assert_eq!(machine.program_pointer(), 2);
+
+        let mut machine: BfMachine<u16> = BfMachine::new(1).unwrap();
+        machine.set_eof_behavior(EofBehavior::Unchanged);
+        machine.set_cell(7);
+        machine.input_value(&mut VecIo::default()).unwrap();
+        assert_eq!(*machine.cells(), [7]);
+
+        let (commands, jumps) = program(">>>+");
+        let mut machine: BfMachine<u8> = BfMachine::new(1).unwrap();
+        machine.set_alloc_strategy(AllocStrategy::TapeCanGrow);
+        machine
+            .run(&commands, &jumps, &mut VecIo::default())
+            .unwrap();
+        assert_eq!(*machine.cells(), [0, 0, 0, 1]);
+        assert_eq!(machine.data_pointer(), 3);
+
+        let mut machine: BfMachine<u8> = BfMachine::new_sparse(3).unwrap();
+        machine.set_alloc_strategy(AllocStrategy::TapeWraps);
+        machine
+            .run(&commands, &jumps, &mut VecIo::default())
+            .unwrap();
+        assert_eq!(*machine.cells(), [1, 0, 0]);
+
+        let (commands, jumps) = program("<+");
+        let mut machine: BfMachine<u8> = BfMachine::new(2).unwrap();
+        machine.set_alloc_strategy(AllocStrategy::TapeCanGrowBothWays);
+        machine
+            .run(&commands, &jumps, &mut VecIo::default())
+            .unwrap();
+        assert_eq!(machine.origin, TAPE_GROWTH_CHUNK);
+        assert_eq!(machine.data_length(), TAPE_GROWTH_CHUNK + 2);
+        assert_eq!(machine.cells()[TAPE_GROWTH_CHUNK - 1], 1);
+
+        let mut machine: BfMachine<i8> = BfMachine::new(1).unwrap();
+        machine.set_overflow_behavior(OverflowBehavior::Saturate);
+        machine.add_to_data_value(-300).unwrap();
+        machine.decrement_data_value().unwrap();
+        assert_eq!(machine.get_cell(), i8::MIN);
+    }
+
+    /// Test that each error is returned with the program pointer left where it happened
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            run("<", 1, b""),
+            Err(MachineError::DataPtrMovedBeforeStart)
+        ));
+        assert!(matches!(
+            run("+>>", 2, b""),
+            Err(MachineError::DataPtrMovedAfterEnd)
+        ));
+        assert!(matches!(
+            BfMachine::<u8>::new(0),
+            Err(MachineError::InvalidTapeSize { requested_cells: 0 })
+        ));
+
+        let (commands, jumps) = program("+>>>");
+        let mut machine: BfMachine<u8> = BfMachine::new(1).unwrap();
+        machine.set_alloc_strategy(AllocStrategy::TapeCanGrowWithLimit(3));
+        let error = machine
+            .run(&commands, &jumps, &mut VecIo::default())
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            MachineError::TapeLimitExceeded { limit: 3 }
+        ));
+        assert_eq!(machine.program_pointer(), 3);
+        assert_eq!(
+            error.to_string(),
+            "[E_TAPE_LIMIT] Tape limit of 3 cells exceeded"
+        );
+
+        let mut machine: BfMachine<u8> = BfMachine::new(1).unwrap();
+        machine.set_overflow_behavior(OverflowBehavior::Error);
+        let (commands, jumps) = program("+--");
+        let error = machine
+            .run(&commands, &jumps, &mut VecIo::default())
+            .unwrap_err();
+        assert!(matches!(error, MachineError::CellOverflow));
+        assert_eq!((machine.program_pointer(), machine.get_cell()), (2, 0));
+
+        // A jump that has to be made needs the matching bracket
+        let mut machine: BfMachine<u8> = BfMachine::new(1).unwrap();
+        assert!(machine.execute_command(Command::JumpBackward, None).is_ok());
+        assert!(matches!(
+            machine.execute_command(Command::JumpForward, None),
+            Err(MachineError::BracketNotFound)
+        ));
+
+        // The errors of the BfIo are passed on
+        struct Broken;
+        impl BfIo for Broken {
+            fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+                Ok(None)
+            }
+
+            fn write_byte(&mut self, _byte: u8) -> Result<(), IoError> {
+                #[cfg(feature = "std")]
+                let error = IoError::other("no output");
+                #[cfg(not(feature = "std"))]
+                let error = IoError::new("no output");
+                Err(error)
+            }
+        }
+        let (commands, jumps) = program(", .");
+        let mut machine: BfMachine<u8> = BfMachine::new(1).unwrap();
+        let error = machine.run(&commands, &jumps, &mut Broken).unwrap_err();
+        assert!(matches!(error, MachineError::IOError { .. }));
+        assert_eq!(machine.program_pointer(), 2);
+        assert_eq!(error.to_string(), "[E_IO] I/O error no output");
+    }
+}
@@ -0,0 +1,8818 @@
+//! The tape that runs a BfProgram, with everything around it that needs the standard
+//! library: the std Read and Write adapters, files, threads, time limits and Ctrl-C.
+
+use crate::machine::{self, BfMachine, MachineError, TapeStorage};
+#[cfg(feature = "async")]
+use crate::VecIo;
+use crate::{BfIo, CellKind};
+use bft_types::BfProgram;
+use cli::OutputFormat;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Number of cells in a tape when no size is given. A size of zero passed to BfTape::new or
+/// BfTapeBuilder::tape_size means this size.
+pub const DEFAULT_TAPE_SIZE: usize = 30000;
+
+/// Number of cells either side of the data pointer shown when a tape is displayed
+const DISPLAY_RADIUS: usize = 5;
+
+/// Number of cells on each line of a tape dump
+const DUMP_CELLS_PER_LINE: usize = 16;
+
+/// Number of characters of a long source line shown when an error is reported
+const SNIPPET_WIDTH: usize = 60;
+
+/// How often a spawned program that is waiting for input checks whether it has been aborted
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Number of steps between checks of the time limit, so that the clock isn't read every step
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Errors that can be returned by functions that handle running the BF program.
+///
+#[derive(Error, Debug)]
+pub enum BfError {
+    /// Error to indicate when the data pointer was moved before the start of the tape
+    #[error(
+        "[E_TAPE_UNDERRUN] Data pointer moved before start of tape at {} {}{}",
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    DataPtrMovedBeforeStart {
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate when the data pointer was moved after the end of the tape
+    #[error(
+        "[E_TAPE_OVERRUN] Data pointer moved after end of tape at {} {}{}",
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    DataPtrMovedAfterEnd {
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate when the program pointer was moved after the end of the program
+    #[error(
+        "[E_PROGRAM_OVERRUN] Program pointer moved after end of program at {} {}{}",
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    ProgramPtrMovedAfterEnd {
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that the program ran for more steps than the configured limit
+    #[error(
+        "[E_STEP_LIMIT] Step limit of {} exceeded at {} {}{}",
+        steps,
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    StepLimitExceeded {
+        steps: u64,
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that the program has run for longer than its time limit
+    #[error(
+        "[E_TIME_LIMIT] Time limit of {:?} exceeded at {} {}{}",
+        limit,
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    TimeLimitExceeded {
+        limit: Duration,
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that the program was interrupted by the user (e.g. Ctrl-C)
+    #[error(
+        "[E_INTERRUPTED] Interrupted at {} {}{}",
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    Interrupted {
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that decimal input wasn't a number that fits in a cell
+    #[error(
+        "[E_INVALID_INPUT] Invalid input {:?} at {} {}{}",
+        input,
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    InvalidInput {
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        input: String,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that a cell was incremented or decremented beyond its limits
+    #[error(
+        "[E_CELL_OVERFLOW] Cell overflow of value {} at {} {}{}",
+        value,
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    CellOverflow {
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        value: String,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that a tape that can grow would have grown beyond its maximum length
+    #[error(
+        "[E_TAPE_LIMIT] Tape limit of {} cells exceeded at {} {}{}",
+        limit,
+        program_pointer,
+        instruction,
+        snippet
+    )]
+    TapeLimitExceeded {
+        limit: usize,
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that an initial image for the tape doesn't fit in the tape
+    #[error(
+        "[E_IMAGE_TOO_LARGE] Initial tape image of {} cells is larger than the tape of {} cells",
+        size,
+        length
+    )]
+    ImageTooLarge { size: usize, length: usize },
+    /// Error to indicate that a tape can't have the number of cells asked for, such as none
+    #[error("[E_TAPE_SIZE] A tape of {} cells can't be created", requested_cells)]
+    InvalidTapeSize { requested_cells: usize },
+    /// Error to indicate that there isn't enough memory for the cells of a tape, when it is
+    /// created or when it grows
+    #[error(
+        "[E_TAPE_ALLOCATION] Not enough memory for a tape of {} cells",
+        requested_cells
+    )]
+    TapeAllocationFailed { requested_cells: usize },
+    /// Error to indicate that a cell outside of the tape was referred to
+    #[error(
+        "[E_CELL_OUT_OF_RANGE] Cell {} is outside of the tape of {} cells",
+        cell,
+        length
+    )]
+    CellOutOfRange { cell: usize, length: usize },
+    /// Error to indicate that a bracket has no matching bracket to jump to. The matching
+    /// brackets are found when the program is validated, and the brackets are checked before
+    /// the program is run, so this only happens if a program that wasn't validated is run.
+    #[error(
+        "[E_BRACKET] No matching bracket for {} at {}, the program must be validated before it is run{}",
+        instruction,
+        program_pointer,
+        snippet
+    )]
+    BracketNotFound {
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error the occurs when reading/writing using the input/output functionality of the tape
+    #[error(
+        "[E_IO] I/O error {} at {} {} {}{}",
+        error_msg,
+        filepath.display(),
+        instruction,
+        program_pointer,
+        snippet
+    )]
+    IOError {
+        #[source]
+        error_msg: std::io::Error,
+        filepath: std::path::PathBuf,
+        instruction: bft_types::BfInstruction,
+        program_pointer: usize,
+        snippet: SourceSnippet,
+    },
+    /// Error to indicate that a file of input for the program couldn't be opened
+    #[error("[E_INPUT_FILE] Can't open input file {}: {}", path.display(), error_msg)]
+    InputFileError {
+        #[source]
+        error_msg: std::io::Error,
+        path: std::path::PathBuf,
+    },
+    /// Error to indicate that a file for the program's output couldn't be created or written
+    #[error("[E_OUTPUT_FILE] Can't write output file {}: {}", path.display(), error_msg)]
+    OutputFileError {
+        #[source]
+        error_msg: std::io::Error,
+        path: std::path::PathBuf,
+    },
+    /// Error to indicate that the program couldn't be parsed or failed validation
+    #[error("[E_INVALID_PROGRAM] Invalid program {}", error_msg)]
+    InvalidProgram { error_msg: anyhow::Error },
+    /// Error to indicate that a loop seems to be going round forever, see
+    /// BfTape::set_loop_guard. This is a guess rather than a certainty.
+    #[error(
+        "[E_INFINITE_LOOP] Probable infinite loop at {} after {} unchanged iterations{}",
+        location,
+        iterations,
+        snippet
+    )]
+    ProbableInfiniteLoop {
+        location: bft_types::BfLocation,
+        iterations: u64,
+        snippet: SourceSnippet,
+    },
+}
+
+/// The line of source code that an error happened at, with a marker under the column, which
+/// is included in the error's message. It is empty if the program's source isn't available.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"tiny.bf", "+\n+<").unwrap();
+///     let snippet = bft_interp::SourceSnippet::new(&program, bft_types::BfLocation::new(2, 2));
+///     assert_eq!(snippet.to_string(), "\n  |\n2 | +<\n  |  ^");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceSnippet(Option<String>);
+
+impl SourceSnippet {
+    /// Capture the line of the program's source at the location
+    pub fn new(program: &BfProgram, location: bft_types::BfLocation) -> Self {
+        let Some(line) = program.source_line(location.line()) else {
+            return Self(None);
+        };
+        let chars: Vec<char> = line.chars().collect();
+        let column = location.offset().saturating_sub(1).min(chars.len());
+
+        // Long lines are cut down to the part around the column
+        let start = column
+            .saturating_sub(SNIPPET_WIDTH / 2)
+            .min(chars.len().saturating_sub(SNIPPET_WIDTH));
+        let end = (start + SNIPPET_WIDTH).min(chars.len());
+        let mut text = if start > 0 {
+            "...".to_string()
+        } else {
+            String::new()
+        };
+        // Tabs are kept in the marker line so that the marker lines up with the column
+        let mut marker: String = text.chars().map(|_| ' ').collect();
+        text.extend(&chars[start..end]);
+        marker.extend(
+            chars[start..column]
+                .iter()
+                .map(|&ch| if ch == '\t' { '\t' } else { ' ' }),
+        );
+        if end < chars.len() {
+            text += "...";
+        }
+
+        let number = location.line().to_string();
+        let gutter = " ".repeat(number.len());
+        Self(Some(format!(
+            "{} |\n{} | {}\n{} | {}^",
+            gutter, number, text, gutter, marker
+        )))
+    }
+
+    /// Whether there is no source to show
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+impl std::fmt::Display for SourceSnippet {
+    /// The snippet starts on a new line so that it follows on from the error's message
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(text) => write!(f, "\n{}", text),
+            None => Ok(()),
+        }
+    }
+}
+
+impl BfError {
+    /// A short code for the error that scripts can check rather than the message. The codes
+    /// are stable and won't change between releases, although new ones may be added. The code
+    /// is also shown in brackets at the start of the error's message.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let error = bft_interp::BfError::CellOutOfRange { cell: 1, length: 1 };
+    ///     assert_eq!(error.code(), "E_CELL_OUT_OF_RANGE");
+    ///     assert!(error.to_string().starts_with("[E_CELL_OUT_OF_RANGE] "));
+    /// ```
+    pub fn code(&self) -> &'static str {
+        match self {
+            BfError::DataPtrMovedBeforeStart { .. } => "E_TAPE_UNDERRUN",
+            BfError::DataPtrMovedAfterEnd { .. } => "E_TAPE_OVERRUN",
+            BfError::ProgramPtrMovedAfterEnd { .. } => "E_PROGRAM_OVERRUN",
+            BfError::StepLimitExceeded { .. } => "E_STEP_LIMIT",
+            BfError::TimeLimitExceeded { .. } => "E_TIME_LIMIT",
+            BfError::Interrupted { .. } => "E_INTERRUPTED",
+            BfError::InvalidInput { .. } => "E_INVALID_INPUT",
+            BfError::CellOverflow { .. } => "E_CELL_OVERFLOW",
+            BfError::TapeLimitExceeded { .. } => "E_TAPE_LIMIT",
+            BfError::ImageTooLarge { .. } => "E_IMAGE_TOO_LARGE",
+            BfError::InvalidTapeSize { .. } => "E_TAPE_SIZE",
+            BfError::TapeAllocationFailed { .. } => "E_TAPE_ALLOCATION",
+            BfError::CellOutOfRange { .. } => "E_CELL_OUT_OF_RANGE",
+            BfError::BracketNotFound { .. } => "E_BRACKET",
+            BfError::IOError { .. } => "E_IO",
+            BfError::InputFileError { .. } => "E_INPUT_FILE",
+            BfError::OutputFileError { .. } => "E_OUTPUT_FILE",
+            BfError::InvalidProgram { .. } => "E_INVALID_PROGRAM",
+            BfError::ProbableInfiniteLoop { .. } => "E_INFINITE_LOOP",
+        }
+    }
+
+    /// The kind of the underlying i/o error, None if the error wasn't caused by input or output
+    ///
+    /// Example usage:
+    /// ```
+    ///     let error = bft_interp::BfError::CellOutOfRange { cell: 1, length: 1 };
+    ///     assert_eq!(error.io_kind(), None);
+    /// ```
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        match self {
+            BfError::IOError { error_msg, .. }
+            | BfError::InputFileError { error_msg, .. }
+            | BfError::OutputFileError { error_msg, .. } => Some(error_msg.kind()),
+            _ => None,
+        }
+    }
+
+    /// Where in the program's source the error happened, None if it isn't tied to a location
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+\n+<").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     let error = tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap_err();
+    ///     assert_eq!(error.location(), Some(bft_types::BfLocation::new(2, 2)));
+    /// ```
+    pub fn location(&self) -> Option<bft_types::BfLocation> {
+        match self {
+            BfError::DataPtrMovedBeforeStart { instruction, .. }
+            | BfError::DataPtrMovedAfterEnd { instruction, .. }
+            | BfError::ProgramPtrMovedAfterEnd { instruction, .. }
+            | BfError::StepLimitExceeded { instruction, .. }
+            | BfError::TimeLimitExceeded { instruction, .. }
+            | BfError::Interrupted { instruction, .. }
+            | BfError::InvalidInput { instruction, .. }
+            | BfError::CellOverflow { instruction, .. }
+            | BfError::TapeLimitExceeded { instruction, .. }
+            | BfError::BracketNotFound { instruction, .. }
+            | BfError::IOError { instruction, .. } => Some(instruction.location()),
+            BfError::ProbableInfiniteLoop { location, .. } => Some(*location),
+            _ => None,
+        }
+    }
+}
+
+/// Reasons for the interpreter to stop running a program that are not errors.
+///
+/// * Halted
+/// * Watchpoint
+/// * Paused
+/// * Aborted
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason<T> {
+    /// The program ran to completion
+    Halted,
+    /// The value in a watched cell was changed by the instruction at the program pointer
+    Watchpoint {
+        cell: usize,
+        old: T,
+        new: T,
+        program_pointer: usize,
+    },
+    /// The hook asked for the program to pause before the instruction at the program pointer
+    Paused { program_pointer: usize },
+    /// The hook asked for the program to be aborted before the instruction at the program pointer
+    Aborted { program_pointer: usize },
+}
+
+/// Statistics about a run of a program.
+///
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Number of instructions executed
+    pub steps: u64,
+    /// The data pointer when the program stopped
+    pub data_pointer: usize,
+    /// Length of the tape when the program stopped
+    pub data_length: usize,
+    /// Memory used by the tape
+    pub memory: MemoryStats,
+    /// Time spent executing each kind of command, if the timing profiler is enabled
+    pub timings: Option<CommandTimings>,
+    /// Highest cell that was read or written, if the heatmap is enabled and any cell was.
+    /// Unlike the high water mark, cells that the data pointer only passed over don't count.
+    pub highest_cell_accessed: Option<usize>,
+}
+
+/// Number of times a cell was read and written, see BfTape::enable_heatmap.
+///
+/// `+` and `-` both read and write the cell, `.`, `[` and `]` read it and `,` writes it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CellAccesses {
+    /// Number of times the cell was read
+    pub reads: u64,
+    /// Number of times the cell was written
+    pub writes: u64,
+}
+
+/// How much memory a tape has used.
+///
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    /// Number of cells held in memory. A sparse tape only holds the cells that aren't zero.
+    pub allocated_cells: usize,
+    /// Number of bytes used by the cells held in memory
+    pub allocated_bytes: usize,
+    /// Highest cell the data pointer has reached
+    pub high_water: usize,
+    /// Number of times a tape that can grow has been made bigger
+    pub growth_events: u64,
+}
+
+/// Commands in the order their timings and profile counts are kept
+const TIMED_COMMANDS: [bft_types::BfCommand; 9] = [
+    bft_types::BfCommand::Comment,
+    bft_types::BfCommand::IncDataPointer,
+    bft_types::BfCommand::DecDataPointer,
+    bft_types::BfCommand::IncValue,
+    bft_types::BfCommand::DecValue,
+    bft_types::BfCommand::OutputValue,
+    bft_types::BfCommand::InputValue,
+    bft_types::BfCommand::JumpForward,
+    bft_types::BfCommand::JumpBackward,
+];
+
+/// Wall clock time spent executing each kind of command, collected when the timing profiler is
+/// enabled. The time is bucketed by command rather than by instruction so that keeping it
+/// costs the same however big the program is.
+///
+/// Timing an instruction takes about as long as executing a simple one, so the times of the
+/// cheap commands are inflated and a timed run is slower than an untimed one. The split
+/// between commands is what is useful, such as whether output or loops take the most time.
+///
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandTimings {
+    /// Time spent in each command, in the order of TIMED_COMMANDS
+    durations: [Duration; TIMED_COMMANDS.len()],
+    /// Number of times each command was executed, in the order of TIMED_COMMANDS
+    counts: [u64; TIMED_COMMANDS.len()],
+}
+
+impl CommandTimings {
+    /// Position of a command in TIMED_COMMANDS
+    fn bucket(command: bft_types::BfCommand) -> usize {
+        match command {
+            bft_types::BfCommand::Comment => 0,
+            bft_types::BfCommand::IncDataPointer => 1,
+            bft_types::BfCommand::DecDataPointer => 2,
+            bft_types::BfCommand::IncValue => 3,
+            bft_types::BfCommand::DecValue => 4,
+            bft_types::BfCommand::OutputValue => 5,
+            bft_types::BfCommand::InputValue => 6,
+            bft_types::BfCommand::JumpForward => 7,
+            bft_types::BfCommand::JumpBackward => 8,
+        }
+    }
+
+    /// Add the time taken to execute a command
+    fn add(&mut self, command: bft_types::BfCommand, elapsed: Duration) {
+        let bucket = Self::bucket(command);
+        self.durations[bucket] += elapsed;
+        self.counts[bucket] += 1;
+    }
+
+    /// Time spent executing a command
+    pub fn duration(&self, command: bft_types::BfCommand) -> Duration {
+        self.durations[Self::bucket(command)]
+    }
+
+    /// Number of times a command was executed while it was being timed
+    pub fn count(&self, command: bft_types::BfCommand) -> u64 {
+        self.counts[Self::bucket(command)]
+    }
+
+    /// Time spent executing all the commands
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+
+    /// Report the time spent in each command that was executed, the most time first.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+++.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.enable_timing_profiler();
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     let report = tape.run_stats().timings.unwrap().report();
+    ///     assert!(report.contains("IncValue"));
+    ///     assert!(!report.contains("InputValue"));
+    /// ```
+    pub fn report(&self) -> String {
+        let total = self.total();
+        let mut buckets: Vec<usize> = (0..TIMED_COMMANDS.len())
+            .filter(|&bucket| self.counts[bucket] > 0)
+            .collect();
+        buckets.sort_by_key(|&bucket| std::cmp::Reverse(self.durations[bucket]));
+        let mut report = format!("Time by command, {:?} in total:\n", total);
+        for bucket in buckets {
+            let percent = if total.is_zero() {
+                0.0
+            } else {
+                self.durations[bucket].as_secs_f64() * 100.0 / total.as_secs_f64()
+            };
+            report += &format!(
+                "  {:?}: {:?} ({:.1}%), executed {} times\n",
+                TIMED_COMMANDS[bucket], self.durations[bucket], percent, self.counts[bucket]
+            );
+        }
+        report
+    }
+}
+
+/// Details of the instruction about to be executed that are passed to the hook.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HookEvent<T> {
+    /// The program pointer of the instruction
+    pub program_pointer: usize,
+    /// The instruction about to be executed
+    pub instruction: bft_types::BfInstruction,
+    /// The data pointer
+    pub data_pointer: usize,
+    /// The value of the cell at the data pointer before the instruction is executed
+    pub value: T,
+}
+
+/// What the interpreter should do after calling the hook.
+///
+/// * Continue
+/// * Pause
+/// * Abort
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HookAction {
+    /// Execute the instruction and carry on
+    Continue,
+    /// Stop before the instruction with StopReason::Paused. Running again executes the
+    /// instruction without calling the hook for it a second time.
+    Pause,
+    /// Stop before the instruction with StopReason::Aborted
+    Abort,
+}
+
+/// A record of an executed instruction written to the trace writer.
+///
+/// The trace consists of one JSON object per line, one line per executed instruction, with
+/// the fields named as below. The format is stable so that traces can be post-processed.
+///
+/// ```text
+/// {"step":1,"program_pointer":0,"command":"+","line":1,"column":1,"data_pointer":0,"value":1,"depth":0}
+/// ```
+///
+/// * step - The number of the step, the first instruction executed is step 1
+/// * program_pointer - The index of the instruction in the program
+/// * command - The BF command character
+/// * line - The line in the source file of the instruction
+/// * column - The offset in the line of the instruction
+/// * data_pointer - The data pointer after the instruction was executed
+/// * value - The value in the cell at the data pointer after the instruction was executed, all
+///   of it whatever the type of the cells
+/// * depth - The number of loops the program is inside after the instruction was executed
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub step: u64,
+    pub program_pointer: usize,
+    pub command: char,
+    pub line: usize,
+    pub column: usize,
+    pub data_pointer: usize,
+    pub value: i128,
+    pub depth: usize,
+}
+
+/// How much is reported by a stream of run events, see BfTape::set_event_sink.
+///
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventGranularity {
+    /// Every instruction that is executed as well as the input, output and control events
+    EveryInstruction,
+    /// Only the input, output and control events
+    IoAndControl,
+}
+
+/// An event in the running of a program, for front ends that want to follow a program as it
+/// runs rather than only see its output. See BfTape::set_event_sink.
+///
+/// Instructions, input and output are reported however the program is run an instruction at
+/// a time, the start and end of the program are only reported by interpreter.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RunEvent {
+    /// The program is about to execute its first instruction
+    ProgramStarted,
+    /// An instruction was executed, in the same form as the trace. Only reported for
+    /// EventGranularity::EveryInstruction.
+    InstructionExecuted(TraceRecord),
+    /// A byte was output by the program
+    OutputByte(u8),
+    /// The program is about to read input
+    InputRequested,
+    /// The program ran to completion
+    Halted(Box<RunStats>),
+    /// The program stopped with an error, which has the error's code
+    Errored(String),
+}
+
+/// Callback that is given the events of a running program
+type EventSink<'a> = Box<dyn FnMut(RunEvent) + Send + 'a>;
+
+/// Profile of a program's execution, built from the counts collected when the profiler is enabled.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// Name of the file the program came from, used for the locations in the report
+    pub filename: std::path::PathBuf,
+    /// Number of times each instruction was executed, indexed by program pointer
+    pub instruction_hits: Vec<u64>,
+    /// Number of times each kind of command was executed, in the order of TIMED_COMMANDS
+    pub command_counts: Vec<(bft_types::BfCommand, u64)>,
+    /// Number of iterations of each loop in the program, in the order the loops end in the source
+    pub loop_iterations: Vec<(bft_types::BfJumpLocation, u64)>,
+}
+
+impl Profile {
+    /// Build the profile from the instruction counts. The iterations of a loop are the number
+    /// of times the jump backward at the end of the loop was executed.
+    fn new(program: &BfProgram, instruction_hits: Vec<u64>) -> Self {
+        let mut command_counts: Vec<(bft_types::BfCommand, u64)> =
+            TIMED_COMMANDS.iter().map(|&command| (command, 0)).collect();
+        let mut loop_iterations = Vec::new();
+        for (i, inst) in program.instructions().iter().enumerate() {
+            command_counts[CommandTimings::bucket(inst.command())].1 += instruction_hits[i];
+            if inst.command() == bft_types::BfCommand::JumpBackward {
+                if let Some(forward) = program.location_map().get_by_right(&inst.location()) {
+                    loop_iterations.push((
+                        bft_types::BfJumpLocation::new(*forward, inst.location()),
+                        instruction_hits[i],
+                    ));
+                }
+            }
+        }
+        Self {
+            filename: program.filename().to_path_buf(),
+            instruction_hits,
+            command_counts,
+            loop_iterations,
+        }
+    }
+
+    /// Report the number of times each command that was executed was executed, followed by
+    /// the loops with the most iterations, at most top loops are included. The locations of
+    /// the loops are given as file:line:col.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let mut program = bft_types::BfProgram::new(&"loop.bf", "+++[-]").unwrap();
+    ///     program.validate().unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.enable_profiler();
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     let report = tape.profile().unwrap().report(10);
+    ///     assert!(report.contains("  IncValue: 3\n"));
+    ///     assert!(report.contains("[ at loop.bf:1:4 ] at loop.bf:1:6: 3 iterations"));
+    /// ```
+    pub fn report(&self, top: usize) -> String {
+        let filename = self.filename.to_string_lossy();
+        let mut report = String::from("Commands executed:\n");
+        for (command, count) in self.command_counts.iter().filter(|(_, count)| *count > 0) {
+            report += &format!("  {:?}: {}\n", command, count);
+        }
+        let mut loops = self.loop_iterations.clone();
+        loops.sort_by_key(|l| std::cmp::Reverse(l.1));
+        report += &format!("Top {} loops by iterations:\n", top.min(loops.len()));
+        for (jump, iterations) in loops.iter().take(top) {
+            report += &format!(
+                "  [ at {}:{} ] at {}:{}: {} iterations\n",
+                filename,
+                jump.forward(),
+                filename,
+                jump.backward(),
+                iterations
+            );
+        }
+        report
+    }
+}
+
+/// A copy of the state of a tape at a point in a program's execution, taken with
+/// BfTape::snapshot. Comparing two snapshots shows what a part of the program changed.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"diff.bf", ">+>++").unwrap();
+///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().tape_size(4).build(&program);
+///     let before = tape.snapshot();
+///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+///     let after = tape.snapshot();
+///     assert_eq!(before.report(&after), "Data pointer 0 -> 2 (+2)\nCell 1: 0 -> 1\nCell 2: 0 -> 2\n");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct BfState<T> {
+    /// The cells in use
+    pub cells: Vec<T>,
+    /// The data pointer
+    pub data_pointer: usize,
+    /// The program pointer
+    pub program_pointer: usize,
+    /// Number of instructions executed
+    pub steps: u64,
+}
+
+/// A cell with a different value in two snapshots
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CellChange<T> {
+    /// Index of the cell
+    pub index: usize,
+    /// Value of the cell in the earlier snapshot
+    pub before: T,
+    /// Value of the cell in the later snapshot
+    pub after: T,
+}
+
+impl<T: CellKind> BfState<T> {
+    /// The cells whose values are different in the other, later, snapshot, in order of their
+    /// index. A cell that is only in one of the snapshots, because the tape grew, is compared
+    /// with zero.
+    pub fn diff(&self, other: &BfState<T>) -> Vec<CellChange<T>> {
+        let length = self.cells.len().max(other.cells.len());
+        (0..length)
+            .filter_map(|index| {
+                let before = self.cells.get(index).copied().unwrap_or_default();
+                let after = other.cells.get(index).copied().unwrap_or_default();
+                (before != after).then_some(CellChange {
+                    index,
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+
+    /// How far the data pointer moved between this snapshot and the other, later, one
+    pub fn data_pointer_move(&self, other: &BfState<T>) -> isize {
+        other.data_pointer as isize - self.data_pointer as isize
+    }
+
+    /// Report the move of the data pointer and the cells that changed, one per line
+    pub fn report(&self, other: &BfState<T>) -> String {
+        let mut report = format!(
+            "Data pointer {} -> {} ({:+})\n",
+            self.data_pointer,
+            other.data_pointer,
+            self.data_pointer_move(other)
+        );
+        for change in self.diff(other) {
+            report += &format!(
+                "Cell {}: {:?} -> {:?}\n",
+                change.index, change.before, change.after
+            );
+        }
+        report
+    }
+}
+
+/// An instruction of a program lowered to what the machine executes, see BfTape::lower
+struct LoweredInstruction {
+    /// The instruction's command
+    command: machine::Command,
+    /// The index of the matching bracket, for a bracket
+    jump: Option<usize>,
+    /// The instruction, for the hook, debug, trace and errors
+    inst: bft_types::BfInstruction,
+}
+
+/// Callback that is given each instruction before it is executed
+type Hook<'a, T> = Box<dyn FnMut(&HookEvent<T>) -> HookAction + Send + 'a>;
+
+/// Install a handler for SIGINT (Ctrl-C) and return the flag that it sets. This is only on
+/// Unix and Windows, which are what ctrlc supports.
+///
+/// The flag can be given to a tape with set_interrupt_flag so that the interpreter stops at
+/// the next instruction with an Interrupted error rather than the process being killed.
+///
+/// Example usage:
+/// ```no_run
+///     let program = bft_types::BfProgram::new(&"forever.bf", "+[]").unwrap();
+///     let mut tape: bft_interp::BfTape<u8> =
+///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+///     tape.set_interrupt_flag(bft_interp::install_interrupt_flag().unwrap());
+/// ```
+#[cfg(any(unix, windows))]
+pub fn install_interrupt_flag() -> Result<Arc<AtomicBool>, ctrlc::Error> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let handler_flag = Arc::clone(&flag);
+    ctrlc::set_handler(move || handler_flag.store(true, Ordering::Relaxed))?;
+    Ok(flag)
+}
+
+/// Writer half of the buffered i/o used by `BfTape::interpreter_buffered`. The buffer is shared
+/// with the reader half so that it can be flushed before the program waits for input.
+struct SharedWriter<W: Write>(Rc<RefCell<BufWriter<W>>>);
+
+impl<W: Write> Write for SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// Reader half of the buffered i/o used by `BfTape::interpreter_buffered`. When no input is
+/// buffered the next read may block, so any buffered output (e.g. a prompt) is flushed first.
+struct PromptingReader<R: Read, W: Write> {
+    reader: BufReader<R>,
+    writer: Rc<RefCell<BufWriter<W>>>,
+}
+
+impl<R: Read, W: Write> Read for PromptingReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.reader.buffer().is_empty() {
+            self.writer.borrow_mut().flush()?;
+        }
+        self.reader.read(buf)
+    }
+}
+
+/// Adapter that lets any `Read` and `Write` pair, such as stdin and stdout, be used as a BfIo.
+///
+/// Example usage:
+/// ```
+///     use bft_interp::BfIo;
+///     let mut io = bft_interp::StdIo::new(std::io::Cursor::new(vec![1, 2]), Vec::new());
+///     assert_eq!(io.read_byte().unwrap(), Some(1));
+///     io.write_bytes(b"ok").unwrap();
+///     let (_, writer) = io.into_inner();
+///     assert_eq!(writer, b"ok");
+/// ```
+#[derive(Debug)]
+pub struct StdIo<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> StdIo<R, W> {
+    /// Wrap a reader and a writer
+    pub fn new(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+
+    /// Unwrap the reader and the writer
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<R: Read, W: Write> BfIo for StdIo<R, W> {
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        // Only one character at a time is read. read_exact retries interrupted reads and
+        // short reads, and only reports UnexpectedEof when the input has really ended
+        let mut data = [0; 1];
+        match self.reader.read_exact(&mut data) {
+            Ok(()) => Ok(Some(data[0])),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> std::io::Result<()> {
+        self.writer.write_all(&[byte])
+    }
+
+    // write_all retries partial and interrupted writes so nothing is dropped
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Progress of the input or output instruction at the program pointer when the future of
+/// `BfTape::interpreter_async` was dropped part way through it, so that resuming carries on
+/// with the instruction rather than starting it again.
+#[cfg(feature = "async")]
+#[derive(Debug, Default)]
+struct AsyncIoState {
+    /// The hook has been called and the instruction counted, but it hasn't finished
+    started: bool,
+    /// Bytes read for the input instruction that haven't been used yet
+    input: Vec<u8>,
+    /// The reader reached end of file while reading for the input instruction
+    eof: bool,
+    /// Output of the output instruction that hasn't been written yet
+    output: Option<Vec<u8>>,
+}
+
+/// BfIo over the input that has been read so far by `BfTape::interpreter_async`. A WouldBlock
+/// error is returned when more input is needed, so the input instruction can be tried again
+/// once another byte has been read.
+#[cfg(feature = "async")]
+struct PendingInput<'b> {
+    input: &'b [u8],
+    eof: bool,
+    position: usize,
+}
+
+#[cfg(feature = "async")]
+impl BfIo for PendingInput<'_> {
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        match self.input.get(self.position) {
+            Some(byte) => {
+                self.position += 1;
+                Ok(Some(*byte))
+            }
+            None if self.eof => Ok(None),
+            None => Err(std::io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn write_byte(&mut self, _byte: u8) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// How to undo a step. A step changes at most the cell under the data pointer, so the old
+/// value of that cell and the pointers are enough, plus any input read by the step.
+#[derive(Clone, Debug)]
+struct UndoRecord<T> {
+    program_pointer: usize,
+    depth: usize,
+    data_pointer: usize,
+    origin: usize,
+    value: T,
+    input: Vec<u8>,
+}
+
+/// The program a tape runs, which is either borrowed or shared.
+///
+/// A tape that borrows its program can't outlive it, so a tape that needs to be stored
+/// alongside its program or sent to another thread should share the program with an Arc.
+/// Either can be passed to the functions that create a tape, e.g. `&program` or
+/// `Arc::new(program)`.
+#[derive(Clone, Debug)]
+pub enum ProgramRef<'a> {
+    /// A program that is borrowed for the lifetime of the tape
+    Borrowed(&'a BfProgram),
+    /// A program that is shared, so the tape doesn't have a lifetime tied to it
+    Shared(Arc<BfProgram>),
+}
+
+impl std::ops::Deref for ProgramRef<'_> {
+    type Target = BfProgram;
+
+    fn deref(&self) -> &BfProgram {
+        match self {
+            ProgramRef::Borrowed(program) => program,
+            ProgramRef::Shared(program) => program,
+        }
+    }
+}
+
+impl<'a> From<&'a BfProgram> for ProgramRef<'a> {
+    fn from(program: &'a BfProgram) -> Self {
+        ProgramRef::Borrowed(program)
+    }
+}
+
+impl From<Arc<BfProgram>> for ProgramRef<'static> {
+    fn from(program: Arc<BfProgram>) -> Self {
+        ProgramRef::Shared(program)
+    }
+}
+
+/// Convert the machine's options to and from cli's, which are the same but need std
+macro_rules! convert_options {
+    ($($option:ident { $($variant:ident $(($value:ident))?),* }),*) => {
+        $(
+            impl From<cli::$option> for machine::$option {
+                fn from(option: cli::$option) -> Self {
+                    match option {
+                        $(cli::$option::$variant $(($value))? => Self::$variant $(($value))?,)*
+                    }
+                }
+            }
+
+            impl From<machine::$option> for cli::$option {
+                fn from(option: machine::$option) -> Self {
+                    match option {
+                        $(machine::$option::$variant $(($value))? => Self::$variant $(($value))?,)*
+                    }
+                }
+            }
+        )*
+    };
+}
+
+convert_options!(
+    AllocStrategy {
+        TapeCanGrow,
+        TapeIsFixed,
+        TapeCanGrowBothWays,
+        TapeCanGrowWithLimit(limit),
+        TapeWraps
+    },
+    OverflowBehavior { Wrap, Saturate, Error },
+    EofBehavior { Zero, NegOne, Unchanged }
+);
+
+impl From<bft_types::BfCommand> for machine::Command {
+    fn from(command: bft_types::BfCommand) -> Self {
+        match command {
+            bft_types::BfCommand::Comment => Self::Comment,
+            bft_types::BfCommand::IncDataPointer => Self::IncDataPointer,
+            bft_types::BfCommand::DecDataPointer => Self::DecDataPointer,
+            bft_types::BfCommand::IncValue => Self::IncValue,
+            bft_types::BfCommand::DecValue => Self::DecValue,
+            bft_types::BfCommand::OutputValue => Self::OutputValue,
+            bft_types::BfCommand::InputValue => Self::InputValue,
+            bft_types::BfCommand::JumpForward => Self::JumpForward,
+            bft_types::BfCommand::JumpBackward => Self::JumpBackward,
+        }
+    }
+}
+
+// The parts of the machine's storage that are only used by BfTape, for the debugger, watches
+// and the views of the tape
+impl<T: CellKind> TapeStorage<T> {
+    /// A reference to a cell, which must be allocated for a dense tape. A sparse tape refers
+    /// to the zero given for cells it doesn't hold.
+    pub(crate) fn get_ref<'s>(&'s self, index: usize, zero: &'s T) -> &'s T {
+        match self {
+            TapeStorage::Dense(cells) => &cells[index],
+            TapeStorage::Sparse(cells) => cells.get(&index).unwrap_or(zero),
+        }
+    }
+
+    /// A mutable reference to a cell, which must be allocated for a dense tape. A sparse tape
+    /// holds the cell from then on, even if it is left at zero.
+    pub(crate) fn get_mut(&mut self, index: usize) -> &mut T {
+        match self {
+            TapeStorage::Dense(cells) => &mut cells[index],
+            TapeStorage::Sparse(cells) => cells.entry(index).or_default(),
+        }
+    }
+
+    /// Number of cells held in memory
+    pub(crate) fn held(&self) -> usize {
+        match self {
+            TapeStorage::Dense(cells) => cells.len(),
+            TapeStorage::Sparse(cells) => cells.len(),
+        }
+    }
+
+    /// The cells that aren't zero in a range, in order of their index
+    pub(crate) fn non_zero(&self, end: usize) -> Vec<(usize, T)> {
+        match self {
+            TapeStorage::Dense(cells) => (0..)
+                .zip(cells[..end].iter().copied())
+                .filter(|(_, cell)| !cell.is_zero())
+                .collect(),
+            TapeStorage::Sparse(cells) => cells
+                .range(..end)
+                .filter(|(_, cell)| !cell.is_zero())
+                .map(|(&index, &cell)| (index, cell))
+                .collect(),
+        }
+    }
+}
+
+/// A tape that shares its program rather than borrowing it, so it can be stored alongside the
+/// program or sent to another thread.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"tiny.bf", "+.").unwrap();
+///     let mut tape: bft_interp::OwnedBfTape<u8> =
+///                             bft_interp::BfTapeBuilder::new().build(std::sync::Arc::new(program));
+///     let handle = std::thread::spawn(move || tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).is_ok());
+///     assert!(handle.join().unwrap());
+/// ```
+pub type OwnedBfTape<T> = BfTape<'static, T>;
+
+/// A tape is a representation of a Brain Fuck program's data as it's being interpreted. The
+/// tape consists of cells which are manipulated as the BF program is interpreted.
+///
+/// A tape is `Send`, so it can be built on one thread and run on another. Hooks, trace and
+/// debug writers must be `Send` to be attached, and the reader and writer given to the
+/// interpreter are only borrowed for the run, so a reader that can't leave its thread (such as
+/// a raw terminal) never ends up inside the tape. Each thread needs its own tape, but a program
+/// can be shared between them with an `Arc`, see [`OwnedBfTape`].
+///
+/// Example usage:
+/// ```
+///     let program = std::sync::Arc::new(bft_types::BfProgram::new(&"tiny.bf", "+.").unwrap());
+///     let handles: Vec<_> = (0..2)
+///         .map(|_| {
+///             let mut tape: bft_interp::OwnedBfTape<u8> =
+///                             bft_interp::BfTapeBuilder::new().build(std::sync::Arc::clone(&program));
+///             std::thread::spawn(move || tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).is_ok())
+///         })
+///         .collect();
+///     assert!(handles.into_iter().all(|handle| handle.join().unwrap()));
+/// ```
+pub struct BfTape<'a, T> {
+    /// The cells, the data and program pointers and the execution of commands
+    pub(crate) machine: BfMachine<T>,
+    /// Reference to the BF program
+    pub(crate) program: ProgramRef<'a>,
+    /// Output format
+    output_format: cli::OutputFormat,
+    /// Input format
+    input_format: cli::InputFormat,
+    /// Flush the writer after every output command
+    auto_flush: bool,
+    /// Separator between values when they are output as numbers
+    separator: String,
+    /// Flag indicating if the separator is output after every number, including the last
+    trailing_separator: bool,
+    /// Flag indicating if the numbers output are ended with a newline when the program finishes
+    final_newline: bool,
+    /// Flag indicating if any numbers have been output yet
+    numbers_output: bool,
+    /// Number of cells the tape started with, which it goes back to when reset
+    initial_length: usize,
+    /// A cell of zero for indexing cells that a sparse tape doesn't hold
+    zero: T,
+    /// The last byte output, None if nothing has been output
+    last_output: Option<u8>,
+    /// Whether a newline is output when the program finishes
+    trailing_newline: cli::TrailingNewline,
+    /// Debug flag
+    debug: cli::DebugLevelType,
+    /// Maximum number of instructions that may be executed, None means unlimited
+    step_limit: Option<u64>,
+    /// Longest the program may run for, None means unlimited
+    time_limit: Option<Duration>,
+    /// When the time limit runs out, set when the program starts running
+    deadline: Option<Instant>,
+    /// Number of times in a row a loop may go round unchanged, None means no limit
+    loop_guard: Option<u64>,
+    /// The end of the last loop that went round, with the data pointer and cell value at
+    /// the time, and how many times in a row it has gone round with them the same
+    loop_repeat: Option<(usize, usize, T, u64)>,
+    /// Number of instructions executed so far
+    steps: u64,
+    /// Most steps kept in the history so that they can be undone, None if no history is kept
+    history_limit: Option<usize>,
+    /// How to undo each of the most recent steps, oldest first
+    history: VecDeque<UndoRecord<T>>,
+    /// How to undo the step being executed
+    undo_pending: Option<UndoRecord<T>>,
+    /// Input given back by steps that were undone, the next byte to be read is last
+    pushed_back_input: Vec<u8>,
+    /// Input given to the program before any is read from the reader, so that a run can be
+    /// replayed
+    replay_input: Vec<u8>,
+    /// Number of bytes of the replayed input that the program has read
+    replay_position: usize,
+    /// Every byte the program has read, if its input is being recorded
+    recorded_input: Option<Vec<u8>>,
+    /// Flag polled by the interpreter which when set stops the program
+    pub(crate) interrupt: Option<Arc<AtomicBool>>,
+    /// Cells which stop the program when their value is changed
+    watchpoints: Vec<usize>,
+    /// Set when the current instruction changes a watched cell
+    watch_hit: Option<StopReason<T>>,
+    /// Callback given each instruction before it is executed
+    hook: Option<Hook<'a, T>>,
+    /// Set when the hook paused the program so that it isn't called again on resuming
+    hook_paused: bool,
+    /// Progress of an input or output instruction that an async interpreter was part way through
+    #[cfg(feature = "async")]
+    async_io: AsyncIoState,
+    /// Where a trace of executed instructions is written
+    trace_writer: Option<Box<dyn Write + Send + 'a>>,
+    /// Where run events are sent and how many of them
+    event_sink: Option<(EventGranularity, EventSink<'a>)>,
+    /// Where debug output is written, stderr is used if not set
+    debug_writer: Option<Box<dyn Write + Send + 'a>>,
+    /// Time spent in each kind of command, if the timing profiler is enabled
+    command_timings: Option<CommandTimings>,
+    /// Number of times each instruction has been executed when profiling
+    profile_hits: Option<Vec<u64>>,
+    /// Number of times each instruction has been executed when recording coverage
+    coverage_hits: Option<Vec<u32>>,
+    /// Number of times each cell has been read and written when the heatmap is enabled
+    cell_accesses: Option<Vec<CellAccesses>>,
+}
+
+/// Implementation of the BF program's tape
+///
+impl<'a, T: CellKind + std::fmt::Debug> BfTape<'a, T> {
+    /// Create a new tape for BF instructions.
+    ///
+    /// The BF program is passed in for reference purposes.
+    ///
+    /// If the size is specified as zero, then the default size of DEFAULT_TAPE_SIZE cells
+    /// will be allocated.
+    ///
+    /// The allocation strategy can be set so that the tape can grow as needed or it can be fixed.
+    ///
+    /// # Panics
+    ///
+    /// If there isn't enough memory for the tape, use try_new to get an error instead.
+    pub fn new(
+        program: impl Into<ProgramRef<'a>>,
+        tape_size: usize,
+        alloc_strategy: cli::AllocStrategy,
+        output_format: cli::OutputFormat,
+    ) -> Self {
+        let tape_size = (tape_size != 0).then_some(tape_size);
+        match Self::try_new(program, tape_size, alloc_strategy, output_format) {
+            Ok(tape) => tape,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Create a new tape for BF instructions, see new. The size is None for the default size of
+    /// DEFAULT_TAPE_SIZE cells.
+    ///
+    /// A tape of one cell is fine, the data pointer just can't move from it unless the tape can
+    /// grow. An InvalidTapeSize error is returned for a tape of no cells, and a
+    /// TapeAllocationFailed error if there isn't enough memory for the cells.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+").unwrap();
+    ///     let tape = bft_interp::BfTape::<u8>::try_new(&program, None, cli::AllocStrategy::TapeIsFixed, cli::OutputFormat::BinaryOutput).unwrap();
+    ///     assert_eq!(tape.data_length(), bft_interp::DEFAULT_TAPE_SIZE);
+    ///     assert!(bft_interp::BfTape::<u8>::try_new(&program, Some(0), cli::AllocStrategy::TapeIsFixed, cli::OutputFormat::BinaryOutput).is_err());
+    /// ```
+    pub fn try_new(
+        program: impl Into<ProgramRef<'a>>,
+        tape_size: Option<usize>,
+        alloc_strategy: cli::AllocStrategy,
+        output_format: cli::OutputFormat,
+    ) -> Result<Self, BfError> {
+        let length = tape_size.unwrap_or(DEFAULT_TAPE_SIZE);
+        if length == 0 {
+            return Err(BfError::InvalidTapeSize {
+                requested_cells: length,
+            });
+        }
+        let machine = BfMachine::new(length).map_err(|_| BfError::TapeAllocationFailed {
+            requested_cells: length,
+        })?;
+        Ok(Self::with_machine(
+            program,
+            machine,
+            alloc_strategy,
+            output_format,
+        ))
+    }
+
+    /// Create a new tape for BF instructions which only stores the cells that aren't zero.
+    ///
+    /// This suits programs that use a few cells spread across a huge tape. The program behaves
+    /// the same as with a tape created by new, but each cell access is slower as the cells are
+    /// held in a BTreeMap.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"far.bf", "+").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(1_000_000_000).output_format(cli::OutputFormat::BinaryOutput).sparse(true).build(&program);
+    ///     assert_eq!(tape.data_length(), 1_000_000_000);
+    /// ```
+    pub fn new_sparse(
+        program: impl Into<ProgramRef<'a>>,
+        tape_size: usize,
+        alloc_strategy: cli::AllocStrategy,
+        output_format: cli::OutputFormat,
+    ) -> Self {
+        let length = if tape_size == 0 {
+            DEFAULT_TAPE_SIZE
+        } else {
+            tape_size
+        };
+        // Only a tape of no cells can't be created, and the length isn't zero
+        let machine = BfMachine::new_sparse(length).expect("a sparse tape can be created");
+        Self::with_machine(program, machine, alloc_strategy, output_format)
+    }
+
+    /// Create a new tape for BF instructions with the start of the tape initialised from
+    /// an image of bytes. See new and load_initial.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"table.bf", ".>.").unwrap();
+    ///     let tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTape::with_initial(&program, 100, cli::AllocStrategy::TapeIsFixed, cli::OutputFormat::BinaryOutput, &[1, 2]).unwrap();
+    ///     assert_eq!(tape.get_cell_at(1).unwrap(), 2);
+    /// ```
+    pub fn with_initial(
+        program: impl Into<ProgramRef<'a>>,
+        tape_size: usize,
+        alloc_strategy: cli::AllocStrategy,
+        output_format: cli::OutputFormat,
+        image: &[u8],
+    ) -> Result<Self, BfError> {
+        let mut tape = Self::new(program, tape_size, alloc_strategy, output_format);
+        tape.load_initial(image)?;
+        Ok(tape)
+    }
+
+    /// Create a new tape that runs the program on a machine
+    fn with_machine(
+        program: impl Into<ProgramRef<'a>>,
+        mut machine: BfMachine<T>,
+        alloc_strategy: cli::AllocStrategy,
+        output_format: cli::OutputFormat,
+    ) -> Self {
+        machine.set_alloc_strategy(alloc_strategy.into());
+        let length = machine.data_length();
+        Self {
+            machine,
+            program: program.into(),
+            output_format,
+            input_format: cli::InputFormat::RawInput,
+            auto_flush: false,
+            separator: String::from(","),
+            trailing_separator: false,
+            final_newline: false,
+            numbers_output: false,
+            initial_length: length,
+            zero: T::default(),
+            last_output: None,
+            trailing_newline: cli::TrailingNewline::Never,
+            debug: cli::DebugLevelType::None,
+            step_limit: None,
+            time_limit: None,
+            deadline: None,
+            loop_guard: None,
+            loop_repeat: None,
+            steps: 0,
+            history_limit: None,
+            history: VecDeque::new(),
+            undo_pending: None,
+            pushed_back_input: Vec::new(),
+            replay_input: Vec::new(),
+            replay_position: 0,
+            recorded_input: None,
+            interrupt: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            hook: None,
+            hook_paused: false,
+            #[cfg(feature = "async")]
+            async_io: AsyncIoState::default(),
+            trace_writer: None,
+            event_sink: None,
+            debug_writer: None,
+            command_timings: None,
+            profile_hits: None,
+            coverage_hits: None,
+            cell_accesses: None,
+        }
+    }
+
+    /// Put the tape back to how it was when it was created so that the program can be run
+    /// again, without allocating the cells again. The cells are set to zero and the data and
+    /// program pointers go back to the start. Any profile, coverage or recorded input is also
+    /// set back to empty, and input being replayed starts again from the beginning.
+    /// Configuration, such as the allocation strategy, formats, debug level and watchpoints,
+    /// is kept.
+    pub fn reset(&mut self) {
+        // Watchpoints moved along when the tape grew to the left so move them back
+        let origin = self.machine.origin;
+        self.watchpoints.retain(|&cell| cell >= origin);
+        for cell in self.watchpoints.iter_mut() {
+            *cell -= origin;
+        }
+        self.machine.reset(self.initial_length);
+        self.steps = 0;
+        self.deadline = None;
+        self.watch_hit = None;
+        self.loop_repeat = None;
+        self.hook_paused = false;
+        self.history.clear();
+        self.undo_pending = None;
+        self.pushed_back_input.clear();
+        self.replay_position = 0;
+        if let Some(recorded) = &mut self.recorded_input {
+            recorded.clear();
+        }
+        #[cfg(feature = "async")]
+        {
+            self.async_io = AsyncIoState::default();
+        }
+        self.last_output = None;
+        self.numbers_output = false;
+        let instructions = self.program.instructions().len();
+        if let Some(hits) = &mut self.profile_hits {
+            *hits = vec![0; instructions];
+        }
+        if let Some(hits) = &mut self.coverage_hits {
+            *hits = vec![0; instructions];
+        }
+        if let Some(accesses) = &mut self.cell_accesses {
+            accesses.clear();
+        }
+        if let Some(timings) = &mut self.command_timings {
+            *timings = CommandTimings::default();
+        }
+    }
+
+    /// Reset the tape, as reset does, so that it can be used to run a different program
+    ///
+    /// Example usage:
+    /// ```
+    ///     let first = bft_types::BfProgram::new(&"first.bf", "+.").unwrap();
+    ///     let second = bft_types::BfProgram::new(&"second.bf", "++.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&first);
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     tape.reset_with_program(&second);
+    ///     let mut writer = std::io::Cursor::new(Vec::new());
+    ///     tape.interpreter(&mut std::io::empty(), &mut writer).unwrap();
+    ///     assert_eq!(writer.into_inner(), b"2");
+    /// ```
+    pub fn reset_with_program(&mut self, program: impl Into<ProgramRef<'a>>) {
+        self.program = program.into();
+        self.reset();
+    }
+
+    // Data pointer handling methods
+    // #############################
+
+    /// The data pointer
+    pub fn data_pointer(&self) -> usize {
+        self.machine.data_pointer
+    }
+
+    /// Position of the data pointer relative to the cell that the program started on. This is
+    /// the same as the data pointer unless the tape has grown to the left, in which case it can
+    /// be negative.
+    pub fn data_position(&self) -> isize {
+        self.machine.data_pointer as isize - self.machine.origin as isize
+    }
+
+    /// Set data pointer to start of program
+    pub fn reset_data_pointer(&mut self) {
+        self.machine.data_pointer = 0;
+    }
+
+    /// Length of data tape. This is the number of cells in use, which is the initial size of
+    /// the tape or, if the tape has grown, one past the furthest cell reached. A tape that can
+    /// grow may have allocated more cells than this.
+    pub fn data_length(&self) -> usize {
+        self.machine.length
+    }
+
+    /// The most cells a tape that can grow is allowed to have, None if there is no limit. The
+    /// limit comes from the allocation strategy, see cli::AllocStrategy::TapeCanGrowWithLimit.
+    pub fn max_tape(&self) -> Option<usize> {
+        self.machine.max_tape()
+    }
+
+    /// Copy an image of bytes into the start of the tape, such as a lookup table that the
+    /// program uses. Each byte is converted to a cell in the same way as input is.
+    ///
+    /// It is an error for the image to be larger than a fixed tape. A tape that can grow is
+    /// grown to fit the image, unless that would take it beyond its maximum length.
+    pub fn load_initial(&mut self, image: &[u8]) -> Result<(), BfError> {
+        if image.len() > self.machine.length {
+            let limit = match self.machine.alloc_strategy() {
+                machine::AllocStrategy::TapeIsFixed | machine::AllocStrategy::TapeWraps => {
+                    self.machine.length
+                }
+                machine::AllocStrategy::TapeCanGrow
+                | machine::AllocStrategy::TapeCanGrowBothWays
+                | machine::AllocStrategy::TapeCanGrowWithLimit(_) => {
+                    self.max_tape().unwrap_or(usize::MAX)
+                }
+            };
+            if image.len() > limit {
+                return Err(BfError::ImageTooLarge {
+                    size: image.len(),
+                    length: limit,
+                });
+            }
+            if image.len() > self.machine.tape.allocated() {
+                self.grow_to(image.len())?;
+            }
+            self.machine.length = image.len();
+        }
+        for (index, byte) in image.iter().enumerate() {
+            self.machine
+                .tape
+                .set(self.machine.origin + index, T::from_u8(*byte));
+        }
+        Ok(())
+    }
+
+    /// All of the cells in the tape. The number of cells is the same as data_length, so for a
+    /// tape that can grow it includes the cells that the tape has grown by.
+    ///
+    /// The cells of a dense tape are borrowed, whereas those of a sparse tape have to be
+    /// gathered up, so non_zero_cells is better for looking at a large sparse tape.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"cells.bf", "+>++").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(3).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     assert_eq!(*tape.cells(), [1, 2, 0]);
+    /// ```
+    pub fn cells(&self) -> Cow<'_, [T]> {
+        self.machine.tape.range(0, self.machine.length)
+    }
+
+    /// A copy of the cells and pointers, to be compared with a later snapshot to see what
+    /// changed, see BfState.
+    pub fn snapshot(&self) -> BfState<T> {
+        BfState {
+            cells: self.cells().into_owned(),
+            data_pointer: self.machine.data_pointer,
+            program_pointer: self.machine.program_pointer,
+            steps: self.steps,
+        }
+    }
+
+    /// The index and value of each cell that isn't zero, in order of the index
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"cells.bf", "+>>++").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(3).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     assert_eq!(tape.non_zero_cells().collect::<Vec<_>>(), [(0, 1), (2, 2)]);
+    /// ```
+    pub fn non_zero_cells(&self) -> impl Iterator<Item = (usize, T)> + '_ {
+        self.machine.tape.non_zero(self.machine.length).into_iter()
+    }
+
+    /// The cells either side of the data pointer, up to radius cells in each direction.
+    /// The index of the first cell in the window is returned along with the cells.
+    pub fn tape_window(&self, radius: usize) -> (usize, Cow<'_, [T]>) {
+        let start = self.machine.data_pointer.saturating_sub(radius);
+        let end = (self.machine.data_pointer + radius + 1).min(self.machine.length);
+        (start, self.machine.tape.range(start, end))
+    }
+
+    /// The cells either side of the data pointer, up to radius cells in each direction, as
+    /// text. The cell at the data pointer is marked with `>` and `<`, the indices of the first
+    /// and last cells shown are in brackets, and `...` shows there are more cells beyond them,
+    /// e.g. `... [5] 0 12 >65< 0 3 [9] ...`.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"window.bf", "").unwrap();
+    ///     let tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTape::with_initial(&program, 100, cli::AllocStrategy::TapeIsFixed, cli::OutputFormat::BinaryOutput, &[65, 66]).unwrap();
+    ///     assert_eq!(tape.render_window(2), "[0] >65< 66 0 [2] ...");
+    /// ```
+    pub fn render_window(&self, radius: usize) -> String {
+        let (start, cells) = self.tape_window(radius);
+        let end = start + cells.len() - 1;
+        let mut text = String::new();
+        if start > 0 {
+            text += "... ";
+        }
+        text += &format!("[{}]", start);
+        for (index, cell) in (start..).zip(cells.iter()) {
+            if index == self.machine.data_pointer {
+                text += &format!(" >{:?}<", cell);
+            } else {
+                text += &format!(" {:?}", cell);
+            }
+        }
+        text += &format!(" [{}]", end);
+        if end < self.machine.length - 1 {
+            text += " ...";
+        }
+        text
+    }
+
+    /// Write a hexdump style listing of the tape, or part of it, e.g. after an error.
+    ///
+    /// Each line has the offset of its first cell, the cells in hex and the cells as ASCII,
+    /// with `.` for characters that can't be shown. The cell at the data pointer is marked
+    /// with `[` and `]`. As with hexdump, lines of zero cells that follow a line of zero cells
+    /// are replaced with a single `*` line, and the last line is the offset of the end.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"dump.bf", "").unwrap();
+    ///     let tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTape::with_initial(&program, 4, cli::AllocStrategy::TapeIsFixed, cli::OutputFormat::BinaryOutput, b"Hi").unwrap();
+    ///     let mut dump = Vec::new();
+    ///     tape.dump(&mut dump, None).unwrap();
+    ///     assert_eq!(
+    ///         String::from_utf8(dump).unwrap(),
+    ///         "00000000 [48]69 00 00                                      |Hi..|\n00000004\n"
+    ///     );
+    /// ```
+    pub fn dump<W: Write>(
+        &self,
+        writer: &mut W,
+        range: Option<std::ops::Range<usize>>,
+    ) -> std::io::Result<()> {
+        let range = range.unwrap_or(0..self.machine.length);
+        let end = range.end.min(self.machine.length);
+        let start = range.start.min(end);
+        let width = std::mem::size_of::<T>() * 2;
+
+        let mut previous_zero = false;
+        let mut collapsed = false;
+        for line_start in (start..end).step_by(DUMP_CELLS_PER_LINE) {
+            let line_end = (line_start + DUMP_CELLS_PER_LINE).min(end);
+            let cells = self.machine.tape.range(line_start, line_end);
+            let has_pointer = (line_start..line_end).contains(&self.machine.data_pointer);
+
+            // Collapse lines of zeros that follow another line of zeros
+            let zero = !has_pointer && cells.iter().all(|cell| cell.is_zero());
+            if zero && previous_zero {
+                if !collapsed {
+                    writeln!(writer, "*")?;
+                    collapsed = true;
+                }
+                continue;
+            }
+            previous_zero = zero;
+            collapsed = false;
+
+            // The separator before and after the cell at the data pointer marks it
+            let mut hex = String::new();
+            let mut ascii = String::new();
+            for (index, cell) in (line_start..).zip(cells.iter()) {
+                hex.push(match index {
+                    i if i == self.machine.data_pointer => '[',
+                    i if i == self.machine.data_pointer + 1 => ']',
+                    _ => ' ',
+                });
+                hex += &cell.to_hex();
+                let byte = cell.to_u8();
+                ascii.push(if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                });
+            }
+            hex.push(if line_end - 1 == self.machine.data_pointer {
+                ']'
+            } else {
+                ' '
+            });
+            writeln!(
+                writer,
+                "{:08x} {:<hex_width$} |{}|",
+                line_start,
+                hex,
+                ascii,
+                hex_width = DUMP_CELLS_PER_LINE * (width + 1) + 1
+            )?;
+        }
+        writeln!(writer, "{:08x}", end)
+    }
+
+    /// Allocate cells up to the given length, or a TapeAllocationFailed error if there isn't
+    /// enough memory for them
+    fn grow_to(&mut self, length: usize) -> Result<(), BfError> {
+        self.machine
+            .grow_to(length)
+            .map_err(|e| self.machine_error(e))
+    }
+
+    /// Moves the data pointer forward
+    pub fn move_data_pointer_forward(&mut self) -> Result<(), BfError> {
+        self.machine
+            .move_data_pointer_forward()
+            .map_err(|e| self.machine_error(e))
+    }
+
+    /// Moves the data pointer backward
+    pub fn move_data_pointer_back(&mut self) -> Result<(), BfError> {
+        let origin = self.machine.origin;
+        self.machine
+            .move_data_pointer_back()
+            .map_err(|e| self.machine_error(e))?;
+        self.cells_moved_along(origin);
+        Ok(())
+    }
+
+    /// Move anything that refers to a cell by its index along with the cells, after the tape
+    /// has grown to the left from where the origin was
+    fn cells_moved_along(&mut self, origin: usize) {
+        let count = self.machine.origin - origin;
+        if count == 0 {
+            return;
+        }
+        for cell in self.watchpoints.iter_mut() {
+            *cell += count;
+        }
+        if let Some(accesses) = &mut self.cell_accesses {
+            accesses.splice(0..0, std::iter::repeat_n(CellAccesses::default(), count));
+        }
+    }
+
+    /// The BfError for an error from the machine, which happened at the program pointer
+    fn machine_error(&self, error: MachineError) -> BfError {
+        let program_pointer = self.machine.program_pointer;
+        let instruction = self.error_instruction(program_pointer);
+        let snippet = self.snippet(instruction);
+        match error {
+            MachineError::DataPtrMovedBeforeStart => BfError::DataPtrMovedBeforeStart {
+                instruction,
+                program_pointer,
+                snippet,
+            },
+            MachineError::DataPtrMovedAfterEnd => BfError::DataPtrMovedAfterEnd {
+                instruction,
+                program_pointer,
+                snippet,
+            },
+            MachineError::TapeLimitExceeded { limit } => BfError::TapeLimitExceeded {
+                limit,
+                instruction,
+                program_pointer,
+                snippet,
+            },
+            MachineError::InvalidTapeSize { requested_cells } => {
+                BfError::InvalidTapeSize { requested_cells }
+            }
+            MachineError::TapeAllocationFailed { requested_cells } => {
+                BfError::TapeAllocationFailed { requested_cells }
+            }
+            // The cell is left as it was
+            MachineError::CellOverflow => BfError::CellOverflow {
+                instruction,
+                program_pointer,
+                value: format!("{:?}", self.machine.get_cell()),
+                snippet,
+            },
+            MachineError::BracketNotFound => self.bracket_not_found(program_pointer),
+            MachineError::IOError { error } => self.io_error(error),
+        }
+    }
+
+    // Data value handling methods
+    // ###########################
+
+    /// Store a value in the cell currently pointed to by the data pointer, noting if a
+    /// watched cell has been changed
+    fn store_data_value(&mut self, value: T) {
+        self.store_cell(self.machine.data_pointer, value);
+    }
+
+    /// Store a value in a cell, noting if a watched cell has been changed
+    fn store_cell(&mut self, index: usize, value: T) {
+        let old = self.machine.tape.get(index);
+        self.machine.tape.set(index, value);
+        self.check_watchpoint(index, old, self.machine.program_pointer);
+    }
+
+    /// Change the value of the cell currently pointed to by the data pointer on the machine,
+    /// noting if a watched cell has been changed
+    fn change_data_value(
+        &mut self,
+        change: impl FnOnce(&mut BfMachine<T>) -> Result<(), MachineError>,
+    ) -> Result<(), BfError> {
+        let (index, old) = (self.machine.data_pointer, self.machine.get_cell());
+        change(&mut self.machine).map_err(|e| self.machine_error(e))?;
+        self.check_watchpoint(index, old, self.machine.program_pointer);
+        Ok(())
+    }
+
+    /// Note if a cell that had the old value has been changed by the instruction at a program
+    /// pointer and is watched
+    fn check_watchpoint(&mut self, index: usize, old: T, program_pointer: usize) {
+        if self.watchpoints.is_empty() || !self.watchpoints.contains(&index) {
+            return;
+        }
+        let new = self.machine.tape.get(index);
+        if old != new {
+            self.watch_hit = Some(StopReason::Watchpoint {
+                cell: index,
+                old,
+                new,
+                program_pointer,
+            });
+        }
+    }
+
+    /// Increment the value of the cell currently pointed to by the data pointer
+    pub fn increment_data_value(&mut self) -> Result<(), BfError> {
+        self.change_data_value(BfMachine::increment_data_value)
+    }
+
+    /// Decrement the value of the cell currently pointed to by the data pointer
+    pub fn decrement_data_value(&mut self) -> Result<(), BfError> {
+        self.change_data_value(BfMachine::decrement_data_value)
+    }
+
+    /// Add a delta, which may be negative, to the value of the cell currently pointed to by
+    /// the data pointer in one go. What happens at the limits of the cell is the same as for
+    /// incrementing or decrementing it, so with OverflowBehavior::Error a delta that would go
+    /// beyond them is a CellOverflow error at the instruction at the program pointer, and the
+    /// cell is left as it was.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.add_to_data_value(300).unwrap();
+    ///     assert_eq!(tape.get_data_value(), 44);
+    ///     tape.set_overflow_behavior(cli::OverflowBehavior::Error);
+    ///     assert!(tape.add_to_data_value(-45).is_err());
+    ///     assert_eq!(tape.get_data_value(), 44);
+    /// ```
+    pub fn add_to_data_value(&mut self, delta: i16) -> Result<(), BfError> {
+        self.change_data_value(|machine| machine.add_to_data_value(delta))
+    }
+
+    /// What happens when a cell is incremented or decremented beyond its limits
+    pub fn overflow_behavior(&self) -> cli::OverflowBehavior {
+        self.machine.overflow_behavior().into()
+    }
+
+    /// Default is for cells to wrap around, but they can saturate or cause an error instead
+    pub fn set_overflow_behavior(&mut self, overflow_behavior: cli::OverflowBehavior) {
+        self.machine.set_overflow_behavior(overflow_behavior.into());
+    }
+
+    /// Get the current value of the cell at the current data pointer position as a u8
+    // Note: Used for tests
+    pub fn get_data_value(&self) -> u8 {
+        self.get_cell().to_u8()
+    }
+
+    /// Set the current value of the cell at the current data pointer position from a u8
+    // Note: Used for tests
+    pub fn set_data_value(&mut self, value: u8) {
+        self.set_cell(<T as CellKind>::from_u8(value));
+    }
+
+    /// Get the current value of the cell at the current data pointer position
+    pub fn get_cell(&self) -> T {
+        self.machine.get_cell()
+    }
+
+    /// Set the current value of the cell at the current data pointer position
+    pub fn set_cell(&mut self, value: T) {
+        self.store_data_value(value);
+    }
+
+    /// Get the value of any cell in the tape.
+    ///
+    /// If the tape is fixed or wraps round, it is an error for the cell to be beyond the end of
+    /// the tape. If the tape can grow, a cell beyond the end of the tape has the default value
+    /// since that is what it will be when the tape grows to include it.
+    pub fn get_cell_at(&self, index: usize) -> Result<T, BfError> {
+        if index < self.machine.length {
+            return Ok(self.machine.tape.get(index));
+        }
+        match self.machine.alloc_strategy() {
+            machine::AllocStrategy::TapeIsFixed | machine::AllocStrategy::TapeWraps => {
+                Err(BfError::CellOutOfRange {
+                    cell: index,
+                    length: self.machine.length,
+                })
+            }
+            machine::AllocStrategy::TapeCanGrow
+            | machine::AllocStrategy::TapeCanGrowBothWays
+            | machine::AllocStrategy::TapeCanGrowWithLimit(_) => Ok(T::default()),
+        }
+    }
+
+    /// Set the value of any cell in the tape.
+    ///
+    /// If the tape is fixed or wraps round, it is an error for the cell to be beyond the end of
+    /// the tape. If the tape can grow, it is grown to include the cell.
+    pub fn set_cell_at(&mut self, index: usize, value: T) -> Result<(), BfError> {
+        if index >= self.machine.length {
+            match self.machine.alloc_strategy() {
+                machine::AllocStrategy::TapeIsFixed | machine::AllocStrategy::TapeWraps => {
+                    return Err(BfError::CellOutOfRange {
+                        cell: index,
+                        length: self.machine.length,
+                    });
+                }
+                machine::AllocStrategy::TapeCanGrow
+                | machine::AllocStrategy::TapeCanGrowBothWays
+                | machine::AllocStrategy::TapeCanGrowWithLimit(_) => {
+                    if let Some(limit) = self.max_tape().filter(|&limit| index >= limit) {
+                        return Err(BfError::CellOutOfRange {
+                            cell: index,
+                            length: limit,
+                        });
+                    }
+                    if index >= self.machine.tape.allocated() {
+                        self.grow_to(index + 1)?;
+                    }
+                    self.machine.length = index + 1;
+                }
+            }
+        }
+        self.store_cell(index, value);
+        Ok(())
+    }
+
+    /// Output the value of the cell currently pointed to by the data pointer
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "><+-.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                         bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     let mut writer = std::io::Cursor::new(Vec::new());
+    ///     assert_eq!(tape.command_output_value(&mut writer).is_ok(), true);
+    ///     assert_eq!(writer.into_inner()[0], 48);
+    /// ```
+    pub fn output_value<W: Write>(&mut self, writer: &mut W) -> Result<(), BfError> {
+        self.output_io(&mut StdIo::new(std::io::empty(), writer))
+    }
+
+    /// Output the value of the cell currently pointed to by the data pointer to a BfIo
+    pub(crate) fn output_io<I: BfIo>(&mut self, io: &mut I) -> Result<(), BfError> {
+        // A loop that outputs is doing something
+        self.loop_repeat = None;
+
+        // Get the value of the cell in the tape at the current data pointer location
+        let value = self.machine.tape.get(self.machine.data_pointer);
+        let data = [value.to_u8(); 1];
+
+        // Write to where ever it's going, handling any i/o errors.
+        // Also output each cell as a number or as an ASCII character depending on the state of the format flag
+        if self.output_format == OutputFormat::BinaryOutput
+            || self.output_format == OutputFormat::HexOutput
+        {
+            // Without a trailing separator, the separator goes before every number except the first.
+            // Numbers are the whole of the cell's value, not just the low byte
+            let mut num = String::new();
+            if self.numbers_output && !self.trailing_separator {
+                num += &self.separator;
+            }
+            if self.output_format == OutputFormat::HexOutput {
+                num += &value.to_hex();
+            } else {
+                num += &value.to_i128().to_string();
+            }
+            if self.trailing_separator {
+                num += &self.separator;
+            }
+            self.numbers_output = true;
+            io.write_bytes(num.as_bytes())
+                .map_err(|e| BfError::IOError {
+                    error_msg: e,
+                    filepath: self.program.filename().to_path_buf(),
+                    instruction: self.error_instruction(self.machine.program_pointer),
+                    snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+                    program_pointer: self.machine.program_pointer,
+                })?;
+            self.emit_output(num.as_bytes());
+            self.last_output = num.as_bytes().last().copied().or(self.last_output);
+        } else if self.output_format == OutputFormat::Utf8Output {
+            // Invalid code points, such as surrogates, are output as the replacement character
+            let c = char::from_u32(self.machine.tape.get(self.machine.data_pointer).to_u32())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            let mut bytes = [0; 4];
+            let encoded = c.encode_utf8(&mut bytes).as_bytes();
+            io.write_bytes(encoded).map_err(|e| BfError::IOError {
+                error_msg: e,
+                filepath: self.program.filename().to_path_buf(),
+                instruction: self.error_instruction(self.machine.program_pointer),
+                snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+                program_pointer: self.machine.program_pointer,
+            })?;
+            self.emit_output(encoded);
+            self.last_output = encoded.last().copied();
+        } else {
+            io.write_bytes(&data).map_err(|e| BfError::IOError {
+                error_msg: e,
+                filepath: self.program.filename().to_path_buf(),
+                instruction: self.error_instruction(self.machine.program_pointer),
+                snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+                program_pointer: self.machine.program_pointer,
+            })?;
+            self.emit_output(&data);
+            self.last_output = Some(data[0]);
+        }
+
+        // Interactive programs need their prompts to appear before any input is read
+        if self.auto_flush {
+            io.flush().map_err(|e| BfError::IOError {
+                error_msg: e,
+                filepath: self.program.filename().to_path_buf(),
+                instruction: self.error_instruction(self.machine.program_pointer),
+                snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+                program_pointer: self.machine.program_pointer,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Input a value into the cell currently pointed to by the data pointer
+    ///
+    /// Reads that are interrupted are retried, and only a true end of file triggers the end of
+    /// file behaviour. One byte is read at a time and nothing is buffered here, since any bytes
+    /// read ahead would be lost between calls. Pass a buffered reader, such as a locked stdin,
+    /// to avoid a system call per byte.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"inout.bf", ",.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     let mut reader = std::io::Cursor::new(vec![55]);
+    ///     assert!(tape.command_input_value(&mut reader).is_ok());
+    /// ```
+    pub fn input_value<R: Read>(&mut self, reader: &mut R) -> Result<(), BfError> {
+        self.input_io(&mut StdIo::new(reader, std::io::sink()))
+    }
+
+    /// Input a value from a BfIo into the cell currently pointed to by the data pointer
+    pub(crate) fn input_io<I: BfIo>(&mut self, io: &mut I) -> Result<(), BfError> {
+        // A loop that inputs can be ended by the input
+        self.loop_repeat = None;
+        self.emit(RunEvent::InputRequested);
+
+        let value = if self.input_format == cli::InputFormat::DecimalInput {
+            self.read_number(io)?
+        } else {
+            self.read_byte(io)?.map(T::from_u8)
+        };
+
+        // Place the value into the tape at the current data pointer location, or what the EOF
+        // behaviour says at the end of the input
+        self.change_data_value(|machine| {
+            machine.store_input(value);
+            Ok(())
+        })
+    }
+
+    /// Read a single byte, None at end of file. Input given back by step_back is read first,
+    /// then any input being replayed, and then the io.
+    fn read_byte<I: BfIo>(&mut self, io: &mut I) -> Result<Option<u8>, BfError> {
+        if let Some(byte) = self.pushed_back_input.pop() {
+            if let Some(record) = &mut self.undo_pending {
+                record.input.push(byte);
+            }
+            return Ok(Some(byte));
+        }
+
+        // Input given back was recorded when it was first read so only new input is recorded
+        let byte = match self.replay_input.get(self.replay_position) {
+            Some(&byte) => {
+                self.replay_position += 1;
+                Some(byte)
+            }
+            None => io.read_byte().map_err(|e| BfError::IOError {
+                error_msg: e,
+                filepath: self.program.filename().to_path_buf(),
+                instruction: self.error_instruction(self.machine.program_pointer),
+                snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+                program_pointer: self.machine.program_pointer,
+            })?,
+        };
+        if let (Some(recorded), Some(byte)) = (&mut self.recorded_input, byte) {
+            recorded.push(byte);
+        }
+        if let (Some(record), Some(byte)) = (&mut self.undo_pending, byte) {
+            record.input.push(byte);
+        }
+        Ok(byte)
+    }
+
+    /// Read a decimal number ended by a comma, whitespace or end of file. Separators before
+    /// the number are skipped. None if the end of file is reached before the number starts.
+    fn read_number<I: BfIo>(&mut self, io: &mut I) -> Result<Option<T>, BfError> {
+        let is_separator = |byte: u8| byte == b',' || byte.is_ascii_whitespace();
+        let mut text = Vec::new();
+        while let Some(byte) = self.read_byte(io)? {
+            if !is_separator(byte) {
+                text.push(byte);
+            } else if !text.is_empty() {
+                break;
+            }
+        }
+        if text.is_empty() {
+            return Ok(None);
+        }
+
+        let text = String::from_utf8_lossy(&text);
+        match T::parse(&text) {
+            Some(value) => Ok(Some(value)),
+            None => Err(BfError::InvalidInput {
+                instruction: self.error_instruction(self.machine.program_pointer),
+                snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+                program_pointer: self.machine.program_pointer,
+                input: text.to_string(),
+            }),
+        }
+    }
+
+    /// How values are input
+    pub fn input_format(&self) -> cli::InputFormat {
+        self.input_format
+    }
+
+    /// Default is for each byte of input to be stored as is, but decimal numbers can be input instead
+    pub fn set_input_format(&mut self, input_format: cli::InputFormat) {
+        self.input_format = input_format;
+    }
+
+    /// Whether a newline is output when the program finishes
+    pub fn trailing_newline(&self) -> cli::TrailingNewline {
+        self.trailing_newline
+    }
+
+    /// Default is to never output a newline when the program finishes. IfMissing is useful when
+    /// the output is going to a terminal so that the shell prompt starts on a new line.
+    pub fn set_trailing_newline(&mut self, trailing_newline: cli::TrailingNewline) {
+        self.trailing_newline = trailing_newline;
+    }
+
+    /// Separator between values when they are output as numbers
+    pub fn separator(&self) -> &str {
+        &self.separator
+    }
+
+    /// Set the separator between values when they are output as numbers. The default is ","
+    pub fn set_separator(&mut self, separator: &str) {
+        self.separator = separator.to_string();
+    }
+
+    /// Flag indicating if the separator is output after every number, including the last
+    pub fn trailing_separator(&self) -> bool {
+        self.trailing_separator
+    }
+
+    /// Output the separator after every number, including the last. The default is to only
+    /// output it between numbers.
+    pub fn set_trailing_separator(&mut self, trailing_separator: bool) {
+        self.trailing_separator = trailing_separator;
+    }
+
+    /// Flag indicating if the numbers output are ended with a newline when the program finishes
+    pub fn final_newline(&self) -> bool {
+        self.final_newline
+    }
+
+    /// End the numbers output with a newline when the program finishes. Nothing is output if
+    /// the program didn't output any numbers.
+    pub fn set_final_newline(&mut self, final_newline: bool) {
+        self.final_newline = final_newline;
+    }
+
+    /// Flag indicating if the writer is flushed after every output command
+    pub fn auto_flush(&self) -> bool {
+        self.auto_flush
+    }
+
+    /// Flush the writer after every output command. The writer is always flushed when the
+    /// interpreter stops, so this is only needed for interactive programs.
+    pub fn set_auto_flush(&mut self, auto_flush: bool) {
+        self.auto_flush = auto_flush;
+    }
+
+    /// What is stored in the cell when the input reaches end of file
+    pub fn eof_behavior(&self) -> cli::EofBehavior {
+        self.machine.eof_behavior().into()
+    }
+
+    /// Default is for the cell to be set to -1 at end of file, but it can be set to 0 or left unchanged instead
+    pub fn set_eof_behavior(&mut self, eof_behavior: cli::EofBehavior) {
+        self.machine.set_eof_behavior(eof_behavior.into());
+    }
+
+    // Program handling methods
+    // ########################
+
+    /// Current program pointer
+    pub fn program_pointer(&self) -> usize {
+        self.machine.program_pointer
+    }
+
+    /// The instruction at the current program pointer, None once the program has finished
+    pub fn current_instruction(&self) -> Option<bft_types::BfInstruction> {
+        self.program
+            .instructions()
+            .get(self.machine.program_pointer)
+            .copied()
+    }
+
+    /// Error for an input or output failure at the current instruction
+    pub(crate) fn io_error(&self, error_msg: std::io::Error) -> BfError {
+        BfError::IOError {
+            error_msg,
+            filepath: self.program.filename().to_path_buf(),
+            instruction: self.error_instruction(self.machine.program_pointer),
+            snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+            program_pointer: self.machine.program_pointer,
+        }
+    }
+
+    /// The instruction to report in an error raised at a program pointer. The program pointer
+    /// can be at or past the end of the program, in which case the last instruction is
+    /// reported, and an empty program has no instructions so a placeholder is reported.
+    fn error_instruction(&self, program_pointer: usize) -> bft_types::BfInstruction {
+        let instructions = self.program.instructions();
+        instructions
+            .get(program_pointer)
+            .or(instructions.last())
+            .copied()
+            .unwrap_or(bft_types::BfInstruction::new(
+                bft_types::BfCommand::Comment,
+                0,
+                0,
+            ))
+    }
+
+    /// The source line of an instruction for an error's message
+    fn snippet(&self, instruction: bft_types::BfInstruction) -> SourceSnippet {
+        SourceSnippet::new(&self.program, instruction.location())
+    }
+
+    /// Moves the program pointer forward
+    // Note: Used for tests
+    pub fn move_program_pointer_forward(&mut self) -> Result<(), BfError> {
+        if self.machine.program_pointer + 1 >= self.program.instructions().len() {
+            return Err(BfError::ProgramPtrMovedAfterEnd {
+                program_pointer: self.machine.program_pointer,
+                instruction: self.error_instruction(self.machine.program_pointer),
+                snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+            });
+        }
+        self.machine.program_pointer += 1;
+        Ok(())
+    }
+
+    /// The index of the bracket matching the one at the program pointer, from the program's
+    /// jump table. The brackets are checked before the program is run, see check_validated,
+    /// so this is only None if the jump commands are used on an unvalidated program.
+    fn jump(&self) -> Option<usize> {
+        self.program
+            .jump_table()
+            .get(self.machine.program_pointer)
+            .copied()
+    }
+
+    /// Error for the bracket at a program pointer having no matching bracket
+    fn bracket_not_found(&self, program_pointer: usize) -> BfError {
+        let instruction = self.error_instruction(program_pointer);
+        BfError::BracketNotFound {
+            instruction,
+            program_pointer,
+            snippet: self.snippet(instruction),
+        }
+    }
+
+    /// Check that the program's jumps can be made before running it. The jump table is filled
+    /// in when the program is validated, which a program without brackets doesn't need.
+    fn check_validated(&self) -> Result<(), BfError> {
+        let instructions = self.program.instructions();
+        if self.program.jump_table().len() == instructions.len() {
+            return Ok(());
+        }
+        let bracket = instructions.iter().position(|inst| {
+            matches!(
+                inst.command(),
+                bft_types::BfCommand::JumpForward | bft_types::BfCommand::JumpBackward
+            )
+        });
+        match bracket {
+            Some(program_pointer) => Err(self.bracket_not_found(program_pointer)),
+            None => Ok(()),
+        }
+    }
+
+    /// Jump forward to the matching bracket if the value at the current data pointer is zero,
+    /// otherwise the loop is entered
+    pub fn jump_forward(&mut self) -> Result<(), BfError> {
+        let jump = self.jump();
+        self.machine
+            .jump_forward(jump)
+            .map_err(|e| self.machine_error(e))
+    }
+
+    /// Jump backward to the matching bracket if the value at the current data pointer is
+    /// non-zero, otherwise the loop is left
+    pub fn jump_backward(&mut self) -> Result<(), BfError> {
+        let jump = self.jump();
+        self.machine
+            .jump_backward(jump)
+            .map_err(|e| self.machine_error(e))
+    }
+
+    /// Number of loops the program pointer is currently inside. A loop that is skipped
+    /// because the cell is zero when it is reached isn't entered, so doesn't count.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let mut program = bft_types::BfProgram::new(&"loops.bf", "+[[-]>]").unwrap();
+    ///     program.validate().unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.step(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     tape.step(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     assert_eq!(tape.current_depth(), 1);
+    ///     tape.step(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     assert_eq!(tape.current_depth(), 2);
+    /// ```
+    pub fn current_depth(&self) -> usize {
+        self.machine.depth
+    }
+
+    /// Work out the depth at an instruction from the brackets before it, for when the program
+    /// has got there without executing the jumps, such as in native code. None is the end
+    /// of the program.
+    #[cfg(feature = "jit")]
+    fn depth_from_source(&self, program_pointer: Option<usize>) -> usize {
+        let Some(program_pointer) = program_pointer else {
+            return 0;
+        };
+        let mut depth: usize = 0;
+        for inst in &self.program.instructions()[..program_pointer] {
+            match inst.command() {
+                bft_types::BfCommand::JumpForward => depth += 1,
+                bft_types::BfCommand::JumpBackward => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        depth
+    }
+
+    // BF Command implementation methods
+    // #################################
+
+    /// Increment the value in the data cell currently pointed to by the data pointer
+    /// The return value is the updated program pointer
+    pub fn command_inc_value(&mut self) -> Result<usize, BfError> {
+        self.execute_command(machine::Command::IncValue, None)
+    }
+
+    /// Decrement the value in the data cell currently pointed to by the data pointer
+    pub fn command_dec_value(&mut self) -> Result<usize, BfError> {
+        self.execute_command(machine::Command::DecValue, None)
+    }
+
+    /// Move data pointer forward to next data cell in tape
+    pub fn command_move_pointer_forward(&mut self) -> Result<usize, BfError> {
+        self.execute_command(machine::Command::IncDataPointer, None)
+    }
+
+    /// Move data pointer back to previous data cell in tape
+    pub fn command_move_pointer_back(&mut self) -> Result<usize, BfError> {
+        self.execute_command(machine::Command::DecDataPointer, None)
+    }
+
+    /// Take input from user and place into the current data cell
+    pub fn command_input_value<R: Read>(&mut self, reader: &mut R) -> Result<usize, BfError> {
+        self.command_input_io(&mut StdIo::new(reader, std::io::sink()))
+    }
+
+    /// Take input from user and place into the current data cell
+    pub fn command_output_value<W: Write>(&mut self, writer: &mut W) -> Result<usize, BfError> {
+        self.command_output_io(&mut StdIo::new(std::io::empty(), writer))
+    }
+
+    /// Take input from a BfIo and place into the current data cell
+    fn command_input_io<I: BfIo>(&mut self, io: &mut I) -> Result<usize, BfError> {
+        self.input_io(io)?;
+        self.machine.program_pointer += 1;
+        Ok(self.machine.program_pointer)
+    }
+
+    /// Output the current data cell to a BfIo
+    fn command_output_io<I: BfIo>(&mut self, io: &mut I) -> Result<usize, BfError> {
+        self.output_io(io)?;
+        self.machine.program_pointer += 1;
+        Ok(self.machine.program_pointer)
+    }
+
+    /// Jump forward if the value in the current data cell is zero
+    pub fn command_jump_forward(&mut self) -> Result<usize, BfError> {
+        self.execute_command(machine::Command::JumpForward, self.jump())
+    }
+
+    /// Jump back if the value in the current data cell is not zero
+    pub fn command_jump_backward(&mut self) -> Result<usize, BfError> {
+        self.execute_command(machine::Command::JumpBackward, self.jump())
+    }
+
+    // Debug handling methods
+    // ######################
+
+    /// Debug level currently in use
+    pub fn debug(&self) -> cli::DebugLevelType {
+        self.debug
+    }
+
+    /// Default is no debug output, but it can be enabled to various levels of detail
+    pub fn set_debug(&mut self, debug: cli::DebugLevelType) {
+        self.debug = debug;
+    }
+
+    /// Debug output goes to stderr by default, but can be sent to another writer. It never goes
+    /// to the writer the program's output is written to.
+    pub fn set_debug_writer(&mut self, writer: Box<dyn Write + Send + 'a>) {
+        self.debug_writer = Some(writer);
+    }
+
+    /// Output a line of debug. Failing to write debug output is not a reason to stop the
+    /// program so any error is ignored.
+    fn debug_output(&mut self, line: &str) {
+        match &mut self.debug_writer {
+            Some(writer) => {
+                let _ = writeln!(writer, "{}", line);
+            }
+            None => eprintln!("{}", line),
+        }
+    }
+
+    // Execution limit methods
+    // #######################
+
+    /// Maximum number of instructions the interpreter will execute
+    pub fn step_limit(&self) -> Option<u64> {
+        self.step_limit
+    }
+
+    /// Default is unlimited, but a limit can be set so that a runaway program is stopped
+    /// with a StepLimitExceeded error rather than running forever
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    /// Longest the interpreter will run the program for
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.time_limit
+    }
+
+    /// Default is unlimited, but a limit can be set so that a program is stopped with a
+    /// TimeLimitExceeded error once it has run for that long. The time counts from when the
+    /// program starts running and carries on across stops, such as at a watchpoint, until
+    /// the tape is reset. The clock is checked every few steps, so a program can run a little
+    /// over the limit, and time spent waiting for input isn't checked until the input arrives.
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.time_limit = limit;
+        self.deadline = None;
+    }
+
+    /// Start the time limit's clock if the program hasn't already started running
+    fn start_clock(&mut self) {
+        if let (Some(limit), None) = (self.time_limit, self.deadline) {
+            self.deadline = Instant::now().checked_add(limit);
+        }
+    }
+
+    /// Number of times in a row a loop may go round unchanged before it is treated as infinite
+    pub fn loop_guard(&self) -> Option<u64> {
+        self.loop_guard
+    }
+
+    /// Default is no guard, but a guard can be set so that a loop that goes round the given
+    /// number of times in a row with the same data pointer and the same value in the cell at
+    /// the data pointer, and no input or output, stops with a ProbableInfiniteLoop error.
+    ///
+    /// This is a heuristic. A loop that changes cells other than the one at the data pointer
+    /// may still end, so set the guard high enough for the programs being run.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let mut program = bft_types::BfProgram::new(&"forever.bf", "+[]").unwrap();
+    ///     program.validate().unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.set_loop_guard(Some(100));
+    ///     let error = tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap_err();
+    ///     assert_eq!(error.code(), "E_INFINITE_LOOP");
+    /// ```
+    pub fn set_loop_guard(&mut self, guard: Option<u64>) {
+        self.loop_guard = guard;
+        self.loop_repeat = None;
+    }
+
+    /// Count how many times in a row the loop ending at the program pointer has gone round
+    /// unchanged, and stop the program once the loop guard is reached
+    fn check_loop_guard(&mut self) -> Result<(), BfError> {
+        let Some(guard) = self.loop_guard else {
+            return Ok(());
+        };
+        let value = self.machine.tape.get(self.machine.data_pointer);
+        let repeats = match self.loop_repeat {
+            Some((end, data_pointer, last, repeats))
+                if end == self.machine.program_pointer
+                    && data_pointer == self.machine.data_pointer
+                    && last == value =>
+            {
+                repeats + 1
+            }
+            _ => 1,
+        };
+        self.loop_repeat = Some((
+            self.machine.program_pointer,
+            self.machine.data_pointer,
+            value,
+            repeats,
+        ));
+        if repeats >= guard {
+            // The loop is reported at its start
+            let start = self
+                .program
+                .jump_table()
+                .get(self.machine.program_pointer)
+                .copied()
+                .unwrap_or(self.machine.program_pointer);
+            let instruction = self.error_instruction(start);
+            return Err(BfError::ProbableInfiniteLoop {
+                location: instruction.location(),
+                iterations: repeats,
+                snippet: self.snippet(instruction),
+            });
+        }
+        Ok(())
+    }
+
+    /// Number of instructions executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Statistics about the run so far
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+>+").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().tape_size(10).build(&program);
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     assert_eq!(tape.run_stats().steps, 3);
+    ///     assert_eq!(tape.run_stats().data_pointer, 1);
+    /// ```
+    pub fn run_stats(&self) -> RunStats {
+        RunStats {
+            steps: self.steps,
+            data_pointer: self.machine.data_pointer,
+            data_length: self.machine.length,
+            memory: self.memory_stats(),
+            timings: self.command_timings,
+            highest_cell_accessed: self.cell_accesses.as_ref().and_then(|accesses| {
+                accesses
+                    .iter()
+                    .rposition(|access| *access != CellAccesses::default())
+            }),
+        }
+    }
+
+    /// How much memory the tape has used so far
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ">>+<").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u16> = bft_interp::BfTapeBuilder::new().tape_size(10).build(&program);
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     let stats = tape.memory_stats();
+    ///     assert_eq!(stats.allocated_cells, 10);
+    ///     assert_eq!(stats.allocated_bytes, 20);
+    ///     assert_eq!(stats.high_water, 2);
+    ///     assert_eq!(stats.growth_events, 0);
+    /// ```
+    pub fn memory_stats(&self) -> MemoryStats {
+        let allocated_cells = self.machine.tape.held();
+        MemoryStats {
+            allocated_cells,
+            allocated_bytes: allocated_cells * std::mem::size_of::<T>(),
+            high_water: self.machine.high_water,
+            growth_events: self.machine.growth_events,
+        }
+    }
+
+    // Watchpoint handling methods
+    // ###########################
+
+    /// Stop the program when the value in a cell is changed. The interpreter (or step) will
+    /// return a StopReason::Watchpoint after the instruction that changed the cell.
+    ///
+    /// If the tape can grow, a cell beyond the current end of the tape can be watched and the
+    /// watchpoint will take effect once the tape has grown to include it.
+    pub fn add_watchpoint(&mut self, cell: usize) -> Result<(), BfError> {
+        if cell >= self.machine.length
+            && matches!(
+                self.machine.alloc_strategy(),
+                machine::AllocStrategy::TapeIsFixed | machine::AllocStrategy::TapeWraps
+            )
+        {
+            return Err(BfError::CellOutOfRange {
+                cell,
+                length: self.machine.length,
+            });
+        }
+        if !self.watchpoints.contains(&cell) {
+            self.watchpoints.push(cell);
+        }
+        Ok(())
+    }
+
+    /// Stop watching a cell
+    pub fn remove_watchpoint(&mut self, cell: usize) {
+        self.watchpoints.retain(|&c| c != cell);
+    }
+
+    /// Cells currently being watched
+    pub fn watchpoints(&self) -> &[usize] {
+        &self.watchpoints
+    }
+
+    // History methods
+    // ###############
+
+    /// Keep a history of the most recent steps so that they can be undone with step_back.
+    /// Up to limit steps are kept, older ones are forgotten. None stops keeping a history.
+    /// Any history already kept is thrown away.
+    pub fn set_history(&mut self, limit: Option<usize>) {
+        self.history_limit = limit;
+        self.history = VecDeque::with_capacity(limit.unwrap_or(0));
+        self.undo_pending = None;
+    }
+
+    /// Most steps kept in the history, None if no history is kept
+    pub fn history_limit(&self) -> Option<usize> {
+        self.history_limit
+    }
+
+    /// Number of steps that can currently be undone
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Undo the last step, putting the cell it changed and the data and program pointers back
+    /// to how they were. Any input the step read is given back and will be read again by the
+    /// next step that inputs a value. Output can't be taken back, so a step that output a value
+    /// is undone but what it wrote stays written. Cells added when the tape grew are kept.
+    ///
+    /// Returns false if there is no step in the history to undo.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "++>+").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.set_history(Some(10));
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     assert!(tape.step_back());
+    ///     assert!(tape.step_back());
+    ///     assert_eq!(tape.data_pointer(), 0);
+    ///     assert_eq!(tape.program_pointer(), 2);
+    ///     assert_eq!(tape.steps(), 2);
+    /// ```
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.history.pop_back() else {
+            return false;
+        };
+        // Cells added to the left since the step moved everything to the right
+        let shift = self.machine.origin - record.origin;
+        self.machine.program_pointer = record.program_pointer;
+        self.machine.depth = record.depth;
+        self.machine.data_pointer = record.data_pointer + shift;
+        self.machine
+            .tape
+            .set(self.machine.data_pointer, record.value);
+        self.pushed_back_input.extend(record.input.iter().rev());
+        self.steps -= 1;
+        self.loop_repeat = None;
+        self.watch_hit = None;
+        true
+    }
+
+    // Hook handling methods
+    // #####################
+
+    /// Set a callback which is called before each instruction is executed. The callback decides
+    /// whether the program continues, pauses or is aborted.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+++").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     tape.set_hook(|event| {
+    ///         println!("{} {}", event.program_pointer, event.instruction);
+    ///         bft_interp::HookAction::Continue
+    ///     });
+    /// ```
+    pub fn set_hook(&mut self, hook: impl FnMut(&HookEvent<T>) -> HookAction + Send + 'a) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    /// Remove the callback
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+    }
+
+    // Trace handling methods
+    // ######################
+
+    /// Write a trace of each executed instruction, one JSON line per instruction, to the
+    /// writer. See TraceRecord for the format.
+    pub fn set_trace_writer(&mut self, writer: Box<dyn Write + Send + 'a>) {
+        self.trace_writer = Some(writer);
+    }
+
+    /// Flush the trace and stop tracing. The trace is only flushed by the interpreter when the
+    /// program halts, so this makes sure all of it is written when the program fails.
+    pub fn finish_trace(&mut self) -> std::io::Result<()> {
+        match self.trace_writer.take() {
+            Some(mut trace_writer) => trace_writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// The trace record for an executed instruction
+    fn trace_record(&self, program_pointer: usize, inst: &bft_types::BfInstruction) -> TraceRecord {
+        TraceRecord {
+            step: self.steps,
+            program_pointer,
+            command: bft_types::BfCommand::to_char(inst.command()),
+            line: inst.location().line(),
+            column: inst.location().offset(),
+            data_pointer: self.machine.data_pointer,
+            value: self.machine.tape.get(self.machine.data_pointer).to_i128(),
+            depth: self.machine.depth,
+        }
+    }
+
+    /// Write the trace record for an executed instruction
+    fn write_trace(
+        &mut self,
+        program_pointer: usize,
+        inst: &bft_types::BfInstruction,
+    ) -> Result<(), BfError> {
+        let record = self.trace_record(program_pointer, inst);
+        if let Some(trace_writer) = &mut self.trace_writer {
+            serde_json::to_writer(&mut *trace_writer, &record)
+                .map_err(std::io::Error::from)
+                .and_then(|_| trace_writer.write_all(b"\n"))
+                .map_err(|e| BfError::IOError {
+                    error_msg: e,
+                    filepath: self.program.filename().to_path_buf(),
+                    instruction: *inst,
+                    snippet: self.snippet(*inst),
+                    program_pointer,
+                })?;
+        }
+        Ok(())
+    }
+
+    // Run event handling methods
+    // ##########################
+
+    /// Give each event of the running program to a callback, see RunEvent. Events are only
+    /// made while there is somewhere for them to go.
+    ///
+    /// Example usage:
+    /// ```
+    ///     use bft_interp::RunEvent;
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",+.").unwrap();
+    ///     let mut events = Vec::new();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.set_event_sink(bft_interp::EventGranularity::IoAndControl, |event| events.push(event));
+    ///     tape.interpreter(&mut "A".as_bytes(), &mut std::io::sink()).unwrap();
+    ///     let stats = tape.run_stats();
+    ///     drop(tape);
+    ///     assert_eq!(events, [RunEvent::ProgramStarted, RunEvent::InputRequested, RunEvent::OutputByte(b'B'), RunEvent::Halted(Box::new(stats))]);
+    /// ```
+    pub fn set_event_sink(
+        &mut self,
+        granularity: EventGranularity,
+        sink: impl FnMut(RunEvent) + Send + 'a,
+    ) {
+        self.event_sink = Some((granularity, Box::new(sink)));
+    }
+
+    /// Send each event of the running program over a channel, such as to a front end running
+    /// on another thread. Events are dropped once the receiver has gone.
+    pub fn set_event_sender(
+        &mut self,
+        granularity: EventGranularity,
+        sender: mpsc::Sender<RunEvent>,
+    ) {
+        self.set_event_sink(granularity, move |event| {
+            sender.send(event).ok();
+        });
+    }
+
+    /// Stop making run events
+    pub fn clear_event_sink(&mut self) {
+        self.event_sink = None;
+    }
+
+    /// Give an event to the event sink, if there is one
+    fn emit(&mut self, event: RunEvent) {
+        if let Some((_, sink)) = &mut self.event_sink {
+            sink(event);
+        }
+    }
+
+    /// Give an event for each byte that was output to the event sink
+    fn emit_output(&mut self, bytes: &[u8]) {
+        if self.event_sink.is_some() {
+            for byte in bytes {
+                self.emit(RunEvent::OutputByte(*byte));
+            }
+        }
+    }
+
+    // Input record and replay methods
+    // ###############################
+
+    /// Keep every byte of input the program reads so that the run can be replayed. Any input
+    /// already recorded is thrown away.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",.,.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.enable_input_recording();
+    ///     tape.interpreter(&mut "ab".as_bytes(), &mut std::io::sink()).unwrap();
+    ///     assert_eq!(tape.recorded_input(), Some(&b"ab"[..]));
+    /// ```
+    pub fn enable_input_recording(&mut self) {
+        self.recorded_input = Some(Vec::new());
+    }
+
+    /// The input read by the program so far, if input is being recorded
+    pub fn recorded_input(&self) -> Option<&[u8]> {
+        self.recorded_input.as_deref()
+    }
+
+    /// Give the program input to read before any is read from the reader, such as input
+    /// recorded from an earlier run. Once it has all been read the reader is used as usual.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",.,.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.set_replay_input(b"a".to_vec());
+    ///     let mut output = Vec::new();
+    ///     tape.interpreter(&mut "b".as_bytes(), &mut output).unwrap();
+    ///     assert_eq!(output, b"ab");
+    /// ```
+    pub fn set_replay_input(&mut self, input: Vec<u8>) {
+        self.replay_input = input;
+        self.replay_position = 0;
+    }
+
+    // Profiler handling methods
+    // #########################
+
+    /// Count how many times each instruction is executed so that a profile can be produced
+    pub fn enable_profiler(&mut self) {
+        self.profile_hits = Some(vec![0; self.program.instructions().len()]);
+    }
+
+    /// The profile of the program so far, if the profiler is enabled. The program needs to have
+    /// been validated for the loops to be included.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile_hits
+            .as_ref()
+            .map(|hits| Profile::new(&self.program, hits.clone()))
+    }
+
+    // Timing profiler methods
+    // #######################
+
+    /// Time how long each kind of command takes to execute, see CommandTimings. The timings
+    /// are part of the run stats. Timing is off unless this is called, and when it is off the
+    /// interpreter doesn't check the time at all.
+    pub fn enable_timing_profiler(&mut self) {
+        self.command_timings = Some(CommandTimings::default());
+    }
+
+    /// Time spent in each kind of command so far, if the timing profiler is enabled
+    pub fn command_timings(&self) -> Option<&CommandTimings> {
+        self.command_timings.as_ref()
+    }
+
+    // Heatmap handling methods
+    // ########################
+
+    /// Count how many times each cell is read and written, see CellAccesses. The highest cell
+    /// accessed is part of the run stats.
+    pub fn enable_heatmap(&mut self) {
+        self.cell_accesses = Some(Vec::new());
+    }
+
+    /// Number of times each cell was read and written, indexed by cell, if the heatmap is
+    /// enabled. The counts end at the highest cell the data pointer has accessed, which may be
+    /// before the end of the tape.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"heat.bf", "+>.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     tape.enable_heatmap();
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     let heatmap = tape.heatmap().unwrap();
+    ///     assert_eq!((heatmap[0].reads, heatmap[0].writes), (1, 1));
+    ///     assert_eq!((heatmap[1].reads, heatmap[1].writes), (1, 0));
+    /// ```
+    pub fn heatmap(&self) -> Option<&[CellAccesses]> {
+        self.cell_accesses.as_deref()
+    }
+
+    /// Count the reads and writes of the cell at the data pointer by a command
+    fn count_cell_access(&mut self, command: bft_types::BfCommand) {
+        let (reads, writes) = match command {
+            bft_types::BfCommand::IncValue | bft_types::BfCommand::DecValue => (1, 1),
+            bft_types::BfCommand::OutputValue
+            | bft_types::BfCommand::JumpForward
+            | bft_types::BfCommand::JumpBackward => (1, 0),
+            bft_types::BfCommand::InputValue => (0, 1),
+            _ => return,
+        };
+        if let Some(accesses) = &mut self.cell_accesses {
+            if self.machine.data_pointer >= accesses.len() {
+                accesses.resize(self.machine.data_pointer + 1, CellAccesses::default());
+            }
+            accesses[self.machine.data_pointer].reads += reads;
+            accesses[self.machine.data_pointer].writes += writes;
+        }
+    }
+
+    // Coverage handling methods
+    // #########################
+
+    /// Record which instructions are executed
+    pub fn enable_coverage(&mut self) {
+        self.coverage_hits = Some(vec![0; self.program.instructions().len()]);
+    }
+
+    /// Number of times each instruction was executed, indexed by program pointer, if coverage
+    /// is enabled. Instructions that were never reached have a count of zero. The counts
+    /// saturate at u32::MAX rather than overflowing.
+    pub fn coverage(&self) -> Option<&[u32]> {
+        self.coverage_hits.as_deref()
+    }
+
+    /// Set a flag that is checked before each instruction. When the flag becomes true the
+    /// interpreter stops with an Interrupted error. See install_interrupt_flag.
+    pub fn set_interrupt_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+}
+
+/// Implementation of the BF program's tape
+///
+impl<'a, T: std::fmt::Debug + CellKind + std::clone::Clone + std::default::Default> BfTape<'a, T> {
+    /// Execute the single instruction at the program pointer.
+    ///
+    /// None is returned if the program can carry on, otherwise the reason for stopping is
+    /// returned. Once the end of the program has been reached, StopReason::Halted is returned.
+    /// The step limit, time limit and interrupt flag are checked before the instruction is
+    /// executed, the same as when the program is run by the interpreter.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     let mut reader = std::io::Cursor::new(Vec::new());
+    ///     let mut writer = std::io::Cursor::new(Vec::new());
+    ///     assert_eq!(tape.step(&mut reader, &mut writer).unwrap(), None);
+    ///     assert_eq!(tape.get_data_value(), 1);
+    /// ```
+    pub fn step<R: Read, W: Write>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<Option<StopReason<T>>, BfError> {
+        self.step_io(&mut StdIo::new(reader, writer))
+    }
+
+    /// Execute the single instruction at the program pointer, with the input and output
+    /// going through a BfIo. See step.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     let mut io = bft_interp::VecIo::new(b"x".to_vec());
+    ///     assert_eq!(tape.step_io(&mut io).unwrap(), None);
+    ///     assert_eq!(tape.get_data_value(), b'x');
+    /// ```
+    pub fn step_io<I: BfIo>(&mut self, io: &mut I) -> Result<Option<StopReason<T>>, BfError> {
+        match (self.debug_enabled(), self.command_timings.is_some()) {
+            (false, false) => self.execute_step::<false, false, I>(io),
+            (false, true) => self.execute_step::<false, true, I>(io),
+            (true, false) => self.execute_step::<true, false, I>(io),
+            (true, true) => self.execute_step::<true, true, I>(io),
+        }
+    }
+
+    /// Whether there is any debug output
+    fn debug_enabled(&self) -> bool {
+        self.debug != cli::DebugLevelType::None
+    }
+
+    /// Execute the single instruction at the program pointer. The debug output and timing are
+    /// compiled in or out depending on DEBUG and TIMED so that the checks aren't made for every
+    /// instruction when they are off.
+    fn execute_step<const DEBUG: bool, const TIMED: bool, I: BfIo>(
+        &mut self,
+        io: &mut I,
+    ) -> Result<Option<StopReason<T>>, BfError> {
+        self.check_validated()?;
+        self.start_clock();
+        let inst = match self
+            .program
+            .instructions()
+            .get(self.machine.program_pointer)
+        {
+            Some(inst) => *inst,
+            None => return Ok(Some(StopReason::Halted)),
+        };
+        self.check_can_continue_at(self.step_limit.unwrap_or(u64::MAX), &inst)?;
+        let lowered = LoweredInstruction {
+            command: inst.command().into(),
+            jump: self.jump(),
+            inst,
+        };
+        self.execute_lowered::<DEBUG, TIMED, I>(&lowered, io)
+    }
+
+    /// Execute an instruction that has been lowered, see execute_step
+    fn execute_lowered<const DEBUG: bool, const TIMED: bool, I: BfIo>(
+        &mut self,
+        lowered: &LoweredInstruction,
+        io: &mut I,
+    ) -> Result<Option<StopReason<T>>, BfError> {
+        let inst = &lowered.inst;
+        if let Some(reason) = self.begin_step::<DEBUG>(inst) {
+            return Ok(Some(reason));
+        }
+        let program_pointer = self.machine.program_pointer;
+        let started = TIMED.then(Instant::now);
+        self.machine.program_pointer = match lowered.command {
+            machine::Command::OutputValue => self.command_output_io(io)?,
+            machine::Command::InputValue => self.command_input_io(io)?,
+            command => self.execute_command(command, lowered.jump)?,
+        };
+        self.time_command(started, inst);
+        self.end_step::<DEBUG>(program_pointer, inst)
+    }
+
+    /// Lower the program into the machine command of each of its instructions, with the
+    /// matching bracket of the brackets. This is done once before the program is run so that
+    /// the interpreter loop doesn't have to convert the command or look up the jump table for
+    /// every instruction it executes.
+    fn lower(&self) -> Vec<LoweredInstruction> {
+        let jumps = self.program.jump_table();
+        self.program
+            .instructions()
+            .iter()
+            .enumerate()
+            .map(|(index, &inst)| LoweredInstruction {
+                command: inst.command().into(),
+                jump: jumps.get(index).copied(),
+                inst,
+            })
+            .collect()
+    }
+
+    /// Add the time since started to the timing of the instruction's command. Nothing is added
+    /// if the instruction wasn't timed.
+    fn time_command(&mut self, started: Option<Instant>, inst: &bft_types::BfInstruction) {
+        if let (Some(timings), Some(started)) = (&mut self.command_timings, started) {
+            timings.add(inst.command(), started.elapsed());
+        }
+    }
+
+    /// Everything that is done before an instruction is executed. The hook is called, which
+    /// may stop the program, then the instruction is counted and debugged.
+    fn begin_step<const DEBUG: bool>(
+        &mut self,
+        inst: &bft_types::BfInstruction,
+    ) -> Option<StopReason<T>> {
+        if let Some(hook) = &mut self.hook {
+            if self.hook_paused {
+                self.hook_paused = false;
+            } else {
+                let event = HookEvent {
+                    program_pointer: self.machine.program_pointer,
+                    instruction: *inst,
+                    data_pointer: self.machine.data_pointer,
+                    value: self.machine.tape.get(self.machine.data_pointer),
+                };
+                match hook(&event) {
+                    HookAction::Continue => {}
+                    HookAction::Pause => {
+                        self.hook_paused = true;
+                        return Some(StopReason::Paused {
+                            program_pointer: self.machine.program_pointer,
+                        });
+                    }
+                    HookAction::Abort => {
+                        return Some(StopReason::Aborted {
+                            program_pointer: self.machine.program_pointer,
+                        });
+                    }
+                }
+            }
+        }
+        self.steps += 1;
+        if let Some(hits) = &mut self.profile_hits {
+            hits[self.machine.program_pointer] += 1;
+        }
+        if let Some(hits) = &mut self.coverage_hits {
+            hits[self.machine.program_pointer] =
+                hits[self.machine.program_pointer].saturating_add(1);
+        }
+        if self.cell_accesses.is_some() {
+            self.count_cell_access(inst.command());
+        }
+        if DEBUG {
+            self.debug_before(inst);
+        }
+        if self.history_limit.is_some() {
+            self.undo_pending = Some(UndoRecord {
+                program_pointer: self.machine.program_pointer,
+                depth: self.machine.depth,
+                data_pointer: self.machine.data_pointer,
+                origin: self.machine.origin,
+                value: self.machine.tape.get(self.machine.data_pointer),
+                input: Vec::new(),
+            });
+        }
+        None
+    }
+
+    /// Execute a command that doesn't need input or output on the machine, returning the new
+    /// program pointer. jump is the index of the matching bracket of a bracket. Input and
+    /// output are done by the caller so that they can be synchronous or asynchronous, and
+    /// formatted.
+    fn execute_command(
+        &mut self,
+        command: machine::Command,
+        jump: Option<usize>,
+    ) -> Result<usize, BfError> {
+        if command == machine::Command::JumpBackward
+            && self.loop_guard.is_some()
+            && !self.machine.get_cell().is_zero()
+        {
+            self.check_loop_guard()?;
+        }
+        let (program_pointer, index, old, origin) = (
+            self.machine.program_pointer,
+            self.machine.data_pointer,
+            self.machine.get_cell(),
+            self.machine.origin,
+        );
+        let next = self
+            .machine
+            .execute_command(command, jump)
+            .map_err(|e| self.machine_error(e))?;
+        self.cells_moved_along(origin);
+        self.check_watchpoint(index + self.machine.origin - origin, old, program_pointer);
+        Ok(next)
+    }
+
+    /// Everything that is done after an instruction has been executed. A watchpoint that was
+    /// hit is returned as the reason to stop.
+    fn end_step<const DEBUG: bool>(
+        &mut self,
+        program_pointer: usize,
+        inst: &bft_types::BfInstruction,
+    ) -> Result<Option<StopReason<T>>, BfError> {
+        if let (Some(limit), Some(record)) = (self.history_limit, self.undo_pending.take()) {
+            if self.history.len() >= limit {
+                self.history.pop_front();
+            }
+            if limit > 0 {
+                self.history.push_back(record);
+            }
+        }
+        if DEBUG {
+            self.debug_after(program_pointer, inst);
+        }
+        if self.trace_writer.is_some() {
+            self.write_trace(program_pointer, inst)?;
+        }
+        if let Some((EventGranularity::EveryInstruction, _)) = self.event_sink {
+            let record = self.trace_record(program_pointer, inst);
+            self.emit(RunEvent::InstructionExecuted(record));
+        }
+        Ok(self.watch_hit.take())
+    }
+
+    /// Debug output for an instruction that is about to be executed
+    fn debug_before(&mut self, inst: &bft_types::BfInstruction) {
+        let name = match inst.command() {
+            bft_types::BfCommand::Comment => "Comment",
+            bft_types::BfCommand::IncDataPointer => "IncPtr",
+            bft_types::BfCommand::DecDataPointer => "DecPtr",
+            bft_types::BfCommand::IncValue => "Inc",
+            bft_types::BfCommand::DecValue => "Dec",
+            bft_types::BfCommand::OutputValue => "Output",
+            bft_types::BfCommand::InputValue => "Input",
+            bft_types::BfCommand::JumpForward => "Jumping forward",
+            bft_types::BfCommand::JumpBackward => "Jumping backward",
+        };
+        self.debug_output(&format!("{} at {}", name, self.machine.program_pointer));
+
+        if self.debug >= cli::DebugLevelType::Verbose {
+            let jumping = match inst.command() {
+                bft_types::BfCommand::JumpForward => {
+                    self.machine.tape.get(self.machine.data_pointer).is_zero()
+                }
+                bft_types::BfCommand::JumpBackward => {
+                    !self.machine.tape.get(self.machine.data_pointer).is_zero()
+                }
+                _ => false,
+            };
+            if jumping {
+                self.debug_output(&format!("Looking for jump loc at {}", inst.location()));
+            }
+        }
+    }
+
+    /// Debug output for an instruction that has just been executed. At the Trace level this
+    /// includes the cells around the data pointer after every instruction.
+    fn debug_after(&mut self, program_pointer: usize, inst: &bft_types::BfInstruction) {
+        if self.debug >= cli::DebugLevelType::Verbose {
+            match inst.command() {
+                bft_types::BfCommand::OutputValue | bft_types::BfCommand::InputValue => {
+                    self.debug_output(&format!("Data={:?}", self.get_cell()));
+                }
+                // The program pointer is moved on from the matching bracket after a jump
+                bft_types::BfCommand::JumpForward | bft_types::BfCommand::JumpBackward
+                    if self.machine.program_pointer != program_pointer + 1 =>
+                {
+                    let target = self.machine.program_pointer - 1;
+                    let location = self.program.instructions()[target].location();
+                    self.debug_output(&format!("Jumping to {} at {}", target, location));
+                }
+                _ => {}
+            }
+        }
+        if self.debug >= cli::DebugLevelType::Trace {
+            let window = self.render_window(DISPLAY_RADIUS);
+            self.debug_output(&format!(
+                "Tape dp={} pp={} depth={} {}",
+                self.machine.data_pointer, self.machine.program_pointer, self.machine.depth, window
+            ));
+        }
+    }
+
+    /// The interpreter of a Brain Fuck program.
+    ///
+    /// When input or output is required, the standard in/out objects should be used.
+    ///
+    /// The return is a Result which contains the reason the program stopped when OK, but will
+    /// contain an error types if there was a problem. If the program stopped before the end
+    /// (e.g. at a watchpoint), calling the interpreter again carries on from where it stopped.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",+.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     match tape.interpreter(&mut std::io::stdin(), &mut std::io::stdout()) {
+    ///         Ok(_) => {}
+    ///         Err(e) => println!("Error {}", e),
+    ///     }
+    /// ```
+    //
+    // Note: The tape "object" handles the program's execution, not the program "object" which
+    // is just a static representation of the program. Sounds like there should be another
+    // module which handles the running of the program and the interaction between program
+    // and tape.
+    pub fn interpreter<R: Read, W: Write>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<StopReason<T>, BfError> {
+        self.interpreter_io(&mut StdIo::new(reader, writer))
+    }
+
+    /// The interpreter of a Brain Fuck program with the input and output going through a
+    /// BfIo rather than a reader and writer. See interpreter.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",+.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     let mut io = bft_interp::VecIo::new(vec![41]);
+    ///     assert!(tape.interpreter_io(&mut io).is_ok());
+    ///     assert_eq!(io.output(), b"42");
+    /// ```
+    pub fn interpreter_io<I: BfIo>(&mut self, io: &mut I) -> Result<StopReason<T>, BfError> {
+        self.debug_listing();
+        if self.steps == 0 {
+            self.emit(RunEvent::ProgramStarted);
+        }
+
+        // Decide once whether debug output and timing are needed rather than for every
+        // instruction
+        let result = match (self.debug_enabled(), self.command_timings.is_some()) {
+            (false, false) => self.run::<false, false, I>(io),
+            (false, true) => self.run::<false, true, I>(io),
+            (true, false) => self.run::<true, false, I>(io),
+            (true, true) => self.run::<true, true, I>(io),
+        };
+        let result = match result {
+            Ok(StopReason::Halted) => self.finish_output(io).map(|_| StopReason::Halted),
+            other => other,
+        };
+
+        // The output is flushed however the program stopped
+        let flushed = io.flush();
+        let result = self.finish_run(result, flushed);
+        if self.event_sink.is_some() {
+            match &result {
+                Ok(StopReason::Halted) => self.emit(RunEvent::Halted(Box::new(self.run_stats()))),
+                Err(error) => self.emit(RunEvent::Errored(error.code().to_string())),
+                Ok(_) => {}
+            }
+        }
+        result
+    }
+
+    /// Output the program's instructions when debugging
+    fn debug_listing(&mut self) {
+        if self.debug > cli::DebugLevelType::None {
+            let program = self.program.clone();
+            for inst in program.instructions() {
+                self.debug_output(&inst.to_string());
+            }
+        }
+    }
+
+    /// Work out the final result of a run once the output has been flushed. An error from the
+    /// program takes priority over an error from flushing.
+    fn finish_run(
+        &mut self,
+        result: Result<StopReason<T>, BfError>,
+        flushed: std::io::Result<()>,
+    ) -> Result<StopReason<T>, BfError> {
+        let reason = result?;
+        // Nothing will have been output by an empty program so there is nothing to report
+        if let (Err(e), false) = (flushed, self.program.instructions().is_empty()) {
+            let program_pointer = self
+                .machine
+                .program_pointer
+                .min(self.program.instructions().len() - 1);
+            return Err(BfError::IOError {
+                error_msg: e,
+                filepath: self.program.filename().to_path_buf(),
+                instruction: self.error_instruction(program_pointer),
+                snippet: self.snippet(self.error_instruction(program_pointer)),
+                program_pointer,
+            });
+        }
+        if reason != StopReason::Halted {
+            return Ok(reason);
+        }
+
+        // Nothing will have been traced for an empty program so there is nothing to flush
+        if let (Some(trace_writer), Some(last)) =
+            (&mut self.trace_writer, self.program.instructions().last())
+        {
+            trace_writer.flush().map_err(|e| BfError::IOError {
+                error_msg: e,
+                filepath: self.program.filename().to_path_buf(),
+                instruction: *last,
+                snippet: self.snippet(*last),
+                program_pointer: self.program.instructions().len() - 1,
+            })?;
+        }
+        Ok(StopReason::Halted)
+    }
+
+    /// End the output with newlines as needed once the program has finished. The numbers
+    /// output is ended first, then the trailing newline is added.
+    fn finish_output<I: BfIo>(&mut self, io: &mut I) -> Result<(), BfError> {
+        if self.final_newline && self.numbers_output {
+            self.write_newline(io)?;
+        }
+        let missing = self.last_output.is_some_and(|byte| byte != b'\n');
+        match self.trailing_newline {
+            cli::TrailingNewline::Always => self.write_newline(io),
+            cli::TrailingNewline::IfMissing if missing => self.write_newline(io),
+            _ => Ok(()),
+        }
+    }
+
+    /// Output a newline at the end of the program
+    fn write_newline<I: BfIo>(&mut self, io: &mut I) -> Result<(), BfError> {
+        io.write_byte(b'\n').map_err(|e| BfError::IOError {
+            error_msg: e,
+            filepath: self.program.filename().to_path_buf(),
+            instruction: self.error_instruction(self.machine.program_pointer),
+            snippet: self.snippet(self.error_instruction(self.machine.program_pointer)),
+            program_pointer: self.program.instructions().len().saturating_sub(1),
+        })?;
+        self.last_output = Some(b'\n');
+        Ok(())
+    }
+
+    /// The interpreter of a Brain Fuck program with the input and output buffered, so that
+    /// programs which stream lots of data don't make a system call for every `,` and `.`.
+    ///
+    /// The output is flushed before waiting for more input so that prompts appear, and when
+    /// the program stops, even if there was an error. Any input that was read ahead but not
+    /// used is discarded when the interpreter returns, so use `interpreter` if the program
+    /// is going to be resumed after it stops early (e.g. at a watchpoint).
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",+.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     let mut reader = std::io::Cursor::new(vec![41]);
+    ///     let mut writer = std::io::Cursor::new(Vec::new());
+    ///     assert!(tape.interpreter_buffered(&mut reader, &mut writer).is_ok());
+    ///     assert_eq!(writer.into_inner(), b"42");
+    /// ```
+    pub fn interpreter_buffered<R: Read, W: Write>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<StopReason<T>, BfError> {
+        let shared = Rc::new(RefCell::new(BufWriter::new(writer)));
+        let mut reader = PromptingReader {
+            reader: BufReader::new(reader),
+            writer: Rc::clone(&shared),
+        };
+        self.interpreter(&mut reader, &mut SharedWriter(shared))
+    }
+
+    /// Check that the step limit hasn't been reached and that the program hasn't been
+    /// interrupted before the next instruction is executed
+    pub(crate) fn check_can_continue(&self, step_limit: u64) -> Result<(), BfError> {
+        self.check_can_continue_at(
+            step_limit,
+            &self.program.instructions()[self.machine.program_pointer],
+        )
+    }
+
+    /// Check that the instruction at the program pointer, inst, can be executed, see
+    /// check_can_continue
+    fn check_can_continue_at(
+        &self,
+        step_limit: u64,
+        inst: &bft_types::BfInstruction,
+    ) -> Result<(), BfError> {
+        if self.steps == step_limit {
+            return Err(BfError::StepLimitExceeded {
+                steps: self.steps,
+                instruction: *inst,
+                snippet: self.snippet(*inst),
+                program_pointer: self.machine.program_pointer,
+            });
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(TIME_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(BfError::TimeLimitExceeded {
+                    limit: self.time_limit.unwrap_or_default(),
+                    instruction: *inst,
+                    snippet: self.snippet(*inst),
+                    program_pointer: self.machine.program_pointer,
+                });
+            }
+        }
+        if let Some(flag) = &self.interrupt {
+            if flag.load(Ordering::Relaxed) {
+                return Err(BfError::Interrupted {
+                    instruction: *inst,
+                    snippet: self.snippet(*inst),
+                    program_pointer: self.machine.program_pointer,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the program lazily, executing just enough of it to produce each byte of output.
+    /// The iterator ends when the program halts, or after returning an error. Watchpoints and
+    /// pauses don't end the iterator, but an abort from the hook does.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let mut program = bft_types::BfProgram::new(&"count.bf", "+[.+]").unwrap();
+    ///     program.validate().unwrap();
+    ///     let tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     let first: Vec<u8> = tape.outputs(std::io::empty()).take(3).map(Result::unwrap).collect();
+    ///     assert_eq!(first, [1, 2, 3]);
+    /// ```
+    pub fn outputs<R: Read + 'a>(self, reader: R) -> impl Iterator<Item = Result<u8, BfError>> + 'a
+    where
+        T: 'a,
+    {
+        Outputs {
+            tape: self,
+            io: StdIo::new(reader, VecDeque::new()),
+            finished: false,
+        }
+    }
+
+    /// Execute the program until it stops or there is an error
+    fn run<const DEBUG: bool, const TIMED: bool, I: BfIo>(
+        &mut self,
+        io: &mut I,
+    ) -> Result<StopReason<T>, BfError> {
+        self.check_validated()?;
+        self.start_clock();
+        // No limit is the same as a limit that can never be reached, which keeps the check
+        // in the loop down to a single compare
+        let step_limit = self.step_limit.unwrap_or(u64::MAX);
+
+        // Execute the program, which is lowered first so that each step is a call to the
+        // function for the instruction at the program pointer
+        let lowered = self.lower();
+        while let Some(instruction) = lowered.get(self.machine.program_pointer) {
+            self.check_can_continue_at(step_limit, &instruction.inst)?;
+            if let Some(reason) = self.execute_lowered::<DEBUG, TIMED, I>(instruction, io)? {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::Halted)
+    }
+
+    // Compiled program methods
+    // ########################
+
+    /// Run a program that has been compiled into BfOps by BfProgram::compile. The ops must
+    /// have been compiled from the tape's program, which is used to report where errors
+    /// happened. The tape, formats, input and output behave the same as with interpreter, and
+    /// the output is the same, it just gets there quicker.
+    ///
+    /// Each op counts as a step. The step limit and interrupt flag are checked before each op,
+    /// but hooks, watchpoints, history, debug, trace, profile, coverage and the heatmap are only
+    /// for running the program an instruction at a time and aren't used.
+    ///
+    /// Values wrap around in the ops that replace loops, so if the tape doesn't wrap values
+    /// those loops are run an instruction at a time to stop or fail where they would have. The
+    /// same is done for a loop that would go off the end of the tape.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let mut program = bft_types::BfProgram::new(&"copy.bf", ",[->+>+<<]>>.").unwrap();
+    ///     program.validate().unwrap();
+    ///     let ir = program.compile().unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     let mut output = Vec::new();
+    ///     tape.run_ir(&ir, &mut "A".as_bytes(), &mut output).unwrap();
+    ///     assert_eq!(output, b"A");
+    /// ```
+    pub fn run_ir<R: Read, W: Write>(
+        &mut self,
+        ir: &bft_types::BfIr,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<StopReason<T>, BfError> {
+        self.run_ir_io(ir, &mut StdIo::new(reader, writer))
+    }
+
+    /// Run a compiled program with the input and output going through a BfIo. See run_ir.
+    pub fn run_ir_io<I: BfIo>(
+        &mut self,
+        ir: &bft_types::BfIr,
+        io: &mut I,
+    ) -> Result<StopReason<T>, BfError> {
+        let result = self.execute_ir(ir, io, 0);
+        self.finish_ir(result, io)
+    }
+
+    /// Run a compiled program as native code, see run_ir. The program's input and output go
+    /// through the reader and writer as usual, and the stats of the run are returned.
+    ///
+    /// Native code is only used for a tape that isn't sparse, whose values wrap around and that
+    /// doesn't have a step or time limit, otherwise the program is run by run_ir. The native code
+    /// checks that the data pointer stays on the tape and stops when interrupted, passing the rest
+    /// of the program to run_ir so that the tape grows, or the error is reported, the same as
+    /// always. Ops run as native code aren't counted as steps.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let mut program = bft_types::BfProgram::new(&"copy.bf", ",[->+>+<<]>>.").unwrap();
+    ///     program.validate().unwrap();
+    ///     let ir = program.compile().unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+    ///     let mut output = Vec::new();
+    ///     let stats = tape.run_jit(&ir, &mut "A".as_bytes(), &mut output).unwrap();
+    ///     assert_eq!(output, b"A");
+    ///     assert_eq!(stats.data_pointer, 2);
+    /// ```
+    #[cfg(feature = "jit")]
+    pub fn run_jit<R: Read, W: Write>(
+        &mut self,
+        ir: &bft_types::BfIr,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<RunStats, BfError> {
+        let mut io = StdIo::new(reader, writer);
+        let native = self.tape_is_dense()
+            && self.machine.overflow_behavior() == machine::OverflowBehavior::Wrap
+            && self.step_limit.is_none()
+            && self.time_limit.is_none();
+        let result = if native {
+            self.undo_pending = None;
+            crate::jit::run(self, ir, &mut io).and_then(|start| {
+                // The native code doesn't keep track of the depth
+                let program_pointer = ir.source_map().get(start).copied();
+                self.machine.depth = self.depth_from_source(program_pointer);
+                self.execute_ir(ir, &mut io, start)
+            })
+        } else {
+            self.execute_ir(ir, &mut io, 0)
+        };
+        self.finish_ir(result, &mut io)?;
+        Ok(self.run_stats())
+    }
+
+    /// Whether the cells are all held in memory, which native code needs
+    #[cfg(feature = "jit")]
+    fn tape_is_dense(&self) -> bool {
+        matches!(self.machine.tape, TapeStorage::Dense(_))
+    }
+
+    /// Finish running ops in the same way as the interpreter finishes
+    fn finish_ir<I: BfIo>(
+        &mut self,
+        result: Result<StopReason<T>, BfError>,
+        io: &mut I,
+    ) -> Result<StopReason<T>, BfError> {
+        let result = match result {
+            Ok(StopReason::Halted) => self.finish_output(io).map(|_| StopReason::Halted),
+            other => other,
+        };
+        // Changes to watched cells aren't reported when running ops
+        self.watch_hit = None;
+
+        let flushed = io.flush();
+        self.finish_run(result, flushed)
+    }
+
+    /// Run the ops, starting at the op at an index. The program pointer is kept at the
+    /// instruction the current op was made from so that errors are reported there.
+    fn execute_ir<I: BfIo>(
+        &mut self,
+        ir: &bft_types::BfIr,
+        io: &mut I,
+        start: usize,
+    ) -> Result<StopReason<T>, BfError> {
+        self.start_clock();
+        let step_limit = self.step_limit.unwrap_or(u64::MAX);
+        let wraps = self.machine.overflow_behavior() == machine::OverflowBehavior::Wrap;
+        let ops = ir.ops();
+        let source_map = ir.source_map();
+        // Input isn't kept for undoing steps
+        self.undo_pending = None;
+
+        let mut index = start;
+        while index < ops.len() {
+            self.machine.program_pointer = source_map[index];
+            self.check_can_continue(step_limit)?;
+            self.steps += 1;
+            match ops[index] {
+                bft_types::BfOp::Add(amount) if wraps => {
+                    let value = self
+                        .machine
+                        .tape
+                        .get(self.machine.data_pointer)
+                        .wrapping_add_i64(amount);
+                    self.store_data_value(value);
+                }
+                bft_types::BfOp::Add(amount) => {
+                    // The ops are all + or all - so adding them in one go saturates the same as
+                    // one at a time, but an overflow is found by doing each on its own so that
+                    // it is reported at the same one
+                    let added = match i16::try_from(amount) {
+                        Ok(delta) => self.add_to_data_value(delta).is_ok(),
+                        Err(_) => false,
+                    };
+                    if !added {
+                        let source = self.machine.program_pointer;
+                        for count in 0..amount.unsigned_abs() as usize {
+                            self.machine.program_pointer = source + count;
+                            if amount > 0 {
+                                self.increment_data_value()?;
+                            } else {
+                                self.decrement_data_value()?;
+                            }
+                        }
+                    }
+                }
+                bft_types::BfOp::Move(amount) => self.move_data_pointer_by(amount)?,
+                bft_types::BfOp::ClearCell | bft_types::BfOp::MulAdd { .. }
+                    if !wraps || !self.replaced_loop_fits(ir, index) =>
+                {
+                    // Run the loop the ops were made from and carry on after its ops
+                    let source = self.machine.program_pointer;
+                    self.execute_loop(source, step_limit)?;
+                    while index < ops.len() && source_map[index] == source {
+                        index += 1;
+                    }
+                    continue;
+                }
+                bft_types::BfOp::ClearCell => self.store_data_value(T::default()),
+                bft_types::BfOp::MulAdd { offset, factor } => {
+                    let value = self.machine.tape.get(self.machine.data_pointer);
+                    if !value.is_zero() {
+                        // The cell is on the tape, see replaced_loop_fits
+                        let cell = self.machine.data_pointer.wrapping_add_signed(offset);
+                        let amount = value.to_i64().wrapping_mul(factor);
+                        let total = self.machine.tape.get(cell).wrapping_add_i64(amount);
+                        self.store_cell(cell, total);
+                    }
+                }
+                bft_types::BfOp::ScanRight => {
+                    // Errors are reported at the > in the loop
+                    self.machine.program_pointer += 1;
+                    while !self.machine.tape.get(self.machine.data_pointer).is_zero() {
+                        self.move_data_pointer_forward()?;
+                    }
+                }
+                bft_types::BfOp::ScanLeft => {
+                    self.machine.program_pointer += 1;
+                    while !self.machine.tape.get(self.machine.data_pointer).is_zero() {
+                        self.move_data_pointer_back()?;
+                    }
+                }
+                bft_types::BfOp::Output => self.output_io(io)?,
+                bft_types::BfOp::Input => self.input_io(io)?,
+                bft_types::BfOp::LoopStart(end) => {
+                    if self.machine.tape.get(self.machine.data_pointer).is_zero() {
+                        index = end;
+                    } else {
+                        self.machine.depth += 1;
+                    }
+                }
+                bft_types::BfOp::LoopEnd(start) => {
+                    if !self.machine.tape.get(self.machine.data_pointer).is_zero() {
+                        index = start;
+                    } else {
+                        self.machine.depth = self.machine.depth.saturating_sub(1);
+                    }
+                }
+            }
+            index += 1;
+        }
+        self.machine.program_pointer = self.program.instructions().len();
+        Ok(StopReason::Halted)
+    }
+
+    /// Run the loop that starts at the program pointer an instruction at a time. Loops that
+    /// are replaced by ops only move and change cells so there is no input or output.
+    fn execute_loop(&mut self, start: usize, step_limit: u64) -> Result<(), BfError> {
+        let end = self.program.jump_table()[start];
+        while self.machine.program_pointer <= end {
+            self.check_can_continue(step_limit)?;
+            self.steps += 1;
+            let command = self.program.instructions()[self.machine.program_pointer].command();
+            self.machine.program_pointer = self.execute_command(command.into(), self.jump())?;
+        }
+        Ok(())
+    }
+
+    /// Whether the loop that the op at an index was made from can be run by its ops. A loop
+    /// that isn't run doesn't move, but one that is run and would go off the end of the tape
+    /// has to be run an instruction at a time so that the tape grows, or the error is reported
+    /// at the instruction that went too far, as it would have been. This is the same for each
+    /// of the loop's ops, so none of them have been run when it isn't.
+    fn replaced_loop_fits(&self, ir: &bft_types::BfIr, index: usize) -> bool {
+        if self.machine.tape.get(self.machine.data_pointer).is_zero() {
+            return true;
+        }
+        let source_map = ir.source_map();
+        let source = source_map[index];
+        let start = source_map[..index]
+            .iter()
+            .rposition(|&op_source| op_source != source)
+            .map_or(0, |before| before + 1);
+        ir.ops()[start..]
+            .iter()
+            .zip(&source_map[start..])
+            .take_while(|&(_, &op_source)| op_source == source)
+            .all(|(op, _)| match *op {
+                bft_types::BfOp::MulAdd { offset, .. } => self
+                    .machine
+                    .data_pointer
+                    .checked_add_signed(offset)
+                    .is_some_and(|cell| cell < self.machine.length),
+                _ => true,
+            })
+    }
+
+    /// Move the data pointer a number of cells, a negative number moves it back. If the move
+    /// goes off the end of the tape, it is made a cell at a time so that the tape grows, or
+    /// the error is reported at the instruction that went too far, as it would have been.
+    fn move_data_pointer_by(&mut self, amount: isize) -> Result<(), BfError> {
+        let target = self.machine.data_pointer.checked_add_signed(amount);
+        if let Some(target) = target.filter(|&target| target < self.machine.length) {
+            self.machine.data_pointer = target;
+            self.machine.high_water = self.machine.high_water.max(target);
+            return Ok(());
+        }
+        let source = self.machine.program_pointer;
+        for count in 0..amount.unsigned_abs() {
+            self.machine.program_pointer = source + count;
+            if amount > 0 {
+                self.move_data_pointer_forward()?;
+            } else {
+                self.move_data_pointer_back()?;
+            }
+        }
+        self.machine.program_pointer = source;
+        Ok(())
+    }
+}
+
+/// Asynchronous interpreter, enabled by the `async` feature
+///
+#[cfg(feature = "async")]
+impl<'a, T: std::fmt::Debug + CellKind + std::clone::Clone + std::default::Default> BfTape<'a, T> {
+    /// The interpreter of a Brain Fuck program with the input and output awaited rather than
+    /// blocking, for use inside an async runtime. Apart from the input and output everything
+    /// is the same as `interpreter`.
+    ///
+    /// The future can be dropped at any point, for example by a timeout. If it is dropped
+    /// while waiting for input or output, the program pointer is left at that instruction and
+    /// calling the interpreter again finishes it, without losing any input already read or
+    /// repeating any output already written. One byte is read at a time, so wrap the reader
+    /// in a `tokio::io::BufReader` to avoid a system call per byte.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", ",+.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> =
+    ///                             bft_interp::BfTapeBuilder::new().tape_size(100).output_format(cli::OutputFormat::BinaryOutput).build(&program);
+    ///     let mut reader: &[u8] = &[41];
+    ///     let mut writer = Vec::new();
+    ///     let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    ///     assert!(runtime.block_on(tape.interpreter_async(&mut reader, &mut writer)).is_ok());
+    ///     assert_eq!(writer, b"42");
+    /// ```
+    pub async fn interpreter_async<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<StopReason<T>, BfError> {
+        self.debug_listing();
+
+        let result = match (self.debug_enabled(), self.command_timings.is_some()) {
+            (false, false) => self.run_async::<false, false, R, W>(reader, writer).await,
+            (false, true) => self.run_async::<false, true, R, W>(reader, writer).await,
+            (true, false) => self.run_async::<true, false, R, W>(reader, writer).await,
+            (true, true) => self.run_async::<true, true, R, W>(reader, writer).await,
+        };
+        let result = match result {
+            Ok(StopReason::Halted) => {
+                let mut output = VecIo::default();
+                match self.finish_output(&mut output) {
+                    Ok(()) => tokio::io::AsyncWriteExt::write_all(writer, output.output())
+                        .await
+                        .map(|_| StopReason::Halted)
+                        .map_err(|e| BfError::IOError {
+                            error_msg: e,
+                            filepath: self.program.filename().to_path_buf(),
+                            instruction: self.error_instruction(self.machine.program_pointer),
+                            snippet: self
+                                .snippet(self.error_instruction(self.machine.program_pointer)),
+                            program_pointer: self.program.instructions().len().saturating_sub(1),
+                        }),
+                    Err(e) => Err(e),
+                }
+            }
+            other => other,
+        };
+
+        // The output is flushed however the program stopped
+        let flushed = tokio::io::AsyncWriteExt::flush(writer).await;
+        self.finish_run(result, flushed)
+    }
+
+    /// Execute the program until it stops or there is an error, awaiting input and output
+    async fn run_async<
+        const DEBUG: bool,
+        const TIMED: bool,
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    >(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<StopReason<T>, BfError> {
+        self.check_validated()?;
+        self.start_clock();
+        let step_limit = self.step_limit.unwrap_or(u64::MAX);
+
+        while self.machine.program_pointer != self.program.instructions().len() {
+            let inst = self.program.instructions()[self.machine.program_pointer];
+            // An instruction that was interrupted by the future being dropped has already
+            // been started, so it is carried on with rather than started again
+            if !self.async_io.started {
+                self.check_can_continue(step_limit)?;
+                if let Some(reason) = self.begin_step::<DEBUG>(&inst) {
+                    return Ok(reason);
+                }
+            }
+            let program_pointer = self.machine.program_pointer;
+            // An instruction carried on with after the future was dropped is only timed from
+            // when it carried on
+            let started = TIMED.then(Instant::now);
+            self.machine.program_pointer = match inst.command() {
+                bft_types::BfCommand::OutputValue => {
+                    self.async_io.started = true;
+                    self.output_async(writer).await?;
+                    program_pointer + 1
+                }
+                bft_types::BfCommand::InputValue => {
+                    self.async_io.started = true;
+                    self.input_async(reader).await?;
+                    program_pointer + 1
+                }
+                command => self.execute_command(command.into(), self.jump())?,
+            };
+            self.async_io.started = false;
+            self.time_command(started, &inst);
+            if let Some(reason) = self.end_step::<DEBUG>(program_pointer, &inst)? {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::Halted)
+    }
+
+    /// Input a value into the current cell, reading a byte at a time until there is enough
+    /// input for the input format. The bytes read are kept in the tape until they are used.
+    async fn input_async<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> Result<(), BfError> {
+        loop {
+            // The input is taken out of the tape while it is used, there is no await until
+            // it is put back
+            let input = std::mem::take(&mut self.async_io.input);
+            // Input given back by step_back, and noted for the history, is also put back if
+            // there isn't enough input yet
+            let pushed_back = self.pushed_back_input.clone();
+            let noted = self
+                .undo_pending
+                .as_ref()
+                .map_or(0, |record| record.input.len());
+            // As is input that was replayed or recorded
+            let replay_position = self.replay_position;
+            let recorded = self.recorded_input.as_ref().map_or(0, Vec::len);
+            let mut pending = PendingInput {
+                input: &input,
+                eof: self.async_io.eof,
+                position: 0,
+            };
+            let result = self.input_io(&mut pending);
+            let used = pending.position;
+            self.async_io.input = input;
+            match result {
+                Ok(()) => {
+                    self.async_io.input.drain(..used);
+                    self.async_io.eof = false;
+                    return Ok(());
+                }
+                Err(BfError::IOError { error_msg, .. })
+                    if error_msg.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    self.pushed_back_input = pushed_back;
+                    if let Some(record) = &mut self.undo_pending {
+                        record.input.truncate(noted);
+                    }
+                    self.replay_position = replay_position;
+                    if let Some(input) = &mut self.recorded_input {
+                        input.truncate(recorded);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+
+            let mut data = [0; 1];
+            match tokio::io::AsyncReadExt::read(reader, &mut data).await {
+                Ok(0) => self.async_io.eof = true,
+                Ok(_) => self.async_io.input.push(data[0]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.io_error(e)),
+            }
+        }
+    }
+
+    /// Output the value of the current cell. The output is kept in the tape until it has all
+    /// been written and flushed.
+    async fn output_async<W: AsyncWrite + Unpin>(&mut self, writer: &mut W) -> Result<(), BfError> {
+        if self.async_io.output.is_none() {
+            let mut output = VecIo::default();
+            self.output_io(&mut output)?;
+            self.async_io.output = Some(output.into_output());
+        }
+        loop {
+            let output = match &self.async_io.output {
+                Some(output) if !output.is_empty() => output,
+                _ => break,
+            };
+            match tokio::io::AsyncWriteExt::write(writer, output).await {
+                Ok(0) => return Err(self.io_error(std::io::ErrorKind::WriteZero.into())),
+                Ok(written) => {
+                    if let Some(output) = &mut self.async_io.output {
+                        output.drain(..written);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.io_error(e)),
+            }
+        }
+        // Interactive programs need their prompts to appear before any input is read
+        if self.auto_flush {
+            tokio::io::AsyncWriteExt::flush(writer)
+                .await
+                .map_err(|e| self.io_error(e))?;
+        }
+        self.async_io.output = None;
+        Ok(())
+    }
+}
+
+/// Builder for a BfTape, so that only the options which differ from the defaults need to be
+/// given. The defaults are a fixed tape of 30,000 cells with ASCII output and raw input, cells
+/// that wrap, -1 at end of file, no debug, no step limit and a dense tape.
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"tiny.bf", "+.").unwrap();
+///     let mut tape: bft_interp::BfTape<u16> = bft_interp::BfTapeBuilder::new()
+///         .tape_size(100)
+///         .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+///         .output_format(cli::OutputFormat::BinaryOutput)
+///         .step_limit(Some(1000))
+///         .build(&program);
+///     assert_eq!(tape.data_length(), 100);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BfTapeBuilder {
+    tape_size: usize,
+    alloc_strategy: cli::AllocStrategy,
+    output_format: cli::OutputFormat,
+    input_format: cli::InputFormat,
+    eof_behavior: cli::EofBehavior,
+    overflow_behavior: cli::OverflowBehavior,
+    trailing_newline: cli::TrailingNewline,
+    debug: cli::DebugLevelType,
+    step_limit: Option<u64>,
+    time_limit: Option<Duration>,
+    loop_guard: Option<u64>,
+    auto_flush: bool,
+    sparse: bool,
+}
+
+impl Default for BfTapeBuilder {
+    /// Default instance of BfTapeBuilder as recommended by Clippy
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BfTapeBuilder {
+    /// Create a builder with the default options
+    pub fn new() -> Self {
+        Self {
+            tape_size: DEFAULT_TAPE_SIZE,
+            alloc_strategy: cli::AllocStrategy::TapeIsFixed,
+            output_format: cli::OutputFormat::AsciiOutput,
+            input_format: cli::InputFormat::RawInput,
+            eof_behavior: cli::EofBehavior::NegOne,
+            overflow_behavior: cli::OverflowBehavior::Wrap,
+            trailing_newline: cli::TrailingNewline::Never,
+            debug: cli::DebugLevelType::None,
+            step_limit: None,
+            time_limit: None,
+            loop_guard: None,
+            auto_flush: false,
+            sparse: false,
+        }
+    }
+
+    /// Number of cells the tape starts with. Zero means the default of 30,000.
+    pub fn tape_size(mut self, tape_size: usize) -> Self {
+        self.tape_size = tape_size;
+        self
+    }
+
+    /// Whether the tape can grow or is fixed, and how far it can grow
+    pub fn alloc_strategy(mut self, alloc_strategy: cli::AllocStrategy) -> Self {
+        self.alloc_strategy = alloc_strategy;
+        self
+    }
+
+    /// How values are output
+    pub fn output_format(mut self, output_format: cli::OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// How values are input
+    pub fn input_format(mut self, input_format: cli::InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    /// What is stored in the cell when the input reaches end of file
+    pub fn eof_behavior(mut self, eof_behavior: cli::EofBehavior) -> Self {
+        self.eof_behavior = eof_behavior;
+        self
+    }
+
+    /// What happens when a cell is incremented or decremented beyond its limits
+    pub fn overflow_behavior(mut self, overflow_behavior: cli::OverflowBehavior) -> Self {
+        self.overflow_behavior = overflow_behavior;
+        self
+    }
+
+    /// Whether a newline is output when the program finishes
+    pub fn trailing_newline(mut self, trailing_newline: cli::TrailingNewline) -> Self {
+        self.trailing_newline = trailing_newline;
+        self
+    }
+
+    /// Amount of debug output
+    pub fn debug(mut self, debug: cli::DebugLevelType) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Most instructions the program can execute, None for no limit
+    pub fn step_limit(mut self, step_limit: Option<u64>) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    /// Longest the program can run for, None for no limit, see BfTape::set_time_limit
+    pub fn time_limit(mut self, time_limit: Option<Duration>) -> Self {
+        self.time_limit = time_limit;
+        self
+    }
+
+    /// Times in a row a loop can go round unchanged, see BfTape::set_loop_guard
+    pub fn loop_guard(mut self, loop_guard: Option<u64>) -> Self {
+        self.loop_guard = loop_guard;
+        self
+    }
+
+    /// Whether the output is flushed after every output command
+    pub fn auto_flush(mut self, auto_flush: bool) -> Self {
+        self.auto_flush = auto_flush;
+        self
+    }
+
+    /// Whether only the cells that aren't zero are stored, see BfTape::new_sparse
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Create the tape for the program
+    ///
+    /// # Panics
+    ///
+    /// If there isn't enough memory for the tape, use try_build to get an error instead.
+    pub fn build<'a, T: CellKind>(&self, program: impl Into<ProgramRef<'a>>) -> BfTape<'a, T> {
+        match self.try_build(program) {
+            Ok(tape) => tape,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Create the tape for the program, or a TapeAllocationFailed error if there isn't enough
+    /// memory for it
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"tiny.bf", "+").unwrap();
+    ///     let builder = bft_interp::BfTapeBuilder::new().tape_size(usize::MAX);
+    ///     assert!(builder.try_build::<u8>(&program).is_err());
+    ///     assert!(builder.sparse(true).try_build::<u8>(&program).is_ok());
+    /// ```
+    pub fn try_build<'a, T: CellKind>(
+        &self,
+        program: impl Into<ProgramRef<'a>>,
+    ) -> Result<BfTape<'a, T>, BfError> {
+        let mut tape = if self.sparse {
+            BfTape::new_sparse(
+                program,
+                self.tape_size,
+                self.alloc_strategy,
+                self.output_format,
+            )
+        } else {
+            BfTape::try_new(
+                program,
+                (self.tape_size != 0).then_some(self.tape_size),
+                self.alloc_strategy,
+                self.output_format,
+            )?
+        };
+        tape.set_input_format(self.input_format);
+        tape.set_eof_behavior(self.eof_behavior);
+        tape.set_overflow_behavior(self.overflow_behavior);
+        tape.set_trailing_newline(self.trailing_newline);
+        tape.set_debug(self.debug);
+        tape.set_step_limit(self.step_limit);
+        tape.set_time_limit(self.time_limit);
+        tape.set_loop_guard(self.loop_guard);
+        tape.set_auto_flush(self.auto_flush);
+        Ok(tape)
+    }
+}
+
+impl<'a, T: CellKind> std::ops::Index<usize> for BfTape<'a, T> {
+    type Output = T;
+
+    /// The cell at an index, without moving the data pointer. As with a slice, an index beyond
+    /// the cells in use panics, get_cell_at returns an error instead.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"cells.bf", "+>++").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().tape_size(3).build(&program);
+    ///     tape.interpreter(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+    ///     assert_eq!((tape[0], tape[1], tape[2]), (1, 2, 0));
+    /// ```
+    fn index(&self, index: usize) -> &T {
+        assert!(
+            index < self.machine.length,
+            "index out of bounds: the tape has {} cells but the index is {}",
+            self.machine.length,
+            index
+        );
+        self.machine.tape.get_ref(index, &self.zero)
+    }
+}
+
+impl<'a, T: CellKind> std::ops::IndexMut<usize> for BfTape<'a, T> {
+    /// The cell at an index to be changed, without moving the data pointer. As with a slice,
+    /// an index beyond the cells in use panics, set_cell_at returns an error instead and grows
+    /// a tape that can grow.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let program = bft_types::BfProgram::new(&"cells.bf", ">.").unwrap();
+    ///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().tape_size(3).build(&program);
+    ///     tape[1] = b'A';
+    ///     let mut output = Vec::new();
+    ///     tape.interpreter(&mut std::io::empty(), &mut output).unwrap();
+    ///     assert_eq!(output, b"A");
+    /// ```
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        assert!(
+            index < self.machine.length,
+            "index out of bounds: the tape has {} cells but the index is {}",
+            self.machine.length,
+            index
+        );
+        self.machine.tape.get_mut(index)
+    }
+}
+
+impl<'a, T: CellKind> std::fmt::Display for BfTape<'a, T> {
+    /// Format the cells around the data pointer for display and human consumption. The
+    /// precision sets how many cells either side are shown, e.g. `{:.3}`, otherwise 5 are.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.render_window(f.precision().unwrap_or(DISPLAY_RADIUS))
+        )
+    }
+}
+
+/// Name given to programs run from source by `run_with_io` and `run_to_string`
+const SOURCE_FILENAME: &str = "<source>";
+
+/// Parse and validate a program's source
+pub(crate) fn parse_source(program_source: &str) -> Result<BfProgram, BfError> {
+    let mut program =
+        BfProgram::new(SOURCE_FILENAME, program_source).map_err(|e| BfError::InvalidProgram {
+            error_msg: e.into(),
+        })?;
+    program
+        .validate()
+        .map_err(|e| BfError::InvalidProgram { error_msg: e })?;
+    Ok(program)
+}
+
+/// Open a file of input for a program, to be given as the reader to the interpreter in place
+/// of stdin. The file is buffered since the input is read a byte at a time. Once the program
+/// has read all of the file it sees the end of file, and the tape's EOF behaviour applies.
+///
+/// Example usage:
+/// ```
+///     let error = bft_interp::open_input("no-such-input.txt").unwrap_err();
+///     assert_eq!(error.code(), "E_INPUT_FILE");
+///     assert!(error.to_string().contains("no-such-input.txt"));
+/// ```
+pub fn open_input(path: impl AsRef<std::path::Path>) -> Result<BufReader<std::fs::File>, BfError> {
+    let path = path.as_ref();
+    std::fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| BfError::InputFileError {
+            error_msg: e,
+            path: path.to_path_buf(),
+        })
+}
+
+/// Read a program from where the command line says it comes from. A program given on the
+/// command line is named cli::INLINE_PROGRAM_NAME, and one read from stdin
+/// cli::STDIN_PROGRAM_NAME, so that is what errors in them show in place of a filename. The
+/// program isn't validated.
+///
+/// Example usage:
+/// ```
+///     let source = cli::ProgramSource::Inline("+[-]".to_string());
+///     let program = bft_interp::load_program(&source).unwrap();
+///     assert_eq!(program.filename(), std::path::Path::new("<eval>"));
+///     assert_eq!(program.instructions().len(), 4);
+/// ```
+pub fn load_program(source: &cli::ProgramSource) -> std::io::Result<BfProgram> {
+    match source {
+        cli::ProgramSource::File(path) => BfProgram::from_file(path),
+        cli::ProgramSource::Inline(code) => BfProgram::new(cli::INLINE_PROGRAM_NAME, code),
+        cli::ProgramSource::Stdin => {
+            BfProgram::from_reader(cli::STDIN_PROGRAM_NAME, std::io::stdin().lock())
+        }
+    }
+}
+
+/// A file that a program's output is written to in place of stdout. The file is created, or
+/// truncated if it exists, and the output is buffered until the file is finished. Errors
+/// writing the file include its path, so that they aren't mistaken for errors in the program.
+///
+/// Example usage:
+/// ```
+///     let path = std::env::temp_dir().join("bft-output-example.txt");
+///     let mut file = bft_interp::OutputFile::create(&path).unwrap();
+///     let program = bft_types::BfProgram::new(&"tiny.bf", "+++.").unwrap();
+///     let mut tape: bft_interp::BfTape<u8> =
+///         bft_interp::BfTapeBuilder::new().output_format(cli::OutputFormat::BinaryOutput).build(&program);
+///     tape.interpreter(&mut std::io::empty(), &mut file).unwrap();
+///     file.finish(false).unwrap();
+///     assert_eq!(std::fs::read_to_string(&path).unwrap(), "3");
+///     # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct OutputFile {
+    writer: BufWriter<std::fs::File>,
+    path: std::path::PathBuf,
+}
+
+impl OutputFile {
+    /// Create the file, truncating it if it exists
+    pub fn create(path: impl AsRef<std::path::Path>) -> Result<Self, BfError> {
+        let path = path.as_ref().to_path_buf();
+        match std::fs::File::create(&path) {
+            Ok(file) => Ok(Self {
+                writer: BufWriter::new(file),
+                path,
+            }),
+            Err(e) => Err(BfError::OutputFileError { error_msg: e, path }),
+        }
+    }
+
+    /// The path of the file
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Write out anything still buffered, and if sync is set wait for the file to reach the
+    /// disk, so that an error doing so isn't lost when the file is dropped
+    pub fn finish(mut self, sync: bool) -> Result<(), BfError> {
+        let result = match self.writer.flush() {
+            Ok(()) if sync => self.writer.get_ref().sync_all(),
+            result => result,
+        };
+        result.map_err(|e| BfError::OutputFileError {
+            error_msg: e,
+            path: self.path,
+        })
+    }
+
+    /// Add the path of the file to an error writing it, keeping the error as its source
+    fn with_path(&self, error: std::io::Error) -> std::io::Error {
+        std::io::Error::new(
+            error.kind(),
+            PathError {
+                path: self.path.clone(),
+                source: error,
+            },
+        )
+    }
+}
+
+/// An error writing an OutputFile, with the path of the file
+#[derive(Error, Debug)]
+#[error("writing {}: {source}", path.display())]
+struct PathError {
+    path: std::path::PathBuf,
+    source: std::io::Error,
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf).map_err(|e| self.with_path(e))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush().map_err(|e| self.with_path(e))
+    }
+}
+
+/// Parse, validate and run a program, with the tape set up by the config. The input is given
+/// to the program and everything that it outputs is returned.
+///
+/// Example usage:
+/// ```
+///     let config = bft_interp::BfTapeBuilder::new();
+///     assert_eq!(bft_interp::run_with_io(",+.", b"A", config).unwrap(), b"B");
+/// ```
+pub fn run_with_io(
+    program_source: &str,
+    input: &[u8],
+    config: BfTapeBuilder,
+) -> Result<Vec<u8>, BfError> {
+    let program = parse_source(program_source)?;
+    let mut tape: BfTape<u8> = config.build(&program);
+    let mut io = StdIo::new(input, Vec::new());
+    tape.interpreter_io(&mut io)?;
+    Ok(io.into_inner().1)
+}
+
+/// Parse, validate and run a program as with `run_with_io`, with the output decoded as UTF-8.
+/// Any invalid UTF-8 is replaced with the replacement character.
+///
+/// Example usage:
+/// ```
+///     let config = bft_interp::BfTapeBuilder::new().output_format(cli::OutputFormat::BinaryOutput);
+///     assert_eq!(bft_interp::run_to_string("+.+.", b"", config).unwrap(), "1,2");
+/// ```
+pub fn run_to_string(
+    program_source: &str,
+    input: &[u8],
+    config: BfTapeBuilder,
+) -> Result<String, BfError> {
+    let output = run_with_io(program_source, input, config)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Iterator over the output of a program, see `BfTape::outputs`
+struct Outputs<'a, T: CellKind, R: Read> {
+    tape: BfTape<'a, T>,
+    io: StdIo<R, VecDeque<u8>>,
+    finished: bool,
+}
+
+impl<T: std::fmt::Debug + CellKind + std::clone::Clone + std::default::Default, R: Read> Iterator
+    for Outputs<'_, T, R>
+{
+    type Item = Result<u8, BfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(byte) = self.io.writer.pop_front() {
+                return Some(Ok(byte));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let result =
+                if self.tape.machine.program_pointer == self.tape.program.instructions().len() {
+                    Ok(Some(StopReason::Halted))
+                } else {
+                    self.tape.step_io(&mut self.io)
+                };
+            match result {
+                Ok(Some(StopReason::Halted)) => {
+                    self.finished = true;
+                    if let Err(e) = self.tape.finish_output(&mut self.io) {
+                        return Some(Err(e));
+                    }
+                }
+                Ok(Some(StopReason::Aborted { .. })) => self.finished = true,
+                Ok(_) => {}
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Controls a program started by `spawn`. The control can be cloned and used from any thread.
+///
+#[derive(Clone, Debug)]
+pub struct RunControl {
+    paused: Arc<AtomicBool>,
+    aborted: Arc<AtomicBool>,
+    worker: std::thread::Thread,
+}
+
+impl RunControl {
+    /// Pause the program before its next instruction
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Carry on with a paused program
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+        self.worker.unpark();
+    }
+
+    /// Stop the program, whether it is running, paused or waiting for input. Joining the
+    /// program then returns an Interrupted error.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+        self.worker.unpark();
+    }
+
+    /// Whether the program has been asked to pause
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+/// A program running on its own thread, see `spawn`.
+///
+#[derive(Debug)]
+pub struct RunningProgram {
+    input: Option<mpsc::Sender<u8>>,
+    output: mpsc::Receiver<u8>,
+    control: RunControl,
+    handle: JoinHandle<Result<RunStats, BfError>>,
+}
+
+impl RunningProgram {
+    /// Sender for bytes to be input to the program. None once the input has been closed.
+    pub fn input(&self) -> Option<&mpsc::Sender<u8>> {
+        self.input.as_ref()
+    }
+
+    /// Close the input so that the program sees end of file once it has used the bytes
+    /// already sent. Any clones of the input sender must also be dropped.
+    pub fn close_input(&mut self) {
+        self.input = None;
+    }
+
+    /// Receiver for the bytes output by the program
+    pub fn output(&self) -> &mpsc::Receiver<u8> {
+        &self.output
+    }
+
+    /// Handle to pause, resume or abort the program
+    pub fn control(&self) -> RunControl {
+        self.control.clone()
+    }
+
+    /// Wait for the program to finish. This waits forever if the program is waiting for
+    /// input and the input hasn't been closed.
+    pub fn join(self) -> Result<RunStats, BfError> {
+        drop(self.input);
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// BfIo for a spawned program, taking input from and sending output to channels.
+struct ChannelIo {
+    input: mpsc::Receiver<u8>,
+    output: mpsc::Sender<u8>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl BfIo for ChannelIo {
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        // An aborted program is given end of file so that it stops at the next instruction
+        loop {
+            match self.input.recv_timeout(INPUT_POLL_INTERVAL) {
+                Ok(byte) => return Ok(Some(byte)),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if self.aborted.load(Ordering::Relaxed) {
+                        return Ok(None);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> std::io::Result<()> {
+        self.output
+            .send(byte)
+            .map_err(|_| std::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+/// Run a program on its own thread, with the tape set up by the config. The input and output
+/// are sent over channels, so a program waiting for input only blocks its own thread.
+///
+/// Example usage:
+/// ```
+///     let mut program = bft_types::BfProgram::new(&"cat.bf", ",[.,]").unwrap();
+///     program.validate().unwrap();
+///     let config = bft_interp::BfTapeBuilder::new().eof_behavior(cli::EofBehavior::Zero);
+///     let mut running = bft_interp::spawn(program, config);
+///     running.input().unwrap().send(b'A').unwrap();
+///     assert_eq!(running.output().recv().unwrap(), b'A');
+///     running.close_input();
+///     assert!(running.join().is_ok());
+/// ```
+pub fn spawn(program: impl Into<Arc<BfProgram>>, config: BfTapeBuilder) -> RunningProgram {
+    let program = program.into();
+    let (input, input_receiver) = mpsc::channel();
+    let (output_sender, output) = mpsc::channel();
+    let paused = Arc::new(AtomicBool::new(false));
+    let aborted = Arc::new(AtomicBool::new(false));
+
+    let worker_paused = Arc::clone(&paused);
+    let worker_aborted = Arc::clone(&aborted);
+    let handle = std::thread::spawn(move || {
+        let mut tape: OwnedBfTape<u8> = config.build(program);
+        tape.set_interrupt_flag(Arc::clone(&worker_aborted));
+        let hook_paused = Arc::clone(&worker_paused);
+        tape.set_hook(move |_| {
+            if hook_paused.load(Ordering::Relaxed) {
+                HookAction::Pause
+            } else {
+                HookAction::Continue
+            }
+        });
+        let mut io = ChannelIo {
+            input: input_receiver,
+            output: output_sender,
+            aborted: Arc::clone(&worker_aborted),
+        };
+        loop {
+            match tape.interpreter_io(&mut io)? {
+                StopReason::Paused { .. } => {
+                    while worker_paused.load(Ordering::Relaxed)
+                        && !worker_aborted.load(Ordering::Relaxed)
+                    {
+                        std::thread::park();
+                    }
+                }
+                _ => return Ok(tape.run_stats()),
+            }
+        }
+    });
+
+    let control = RunControl {
+        paused,
+        aborted,
+        worker: handle.thread().clone(),
+    };
+    RunningProgram {
+        input: Some(input),
+        output,
+        control,
+        handle,
+    }
+}
+
+// Tapes are sent to other threads by spawn, so fail to compile if a change stops them being Send
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<BfTape<'static, u8>>();
+    assert_send::<BfTape<'static, u16>>();
+    assert_send::<BfTape<'static, u32>>();
+    assert_send::<BfTape<'static, u64>>();
+    assert_send::<BfTapeBuilder>();
+    assert_send::<RunStats>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::TAPE_GROWTH_CHUNK;
+    use crate::VecIo;
+
+    /// Test that specifying zero for the size creates a tape of the default size.
+    #[test]
+    fn new_default_size() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let tape: BfTape<u8> = BfTape::new(
+            &program,
+            0,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert_eq!(tape.data_length(), DEFAULT_TAPE_SIZE);
+    }
+
+    /// Test for a valid size of the normal base type.
+    #[test]
+    fn new_size_of_10000() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let tape: BfTape<u8> = BfTape::new(
+            &program,
+            10000,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert_eq!(tape.data_length(), 10000);
+    }
+
+    /// Test that an error is raised when moving the data pointer before the start of the tape
+    #[test]
+    fn data_pointer_moved_before_start() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.reset_data_pointer();
+        // Now move the before the beginning of the tape
+        let result = tape.move_data_pointer_back();
+        assert!(result.is_err());
+    }
+
+    /// Test that an error is raised when moving the data pointer after the end of the tape
+    #[test]
+    fn data_pointer_moved_after_end() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.reset_data_pointer();
+        for i in 0..99 {
+            if tape.move_data_pointer_forward().is_err() {
+                panic!("The tape should have 100 cells {}", i);
+            }
+        }
+        // Now move past the end of the tape
+        let result = tape.move_data_pointer_forward();
+        assert!(result.is_err());
+    }
+
+    /// Test that the tape is extended when moving the data pointer after the end of the tape
+    #[test]
+    fn data_pointer_moved_after_end_and_can_grow() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeCanGrow,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.reset_data_pointer();
+        for i in 0..99 {
+            if tape.move_data_pointer_forward().is_err() {
+                panic!("The tape should have 100 cells {}", i);
+            }
+        }
+        // Now move past the end of the tape
+        let result = tape.move_data_pointer_forward();
+        assert!(result.is_ok());
+
+        // Check that the data pointer is on a new cell which is zero
+        assert_eq!(tape.data_pointer(), 100);
+        assert_eq!(tape[100], 0);
+        assert_eq!(tape.data_length(), 101);
+    }
+
+    /// Test that no error is raised when moving the data pointer normally
+    #[test]
+    fn data_pointer_moved_normally() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.reset_data_pointer();
+
+        // Move data pointer to end
+        for _ in 0..99 {
+            let result = tape.move_data_pointer_forward();
+            assert!(result.is_ok());
+        }
+        assert_eq!(tape.data_pointer(), 99);
+
+        // Move data pointer back to beginning
+        for _ in 0..99 {
+            let result = tape.move_data_pointer_back();
+            assert!(result.is_ok());
+        }
+        assert_eq!(tape.data_pointer(), 0);
+    }
+
+    /// Test that the value in a cell is set
+    #[test]
+    fn set_cell_value() {
+        let program = BfProgram::new("tiny.bf", "+-").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.reset_data_pointer();
+        tape.set_data_value(55);
+
+        assert_eq!(tape.get_data_value(), 55);
+    }
+
+    /// Test that the value in a cell is incremented. Also checks that data value can be read.
+    #[test]
+    fn increment_cell_value() {
+        let program = BfProgram::new("increment.bf", "+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.reset_data_pointer();
+        tape.increment_data_value().unwrap();
+
+        // Check that the initial value of zero has been incremented to one
+        assert_eq!(tape.get_data_value(), 1);
+    }
+
+    /// Test that the value in a cell is incremented. Also checks that data value can be read.
+    #[test]
+    fn decrement_cell_value() {
+        let program = BfProgram::new("decrement.bf", "-").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.reset_data_pointer();
+        tape.decrement_data_value().unwrap();
+
+        // Check that the initial value of zero has been decremented and wrapped around to 255 (the max in a u8)
+        assert_eq!(tape.get_data_value(), 255);
+    }
+
+    /// Test that output works
+    #[test]
+    fn output_cell_value_as_binary() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+
+        // Create a writer as a sink for the value that is being output
+        let mut writer = std::io::Cursor::new(Vec::new());
+
+        // Output the value from the current cell in the tape to the writer
+        assert!(tape.output_value(&mut writer).is_ok());
+
+        // Check that the value was output correctly, as "0"
+        assert_eq!(writer.into_inner()[0], 48);
+    }
+
+    /// Test that output as ascii works
+    #[test]
+    fn output_cell_value_as_ascii() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::AsciiOutput,
+        );
+
+        // Create a writer as a sink for the value that is being output
+        let mut writer = std::io::Cursor::new(Vec::new());
+
+        // Output the value from the current cell in the tape to the writer
+        assert!(tape.output_value(&mut writer).is_ok());
+
+        // Check that the value was output correctly, as 0 (null character)
+        assert_eq!(writer.into_inner()[0], 0);
+    }
+
+    /// Test that input  works
+    #[test]
+    fn input_cell_value() {
+        let program = BfProgram::new("tiny.bf", "><+-.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+
+        // Create a reader as a source for the value that is being input
+        let mut reader = std::io::Cursor::new(vec![55, 11, 22]);
+
+        // Input the value into the current cell in the tape
+        tape.reset_data_pointer();
+        assert!(tape.input_value(&mut reader).is_ok());
+        assert!(tape.move_data_pointer_forward().is_ok());
+        assert!(tape.input_value(&mut reader).is_ok());
+        assert!(tape.move_data_pointer_forward().is_ok());
+        assert!(tape.input_value(&mut reader).is_ok());
+
+        // Check that the values were written
+        assert_eq!([tape[0], tape[1], tape[2]], [55, 11, 22]);
+    }
+
+    /// Test that an error is raised when moving the program pointer past the end of the program
+    #[test]
+    fn program_pointer_moved_after_end() {
+        let program = BfProgram::new("tiny.bf", "><").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+
+        // Check that program is only two instructions
+        assert_eq!(tape.program.instructions().len(), 2);
+
+        // Move program pointer past end of program
+        let result = tape.move_program_pointer_forward();
+        assert!(result.is_ok());
+        let result = tape.move_program_pointer_forward();
+        assert!(result.is_err());
+    }
+
+    /// Test that an infinite loop is stopped once the step limit is reached
+    #[test]
+    fn step_limit_stops_infinite_loop() {
+        let mut program = BfProgram::new("forever.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_step_limit(Some(1000));
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        match tape.interpreter(&mut reader, &mut writer) {
+            Err(BfError::StepLimitExceeded { steps, .. }) => assert_eq!(steps, 1000),
+            _ => panic!("Expected the step limit to be exceeded"),
+        }
+    }
+
+    /// Test that stepping through an infinite loop is stopped once the step limit is reached
+    #[test]
+    fn step_limit_stops_stepping() {
+        let mut program = BfProgram::new("forever.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().step_limit(Some(10)).build(&program);
+
+        let mut io = VecIo::new(Vec::new());
+        for _ in 0..10 {
+            assert_eq!(tape.step_io(&mut io).unwrap(), None);
+        }
+        match tape.step_io(&mut io) {
+            Err(BfError::StepLimitExceeded { steps, .. }) => assert_eq!(steps, 10),
+            _ => panic!("Expected the step limit to be exceeded"),
+        }
+        assert_eq!(tape.steps(), 10);
+    }
+
+    /// Test that a program which finishes within the step limit is unaffected by it
+    #[test]
+    fn step_limit_not_reached() {
+        let mut program = BfProgram::new("loop.bf", "+++[-]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_step_limit(Some(1000));
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+    }
+
+    /// Test that a program stops straight away if the interrupt flag is already set
+    #[test]
+    fn interrupt_flag_set_before_run() {
+        let mut program = BfProgram::new("forever.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_interrupt_flag(Arc::new(AtomicBool::new(true)));
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        match tape.interpreter(&mut reader, &mut writer) {
+            Err(BfError::Interrupted {
+                program_pointer, ..
+            }) => assert_eq!(program_pointer, 0),
+            _ => panic!("Expected the program to be interrupted"),
+        }
+        assert_eq!(tape.steps(), 0);
+    }
+
+    /// Test that a running infinite loop stops promptly when the interrupt flag is set
+    #[test]
+    fn interrupt_flag_stops_running_program() {
+        let mut program = BfProgram::new("forever.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let flag = Arc::new(AtomicBool::new(false));
+        tape.set_interrupt_flag(Arc::clone(&flag));
+
+        let setter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        let result = tape.interpreter(&mut reader, &mut writer);
+        setter.join().unwrap();
+        assert!(matches!(result, Err(BfError::Interrupted { .. })));
+        // The loop is the 2nd and 3rd instructions
+        assert!(tape.program_pointer() == 1 || tape.program_pointer() == 2);
+    }
+
+    /// Test that changing a watched cell stops the program
+    #[test]
+    fn watchpoint_stops_program() {
+        let program = BfProgram::new("watch.bf", ">>+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.add_watchpoint(2).unwrap();
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert_eq!(
+            tape.interpreter(&mut reader, &mut writer).unwrap(),
+            StopReason::Watchpoint {
+                cell: 2,
+                old: 0,
+                new: 1,
+                program_pointer: 2
+            }
+        );
+
+        // Carrying on runs the program to the end
+        assert_eq!(
+            tape.interpreter(&mut reader, &mut writer).unwrap(),
+            StopReason::Halted
+        );
+    }
+
+    /// Test that a watchpoint is reported by the step method and only when the cell changes
+    #[test]
+    fn watchpoint_stops_step() {
+        let program = BfProgram::new("watch.bf", "+>+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.add_watchpoint(1).unwrap();
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert_eq!(tape.step(&mut reader, &mut writer).unwrap(), None);
+        assert_eq!(tape.step(&mut reader, &mut writer).unwrap(), None);
+        assert!(matches!(
+            tape.step(&mut reader, &mut writer).unwrap(),
+            Some(StopReason::Watchpoint { cell: 1, .. })
+        ));
+        assert_eq!(
+            tape.step(&mut reader, &mut writer).unwrap(),
+            Some(StopReason::Halted)
+        );
+    }
+
+    /// Test watching a cell beyond the end of the tape
+    #[test]
+    fn watchpoint_beyond_end_of_tape() {
+        let program = BfProgram::new("watch.bf", ">>>+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            2,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert!(matches!(
+            tape.add_watchpoint(3),
+            Err(BfError::CellOutOfRange { cell: 3, length: 2 })
+        ));
+
+        // Tape that can grow arms the watchpoint once the cell exists
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            2,
+            cli::AllocStrategy::TapeCanGrow,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert!(tape.add_watchpoint(3).is_ok());
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(matches!(
+            tape.interpreter(&mut reader, &mut writer).unwrap(),
+            StopReason::Watchpoint { cell: 3, .. }
+        ));
+    }
+
+    /// Test that the hook sees every instruction and can abort the program
+    #[test]
+    fn hook_aborts_program() {
+        let mut program = BfProgram::new("forever.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut count = 0;
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_hook(|_| {
+            if count == 10 {
+                return HookAction::Abort;
+            }
+            count += 1;
+            HookAction::Continue
+        });
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        let result = tape.interpreter(&mut reader, &mut writer).unwrap();
+        assert_eq!(tape.steps(), 10);
+        // Loop is [] so after the + the program alternates between instructions 1 and 2
+        assert_eq!(result, StopReason::Aborted { program_pointer: 2 });
+    }
+
+    /// Test that the hook is given the instruction details and can pause the program
+    #[test]
+    fn hook_pauses_program() {
+        let program = BfProgram::new("tiny.bf", "+>+").unwrap();
+        let mut events = Vec::new();
+        {
+            let mut tape: BfTape<u8> = BfTape::new(
+                &program,
+                100,
+                cli::AllocStrategy::TapeIsFixed,
+                cli::OutputFormat::BinaryOutput,
+            );
+            tape.set_hook(|event| {
+                events.push(*event);
+                if event.program_pointer == 1 {
+                    HookAction::Pause
+                } else {
+                    HookAction::Continue
+                }
+            });
+
+            let mut reader = std::io::Cursor::new(Vec::new());
+            let mut writer = std::io::Cursor::new(Vec::new());
+            assert_eq!(
+                tape.interpreter(&mut reader, &mut writer).unwrap(),
+                StopReason::Paused { program_pointer: 1 }
+            );
+            assert_eq!(
+                tape.interpreter(&mut reader, &mut writer).unwrap(),
+                StopReason::Halted
+            );
+        }
+
+        // The hook is not called a second time for the paused instruction
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[1].value, 1);
+        assert_eq!(events[1].data_pointer, 0);
+        assert_eq!(
+            events[1].instruction.command(),
+            bft_types::BfCommand::IncDataPointer
+        );
+        assert_eq!(events[2].data_pointer, 1);
+    }
+
+    /// Test that a trace of the executed instructions is written as JSON lines
+    #[test]
+    fn trace_written_as_json() {
+        let program = BfProgram::new("trace.bf", "+>.").unwrap();
+        let mut trace = Vec::new();
+        {
+            let mut tape: BfTape<u8> = BfTape::new(
+                &program,
+                100,
+                cli::AllocStrategy::TapeIsFixed,
+                cli::OutputFormat::BinaryOutput,
+            );
+            tape.set_trace_writer(Box::new(&mut trace));
+            let mut reader = std::io::Cursor::new(Vec::new());
+            let mut writer = std::io::Cursor::new(Vec::new());
+            assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+        }
+
+        let records: Vec<TraceRecord> = String::from_utf8(trace)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                TraceRecord {
+                    step: 1,
+                    program_pointer: 0,
+                    command: '+',
+                    line: 1,
+                    column: 1,
+                    data_pointer: 0,
+                    value: 1,
+                    depth: 0
+                },
+                TraceRecord {
+                    step: 2,
+                    program_pointer: 1,
+                    command: '>',
+                    line: 1,
+                    column: 2,
+                    data_pointer: 1,
+                    value: 0,
+                    depth: 0
+                },
+                TraceRecord {
+                    step: 3,
+                    program_pointer: 2,
+                    command: '.',
+                    line: 1,
+                    column: 3,
+                    data_pointer: 1,
+                    value: 0,
+                    depth: 0
+                },
+            ]
+        );
+    }
+
+    /// Test that the profiler counts instructions and loop iterations
+    #[test]
+    fn profiler_counts_loop() {
+        let mut program = BfProgram::new("profile.bf", "++[->+<]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.enable_profiler();
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+
+        let profile = tape.profile().unwrap();
+        assert_eq!(profile.instruction_hits, vec![1, 1, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(
+            profile.loop_iterations,
+            vec![(
+                bft_types::BfJumpLocation::new(
+                    bft_types::BfLocation::new(1, 3),
+                    bft_types::BfLocation::new(1, 8)
+                ),
+                2
+            )]
+        );
+        assert_eq!(
+            profile.report(10),
+            "Commands executed:\n  IncDataPointer: 2\n  DecDataPointer: 2\n  IncValue: 4\n  DecValue: 2\n  JumpForward: 1\n  JumpBackward: 2\nTop 1 loops by iterations:\n  [ at profile.bf:1:3 ] at profile.bf:1:8: 2 iterations\n"
+        );
+    }
+
+    /// Test that there is no profile unless the profiler is enabled
+    #[test]
+    fn profiler_disabled() {
+        let program = BfProgram::new("profile.bf", "+").unwrap();
+        let tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert!(tape.profile().is_none());
+    }
+
+    /// Test that instructions in a loop that is never entered are not covered
+    #[test]
+    fn coverage_of_dead_loop() {
+        let mut program = BfProgram::new("dead.bf", "[+>]+").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert!(tape.coverage().is_none());
+        tape.enable_coverage();
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+
+        assert_eq!(tape.coverage().unwrap(), &[1, 0, 0, 0, 1]);
+    }
+
+    /// Test that the coverage counts saturate rather than overflow
+    #[test]
+    fn coverage_saturates() {
+        let program = BfProgram::new("hot.bf", "+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.enable_coverage();
+        tape.coverage_hits = Some(vec![u32::MAX]);
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+
+        assert_eq!(tape.coverage().unwrap(), &[u32::MAX]);
+    }
+
+    /// Test that debug output doesn't get mixed up with the program's output
+    #[test]
+    fn debug_output_kept_separate() {
+        let mut program = BfProgram::new("debug.bf", "++[>+++++<-]>.").unwrap();
+        program.validate().unwrap();
+        let mut debug = Vec::new();
+        let mut writer = std::io::Cursor::new(Vec::new());
+        {
+            let mut tape: BfTape<u8> = BfTape::new(
+                &program,
+                100,
+                cli::AllocStrategy::TapeIsFixed,
+                cli::OutputFormat::AsciiOutput,
+            );
+            tape.set_debug(cli::DebugLevelType::Detailed);
+            tape.set_debug_writer(Box::new(&mut debug));
+            let mut reader = std::io::Cursor::new(Vec::new());
+            assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+        }
+
+        assert_eq!(writer.into_inner(), vec![10]);
+        let debug = String::from_utf8(debug).unwrap();
+        assert!(debug.contains("Output at 13"));
+        assert!(debug.contains("Jumping to 2 at 1:3"));
+    }
+
+    /// Test that a program runs the same with and without debug output
+    #[test]
+    fn debug_does_not_change_semantics() {
+        let mut program = BfProgram::new("hello.bf", include_str!("../../hello-world.bf")).unwrap();
+        program.validate().unwrap();
+        let mut results = Vec::new();
+        for debug in [cli::DebugLevelType::None, cli::DebugLevelType::Detailed] {
+            let mut tape: BfTape<u8> = BfTape::new(
+                &program,
+                100,
+                cli::AllocStrategy::TapeIsFixed,
+                cli::OutputFormat::AsciiOutput,
+            );
+            tape.set_debug(debug);
+            tape.set_debug_writer(Box::new(std::io::sink()));
+            let mut reader = std::io::Cursor::new(Vec::new());
+            let mut writer = std::io::Cursor::new(Vec::new());
+            assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+            results.push((
+                writer.into_inner(),
+                tape.data_pointer(),
+                tape.cells().to_vec(),
+                tape.steps(),
+            ));
+        }
+        assert_eq!(results[0].0, b"Hello World!\n");
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0].2[..7], [0, 0, 72, 100, 87, 33, 10]);
+    }
+
+    /// Test that jumps go to the right bracket when brackets are in the same column
+    #[test]
+    fn jump_between_lines() {
+        let mut program = BfProgram::new("jumps.bf", "[>]\n[<]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+
+        // Cell is zero so jumps over the first loop to the start of the second
+        assert_eq!(tape.command_jump_forward().unwrap(), 3);
+        // And then over the second loop to the end of the program
+        assert_eq!(tape.command_jump_forward().unwrap(), 6);
+
+        // Cell is non-zero so jumps back from the end of the second loop into its body
+        tape.set_data_value(1);
+        tape.machine.program_pointer = 5;
+        assert_eq!(tape.command_jump_backward().unwrap(), 4);
+    }
+
+    /// Test that jumping in a program that hasn't been validated is an error
+    #[test]
+    fn jump_in_unvalidated_program() {
+        let program = BfProgram::new("jumps.bf", "[>]").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert!(matches!(
+            tape.command_jump_forward(),
+            Err(BfError::BracketNotFound {
+                program_pointer: 0,
+                ..
+            })
+        ));
+    }
+
+    /// Test that an unvalidated program with brackets is stopped before it is run, with the
+    /// bracket that has no matching bracket in the error
+    #[test]
+    fn unvalidated_program_not_run() {
+        let program = BfProgram::new("jumps.bf", "+\n>[<]").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[E_BRACKET] No matching bracket for Jump forward if zero @2:2 at 2, the program must be validated \
+             before it is run\n  |\n2 | >[<]\n  |  ^"
+        );
+        assert_eq!(error.location(), Some(bft_types::BfLocation::new(2, 2)));
+        assert_eq!(tape.steps(), 0);
+        assert_eq!(tape.get_data_value(), 0);
+
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape.step_io(&mut VecIo::new(Vec::new())).unwrap_err();
+        assert_eq!(error.code(), "E_BRACKET");
+        assert_eq!(tape.steps(), 0);
+
+        // A program without brackets doesn't need validating
+        let program = BfProgram::new("tiny.bf", "+.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        assert!(tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .is_ok());
+    }
+
+    /// Test that the wider cell types wrap around at their maximum values
+    #[test]
+    fn wide_cells_wrap_around() {
+        assert_eq!(0u16.dec(), u16::MAX);
+        assert_eq!(65535u16.inc(), 0);
+        assert_eq!(0u32.dec(), u32::MAX);
+        assert_eq!(4294967295u32.inc(), 0);
+        assert_eq!(0u64.dec(), u64::MAX);
+        assert_eq!(18446744073709551615u64.inc(), 0);
+        assert_eq!(255u16.inc(), 256);
+        assert_eq!(<u32 as CellKind>::from_u8(200), 200);
+    }
+
+    /// Test that the wider cell types are truncated when output as ASCII, but output whole
+    /// as numbers
+    #[test]
+    fn wide_cells_truncated_on_output() {
+        let program = BfProgram::new("wide.bf", ".").unwrap();
+        let mut tape: BfTape<u16> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::AsciiOutput,
+        );
+        // 0x141 is 'A' (0x41) in the low byte
+        for _ in 0..0x141 {
+            tape.increment_data_value().unwrap();
+        }
+        assert_eq!(tape.get_data_value(), 0x41);
+
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape.output_value(&mut writer).is_ok());
+        assert_eq!(writer.into_inner(), b"A");
+
+        let mut tape: BfTape<u64> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.decrement_data_value().unwrap();
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape.output_value(&mut writer).is_ok());
+        assert_eq!(writer.into_inner(), b"18446744073709551615");
+    }
+
+    /// Test the conversions of signed cells to and from u8
+    #[test]
+    fn signed_cells_conversions() {
+        assert_eq!(<i8 as CellKind>::from_u8(255), -1);
+        assert_eq!(<i16 as CellKind>::from_u8(255), 255);
+        assert_eq!((-1i32).to_u8(), 0xff);
+        assert_eq!(127i8.inc(), i8::MIN);
+        assert_eq!((-32768i16).dec(), i16::MAX);
+    }
+
+    /// Test that a signed cell decremented from zero is -1, outputs as -1 and is non-zero when
+    /// used as a loop condition
+    #[test]
+    fn signed_cell_minus_one() {
+        let mut program = BfProgram::new("signed.bf", "-.[+]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<i8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert_eq!(tape.step(&mut reader, &mut writer).unwrap(), None);
+        assert_eq!(*tape.tape_window(0).1, [-1]);
+        assert_eq!(tape.step(&mut reader, &mut writer).unwrap(), None);
+        assert_eq!(writer.get_ref(), b"-1");
+
+        // The loop is entered and adds one to get back to zero
+        assert_eq!(tape.step(&mut reader, &mut writer).unwrap(), None);
+        assert_eq!(tape.program_pointer(), 3);
+        assert_eq!(
+            tape.interpreter(&mut reader, &mut writer).unwrap(),
+            StopReason::Halted
+        );
+        assert_eq!(*tape.tape_window(0).1, [0]);
+    }
+
+    /// Test that a wide cell whose low byte is zero doesn't end a loop
+    #[test]
+    fn wide_cell_loop_not_ended_by_low_byte() {
+        let mut program = BfProgram::new("wide.bf", "[->+<]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u16> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        for _ in 0..256 {
+            tape.increment_data_value().unwrap();
+        }
+        assert!(!tape.tape_window(0).1[0].is_zero());
+
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape.interpreter(&mut reader, &mut writer).is_ok());
+        // The loop ran 256 times, moving the value to the next cell
+        assert_eq!(*tape.tape_window(1).1, [0, 256]);
+    }
+
+    /// Test that wide cells hold values above 255
+    #[test]
+    fn wide_cell_get_and_set() {
+        let program = BfProgram::new("wide.bf", "").unwrap();
+        let mut tape: BfTape<u32> = BfTape::new(
+            &program,
+            10,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_cell(300);
+        assert_eq!(tape.get_cell(), 300);
+        assert_eq!(tape.get_data_value(), 44);
+
+        assert!(tape.set_cell_at(9, 70000).is_ok());
+        assert_eq!(tape.get_cell_at(9).unwrap(), 70000);
+        assert_eq!(tape.get_cell_at(0).unwrap(), 300);
+        assert_eq!(tape.data_pointer(), 0);
+
+        assert!(matches!(
+            tape.get_cell_at(10),
+            Err(BfError::CellOutOfRange {
+                cell: 10,
+                length: 10
+            })
+        ));
+        assert!(tape.set_cell_at(10, 1).is_err());
+    }
+
+    /// Run a program on a tape, returning the output and final data pointer
+    fn run_on(mut tape: BfTape<u8>) -> (Vec<u8>, usize, usize) {
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+        (writer.into_inner(), tape.data_pointer(), tape.data_length())
+    }
+
+    /// Test that dense and sparse tapes behave the same
+    #[test]
+    fn sparse_tape_matches_dense() {
+        let mut program = BfProgram::new("hello.bf", include_str!("../../hello-world.bf")).unwrap();
+        program.validate().unwrap();
+        for alloc in [
+            cli::AllocStrategy::TapeIsFixed,
+            cli::AllocStrategy::TapeCanGrow,
+        ] {
+            let dense = BfTape::new(&program, 10, alloc, cli::OutputFormat::BinaryOutput);
+            let sparse = BfTape::new_sparse(&program, 10, alloc, cli::OutputFormat::BinaryOutput);
+            assert_eq!(run_on(dense), run_on(sparse));
+        }
+
+        // Growing past the end of the tape and moving off the end of a fixed tape
+        let program = BfProgram::new("grow.bf", "+>>>>+.").unwrap();
+        let grow = cli::AllocStrategy::TapeCanGrow;
+        let dense = BfTape::new(&program, 2, grow, cli::OutputFormat::BinaryOutput);
+        let sparse = BfTape::new_sparse(&program, 2, grow, cli::OutputFormat::BinaryOutput);
+        assert_eq!(run_on(dense), (b"1".to_vec(), 4, 5));
+        assert_eq!(run_on(sparse), (b"1".to_vec(), 4, 5));
+
+        let fixed = cli::AllocStrategy::TapeIsFixed;
+        let mut sparse: BfTape<u8> =
+            BfTape::new_sparse(&program, 2, fixed, cli::OutputFormat::BinaryOutput);
+        assert!(matches!(
+            sparse.interpreter(&mut std::io::empty(), &mut std::io::sink()),
+            Err(BfError::DataPtrMovedAfterEnd {
+                program_pointer: 2,
+                ..
+            })
+        ));
+    }
+
+    /// Test that a sparse tape only holds the cells that aren't zero
+    #[test]
+    fn sparse_tape_stays_small() {
+        // Set every cell across a wide range and then clear it again
+        let source = format!("{}{}", "+>".repeat(50_000), "<-".repeat(49_990));
+        let program = BfProgram::new("sweep.bf", &source).unwrap();
+        let mut tape: BfTape<u8> = BfTape::new_sparse(
+            &program,
+            1_000_000_000,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        // The BTreeMap only holds the ten cells left at one, which come out in order of their
+        // index
+        match &tape.machine.tape {
+            TapeStorage::Sparse(cells) => assert_eq!(cells.len(), 10),
+            TapeStorage::Dense(_) => panic!("Expected a sparse tape"),
+        }
+        assert_eq!(
+            tape.non_zero_cells().collect::<Vec<_>>(),
+            (0..10).map(|index| (index, 1)).collect::<Vec<_>>()
+        );
+        assert_eq!(*tape.tape_window(1).1, [1, 0, 0]);
+    }
+
+    /// Test that a tape can grow to the left when allowed to
+    #[test]
+    fn tape_grows_left() {
+        let program = BfProgram::new("left.bf", "<+.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            10,
+            cli::AllocStrategy::TapeCanGrowBothWays,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+        assert_eq!(writer.into_inner(), b"1");
+        assert_eq!(tape.data_position(), -1);
+        assert_eq!(tape.data_pointer(), TAPE_GROWTH_CHUNK - 1);
+        assert_eq!(tape.data_length(), TAPE_GROWTH_CHUNK + 10);
+
+        // A sparse tape grows the same way
+        let tape: BfTape<u8> = BfTape::new_sparse(
+            &program,
+            10,
+            cli::AllocStrategy::TapeCanGrowBothWays,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert_eq!(
+            run_on(tape),
+            (b"1".to_vec(), TAPE_GROWTH_CHUNK - 1, TAPE_GROWTH_CHUNK + 10)
+        );
+
+        // A tape that only grows to the right, or a fixed tape, can't go left of the start
+        for alloc in [
+            cli::AllocStrategy::TapeCanGrow,
+            cli::AllocStrategy::TapeIsFixed,
+        ] {
+            let mut tape: BfTape<u8> =
+                BfTape::new(&program, 10, alloc, cli::OutputFormat::BinaryOutput);
+            assert!(matches!(
+                tape.interpreter(&mut std::io::empty(), &mut std::io::sink()),
+                Err(BfError::DataPtrMovedBeforeStart { .. })
+            ));
+        }
+    }
+
+    /// Test that cells keep their values and watchpoints follow them when the tape grows left
+    #[test]
+    fn tape_grows_left_keeps_cells() {
+        let program = BfProgram::new("left.bf", "+++<+>+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            1,
+            cli::AllocStrategy::TapeCanGrowBothWays,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.add_watchpoint(0).unwrap();
+        let mut reader = std::io::empty();
+        let mut writer = std::io::sink();
+        // Stops at each change of the starting cell, which moves along by a chunk of cells
+        // after growing left
+        for _ in 0..3 {
+            assert!(matches!(
+                tape.interpreter(&mut reader, &mut writer).unwrap(),
+                StopReason::Watchpoint { .. }
+            ));
+        }
+        assert_eq!(
+            tape.interpreter(&mut reader, &mut writer).unwrap(),
+            StopReason::Watchpoint {
+                cell: TAPE_GROWTH_CHUNK,
+                old: 3,
+                new: 4,
+                program_pointer: 6
+            }
+        );
+        assert_eq!(*tape.tape_window(2).1, [0, 1, 4]);
+        assert_eq!(tape.data_position(), 0);
+    }
+
+    /// Test that a tape that grows both ways is unbounded to the left as well as the right,
+    /// with the position of the data pointer relative to where the program started
+    #[test]
+    fn tape_grows_both_ways() {
+        let builder = BfTapeBuilder::new()
+            .tape_size(1)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrowBothWays)
+            .output_format(cli::OutputFormat::BinaryOutput);
+        let program = BfProgram::new("left.bf", "<<<+.").unwrap();
+        let mut tape: BfTape<u8> = builder.build(&program);
+        let mut output = Vec::new();
+        tape.interpreter(&mut std::io::empty(), &mut output)
+            .unwrap();
+        assert_eq!(output, b"1");
+        assert_eq!(tape.data_position(), -3);
+
+        // Far enough each way to grow by more than a chunk
+        let source = format!(
+            "+{}+{}+",
+            "<".repeat(TAPE_GROWTH_CHUNK + 1),
+            ">".repeat(2 * TAPE_GROWTH_CHUNK + 2)
+        );
+        let program = BfProgram::new("both.bf", &source).unwrap();
+        let mut tape: BfTape<u8> = builder.build(&program);
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        assert_eq!(tape.data_position(), TAPE_GROWTH_CHUNK as isize + 1);
+        let cells: Vec<(usize, u8)> = tape.non_zero_cells().collect();
+        let start = tape.data_pointer() - 2 * (TAPE_GROWTH_CHUNK + 1);
+        assert_eq!(
+            cells,
+            [
+                (start, 1),
+                (start + TAPE_GROWTH_CHUNK + 1, 1),
+                (tape.data_pointer(), 1)
+            ]
+        );
+    }
+
+    /// Test that the tape can be initialised with an image before the program runs
+    #[test]
+    fn initial_image() {
+        let program = BfProgram::new("table.bf", ".>.>.").unwrap();
+        let tape: BfTape<u8> = BfTape::with_initial(
+            &program,
+            10,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+            &[1, 2, 3],
+        )
+        .unwrap();
+        assert_eq!(run_on(tape).0, b"1,2,3");
+
+        // A tape that can grow is grown to fit the image
+        let mut tape: BfTape<u16> = BfTape::new(
+            &program,
+            2,
+            cli::AllocStrategy::TapeCanGrow,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.load_initial(&[1, 2, 255]).unwrap();
+        assert_eq!(*tape.cells(), [1, 2, 255]);
+    }
+
+    /// Test that an image larger than a fixed tape, or the limit of a growing tape, is an error
+    #[test]
+    fn initial_image_too_large() {
+        let program = BfProgram::new("table.bf", ".").unwrap();
+        assert!(matches!(
+            BfTape::<u8>::with_initial(
+                &program,
+                2,
+                cli::AllocStrategy::TapeIsFixed,
+                cli::OutputFormat::BinaryOutput,
+                &[1, 2, 3],
+            ),
+            Err(BfError::ImageTooLarge { size: 3, length: 2 })
+        ));
+
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            2,
+            cli::AllocStrategy::TapeCanGrowWithLimit(3),
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert!(matches!(
+            tape.load_initial(&[1, 2, 3, 4]),
+            Err(BfError::ImageTooLarge { size: 4, length: 3 })
+        ));
+        assert!(tape.load_initial(&[1, 2, 3]).is_ok());
+    }
+
+    /// Test the window of cells shown with the data pointer at the start, middle and end of the tape
+    #[test]
+    fn render_window() {
+        let program = BfProgram::new("window.bf", "").unwrap();
+        let mut tape: BfTape<u8> = BfTape::with_initial(
+            &program,
+            12,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        )
+        .unwrap();
+        assert_eq!(tape.render_window(2), "[0] >1< 2 3 [2] ...");
+        assert_eq!(tape.to_string(), "[0] >1< 2 3 4 5 6 [5] ...");
+
+        for _ in 0..6 {
+            tape.move_data_pointer_forward().unwrap();
+        }
+        assert_eq!(tape.render_window(2), "... [4] 5 6 >7< 8 9 [8] ...");
+        assert_eq!(format!("{:.1}", tape), "... [5] 6 >7< 8 [7] ...");
+        assert_eq!(
+            tape.render_window(20),
+            "[0] 1 2 3 4 5 6 >7< 8 9 10 11 12 [11]"
+        );
+
+        for _ in 0..5 {
+            tape.move_data_pointer_forward().unwrap();
+        }
+        assert_eq!(tape.render_window(2), "... [9] 10 11 >12< [11]");
+        assert_eq!(tape.render_window(0), "... [11] >12< [11]");
+    }
+
+    /// Test that wide and signed cells are shown with their full values
+    #[test]
+    fn render_window_wide_cells() {
+        let program = BfProgram::new("window.bf", "").unwrap();
+        let mut tape: BfTape<i32> = BfTape::new(
+            &program,
+            3,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_cell_at(0, 70000).unwrap();
+        tape.set_cell_at(1, -1).unwrap();
+        tape.move_data_pointer_forward().unwrap();
+        assert_eq!(tape.to_string(), "[0] 70000 >-1< 0 [2]");
+    }
+
+    /// Test that a reset tape runs another program as if it were new
+    #[test]
+    fn reset() {
+        let dirty = BfProgram::new("dirty.bf", "+++>++>+.").unwrap();
+        let mut cat = BfProgram::new("cat.bf", ",[.,]").unwrap();
+        cat.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &dirty,
+            2,
+            cli::AllocStrategy::TapeCanGrow,
+            cli::OutputFormat::AsciiOutput,
+        );
+        tape.set_eof_behavior(cli::EofBehavior::Zero);
+        tape.enable_coverage();
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        assert_eq!(*tape.cells(), [3, 2, 1]);
+
+        tape.reset_with_program(&cat);
+        assert_eq!(*tape.cells(), [0, 0]);
+        assert_eq!(
+            (tape.data_pointer(), tape.program_pointer(), tape.steps()),
+            (0, 0, 0)
+        );
+        assert_eq!(tape.coverage(), Some(&[0, 0, 0, 0, 0][..]));
+
+        let mut reader = std::io::Cursor::new(b"cat".to_vec());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer.into_inner(), b"cat");
+        assert_eq!(*tape.cells(), [0, 0]);
+        assert_eq!(tape.eof_behavior(), cli::EofBehavior::Zero);
+
+        // The same program can be run again after a reset
+        tape.reset();
+        let mut reader = std::io::Cursor::new(b"again".to_vec());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer.into_inner(), b"again");
+    }
+
+    /// Test that a tape built with the default options is the same as one from new
+    #[test]
+    fn builder_defaults() {
+        let program = BfProgram::new("tiny.bf", "+.").unwrap();
+        let tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        assert_eq!(tape.data_length(), DEFAULT_TAPE_SIZE);
+        assert_eq!(
+            tape.machine.alloc_strategy(),
+            machine::AllocStrategy::TapeIsFixed
+        );
+        assert_eq!(tape.output_format, cli::OutputFormat::AsciiOutput);
+        assert_eq!(tape.input_format(), cli::InputFormat::RawInput);
+        assert_eq!(tape.eof_behavior(), cli::EofBehavior::NegOne);
+        assert_eq!(tape.overflow_behavior(), cli::OverflowBehavior::Wrap);
+        assert_eq!(tape.trailing_newline(), cli::TrailingNewline::Never);
+        assert_eq!(tape.debug(), cli::DebugLevelType::None);
+        assert_eq!(tape.step_limit(), None);
+        assert_eq!(tape.max_tape(), None);
+        assert!(!tape.auto_flush());
+        assert!(matches!(tape.machine.tape, TapeStorage::Dense(_)));
+    }
+
+    /// Test that every option of the builder is passed on to the tape
+    #[test]
+    fn builder_customised() {
+        let program = BfProgram::new("tiny.bf", "+.").unwrap();
+        let tape: BfTape<i16> = BfTapeBuilder::new()
+            .tape_size(10)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrowWithLimit(20))
+            .output_format(cli::OutputFormat::HexOutput)
+            .input_format(cli::InputFormat::DecimalInput)
+            .eof_behavior(cli::EofBehavior::Unchanged)
+            .overflow_behavior(cli::OverflowBehavior::Error)
+            .trailing_newline(cli::TrailingNewline::Always)
+            .debug(cli::DebugLevelType::Verbose)
+            .step_limit(Some(50))
+            .auto_flush(true)
+            .sparse(true)
+            .build(&program);
+        assert_eq!(tape.data_length(), 10);
+        assert_eq!(
+            tape.machine.alloc_strategy(),
+            machine::AllocStrategy::TapeCanGrowWithLimit(20)
+        );
+        assert_eq!(tape.output_format, cli::OutputFormat::HexOutput);
+        assert_eq!(tape.input_format(), cli::InputFormat::DecimalInput);
+        assert_eq!(tape.eof_behavior(), cli::EofBehavior::Unchanged);
+        assert_eq!(tape.overflow_behavior(), cli::OverflowBehavior::Error);
+        assert_eq!(tape.trailing_newline(), cli::TrailingNewline::Always);
+        assert_eq!(tape.debug(), cli::DebugLevelType::Verbose);
+        assert_eq!(tape.step_limit(), Some(50));
+        assert_eq!(tape.max_tape(), Some(20));
+        assert!(tape.auto_flush());
+        assert!(matches!(tape.machine.tape, TapeStorage::Sparse(_)));
+    }
+
+    /// Test that a tape with a shared program can be run in another thread
+    #[test]
+    fn shared_program_in_thread() {
+        let mut program = BfProgram::new("hello.bf", include_str!("../../hello-world.bf")).unwrap();
+        program.validate().unwrap();
+        let program = Arc::new(program);
+        let mut tape: OwnedBfTape<u8> = BfTape::new(
+            Arc::clone(&program),
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::AsciiOutput,
+        );
+        let handle = std::thread::spawn(move || {
+            let mut writer = std::io::Cursor::new(Vec::new());
+            tape.interpreter(&mut std::io::empty(), &mut writer)
+                .unwrap();
+            (tape, writer.into_inner())
+        });
+        let (tape, output) = handle.join().unwrap();
+        assert_eq!(output, b"Hello World!\n");
+        assert_eq!(tape.program_pointer(), program.instructions().len());
+    }
+
+    /// Test the dump of a small tape, including the data pointer and collapsed lines of zeros
+    #[test]
+    fn dump() {
+        let program = BfProgram::new("dump.bf", ">>>>>>>>>>>>>>>>>").unwrap();
+        let mut tape: BfTape<u8> = BfTape::with_initial(
+            &program,
+            80,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+            b"Hello\n",
+        )
+        .unwrap();
+        tape.set_cell_at(79, 0xff).unwrap();
+        for _ in 0..17 {
+            tape.move_data_pointer_forward().unwrap();
+        }
+        let mut dump = Vec::new();
+        tape.dump(&mut dump, None).unwrap();
+        assert_eq!(
+            String::from_utf8(dump).unwrap(),
+            "\
+00000000  48 65 6c 6c 6f 0a 00 00 00 00 00 00 00 00 00 00  |Hello...........|
+00000010  00[00]00 00 00 00 00 00 00 00 00 00 00 00 00 00  |................|
+00000020  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  |................|
+*
+00000040  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 ff  |................|
+00000050
+"
+        );
+
+        // Part of the tape, with wide cells and the pointer on the last cell of a line
+        let mut tape: BfTape<u16> = BfTape::new(
+            &program,
+            80,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        for _ in 0..15 {
+            tape.move_data_pointer_forward().unwrap();
+        }
+        tape.set_cell(0x141);
+        let mut dump = Vec::new();
+        tape.dump(&mut dump, Some(12..16)).unwrap();
+        assert_eq!(
+            String::from_utf8(dump).unwrap(),
+            format!(
+                "0000000c  0000 0000 0000[0141]{}|...A|\n00000010\n",
+                " ".repeat(61)
+            )
+        );
+    }
+
+    /// Test that a tape grown in chunks reports the cells in use and keeps the new cells zero
+    #[test]
+    fn tape_grows_in_chunks() {
+        let source = format!("{}+{}", ">".repeat(10), ">".repeat(TAPE_GROWTH_CHUNK));
+        let program = BfProgram::new("grow.bf", &source).unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            1,
+            cli::AllocStrategy::TapeCanGrow,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+        assert_eq!(tape.data_pointer(), TAPE_GROWTH_CHUNK + 10);
+        assert_eq!(tape.data_length(), TAPE_GROWTH_CHUNK + 11);
+        assert_eq!(tape.get_data_value(), 0);
+        assert_eq!(tape.get_cell_at(10).unwrap(), 1);
+        assert_eq!(
+            tape.tape_window(2),
+            (TAPE_GROWTH_CHUNK + 8, Cow::Borrowed(&[0, 0, 0][..]))
+        );
+    }
+
+    /// Test that a tape can grow up to exactly its limit and no further
+    #[test]
+    fn tape_limit() {
+        // Growing to exactly the limit is fine
+        let program = BfProgram::new("fits.bf", ">>>>>>>+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            2,
+            cli::AllocStrategy::TapeCanGrowWithLimit(8),
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+        assert_eq!(tape.data_length(), 8);
+        assert_eq!(tape.get_cell_at(7).unwrap(), 1);
+
+        // One cell more isn't
+        let program = BfProgram::new("runaway.bf", ">>>>>>\n>>").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            2,
+            cli::AllocStrategy::TapeCanGrowWithLimit(8),
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap_err();
+        assert_eq!(tape.data_pointer(), 7);
+        assert_eq!(tape.data_length(), 8);
+        assert!(matches!(
+            error,
+            BfError::TapeLimitExceeded {
+                limit: 8,
+                program_pointer: 7,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "[E_TAPE_LIMIT] Tape limit of 8 cells exceeded at 7 Increment data pointer @2:2\n  |\n2 | >>\n  |  ^"
+        );
+        assert!(tape.set_cell_at(7, 1).is_ok());
+        assert!(matches!(
+            tape.set_cell_at(8, 1),
+            Err(BfError::CellOutOfRange { cell: 8, length: 8 })
+        ));
+    }
+
+    /// Test that cells beyond the end of a tape that can grow are accessible
+    #[test]
+    fn cell_at_beyond_end_of_growable_tape() {
+        let program = BfProgram::new("wide.bf", "").unwrap();
+        let mut tape: BfTape<u32> = BfTape::new(
+            &program,
+            10,
+            cli::AllocStrategy::TapeCanGrow,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert_eq!(tape.get_cell_at(20).unwrap(), 0);
+        assert_eq!(tape.data_length(), 10);
+        assert!(tape.set_cell_at(20, 1000).is_ok());
+        assert_eq!(tape.data_length(), 21);
+        assert_eq!(tape.get_cell_at(20).unwrap(), 1000);
+    }
+
+    /// A reader that is interrupted on its first read and then returns its data
+    struct InterruptedReader {
+        interrupted: bool,
+        data: std::io::Cursor<Vec<u8>>,
+    }
+
+    impl std::io::Read for InterruptedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(std::io::Error::from(std::io::ErrorKind::Interrupted));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    /// Test that an interrupted read is retried rather than treated as an error or end of file
+    #[test]
+    fn input_retries_interrupted_read() {
+        let program = BfProgram::new("input.bf", ",,").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut reader = InterruptedReader {
+            interrupted: false,
+            data: std::io::Cursor::new(b"Z".to_vec()),
+        };
+        tape.input_value(&mut reader).unwrap();
+        assert!(reader.interrupted);
+        assert_eq!(tape.get_data_value(), b'Z');
+
+        // The real end of file still uses the end of file behaviour
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_data_value(), 255);
+    }
+
+    /// Test that other read errors are reported
+    #[test]
+    fn input_reports_read_error() {
+        struct FailingReader;
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+            }
+        }
+        let program = BfProgram::new("input.bf", ",").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert!(matches!(
+            tape.input_value(&mut FailingReader),
+            Err(BfError::IOError { .. })
+        ));
+    }
+
+    /// A writer that counts how many times it has been flushed
+    #[derive(Default)]
+    struct FlushCountingWriter {
+        flushes: usize,
+        data: Vec<u8>,
+    }
+
+    impl std::io::Write for FlushCountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    /// Test that the output is flushed once per output command with auto flush and once at the end without
+    #[test]
+    fn auto_flush() {
+        let program = BfProgram::new("flush.bf", "+.+.+.").unwrap();
+        let mut reader = std::io::empty();
+
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = FlushCountingWriter::default();
+        tape.interpreter(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer.flushes, 1);
+        assert_eq!(writer.data, b"1,2,3");
+
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_auto_flush(true);
+        let mut writer = FlushCountingWriter::default();
+        tape.interpreter(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer.flushes, 4);
+    }
+
+    /// Test that the output is flushed even when the program stops with an error
+    #[test]
+    fn flush_on_error() {
+        let program = BfProgram::new("flush.bf", "+.<").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = FlushCountingWriter::default();
+        assert!(tape
+            .interpreter(&mut std::io::empty(), &mut writer)
+            .is_err());
+        assert_eq!(writer.flushes, 1);
+    }
+
+    /// Test that a large amount of data passes through a cat program with buffering
+    #[test]
+    fn buffered_throughput() {
+        let mut program = BfProgram::new("cat.bf", ",[.,]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::AsciiOutput,
+        );
+        tape.set_eof_behavior(cli::EofBehavior::Zero);
+        let input: Vec<u8> = (1..=255u8).cycle().take(300 * 1024).collect();
+        let mut reader = std::io::Cursor::new(input.clone());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter_buffered(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer.into_inner(), input);
+    }
+
+    /// Test that buffered output is flushed before the program waits for input
+    #[test]
+    fn buffered_prompt_is_flushed() {
+        struct SharedVec(Rc<RefCell<Vec<u8>>>);
+        impl std::io::Write for SharedVec {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        struct CheckingReader(Rc<RefCell<Vec<u8>>>);
+        impl std::io::Read for CheckingReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                assert_eq!(*self.0.borrow(), b"?");
+                buf[0] = b'y';
+                Ok(1)
+            }
+        }
+
+        // Output a '?' prompt then read an answer
+        let program = BfProgram::new("prompt.bf", &format!("{}.,.", "+".repeat(63))).unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::AsciiOutput,
+        );
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut reader = CheckingReader(Rc::clone(&output));
+        let mut writer = SharedVec(Rc::clone(&output));
+        tape.interpreter_buffered(&mut reader, &mut writer).unwrap();
+        assert_eq!(*output.borrow(), b"?y");
+    }
+
+    /// Test that buffered output is flushed when the program stops with an error
+    #[test]
+    fn buffered_flush_on_error() {
+        let program = BfProgram::new("error.bf", "+.<").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        assert!(tape
+            .interpreter_buffered(&mut std::io::empty(), &mut writer)
+            .is_err());
+        assert_eq!(writer.into_inner(), b"1");
+    }
+
+    /// Run a program with numbers output, returning what was output
+    fn numbers_output(code: &str, trailing_separator: bool, final_newline: bool) -> Vec<u8> {
+        let program = BfProgram::new("numbers.bf", code).unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_separator(", ");
+        tape.set_trailing_separator(trailing_separator);
+        tape.set_final_newline(final_newline);
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+        writer.into_inner()
+    }
+
+    /// Run a program with ASCII output and a trailing newline mode, returning what was output
+    fn trailing_newline_output(code: &str, trailing_newline: cli::TrailingNewline) -> Vec<u8> {
+        let program = BfProgram::new("newline.bf", code).unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::AsciiOutput,
+        );
+        tape.set_trailing_newline(trailing_newline);
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+        writer.into_inner()
+    }
+
+    /// Test each trailing newline mode with output that does and doesn't end with a newline
+    #[test]
+    fn trailing_newline() {
+        let a = &"+".repeat(65);
+        let a_then_newline = &format!("{}.>++++++++++.", a);
+        let a = &format!("{}.", a);
+        assert_eq!(
+            trailing_newline_output(a, cli::TrailingNewline::Never),
+            b"A"
+        );
+        assert_eq!(
+            trailing_newline_output(a_then_newline, cli::TrailingNewline::Never),
+            b"A\n"
+        );
+        assert_eq!(
+            trailing_newline_output(a, cli::TrailingNewline::Always),
+            b"A\n"
+        );
+        assert_eq!(
+            trailing_newline_output(a_then_newline, cli::TrailingNewline::Always),
+            b"A\n\n"
+        );
+        assert_eq!(
+            trailing_newline_output(a, cli::TrailingNewline::IfMissing),
+            b"A\n"
+        );
+        assert_eq!(
+            trailing_newline_output(a_then_newline, cli::TrailingNewline::IfMissing),
+            b"A\n"
+        );
+        // Nothing is missing if nothing was output
+        assert_eq!(
+            trailing_newline_output("+", cli::TrailingNewline::IfMissing),
+            b""
+        );
+    }
+
+    /// Test the separators and ending of numbers output
+    #[test]
+    fn numbers_separators() {
+        assert_eq!(numbers_output("+", false, false), b"");
+        assert_eq!(numbers_output("+.", false, false), b"1");
+        assert_eq!(numbers_output("+.+.+.", false, false), b"1, 2, 3");
+
+        assert_eq!(numbers_output("+", true, false), b"");
+        assert_eq!(numbers_output("+.", true, false), b"1, ");
+        assert_eq!(numbers_output("+.+.+.", true, false), b"1, 2, 3, ");
+
+        assert_eq!(numbers_output("+", false, true), b"");
+        assert_eq!(numbers_output("+.", false, true), b"1\n");
+        assert_eq!(numbers_output("+.+.+.", false, true), b"1, 2, 3\n");
+        assert_eq!(numbers_output("+.+.+.", true, true), b"1, 2, 3, \n");
+    }
+
+    /// Test UTF-8 output of wide cells, including an invalid code point
+    #[test]
+    fn utf8_output() {
+        let program = BfProgram::new("utf8.bf", ".").unwrap();
+        let mut tape: BfTape<u32> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::Utf8Output,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.set_cell(0x1F600);
+        tape.output_value(&mut writer).unwrap();
+        assert_eq!(writer.get_ref(), "\u{1F600}".as_bytes());
+        assert_eq!(writer.get_ref().len(), 4);
+
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.set_cell(0xD800);
+        tape.output_value(&mut writer).unwrap();
+        assert_eq!(writer.into_inner(), "\u{FFFD}".as_bytes());
+    }
+
+    /// Test UTF-8 output of u8 cells maps the values straight to code points
+    #[test]
+    fn utf8_output_u8_cells() {
+        let program = BfProgram::new("utf8.bf", ".").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::Utf8Output,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        for value in [b'A', 0xE9] {
+            tape.set_data_value(value);
+            tape.output_value(&mut writer).unwrap();
+        }
+        assert_eq!(writer.into_inner(), "A\u{E9}".as_bytes());
+    }
+
+    /// Test hex output of u8 cells
+    #[test]
+    fn hex_output() {
+        let program = BfProgram::new("hex.bf", ".").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::HexOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        for value in [0, 10, 255] {
+            tape.set_data_value(value);
+            tape.output_value(&mut writer).unwrap();
+        }
+        assert_eq!(writer.into_inner(), b"00,0a,ff");
+    }
+
+    /// Test hex output of wide cells uses the full width of the cell
+    #[test]
+    fn hex_output_wide_cells() {
+        let program = BfProgram::new("hex.bf", ".").unwrap();
+        let mut tape: BfTape<u32> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::HexOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        for value in [0, 10, 255, 0x1234_5678] {
+            tape.set_cell(value);
+            tape.output_value(&mut writer).unwrap();
+        }
+        assert_eq!(writer.into_inner(), b"00000000,0000000a,000000ff,12345678");
+
+        let mut tape: BfTape<i16> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::HexOutput,
+        );
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.set_cell(-1);
+        tape.output_value(&mut writer).unwrap();
+        assert_eq!(writer.into_inner(), b"ffff");
+    }
+
+    /// A writer that only accepts one byte per call and is interrupted before each byte
+    struct TrickleWriter {
+        interrupt: bool,
+        data: Vec<u8>,
+    }
+
+    impl std::io::Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::Error::from(std::io::ErrorKind::Interrupted));
+            }
+            match buf.first() {
+                Some(byte) => {
+                    self.data.push(*byte);
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Test that partial and interrupted writes still output the full decimal representation
+    #[test]
+    fn output_handles_partial_writes() {
+        let program = BfProgram::new("output.bf", "..").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut writer = TrickleWriter {
+            interrupt: false,
+            data: Vec::new(),
+        };
+        tape.set_data_value(255);
+        tape.output_value(&mut writer).unwrap();
+        tape.set_data_value(7);
+        tape.output_value(&mut writer).unwrap();
+        assert_eq!(writer.data, b"255,7");
+    }
+
+    /// Test that decimal numbers can be input and output
+    #[test]
+    fn decimal_round_trip() {
+        let program = BfProgram::new("numbers.bf", ",.,.").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_input_format(cli::InputFormat::DecimalInput);
+        let mut reader = std::io::Cursor::new(b"12,255,".to_vec());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        tape.interpreter(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer.into_inner(), b"12,255");
+    }
+
+    /// Test decimal input separators and end of file handling
+    #[test]
+    fn decimal_input_eof() {
+        let program = BfProgram::new("numbers.bf", ",").unwrap();
+        let mut tape: BfTape<i16> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_input_format(cli::InputFormat::DecimalInput);
+        tape.set_eof_behavior(cli::EofBehavior::Zero);
+        let mut reader = std::io::Cursor::new(b" 7\n -300".to_vec());
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_cell(), 7);
+        // End of file part way through a number stores what was read
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_cell(), -300);
+        // End of file before a number uses the end of file behaviour
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_cell(), 0);
+    }
+
+    /// Test that decimal input which isn't a number, or doesn't fit in a cell, is an error
+    #[test]
+    fn decimal_input_invalid() {
+        let program = BfProgram::new("numbers.bf", "+\n,").unwrap();
+        for input in ["12x,", "256,", "-1,"] {
+            let mut tape: BfTape<u8> = BfTape::new(
+                &program,
+                100,
+                cli::AllocStrategy::TapeIsFixed,
+                cli::OutputFormat::BinaryOutput,
+            );
+            tape.set_input_format(cli::InputFormat::DecimalInput);
+            let mut reader = std::io::Cursor::new(input.as_bytes().to_vec());
+            let mut writer = std::io::Cursor::new(Vec::new());
+            match tape.interpreter(&mut reader, &mut writer) {
+                Err(BfError::InvalidInput {
+                    instruction,
+                    program_pointer,
+                    input: text,
+                    ..
+                }) => {
+                    assert_eq!(program_pointer, 1);
+                    assert_eq!(instruction.location(), bft_types::BfLocation::new(2, 1));
+                    assert_eq!(text, input.trim_end_matches(','));
+                }
+                _ => panic!("Expected {:?} to be invalid", input),
+            }
+        }
+    }
+
+    /// Test each end of file behaviour when there is no input left
+    #[test]
+    fn eof_behaviors() {
+        let program = BfProgram::new("eof.bf", ",").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        let mut reader = std::io::empty();
+        assert_eq!(tape.eof_behavior(), cli::EofBehavior::NegOne);
+        tape.set_data_value(42);
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_data_value(), 255);
+
+        tape.set_eof_behavior(cli::EofBehavior::Zero);
+        tape.set_data_value(42);
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_data_value(), 0);
+
+        tape.set_eof_behavior(cli::EofBehavior::Unchanged);
+        tape.set_data_value(42);
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_data_value(), 42);
+
+        // Input that isn't at end of file is unaffected
+        let mut reader = std::io::Cursor::new(b"A".to_vec());
+        tape.input_value(&mut reader).unwrap();
+        assert_eq!(tape.get_data_value(), b'A');
+    }
+
+    /// Test each overflow behaviour at both limits of a u8 cell
+    #[test]
+    fn overflow_behaviors() {
+        let program = BfProgram::new("overflow.bf", "+-").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert_eq!(tape.overflow_behavior(), cli::OverflowBehavior::Wrap);
+        tape.set_data_value(255);
+        tape.increment_data_value().unwrap();
+        assert_eq!(tape.get_data_value(), 0);
+        tape.decrement_data_value().unwrap();
+        assert_eq!(tape.get_data_value(), 255);
+
+        tape.set_overflow_behavior(cli::OverflowBehavior::Saturate);
+        tape.increment_data_value().unwrap();
+        assert_eq!(tape.get_data_value(), 255);
+        tape.set_data_value(0);
+        tape.decrement_data_value().unwrap();
+        assert_eq!(tape.get_data_value(), 0);
+
+        tape.set_overflow_behavior(cli::OverflowBehavior::Error);
+        match tape.decrement_data_value() {
+            Err(BfError::CellOverflow { value, .. }) => assert_eq!(value, "0"),
+            _ => panic!("Expected decrementing 0 to overflow"),
+        }
+        assert_eq!(tape.get_data_value(), 0);
+        tape.set_data_value(255);
+        assert!(matches!(
+            tape.increment_data_value(),
+            Err(BfError::CellOverflow { .. })
+        ));
+        assert_eq!(tape.get_data_value(), 255);
+        tape.decrement_data_value().unwrap();
+        assert_eq!(tape.get_data_value(), 254);
+    }
+
+    /// Test that an overflow error names the instruction that caused it
+    #[test]
+    fn overflow_error_location() {
+        let program = BfProgram::new("overflow.bf", "+\n--").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            100,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        tape.set_overflow_behavior(cli::OverflowBehavior::Error);
+        let mut reader = std::io::Cursor::new(Vec::new());
+        let mut writer = std::io::Cursor::new(Vec::new());
+        match tape.interpreter(&mut reader, &mut writer) {
+            Err(BfError::CellOverflow {
+                instruction,
+                program_pointer,
+                ..
+            }) => {
+                assert_eq!(program_pointer, 2);
+                assert_eq!(instruction.location(), bft_types::BfLocation::new(2, 2));
+            }
+            _ => panic!("Expected an overflow"),
+        }
+    }
+
+    /// Test the window of cells around the data pointer
+    #[test]
+    fn tape_window_is_clamped_at_start() {
+        let program = BfProgram::new("tiny.bf", "").unwrap();
+        let mut tape: BfTape<u8> = BfTape::new(
+            &program,
+            10,
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        );
+        assert_eq!(tape.tape_window(3), (0, Cow::Borrowed(&[0u8; 4][..])));
+        tape.move_data_pointer_forward().unwrap();
+        tape.set_data_value(7);
+        assert_eq!(tape.tape_window(1), (0, Cow::Borrowed(&[0u8, 7, 0][..])));
+    }
+
+    /// Test running a program through the Read/Write adapter and through VecIo
+    #[test]
+    fn bf_io_implementations() {
+        let mut program = BfProgram::new("rot.bf", ",[+.,]").unwrap();
+        program.validate().unwrap();
+        let builder = BfTapeBuilder::new().eof_behavior(cli::EofBehavior::Zero);
+
+        let mut tape: BfTape<u8> = builder.build(&program);
+        let mut io = StdIo::new(std::io::Cursor::new(b"HAL".to_vec()), Vec::new());
+        assert_eq!(tape.interpreter_io(&mut io).unwrap(), StopReason::Halted);
+        assert_eq!(io.into_inner().1, b"IBM");
+
+        let mut tape: BfTape<u8> = builder.build(&program);
+        let mut io = VecIo::new(b"HAL".to_vec());
+        assert_eq!(tape.interpreter_io(&mut io).unwrap(), StopReason::Halted);
+        assert_eq!(io.output(), b"IBM");
+        assert!(io.remaining_input().is_empty());
+
+        // Stepping only takes as much input as is needed
+        let mut tape: BfTape<u8> = builder.build(&program);
+        let mut io = VecIo::new(b"HAL".to_vec());
+        for _ in 0..4 {
+            assert_eq!(tape.step_io(&mut io).unwrap(), None);
+        }
+        assert_eq!(io.output(), b"I");
+        assert_eq!(io.remaining_input(), b"AL");
+    }
+
+    /// Test that numbers and newlines are written through a BfIo
+    #[test]
+    fn bf_io_numbers() {
+        let program = BfProgram::new("numbers.bf", "+.+.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .output_format(cli::OutputFormat::BinaryOutput)
+            .trailing_newline(cli::TrailingNewline::Always)
+            .build(&program);
+        let mut io = VecIo::default();
+        tape.interpreter_io(&mut io).unwrap();
+        assert_eq!(io.into_output(), b"1,2\n");
+    }
+
+    /// Test the cat program running asynchronously, with the input arriving over a stream
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_cat() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut program = BfProgram::new("cat.bf", ",[.,]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .eof_behavior(cli::EofBehavior::Zero)
+            .build(&program);
+
+        let (mut input, mut reader) = tokio::io::duplex(4);
+        let (mut writer, mut output) = tokio::io::duplex(4);
+        let send = async {
+            input.write_all(b"Hello, world!").await.unwrap();
+            drop(input);
+        };
+        let receive = async {
+            let mut received = Vec::new();
+            output.read_to_end(&mut received).await.unwrap();
+            received
+        };
+        let run = async {
+            let result = tape.interpreter_async(&mut reader, &mut writer).await;
+            drop(writer);
+            result
+        };
+        let (_, received, result) = tokio::join!(send, receive, run);
+        assert_eq!(result.unwrap(), StopReason::Halted);
+        assert_eq!(received, b"Hello, world!");
+    }
+
+    /// Test that dropping the future leaves the program at the input instruction it was
+    /// waiting on, and that it carries on when the interpreter is called again
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_cancelled_on_input() {
+        use tokio::io::AsyncWriteExt;
+
+        let program = BfProgram::new("add.bf", "+.,.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .output_format(cli::OutputFormat::BinaryOutput)
+            .build(&program);
+        let (mut input, mut reader) = tokio::io::duplex(4);
+        let mut writer = Vec::new();
+
+        tokio::select! {
+            biased;
+            _ = tape.interpreter_async(&mut reader, &mut writer) => panic!("No input was sent"),
+            _ = tokio::task::yield_now() => {}
+        }
+        assert_eq!(tape.program_pointer(), 2);
+        assert_eq!(tape.steps(), 3);
+        assert_eq!(writer, b"1");
+
+        input.write_all(&[5]).await.unwrap();
+        assert_eq!(
+            tape.interpreter_async(&mut reader, &mut writer)
+                .await
+                .unwrap(),
+            StopReason::Halted
+        );
+        assert_eq!(tape.steps(), 4);
+        assert_eq!(writer, b"1,5");
+    }
+
+    /// Test that dropping the future while output is blocked doesn't lose or repeat output
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_cancelled_on_output() {
+        use tokio::io::AsyncReadExt;
+
+        let program = BfProgram::new("count.bf", "+.+.+.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .output_format(cli::OutputFormat::BinaryOutput)
+            .build(&program);
+        let (mut writer, mut output) = tokio::io::duplex(2);
+        let mut reader = tokio::io::empty();
+
+        tokio::select! {
+            biased;
+            _ = tape.interpreter_async(&mut reader, &mut writer) => panic!("The output is full"),
+            _ = tokio::task::yield_now() => {}
+        }
+        assert_eq!(tape.program_pointer(), 3);
+
+        let run = async {
+            let result = tape.interpreter_async(&mut reader, &mut writer).await;
+            drop(writer);
+            result
+        };
+        let receive = async {
+            let mut received = Vec::new();
+            output.read_to_end(&mut received).await.unwrap();
+            received
+        };
+        let (result, received) = tokio::join!(run, receive);
+        assert_eq!(result.unwrap(), StopReason::Halted);
+        assert_eq!(received, b"1,2,3");
+    }
+
+    /// Test the cat program running on its own thread
+    #[test]
+    fn spawn_cat() {
+        let mut program = BfProgram::new("cat.bf", ",[.,]").unwrap();
+        program.validate().unwrap();
+        let config = BfTapeBuilder::new().eof_behavior(cli::EofBehavior::Zero);
+        let mut running = spawn(program, config);
+
+        for byte in b"echo" {
+            running.input().unwrap().send(*byte).unwrap();
+            assert_eq!(running.output().recv().unwrap(), *byte);
+        }
+        running.close_input();
+        let stats = running.join().unwrap();
+        // The first byte is read and tested, then each byte is output and the next one read
+        // and tested
+        assert_eq!(stats.steps, 2 + 4 * 3);
+    }
+
+    /// Test pausing, resuming and aborting a program that never ends
+    #[test]
+    fn spawn_pause_and_abort() {
+        let mut program = BfProgram::new("forever.bf", "+[.]").unwrap();
+        program.validate().unwrap();
+        let running = spawn(program, BfTapeBuilder::new());
+        let control = running.control();
+        assert_eq!(running.output().recv().unwrap(), 1);
+
+        control.pause();
+        assert!(control.is_paused());
+        std::thread::sleep(Duration::from_millis(50));
+        while running.output().try_recv().is_ok() {}
+        assert!(running
+            .output()
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
+
+        control.resume();
+        assert_eq!(running.output().recv().unwrap(), 1);
+
+        control.abort();
+        assert!(matches!(running.join(), Err(BfError::Interrupted { .. })));
+    }
+
+    /// Test aborting a program that is waiting for input
+    #[test]
+    fn spawn_abort_waiting_for_input() {
+        let program = BfProgram::new("read.bf", ",+").unwrap();
+        let running = spawn(program, BfTapeBuilder::new());
+        std::thread::sleep(Duration::from_millis(50));
+        // The input is still open, so the program only stops because it was aborted
+        running.control().abort();
+        assert!(matches!(running.join(), Err(BfError::Interrupted { .. })));
+    }
+
+    /// Test taking the start of the output of a program that never ends
+    #[test]
+    fn outputs_of_endless_program() {
+        let mut program = BfProgram::new("count.bf", "+[.+]").unwrap();
+        program.validate().unwrap();
+        let tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let mut outputs = tape.outputs(std::io::empty());
+        let first: Vec<u8> = outputs.by_ref().take(5).map(Result::unwrap).collect();
+        assert_eq!(first, [1, 2, 3, 4, 5]);
+        assert_eq!(outputs.next().unwrap().unwrap(), 6);
+        drop(outputs);
+    }
+
+    /// Test that the output of a program that ends is the same as from the interpreter
+    #[test]
+    fn outputs_of_finite_program() {
+        let mut program = BfProgram::new("hello.bf", include_str!("../../hello-world.bf")).unwrap();
+        program.validate().unwrap();
+        let builder = BfTapeBuilder::new().trailing_newline(cli::TrailingNewline::Always);
+        let mut writer = Vec::new();
+        builder
+            .build::<u8>(&program)
+            .interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+
+        let mut outputs = builder.build::<u8>(&program).outputs(std::io::empty());
+        let output: Result<Vec<u8>, BfError> = outputs.by_ref().collect();
+        assert_eq!(output.unwrap(), writer);
+        assert!(outputs.next().is_none());
+
+        // An error ends the output
+        let program = BfProgram::new("error.bf", ".<.").unwrap();
+        let mut outputs = BfTapeBuilder::new()
+            .build::<u8>(&program)
+            .outputs(std::io::empty());
+        assert_eq!(outputs.next().unwrap().unwrap(), 0);
+        assert!(matches!(
+            outputs.next(),
+            Some(Err(BfError::DataPtrMovedBeforeStart { .. }))
+        ));
+        assert!(outputs.next().is_none());
+    }
+
+    /// Test running a program from its source
+    #[test]
+    fn run_from_source() {
+        let output = run_to_string(
+            include_str!("../../hello-world.bf"),
+            b"",
+            BfTapeBuilder::new(),
+        );
+        assert_eq!(output.unwrap(), "Hello World!\n");
+
+        let output = run_with_io(
+            ",[.,]",
+            b"cat",
+            BfTapeBuilder::new().eof_behavior(cli::EofBehavior::Zero),
+        );
+        assert_eq!(output.unwrap(), b"cat");
+
+        // Invalid UTF-8 is replaced
+        let output = run_to_string("-.", b"", BfTapeBuilder::new());
+        assert_eq!(output.unwrap(), "\u{FFFD}");
+    }
+
+    /// Test that an invalid program is reported through the same result as running it
+    #[test]
+    fn run_from_invalid_source() {
+        match run_with_io("+[.", b"", BfTapeBuilder::new()) {
+            Err(BfError::InvalidProgram { error_msg }) => {
+                assert!(error_msg.to_string().contains("1:2"), "{}", error_msg)
+            }
+            other => panic!("Expected an invalid program, got {:?}", other),
+        }
+    }
+
+    /// Test that errors can be raised by an empty program without panicking
+    #[test]
+    fn errors_in_empty_program() {
+        let program = BfProgram::new("empty.bf", "").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().tape_size(1).build(&program);
+        assert_eq!(tape.current_instruction(), None);
+        assert!(matches!(
+            tape.move_data_pointer_back(),
+            Err(BfError::DataPtrMovedBeforeStart {
+                program_pointer: 0,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tape.move_data_pointer_forward(),
+            Err(BfError::DataPtrMovedAfterEnd { .. })
+        ));
+        assert!(matches!(
+            tape.move_program_pointer_forward(),
+            Err(BfError::ProgramPtrMovedAfterEnd { .. })
+        ));
+        assert_eq!(
+            tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+                .unwrap(),
+            StopReason::Halted
+        );
+    }
+
+    /// Test that errors raised at and after the last instruction report the last instruction
+    #[test]
+    fn errors_at_last_instruction() {
+        let program = BfProgram::new("last.bf", "+\n<").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        match tape.interpreter(&mut std::io::empty(), &mut std::io::sink()) {
+            Err(BfError::DataPtrMovedBeforeStart {
+                instruction,
+                program_pointer,
+                ..
+            }) => {
+                assert_eq!(program_pointer, 1);
+                assert_eq!(instruction.location(), bft_types::BfLocation::new(2, 1));
+            }
+            other => panic!(
+                "Expected the data pointer to move before the start, got {:?}",
+                other
+            ),
+        }
+
+        let program = BfProgram::new("last.bf", "+\n+").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.move_program_pointer_forward().unwrap();
+        assert!(matches!(
+            tape.move_program_pointer_forward(),
+            Err(BfError::ProgramPtrMovedAfterEnd {
+                program_pointer: 1,
+                ..
+            })
+        ));
+
+        // Once the program has finished the program pointer is past the last instruction
+        tape.reset();
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        assert_eq!(tape.current_instruction(), None);
+        match tape.move_data_pointer_back() {
+            Err(BfError::DataPtrMovedBeforeStart {
+                instruction,
+                program_pointer,
+                ..
+            }) => {
+                assert_eq!(program_pointer, 2);
+                assert_eq!(instruction.location(), bft_types::BfLocation::new(2, 1));
+            }
+            other => panic!(
+                "Expected the data pointer to move before the start, got {:?}",
+                other
+            ),
+        }
+    }
+
+    /// Test that comment instructions do nothing when run
+    #[test]
+    fn comments_are_skipped() {
+        use bft_types::{BfCommand, BfInstruction};
+        let mut program = BfProgram::from_instructions(
+            "comments.bf",
+            vec![
+                BfInstruction::new(BfCommand::Comment, 1, 1),
+                BfInstruction::new(BfCommand::IncValue, 1, 2),
+                BfInstruction::new(BfCommand::JumpForward, 1, 3),
+                BfInstruction::new(BfCommand::Comment, 1, 4),
+                BfInstruction::new(BfCommand::OutputValue, 1, 5),
+                BfInstruction::new(BfCommand::DecValue, 1, 6),
+                BfInstruction::new(BfCommand::JumpBackward, 1, 7),
+                BfInstruction::new(BfCommand::Comment, 1, 8),
+            ],
+        );
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let mut writer = Vec::new();
+        assert_eq!(
+            tape.interpreter(&mut std::io::empty(), &mut writer)
+                .unwrap(),
+            StopReason::Halted
+        );
+        assert_eq!(writer, [1]);
+        assert_eq!(tape.program_pointer(), 8);
+        assert_eq!(tape.steps(), 8);
+    }
+
+    /// Test that errors show the line of source that they happened on
+    #[test]
+    fn error_source_snippets() {
+        let program = BfProgram::new("before.bf", "+\n\t+ <<").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[E_TAPE_UNDERRUN] Data pointer moved before start of tape at 2 Decrement data pointer @2:4\n\
+             \x20 |\n\
+             2 | \t+ <<\n\
+             \x20 | \t  ^"
+        );
+
+        // Only the part of a long line around the column is shown
+        let source = format!("{}<{}", "+".repeat(100), "+".repeat(5));
+        let program = BfProgram::new("long.bf", &source).unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "[E_TAPE_UNDERRUN] Data pointer moved before start of tape at 100 Decrement data pointer @1:101\n  |\n1 | ...{}<+++++\n  | {}^",
+                "+".repeat(54),
+                " ".repeat(3 + 54)
+            )
+        );
+        let source = format!("{}<{}", "+".repeat(100), "+".repeat(100));
+        let program = BfProgram::new("long.bf", &source).unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "[E_TAPE_UNDERRUN] Data pointer moved before start of tape at 100 Decrement data pointer @1:101\n  |\n1 | ...{}<{}...\n  | {}^",
+                "+".repeat(30),
+                "+".repeat(29),
+                " ".repeat(3 + 30)
+            )
+        );
+
+        // Programs built from instructions have no source to show
+        let program = BfProgram::from_instructions(
+            "built.bf",
+            vec![bft_types::BfInstruction::new(
+                bft_types::BfCommand::DecDataPointer,
+                1,
+                1,
+            )],
+        );
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[E_TAPE_UNDERRUN] Data pointer moved before start of tape at 0 Decrement data pointer @1:1"
+        );
+    }
+
+    /// Writer whose reader has gone away, such as a pipe into a program that has exited
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Test that the underlying i/o error can be got at from an IOError
+    #[test]
+    fn io_error_source() {
+        use std::error::Error;
+
+        let program = BfProgram::new("out.bf", "+.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut ClosedPipe)
+            .unwrap_err();
+        assert_eq!(error.io_kind(), Some(std::io::ErrorKind::BrokenPipe));
+        let source = error
+            .source()
+            .and_then(|source| source.downcast_ref::<std::io::Error>())
+            .unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::BrokenPipe);
+
+        let program = BfProgram::new("back.bf", "<").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut ClosedPipe)
+            .unwrap_err();
+        assert_eq!(error.io_kind(), None);
+        assert!(error.source().is_none());
+    }
+
+    /// Test that every error has its own code, which is shown in its message
+    #[test]
+    fn error_codes() {
+        let instruction = bft_types::BfInstruction::new(bft_types::BfCommand::IncValue, 1, 1);
+        // One of each error, the code method's match makes sure that new errors get a code
+        let errors = [
+            BfError::DataPtrMovedBeforeStart {
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::DataPtrMovedAfterEnd {
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::ProgramPtrMovedAfterEnd {
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::StepLimitExceeded {
+                steps: 1,
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::Interrupted {
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::InvalidInput {
+                instruction,
+                program_pointer: 0,
+                input: "x".to_string(),
+                snippet: SourceSnippet::default(),
+            },
+            BfError::CellOverflow {
+                instruction,
+                program_pointer: 0,
+                value: "256".to_string(),
+                snippet: SourceSnippet::default(),
+            },
+            BfError::TapeLimitExceeded {
+                limit: 1,
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::ImageTooLarge { size: 2, length: 1 },
+            BfError::CellOutOfRange { cell: 1, length: 1 },
+            BfError::BracketNotFound {
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::IOError {
+                error_msg: std::io::ErrorKind::Other.into(),
+                filepath: std::path::PathBuf::from("io.bf"),
+                instruction,
+                program_pointer: 0,
+                snippet: SourceSnippet::default(),
+            },
+            BfError::InvalidProgram {
+                error_msg: anyhow::anyhow!("Extra ]"),
+            },
+            BfError::ProbableInfiniteLoop {
+                location: instruction.location(),
+                iterations: 1,
+                snippet: SourceSnippet::default(),
+            },
+        ];
+
+        let mut codes = std::collections::HashSet::new();
+        for error in &errors {
+            assert!(error.code().starts_with("E_"));
+            assert!(
+                codes.insert(error.code()),
+                "Duplicate code {}",
+                error.code()
+            );
+            assert!(error
+                .to_string()
+                .starts_with(&format!("[{}] ", error.code())));
+        }
+        assert_eq!(errors[0].code(), "E_TAPE_UNDERRUN");
+        assert_eq!(errors[1].code(), "E_TAPE_OVERRUN");
+        assert_eq!(errors[3].code(), "E_STEP_LIMIT");
+        assert_eq!(errors[10].code(), "E_BRACKET");
+        assert_eq!(errors[11].code(), "E_IO");
+    }
+
+    /// Test that a loop that never changes is stopped by the loop guard
+    #[test]
+    fn loop_guard_stops_infinite_loop() {
+        let mut program = BfProgram::new("forever.bf", ">+\n[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().loop_guard(Some(1000)).build(&program);
+        match tape.interpreter(&mut std::io::empty(), &mut std::io::sink()) {
+            Err(BfError::ProbableInfiniteLoop {
+                location,
+                iterations,
+                ..
+            }) => {
+                assert_eq!(location, bft_types::BfLocation::new(2, 1));
+                assert_eq!(iterations, 1000);
+            }
+            other => panic!("Expected an infinite loop, got {:?}", other),
+        }
+        assert_eq!(tape.steps(), 2 + 1 + 1000);
+
+        // A loop that waits for input isn't stopped
+        let mut program = BfProgram::new("wait.bf", "+[,]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .loop_guard(Some(2))
+            .eof_behavior(cli::EofBehavior::Zero)
+            .build(&program);
+        let mut reader = std::io::Cursor::new(vec![1; 10]);
+        assert!(tape.interpreter(&mut reader, &mut std::io::sink()).is_ok());
+    }
+
+    /// Test that a long loop that changes its cell each time isn't stopped by the loop guard
+    #[test]
+    fn loop_guard_allows_counting_loop() {
+        let mut program = BfProgram::new("count.bf", "-[>++++[-]<-]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().loop_guard(Some(10)).build(&program);
+        assert_eq!(
+            tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+                .unwrap(),
+            StopReason::Halted
+        );
+        assert_eq!(tape.get_cell_at(0).unwrap(), 0);
+    }
+
+    #[test]
+    fn step_back_matches_fewer_steps() {
+        let program = bft_types::BfProgram::new("history.bf", "+>+<-+>+<-").unwrap();
+        let mut reader = std::io::empty();
+        let mut writer = std::io::sink();
+
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.set_history(Some(5));
+        for _ in 0..10 {
+            tape.step(&mut reader, &mut writer).unwrap();
+        }
+        assert_eq!(tape.history_len(), 5);
+        for _ in 0..3 {
+            assert!(tape.step_back());
+        }
+
+        let mut fresh: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        for _ in 0..7 {
+            fresh.step(&mut reader, &mut writer).unwrap();
+        }
+        assert_eq!(tape.cells(), fresh.cells());
+        assert_eq!(tape.data_pointer(), fresh.data_pointer());
+        assert_eq!(tape.program_pointer(), fresh.program_pointer());
+        assert_eq!(tape.steps(), fresh.steps());
+
+        // Only as many steps as the history holds can be undone
+        assert!(tape.step_back());
+        assert!(tape.step_back());
+        assert!(!tape.step_back());
+        assert_eq!(tape.steps(), 5);
+    }
+
+    #[test]
+    fn step_back_gives_back_input() {
+        let program = bft_types::BfProgram::new("history.bf", ",>,").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.set_history(Some(10));
+        let mut writer = std::io::sink();
+        tape.interpreter(&mut "ab".as_bytes(), &mut writer).unwrap();
+        assert!(tape.step_back());
+        assert!(tape.step_back());
+        assert_eq!(&tape.cells()[..2], &[b'a', 0]);
+
+        // The second byte is read again even though the reader has nothing left
+        tape.interpreter(&mut std::io::empty(), &mut writer)
+            .unwrap();
+        assert_eq!(&tape.cells()[..2], b"ab");
+    }
+
+    /// Test the memory used by a tape that grows
+    #[test]
+    fn memory_stats_of_growing_tape() {
+        let program = BfProgram::new("grow.bf", &">".repeat(2 * TAPE_GROWTH_CHUNK + 10)).unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(10)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+            .build(&program);
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        let stats = tape.run_stats().memory;
+        assert_eq!(stats.growth_events, 3);
+        assert_eq!(stats.high_water, 2 * TAPE_GROWTH_CHUNK + 10);
+        assert_eq!(stats.allocated_cells, 3 * TAPE_GROWTH_CHUNK + 10);
+        assert_eq!(stats.allocated_bytes, stats.allocated_cells);
+
+        // Resetting the tape starts counting again, the cells stay allocated
+        tape.reset();
+        assert_eq!(tape.memory_stats().growth_events, 0);
+        assert_eq!(tape.memory_stats().high_water, 0);
+        assert_eq!(
+            tape.memory_stats().allocated_cells,
+            3 * TAPE_GROWTH_CHUNK + 10
+        );
+    }
+
+    /// Run a program an instruction at a time and compiled, returning the output or error of
+    /// each and the tapes
+    #[allow(clippy::type_complexity)]
+    fn run_both_ways<'a>(
+        program: &'a BfProgram,
+        builder: &BfTapeBuilder,
+        input: &[u8],
+    ) -> (
+        (Result<Vec<u8>, BfError>, BfTape<'a, u8>),
+        (Result<Vec<u8>, BfError>, BfTape<'a, u8>),
+    ) {
+        let ir = program.compile().unwrap();
+        let mut tape = builder.build(program);
+        let mut output = Vec::new();
+        let result = tape.interpreter(&mut &input[..], &mut output);
+        let naive = (result.map(|_| output), tape);
+
+        let mut tape = builder.build(program);
+        let mut output = Vec::new();
+        let result = tape.run_ir(&ir, &mut &input[..], &mut output);
+        (naive, (result.map(|_| output), tape))
+    }
+
+    /// Test that compiled programs output the same as running them an instruction at a time
+    #[test]
+    fn compiled_output_is_identical() {
+        let mut hello = BfProgram::new("hello.bf", include_str!("../../hello-world.bf")).unwrap();
+        hello.validate().unwrap();
+        let ((naive, naive_tape), (compiled, compiled_tape)) =
+            run_both_ways(&hello, &BfTapeBuilder::new(), b"");
+        assert_eq!(naive.unwrap(), compiled.unwrap());
+        assert_eq!(naive_tape.cells(), compiled_tape.cells());
+        assert_eq!(naive_tape.data_pointer(), compiled_tape.data_pointer());
+        assert!(compiled_tape.steps() < naive_tape.steps());
+
+        let mut cat = BfProgram::new("cat.bf", ",[.,]").unwrap();
+        cat.validate().unwrap();
+        let input: Vec<u8> = (1..=255).collect();
+        let builder = BfTapeBuilder::new().eof_behavior(cli::EofBehavior::Zero);
+        let ((naive, _), (compiled, _)) = run_both_ways(&cat, &builder, &input);
+        assert_eq!(naive.unwrap(), input);
+        assert_eq!(compiled.unwrap(), input);
+    }
+
+    /// Test that errors in compiled programs are reported at the same instruction
+    #[test]
+    fn compiled_errors_are_identical() {
+        let cases = [
+            // Off the end in the middle of a run of moves
+            ("tape.bf", "+>>>>", BfTapeBuilder::new().tape_size(3)),
+            // Scanning off the end of the tape
+            ("scan.bf", "+>+>+<<[>]", BfTapeBuilder::new().tape_size(3)),
+            // A multiply that overflows part way through the loop
+            (
+                "overflow.bf",
+                "++++++++++++++++[->++++++++++++++++<]",
+                BfTapeBuilder::new().overflow_behavior(cli::OverflowBehavior::Error),
+            ),
+        ];
+        for (name, source, builder) in cases {
+            let mut program = BfProgram::new(name, source).unwrap();
+            program.validate().unwrap();
+            let ((naive, _), (compiled, _)) = run_both_ways(&program, &builder, b"");
+            let (naive, compiled) = (naive.unwrap_err(), compiled.unwrap_err());
+            assert_eq!(naive.code(), compiled.code(), "{}", name);
+            assert_eq!(naive.to_string(), compiled.to_string(), "{}", name);
+        }
+    }
+
+    /// Test that a multiply on a tape that can grow grows it as the loop would have
+    #[test]
+    fn compiled_multiply_grows_tape() {
+        let mut program = BfProgram::new("grow.bf", "+++[->>++<<]>>.").unwrap();
+        program.validate().unwrap();
+        let builder = BfTapeBuilder::new()
+            .tape_size(1)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+            .output_format(cli::OutputFormat::BinaryOutput);
+        let ((naive, _), (compiled, tape)) = run_both_ways(&program, &builder, b"");
+        assert_eq!(naive.unwrap(), b"6");
+        assert_eq!(compiled.unwrap(), b"6");
+        assert_eq!(tape.memory_stats().high_water, 2);
+    }
+
+    /// Output of a run of a program and the tape it ran on
+    #[cfg(feature = "jit")]
+    type OutputAndTape<'a, T> = (Result<Vec<u8>, BfError>, BfTape<'a, T>);
+
+    /// Run a program with the jit and with the interpreter, returning the output and tape of each
+    #[cfg(feature = "jit")]
+    fn run_jit_and_naive<'a, T: CellKind>(
+        program: &'a BfProgram,
+        builder: &BfTapeBuilder,
+        input: &[u8],
+    ) -> (OutputAndTape<'a, T>, OutputAndTape<'a, T>) {
+        let ir = program.compile().unwrap();
+        let mut tape = builder.build(program);
+        let mut output = Vec::new();
+        let result = tape.interpreter(&mut &input[..], &mut output);
+        let naive = (result.map(|_| output), tape);
+
+        let mut tape = builder.build(program);
+        let mut output = Vec::new();
+        let result = tape.run_jit(&ir, &mut &input[..], &mut output);
+        (naive, (result.map(|_| output), tape))
+    }
+
+    /// Test that native code outputs the same and leaves the tape the same as the interpreter
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_output_is_identical() {
+        let examples = [
+            ("hello.bf", include_str!("../../hello-world.bf"), &b""[..]),
+            (
+                "rot13.bf",
+                include_str!("../../rot13.bf"),
+                b"Hello, World!\n",
+            ),
+            ("prob2.bf", include_str!("../../prob2.bf"), b""),
+            ("loop.bf", include_str!("../../loop-three-times.bf"), b""),
+            ("seven.bf", include_str!("../../output-seven.bf"), b""),
+            (
+                "three.bf",
+                include_str!("../../input-and-output-three-values.bf"),
+                b"abc",
+            ),
+        ];
+        let builder = BfTapeBuilder::new();
+        for (name, source, input) in examples {
+            let mut program = BfProgram::new(name, source).unwrap();
+            program.validate().unwrap();
+            let ((naive, naive_tape), (jit, jit_tape)) =
+                run_jit_and_naive::<u8>(&program, &builder, input);
+            assert_eq!(naive.unwrap(), jit.unwrap(), "{}", name);
+            assert_eq!(naive_tape.cells(), jit_tape.cells(), "{}", name);
+            assert_eq!(
+                naive_tape.data_pointer(),
+                jit_tape.data_pointer(),
+                "{}",
+                name
+            );
+        }
+
+        let mut cat = BfProgram::new("cat.bf", ",[.,]").unwrap();
+        cat.validate().unwrap();
+        let input: Vec<u8> = (1..=255).collect();
+        let builder = BfTapeBuilder::new().eof_behavior(cli::EofBehavior::Zero);
+        let (_, (jit, _)) = run_jit_and_naive::<u8>(&cat, &builder, &input);
+        assert_eq!(jit.unwrap(), input);
+
+        // Wider cells wrap at their own size
+        let mut count = BfProgram::new("count.bf", "-[->+<]").unwrap();
+        count.validate().unwrap();
+        let ((_, naive_tape), (jit, jit_tape)) =
+            run_jit_and_naive::<u16>(&count, &BfTapeBuilder::new(), b"");
+        jit.unwrap();
+        assert_eq!(jit_tape.cells()[..2], [0, u16::MAX]);
+        assert_eq!(naive_tape.cells(), jit_tape.cells());
+    }
+
+    /// Test that native code hands over to the interpreter to grow the tape or report errors
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_leaves_tape_errors_to_interpreter() {
+        let mut program = BfProgram::new("scan.bf", "+>+>+<<[>]").unwrap();
+        program.validate().unwrap();
+        let builder = BfTapeBuilder::new().tape_size(3);
+        let ((naive, _), (jit, _)) = run_jit_and_naive::<u8>(&program, &builder, b"");
+        let (naive, jit) = (naive.unwrap_err(), jit.unwrap_err());
+        assert_eq!(naive.code(), jit.code());
+        assert_eq!(naive.to_string(), jit.to_string());
+
+        let mut program = BfProgram::new("grow.bf", "+++[->>++<<]>>.").unwrap();
+        program.validate().unwrap();
+        let builder = BfTapeBuilder::new()
+            .tape_size(1)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+            .output_format(cli::OutputFormat::BinaryOutput);
+        let ((naive, naive_tape), (jit, jit_tape)) =
+            run_jit_and_naive::<u8>(&program, &builder, b"");
+        assert_eq!(naive.unwrap(), b"6");
+        assert_eq!(jit.unwrap(), b"6");
+        assert_eq!(naive_tape.data_pointer(), jit_tape.data_pointer());
+        assert_eq!(naive_tape.current_depth(), jit_tape.current_depth());
+    }
+
+    /// Test that input recorded from one run replays the same run without a reader
+    #[test]
+    fn record_and_replay_input() {
+        let mut program = BfProgram::new("rot13.bf", include_str!("../../rot13.bf")).unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.enable_input_recording();
+        let mut recorded_output = Vec::new();
+        tape.interpreter(
+            &mut std::io::Cursor::new(b"Hello, World!"),
+            &mut recorded_output,
+        )
+        .unwrap();
+        let recorded = tape.recorded_input().unwrap().to_vec();
+        assert_eq!(recorded, b"Hello, World!");
+
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.set_replay_input(recorded);
+        let mut replayed_output = Vec::new();
+        tape.interpreter(&mut std::io::empty(), &mut replayed_output)
+            .unwrap();
+        assert_eq!(replayed_output, recorded_output);
+        assert_eq!(tape.recorded_input(), None);
+
+        // Reset starts the replay again and the reader is used once the replay runs out
+        tape.reset();
+        let mut output = Vec::new();
+        tape.interpreter(&mut std::io::Cursor::new(b"!"), &mut output)
+            .unwrap();
+        assert_eq!(output, b"Uryyb, Jbeyq!!");
+    }
+
+    /// Test that input given back by step_back isn't recorded twice
+    #[test]
+    fn recording_ignores_undone_input() {
+        let program = BfProgram::new("tiny.bf", ",.,.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.enable_input_recording();
+        tape.set_history(Some(4));
+        let mut io = VecIo::new(b"ab".to_vec());
+        tape.interpreter_io(&mut io).unwrap();
+        assert!(tape.step_back());
+        assert!(tape.step_back());
+        tape.interpreter_io(&mut io).unwrap();
+        assert_eq!(io.output(), b"abb");
+        assert_eq!(tape.recorded_input(), Some(&b"ab"[..]));
+    }
+
+    /// Test that the Trace debug level shows the tape after every instruction
+    #[test]
+    fn trace_debug_shows_tape() {
+        let program = BfProgram::new("debug.bf", "+>++<").unwrap();
+        let mut debug = Vec::new();
+        let mut output = Vec::new();
+        {
+            let mut tape: BfTape<u8> = BfTapeBuilder::new()
+                .tape_size(4)
+                .debug(cli::DebugLevelType::Trace)
+                .build(&program);
+            tape.set_debug_writer(Box::new(&mut debug));
+            tape.interpreter(&mut std::io::empty(), &mut output)
+                .unwrap();
+        }
+        assert!(output.is_empty());
+        let debug = String::from_utf8(debug).unwrap();
+        let windows: Vec<&str> = debug
+            .lines()
+            .filter(|line| line.starts_with("Tape "))
+            .collect();
+        assert_eq!(
+            windows,
+            [
+                "Tape dp=0 pp=1 depth=0 [0] >1< 0 0 0 [3]",
+                "Tape dp=1 pp=2 depth=0 [0] 1 >0< 0 0 [3]",
+                "Tape dp=1 pp=3 depth=0 [0] 1 >1< 0 0 [3]",
+                "Tape dp=1 pp=4 depth=0 [0] 1 >2< 0 0 [3]",
+                "Tape dp=0 pp=5 depth=0 [0] >1< 2 0 0 [3]",
+            ]
+        );
+
+        // Only the Trace level shows the tape
+        let mut debug = Vec::new();
+        {
+            let mut tape: BfTape<u8> = BfTapeBuilder::new()
+                .debug(cli::DebugLevelType::Detailed)
+                .build(&program);
+            tape.set_debug_writer(Box::new(&mut debug));
+            tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+                .unwrap();
+        }
+        assert!(!String::from_utf8(debug).unwrap().contains("Tape "));
+    }
+
+    /// A writer that takes a while to write, like a slow terminal
+    struct SlowWriter;
+
+    impl std::io::Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(2));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Test that the time of an output heavy program is put down to the output command
+    #[test]
+    fn timing_profiler() {
+        let mut program = BfProgram::new("output.bf", "++++++++++[>+.<-]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        assert_eq!(tape.run_stats().timings, None);
+        tape.enable_timing_profiler();
+        let started = Instant::now();
+        tape.interpreter(&mut std::io::empty(), &mut SlowWriter)
+            .unwrap();
+        let elapsed = started.elapsed();
+
+        let timings = tape.run_stats().timings.unwrap();
+        assert_eq!(timings.count(bft_types::BfCommand::OutputValue), 10);
+        assert_eq!(timings.count(bft_types::BfCommand::IncValue), 20);
+        assert_eq!(timings.count(bft_types::BfCommand::InputValue), 0);
+        // The buckets account for nearly all of the run
+        assert!(timings.total() <= elapsed);
+        assert!(timings.total() >= elapsed.mul_f64(0.8));
+        assert!(
+            timings.duration(bft_types::BfCommand::OutputValue) >= timings.total().mul_f64(0.9)
+        );
+        assert!(timings.report().contains("OutputValue: "));
+
+        tape.reset();
+        assert_eq!(tape.command_timings().unwrap().total(), Duration::ZERO);
+    }
+
+    /// Test adding positive and negative deltas to a cell in one go
+    #[test]
+    fn add_to_data_value() {
+        let program = BfProgram::new("add.bf", "+").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.add_to_data_value(200).unwrap();
+        assert_eq!(tape.get_data_value(), 200);
+        tape.add_to_data_value(-50).unwrap();
+        assert_eq!(tape.get_data_value(), 150);
+        // Wrapping around in both directions, more than once
+        tape.add_to_data_value(106).unwrap();
+        assert_eq!(tape.get_data_value(), 0);
+        tape.add_to_data_value(-513).unwrap();
+        assert_eq!(tape.get_data_value(), 255);
+
+        tape.set_overflow_behavior(cli::OverflowBehavior::Saturate);
+        tape.add_to_data_value(1000).unwrap();
+        assert_eq!(tape.get_data_value(), 255);
+        tape.add_to_data_value(i16::MIN).unwrap();
+        assert_eq!(tape.get_data_value(), 0);
+
+        let mut wide: BfTape<i16> = BfTapeBuilder::new().build(&program);
+        wide.add_to_data_value(i16::MIN).unwrap();
+        wide.add_to_data_value(-1).unwrap();
+        assert_eq!(wide.get_cell(), i16::MAX);
+    }
+
+    /// Test that adding to a cell in strict mode fails exactly at the limits
+    #[test]
+    fn add_to_data_value_strict() {
+        let program = BfProgram::new("add.bf", "+").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .overflow_behavior(cli::OverflowBehavior::Error)
+            .build(&program);
+        tape.add_to_data_value(250).unwrap();
+        tape.add_to_data_value(5).unwrap();
+        assert_eq!(tape.get_data_value(), 255);
+        let err = tape.add_to_data_value(1).unwrap_err();
+        assert!(matches!(
+            err,
+            BfError::CellOverflow { program_pointer: 0, ref value, .. } if value == "255"
+        ));
+        assert_eq!(tape.get_data_value(), 255);
+
+        tape.add_to_data_value(-255).unwrap();
+        assert!(tape.add_to_data_value(-1).is_err());
+        assert_eq!(tape.get_data_value(), 0);
+        tape.add_to_data_value(0).unwrap();
+    }
+
+    /// Test the loop depth after each instruction, including a loop that is skipped
+    #[test]
+    fn current_depth() {
+        let mut program = BfProgram::new("depth.bf", "+[>[-]+[-]<-]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.set_history(Some(100));
+        let mut depths = Vec::new();
+        while tape
+            .step(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap()
+            .is_none()
+        {
+            depths.push(tape.current_depth());
+        }
+        // + [ > [ skipped, + [ - ] leaves, < - ] leaves
+        assert_eq!(depths, [0, 1, 1, 1, 1, 2, 2, 1, 1, 1, 0]);
+
+        // Undoing steps puts the depth back
+        for _ in 0..5 {
+            assert!(tape.step_back());
+        }
+        assert_eq!(tape.current_depth(), 2);
+        tape.reset();
+        assert_eq!(tape.current_depth(), 0);
+
+        // Compiled programs keep track of it the same way
+        let ir = program.compile().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.run_ir(&ir, &mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        assert_eq!(tape.current_depth(), 0);
+    }
+
+    /// Test a tape of a single cell, which the data pointer can only move off if it can grow
+    #[test]
+    fn single_cell_tape() {
+        let program = BfProgram::new("one.bf", "+").unwrap();
+        let mut tape: BfTape<u8> = BfTape::try_new(
+            &program,
+            Some(1),
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        )
+        .unwrap();
+        assert_eq!(tape.data_length(), 1);
+        assert!(matches!(
+            tape.move_data_pointer_forward(),
+            Err(BfError::DataPtrMovedAfterEnd { .. })
+        ));
+        assert!(matches!(
+            tape.move_data_pointer_back(),
+            Err(BfError::DataPtrMovedBeforeStart { .. })
+        ));
+        assert_eq!(tape.data_pointer(), 0);
+
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(1)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+            .build(&program);
+        tape.move_data_pointer_forward().unwrap();
+        assert_eq!(tape.data_pointer(), 1);
+        assert_eq!(tape.data_length(), 2);
+    }
+
+    /// Test that tapes of no cells, or too many to allocate, are rejected
+    #[test]
+    fn invalid_tape_size() {
+        let program = BfProgram::new("size.bf", "+").unwrap();
+        let err = BfTape::<u16>::try_new(
+            &program,
+            Some(0),
+            cli::AllocStrategy::TapeIsFixed,
+            cli::OutputFormat::BinaryOutput,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            BfError::InvalidTapeSize { requested_cells: 0 }
+        ));
+        assert_eq!(err.code(), "E_TAPE_SIZE");
+
+        // Zero is the default size when it isn't an Option
+        let tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(0)
+            .try_build(&program)
+            .unwrap();
+        assert_eq!(tape.data_length(), DEFAULT_TAPE_SIZE);
+    }
+
+    /// Test that running out of memory for the tape is an error rather than an abort
+    #[test]
+    fn tape_allocation_failed() {
+        let program = BfProgram::new("huge.bf", "+").unwrap();
+        // Too many bytes for a Vec to ever hold
+        for size in [usize::MAX, usize::MAX / 4 + 1] {
+            let result = BfTape::<u64>::try_new(
+                &program,
+                Some(size),
+                cli::AllocStrategy::TapeIsFixed,
+                cli::OutputFormat::BinaryOutput,
+            );
+            let err = result.err().unwrap();
+            assert!(
+                matches!(err, BfError::TapeAllocationFailed { requested_cells } if requested_cells == size)
+            );
+            assert_eq!(err.code(), "E_TAPE_ALLOCATION");
+        }
+
+        // Growing a tape part way through a run
+        let mut tape: BfTape<u32> = BfTapeBuilder::new()
+            .tape_size(10)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+            .build(&program);
+        let err = tape.set_cell_at(usize::MAX / 2, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            BfError::TapeAllocationFailed { requested_cells } if requested_cells == usize::MAX / 2 + 1
+        ));
+        assert_eq!(tape.data_length(), 10);
+        tape.set_cell_at(10, 1).unwrap();
+    }
+
+    /// Test different programs running at the same time on their own threads
+    #[test]
+    fn programs_on_separate_threads() {
+        let examples = [
+            (
+                "hello.bf",
+                include_str!("../../hello-world.bf"),
+                &b""[..],
+                &b"Hello World!\n"[..],
+            ),
+            (
+                "rot13.bf",
+                include_str!("../../rot13.bf"),
+                b"Hello, World!",
+                b"Uryyb, Jbeyq!",
+            ),
+            (
+                "three.bf",
+                include_str!("../../input-and-output-three-values.bf"),
+                b"a",
+                b"bcd",
+            ),
+        ];
+        let handles: Vec<_> = examples
+            .into_iter()
+            .map(|(name, source, input, expected)| {
+                let mut program = BfProgram::new(name, source).unwrap();
+                program.validate().unwrap();
+                // The tape is built here and moved to the thread that runs it
+                let mut tape: OwnedBfTape<u8> = BfTapeBuilder::new().build(Arc::new(program));
+                let handle = std::thread::spawn(move || {
+                    let mut output = std::io::Cursor::new(Vec::new());
+                    tape.interpreter(&mut std::io::Cursor::new(input), &mut output)
+                        .map(|_| output.into_inner())
+                });
+                (name, expected, handle)
+            })
+            .collect();
+        for (name, expected, handle) in handles {
+            assert_eq!(handle.join().unwrap().unwrap(), expected, "{}", name);
+        }
+    }
+
+    /// Test reading and writing cells by index, which leaves the data pointer alone
+    #[test]
+    fn index_cells() {
+        let program = BfProgram::new("index.bf", "").unwrap();
+        for alloc_strategy in [
+            cli::AllocStrategy::TapeIsFixed,
+            cli::AllocStrategy::TapeCanGrow,
+        ] {
+            let mut tape: BfTape<u16> = BfTapeBuilder::new()
+                .tape_size(4)
+                .alloc_strategy(alloc_strategy)
+                .build(&program);
+            tape[0] = 1;
+            tape[3] = 300;
+            tape[2] += 2;
+            assert_eq!(tape.data_pointer(), 0);
+            assert_eq!(*tape.cells(), [1, 0, 2, 300]);
+            assert_eq!(tape[3], tape.get_cell_at(3).unwrap());
+        }
+
+        // A sparse tape doesn't hold the cells that are only read, or are left at zero
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(1000)
+            .sparse(true)
+            .build(&program);
+        assert_eq!(tape[999], 0);
+        tape[5] = 7;
+        tape[6] = 0;
+        assert_eq!((tape[5], tape[6]), (7, 0));
+        assert_eq!(tape.non_zero_cells().collect::<Vec<_>>(), [(5, 7)]);
+    }
+
+    /// Test that indexing beyond the end of the tape panics rather than growing it
+    #[test]
+    #[should_panic(expected = "the tape has 4 cells but the index is 4")]
+    fn index_beyond_end() {
+        let program = BfProgram::new("index.bf", "").unwrap();
+        let tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(4)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+            .build(&program);
+        assert!(tape.get_cell_at(4).is_ok());
+        let _ = tape[4];
+    }
+
+    /// Test that assigning beyond the end of the tape panics
+    #[test]
+    #[should_panic(expected = "the tape has 4 cells but the index is 4")]
+    fn index_mut_beyond_end() {
+        let program = BfProgram::new("index.bf", "").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().tape_size(4).build(&program);
+        assert!(tape.set_cell_at(4, 1).is_err());
+        tape[4] = 1;
+    }
+
+    /// Test comparing snapshots taken before and after running a program
+    #[test]
+    fn snapshot_diff() {
+        let program = BfProgram::new("diff.bf", ">+>++").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().tape_size(10).build(&program);
+        tape[5] = 9;
+        let before = tape.snapshot();
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        let after = tape.snapshot();
+        assert_eq!(
+            before.diff(&after),
+            [
+                CellChange {
+                    index: 1,
+                    before: 0,
+                    after: 1
+                },
+                CellChange {
+                    index: 2,
+                    before: 0,
+                    after: 2
+                },
+            ]
+        );
+        assert_eq!(before.data_pointer_move(&after), 2);
+        assert_eq!(after.data_pointer_move(&before), -2);
+        assert_eq!(after.steps - before.steps, 5);
+        assert!(after.diff(&after).is_empty());
+
+        // Cells the tape grows by are compared with zero
+        let program = BfProgram::new("grow.bf", ">>>+>").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(2)
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow)
+            .build(&program);
+        let before = tape.snapshot();
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        let after = tape.snapshot();
+        assert!(after.cells.len() > before.cells.len());
+        assert_eq!(
+            before.report(&after),
+            "Data pointer 0 -> 4 (+4)\nCell 3: 0 -> 1\n"
+        );
+    }
+
+    /// Test counting the reads and writes of each cell
+    #[test]
+    fn heatmap() {
+        let mut program = BfProgram::new("heat.bf", "++>+[-]<.>>>,").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().tape_size(10).build(&program);
+        tape.interpreter(&mut std::io::Cursor::new(b"x"), &mut std::io::sink())
+            .unwrap();
+        assert_eq!(tape.heatmap(), None);
+        assert_eq!(tape.run_stats().highest_cell_accessed, None);
+
+        tape.reset();
+        tape.enable_heatmap();
+        tape.interpreter(&mut std::io::Cursor::new(b"x"), &mut std::io::sink())
+            .unwrap();
+        let counts: Vec<_> = tape
+            .heatmap()
+            .unwrap()
+            .iter()
+            .map(|access| (access.reads, access.writes))
+            .collect();
+        assert_eq!(counts, [(3, 2), (4, 2), (0, 0), (0, 1)]);
+        let stats = tape.run_stats();
+        assert_eq!(stats.highest_cell_accessed, Some(3));
+        assert_eq!(stats.memory.high_water, 3);
+
+        tape.reset();
+        assert_eq!(tape.heatmap(), Some(&[][..]));
+    }
+
+    /// Test the events of a run at each granularity
+    #[test]
+    fn run_events() {
+        let program = BfProgram::new("events.bf", ",+.").unwrap();
+        let record = |step, program_pointer, command, value: u8| TraceRecord {
+            step,
+            program_pointer,
+            command,
+            line: 1,
+            column: program_pointer + 1,
+            data_pointer: 0,
+            value: value.into(),
+            depth: 0,
+        };
+
+        let mut events = Vec::new();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.set_event_sink(EventGranularity::EveryInstruction, |event| {
+            events.push(event)
+        });
+        tape.interpreter(&mut "A".as_bytes(), &mut std::io::sink())
+            .unwrap();
+        let stats = tape.run_stats();
+        drop(tape);
+        assert_eq!(
+            events,
+            [
+                RunEvent::ProgramStarted,
+                RunEvent::InputRequested,
+                RunEvent::InstructionExecuted(record(1, 0, ',', b'A')),
+                RunEvent::InstructionExecuted(record(2, 1, '+', b'B')),
+                RunEvent::OutputByte(b'B'),
+                RunEvent::InstructionExecuted(record(3, 2, '.', b'B')),
+                RunEvent::Halted(Box::new(stats)),
+            ]
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .output_format(cli::OutputFormat::BinaryOutput)
+            .build(&program);
+        tape.set_event_sender(EventGranularity::IoAndControl, sender);
+        tape.interpreter(&mut "A".as_bytes(), &mut std::io::sink())
+            .unwrap();
+        let stats = tape.run_stats();
+        drop(tape);
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            [
+                RunEvent::ProgramStarted,
+                RunEvent::InputRequested,
+                RunEvent::OutputByte(b'6'),
+                RunEvent::OutputByte(b'6'),
+                RunEvent::Halted(Box::new(stats)),
+            ]
+        );
+
+        // Errors end the stream with their code, and events can be sent on as JSON
+        let program = BfProgram::new("underrun.bf", "<").unwrap();
+        let mut events = Vec::new();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.set_event_sink(EventGranularity::IoAndControl, |event| events.push(event));
+        assert!(tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .is_err());
+        drop(tape);
+        assert_eq!(
+            events,
+            [
+                RunEvent::ProgramStarted,
+                RunEvent::Errored("E_TAPE_UNDERRUN".to_string())
+            ]
+        );
+        let json = serde_json::to_string(&events[1]).unwrap();
+        assert_eq!(json, r#"{"Errored":"E_TAPE_UNDERRUN"}"#);
+        assert_eq!(serde_json::from_str::<RunEvent>(&json).unwrap(), events[1]);
+    }
+
+    /// Test that the instruction reported in an error is the one that was being executed,
+    /// whether the program is run or stepped
+    #[test]
+    fn error_instruction() {
+        let cases = [
+            ("underrun.bf", "+\n+<", None, cli::OverflowBehavior::Wrap),
+            ("overrun.bf", ">>\n>>", None, cli::OverflowBehavior::Wrap),
+            (
+                "overflow.bf",
+                "-\n [-]-",
+                None,
+                cli::OverflowBehavior::Error,
+            ),
+            ("limit.bf", "+[\n>+<]", Some(7), cli::OverflowBehavior::Wrap),
+        ];
+        for (filename, source, step_limit, overflow_behavior) in cases {
+            let mut program = BfProgram::new(filename, source).unwrap();
+            program.validate().unwrap();
+            let builder = BfTapeBuilder::new()
+                .tape_size(3)
+                .step_limit(step_limit)
+                .overflow_behavior(overflow_behavior);
+
+            let mut tape: BfTape<u8> = builder.clone().build(&program);
+            let mut io = VecIo::new(Vec::new());
+            let (executing, stepped) = loop {
+                let executing = tape.current_instruction().unwrap();
+                if let Err(error) = tape.step_io(&mut io) {
+                    break (executing, error);
+                }
+            };
+            assert_eq!(stepped.location(), Some(executing.location()), "{filename}");
+
+            let mut tape: BfTape<u8> = builder.build(&program);
+            let run = tape
+                .interpreter(&mut std::io::empty(), &mut std::io::sink())
+                .unwrap_err();
+            assert_eq!(run.location(), Some(executing.location()), "{filename}");
+            assert_eq!(run.to_string(), stepped.to_string(), "{filename}");
+        }
+    }
+
+    /// Run a program an instruction at a time with a step limit, checking the invariants of
+    /// the tape after each instruction. The first invariant that is broken is returned, and a
+    /// panic counts as breaking one. Errors such as going off the end of the tape just stop
+    /// the program.
+    fn check_invariants(
+        source: &str,
+        input: &[u8],
+        builder: &BfTapeBuilder,
+        fixed: bool,
+    ) -> Result<(), String> {
+        let mut program = BfProgram::new("random.bf", source).unwrap();
+        program.validate().unwrap();
+        let run = std::panic::AssertUnwindSafe(|| {
+            let mut tape: BfTape<u8> = builder.build(&program);
+            let mut io = VecIo::new(input.to_vec());
+            let initial = tape.data_length();
+            let mut length = initial;
+            let mut outputs = 0;
+            while let Some(inst) = tape.current_instruction() {
+                if tape.check_can_continue(1000).is_err() || tape.step_io(&mut io).is_err() {
+                    break;
+                }
+                if inst.command() == bft_types::BfCommand::OutputValue {
+                    outputs += 1;
+                }
+                if tape.data_pointer() >= tape.data_length() {
+                    return Err(format!(
+                        "the data pointer {} is off the tape of {} cells",
+                        tape.data_pointer(),
+                        tape.data_length()
+                    ));
+                }
+                if tape.data_length() < length {
+                    return Err(format!(
+                        "the tape shrank from {} to {} cells",
+                        length,
+                        tape.data_length()
+                    ));
+                }
+                if fixed && tape.data_length() > initial {
+                    return Err(format!(
+                        "the fixed tape grew from {} to {} cells",
+                        initial,
+                        tape.data_length()
+                    ));
+                }
+                if io.output().len() != outputs {
+                    return Err(format!(
+                        "{} bytes were output by {} . commands",
+                        io.output().len(),
+                        outputs
+                    ));
+                }
+                length = tape.data_length();
+            }
+            Ok(())
+        });
+        std::panic::catch_unwind(run).unwrap_or_else(|_| Err("it panicked".to_string()))
+    }
+
+    /// Test the invariants of the tape with generated programs and input, on tapes that are
+    /// fixed, grow to the right and grow both ways. A program that breaks one is shrunk to the
+    /// smallest program that still breaks it.
+    #[test]
+    fn tape_invariants() {
+        let builder = BfTapeBuilder::new().tape_size(4);
+        let grows = builder
+            .clone()
+            .alloc_strategy(cli::AllocStrategy::TapeCanGrow);
+        let tapes = [
+            (true, builder),
+            (false, grows.clone()),
+            (
+                false,
+                grows.alloc_strategy(cli::AllocStrategy::TapeCanGrowBothWays),
+            ),
+        ];
+        for seed in 0..300 {
+            let mut generator = crate::generate::ProgramGenerator::new(seed);
+            let source = generator.program();
+            let input = generator.input(8);
+            for (fixed, builder) in &tapes {
+                if let Err(broken) = check_invariants(&source, &input, builder, *fixed) {
+                    let smallest = crate::generate::shrink(&source, |source| {
+                        check_invariants(source, &input, builder, *fixed).is_err()
+                    });
+                    panic!("{broken} running {source} (seed {seed}), which shrinks to {smallest}");
+                }
+            }
+        }
+    }
+
+    /// Test how the allocation strategies are shown
+    #[test]
+    fn alloc_strategy_display() {
+        assert_eq!(cli::AllocStrategy::TapeIsFixed.to_string(), "fixed");
+        assert_eq!(cli::AllocStrategy::TapeCanGrow.to_string(), "can grow");
+        assert_eq!(
+            cli::AllocStrategy::TapeCanGrowBothWays.to_string(),
+            "can grow both ways"
+        );
+        let limited = cli::AllocStrategy::TapeCanGrowWithLimit(100);
+        assert_eq!(limited.to_string(), "can grow to 100 cells");
+        assert_eq!(format!("{:?}", limited), "TapeCanGrowWithLimit(100)");
+    }
+
+    /// Test that the debug output and trace show all of a cell's value, not just its low byte
+    #[test]
+    fn debug_shows_whole_cell() {
+        let program = BfProgram::new("wide.bf", ",+").unwrap();
+        let mut debug = Vec::new();
+        let mut trace = Vec::new();
+        {
+            let mut tape: BfTape<u32> = BfTapeBuilder::new()
+                .tape_size(4)
+                .input_format(cli::InputFormat::DecimalInput)
+                .debug(cli::DebugLevelType::Trace)
+                .build(&program);
+            tape.set_debug_writer(Box::new(&mut debug));
+            tape.set_trace_writer(Box::new(&mut trace));
+            tape.interpreter(&mut "70000".as_bytes(), &mut std::io::sink())
+                .unwrap();
+        }
+        let debug = String::from_utf8(debug).unwrap();
+        assert!(debug.contains("Data=70000"), "{}", debug);
+        assert!(debug.contains("[0] >70001< 0 0 0 [3]"), "{}", debug);
+
+        let trace = String::from_utf8(trace).unwrap();
+        assert!(trace.contains("\"value\":70000,"), "{}", trace);
+        let values: Vec<i128> = trace
+            .lines()
+            .map(|l| serde_json::from_str::<TraceRecord>(l).unwrap().value)
+            .collect();
+        assert_eq!(values, [70000, 70001]);
+    }
+
+    /// Test that a program reads its input from a file, and sees the end of file after it
+    #[test]
+    fn input_from_file() {
+        let path = std::env::temp_dir().join(format!("bft-input-{}.txt", std::process::id()));
+        std::fs::write(&path, "Uryyb").unwrap();
+        let mut program = BfProgram::new("rot13.bf", include_str!("../../rot13.bf")).unwrap();
+        program.validate().unwrap();
+        // The end of file is -1 by default, which is how rot13.bf knows to stop
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let mut reader = open_input(&path).unwrap();
+        let mut output = Vec::new();
+        let result = tape.interpreter(&mut reader, &mut output);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), StopReason::Halted);
+        assert_eq!(output, b"Hello");
+
+        let error = open_input(&path).unwrap_err();
+        assert!(matches!(error, BfError::InputFileError { .. }));
+        assert_eq!(error.io_kind(), Some(std::io::ErrorKind::NotFound));
+        assert!(error.to_string().starts_with(&format!(
+            "[E_INPUT_FILE] Can't open input file {}: ",
+            path.display()
+        )));
+    }
+
+    /// Test that a program's output is written to a file, formatted as it would be on stdout
+    #[test]
+    fn output_to_file() {
+        let path = std::env::temp_dir().join(format!("bft-output-{}.txt", std::process::id()));
+        std::fs::write(&path, "something longer that is truncated").unwrap();
+        let program = BfProgram::new("numbers.bf", "+.+.+.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .output_format(cli::OutputFormat::BinaryOutput)
+            .build(&program);
+        let mut file = OutputFile::create(&path).unwrap();
+        assert_eq!(file.path(), path);
+        tape.interpreter_buffered(&mut std::io::empty(), &mut file)
+            .unwrap();
+        file.finish(true).unwrap();
+        let output = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(output.unwrap(), "1,2,3");
+
+        let missing = path.join("no-such-directory").join("output.txt");
+        let error = OutputFile::create(&missing).unwrap_err();
+        assert_eq!(error.code(), "E_OUTPUT_FILE");
+        assert_eq!(error.io_kind(), Some(std::io::ErrorKind::NotFound));
+        assert!(error.to_string().contains(&missing.display().to_string()));
+    }
+
+    /// Test that an error writing an output file gives the path, and keeps the original error
+    /// as its source
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn output_file_write_error() {
+        let mut file = OutputFile::create("/dev/full").unwrap();
+        file.write_all(b"more than fits").unwrap();
+        let error = file.flush().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::StorageFull);
+        assert!(
+            error.to_string().starts_with("writing /dev/full: "),
+            "{}",
+            error
+        );
+        let source = std::error::Error::source(error.get_ref().unwrap()).unwrap();
+        let source = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::StorageFull);
+    }
+
+    /// Test that a program given on the command line is named <eval> in its errors
+    #[test]
+    fn inline_program() {
+        let source = cli::ProgramSource::Inline("++\n++[>++<-]>+<<.".to_string());
+        let mut program = load_program(&source).unwrap();
+        assert_eq!(
+            program.filename().to_string_lossy(),
+            cli::INLINE_PROGRAM_NAME
+        );
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(error.location(), Some(bft_types::BfLocation::new(2, 13)));
+
+        let source = cli::ProgramSource::File("no-such-program.bf".into());
+        let error = load_program(&source).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    /// Test that a program relying on values above 255 works with 16 bit cells. Output is
+    /// still the low byte of the cell, so the difference shows in what the program does.
+    #[test]
+    fn wide_cells_hold_large_values() {
+        // Put 256 in a cell and output it, then output 1 if the cell isn't zero
+        let mut program = BfProgram::new(
+            "large.bf",
+            "++++++++++++++++[>++++++++++++++++<-]>.[[-]<+>]<.",
+        )
+        .unwrap();
+        program.validate().unwrap();
+        let config = BfTapeBuilder::new().output_format(cli::OutputFormat::BinaryOutput);
+        let mut narrow: BfTape<u8> = config.build(&program);
+        let mut output = Vec::new();
+        narrow
+            .interpreter(&mut std::io::empty(), &mut output)
+            .unwrap();
+        assert_eq!(output, b"0,0");
+        let mut wide: BfTape<u16> = config.build(&program);
+        let mut output = Vec::new();
+        wide.interpreter(&mut std::io::empty(), &mut output)
+            .unwrap();
+        assert_eq!(output, b"256,1");
+        assert_eq!(wide.get_cell_at(1).unwrap(), 0);
+    }
+
+    /// Test what a program outputs after reading past the end of its input, in each mode
+    #[test]
+    fn eof_behavior_output() {
+        for (eof_behavior, expected) in [
+            (cli::EofBehavior::Zero, 0),
+            (cli::EofBehavior::NegOne, 255),
+            (cli::EofBehavior::Unchanged, 3),
+        ] {
+            let config = BfTapeBuilder::new().eof_behavior(eof_behavior);
+            assert_eq!(run_with_io("+++,.", b"", config).unwrap(), [expected]);
+        }
+    }
+
+    /// Test that a program that never ends is stopped by the step limit, saying where it was
+    #[test]
+    fn step_limit_stops_endless_loop() {
+        let config = BfTapeBuilder::new().step_limit(Some(1000));
+        let error = run_with_io("+[]", b"", config).unwrap_err();
+        assert!(matches!(
+            error,
+            BfError::StepLimitExceeded { steps: 1000, .. }
+        ));
+        assert_eq!(error.location(), Some(bft_types::BfLocation::new(1, 3)));
+        assert_eq!(
+            error.to_string(),
+            "[E_STEP_LIMIT] Step limit of 1000 exceeded at 2 Jump backward if nonzero @1:3\n  |\n1 | +[]\n  |   ^"
+        );
+    }
+
+    /// Test that a program that never ends is stopped by the time limit soon after it runs out,
+    /// and by whichever of the step and time limits is reached first
+    #[test]
+    fn time_limit_stops_endless_loop() {
+        let limit = Duration::from_millis(200);
+        let config = BfTapeBuilder::new().time_limit(Some(limit));
+        let started = Instant::now();
+        let error = run_with_io("+[]", b"", config.clone()).unwrap_err();
+        let elapsed = started.elapsed();
+        assert!(matches!(
+            error,
+            BfError::TimeLimitExceeded { limit: l, .. } if l == limit
+        ));
+        assert!(elapsed >= limit && elapsed < limit * 5, "{:?}", elapsed);
+        assert!(error
+            .to_string()
+            .starts_with("[E_TIME_LIMIT] Time limit of 200ms exceeded at "));
+        assert!(error.location().is_some());
+
+        let error = run_with_io("+[]", b"", config.step_limit(Some(1000))).unwrap_err();
+        assert_eq!(error.code(), "E_STEP_LIMIT");
+
+        // The clock starts again when the tape is reset
+        let mut program = BfProgram::new("quick.bf", "+++.").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().time_limit(Some(limit)).build(&program);
+        assert!(tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .is_ok());
+        std::thread::sleep(limit);
+        tape.reset();
+        assert!(tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .is_ok());
+    }
+
+    /// Test that the ceiling given on the command line stops a long scan of the tape
+    #[test]
+    fn max_tape_from_args() {
+        let args = cli::RunArgs::try_new_with_config_paths(
+            [
+                "bft",
+                "-e",
+                "--cells",
+                "1k",
+                "--max-tape",
+                "2k",
+                "--eval",
+                "+[>+]",
+            ],
+            &[],
+        )
+        .unwrap();
+        let mut program = load_program(args.source()).unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(args.cell_count())
+            .alloc_strategy(args.tape_mode())
+            .try_build(&program)
+            .unwrap();
+        assert_eq!(tape.data_length(), 1024);
+        let error = tape
+            .interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            BfError::TapeLimitExceeded { limit: 2048, .. }
+        ));
+        assert_eq!(tape.data_pointer(), 2047);
+        assert_eq!(tape.data_length(), 2048);
+    }
+
+    /// Test dumping the number of cells given on the command line once a program has finished
+    #[test]
+    fn dump_tape_from_args() {
+        let args = cli::RunArgs::try_new_with_config_paths(
+            [
+                "bft",
+                "--cells",
+                "4",
+                "--dump-tape",
+                "100",
+                "--eval",
+                "+++>++>+<",
+            ],
+            &[],
+        )
+        .unwrap();
+        let mut program = load_program(args.source()).unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new()
+            .tape_size(args.cell_count())
+            .try_build(&program)
+            .unwrap();
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+
+        // More cells than the tape has are clamped to its length
+        let mut dump = Vec::new();
+        tape.dump(&mut dump, args.dump_tape().map(|count| 0..count))
+            .unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.starts_with("00000000  03[02]01 00 "), "{}", dump);
+        assert!(dump.ends_with("\n00000004\n"), "{}", dump);
+    }
+
+    /// Test the profile of a program run with the profiler asked for on the command line
+    #[test]
+    fn profile_from_args() {
+        let args = cli::RunArgs::try_new_with_config_paths(
+            ["bft", "--profile", "--eval", "++[->+<]"],
+            &[],
+        )
+        .unwrap();
+        assert!(args.profile());
+        let mut program = load_program(args.source()).unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.enable_profiler();
+        tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+
+        let report = tape.profile().unwrap().report(10);
+        assert!(report.contains("  IncValue: 4\n"), "{}", report);
+        assert!(
+            report.contains("[ at <eval>:1:3 ] at <eval>:1:8: 2 iterations"),
+            "{}",
+            report
+        );
+    }
+
+    /// Test tracing to the file given on the command line, including when the program fails
+    #[test]
+    fn trace_to_file_from_args() {
+        let path = std::env::temp_dir().join(format!("bft-trace-{}.jsonl", std::process::id()));
+        let path_arg = path.to_string_lossy().to_string();
+        let run = |code: &str| {
+            let args = cli::RunArgs::try_new_with_config_paths(
+                [
+                    "bft",
+                    "--cells",
+                    "4",
+                    "--trace-file",
+                    &path_arg,
+                    "--eval",
+                    code,
+                ],
+                &[],
+            )
+            .unwrap();
+            assert_eq!(args.trace_file(), Some(&path));
+            let mut program = load_program(args.source()).unwrap();
+            program.validate().unwrap();
+            let mut tape: BfTape<u8> = BfTapeBuilder::new()
+                .tape_size(args.cell_count())
+                .build(&program);
+            tape.set_trace_writer(Box::new(OutputFile::create(&path).unwrap()));
+            let result = tape
+                .interpreter(&mut std::io::empty(), &mut std::io::sink())
+                .map(|_| ());
+            tape.finish_trace().unwrap();
+            // Finishing again does nothing
+            tape.finish_trace().unwrap();
+
+            let trace = std::fs::read_to_string(&path);
+            std::fs::remove_file(&path).unwrap();
+            let trace = trace.unwrap();
+            for line in trace.lines() {
+                serde_json::from_str::<TraceRecord>(line).unwrap();
+            }
+            (result, trace.lines().count() as u64, tape.run_stats().steps)
+        };
+
+        let (result, lines, steps) = run("++[->+<]");
+        assert!(result.is_ok());
+        assert_eq!(lines, steps);
+
+        // The instruction that failed wasn't executed so only the ones before it are traced
+        let (result, lines, steps) = run("+[>+]");
+        assert!(matches!(result, Err(BfError::DataPtrMovedAfterEnd { .. })));
+        assert_eq!(lines, steps - 1);
+    }
+
+    /// Test that the data pointer comes back round at the other end of a tape that wraps,
+    /// including in the loops that are replaced by ops
+    #[test]
+    fn tape_wraps() {
+        let mut program = BfProgram::new("wrap.bf", "<+++[->+<]>>+[>]").unwrap();
+        program.validate().unwrap();
+        let ir = program.compile().unwrap();
+        let builder = BfTapeBuilder::new()
+            .tape_size(3)
+            .alloc_strategy(cli::AllocStrategy::TapeWraps);
+        for compiled in [false, true] {
+            let mut tape: BfTape<u8> = builder.build(&program);
+            if compiled {
+                tape.run_ir(&ir, &mut std::io::empty(), &mut std::io::sink())
+                    .unwrap();
+            } else {
+                tape.interpreter(&mut std::io::empty(), &mut std::io::sink())
+                    .unwrap();
+            }
+            // The copy loop at the last cell adds to the first, and the scan comes back round
+            // to the cell it cleared
+            assert_eq!(tape.tape_window(3).1.to_vec(), vec![3, 1, 0]);
+            assert_eq!(tape.data_pointer(), 2);
+            assert_eq!(tape.data_length(), 3);
+            assert!(matches!(
+                tape.get_cell_at(3),
+                Err(BfError::CellOutOfRange { cell: 3, length: 3 })
+            ));
+        }
+    }
+}
@@ -0,0 +1,515 @@
+//! Live view of a BF program as it runs, drawn on a terminal with ratatui.
+//!
+//! Each frame shows the number of steps and the speed, the cells around the data pointer, the
+//! line of source with the next instruction highlighted and the last lines of the program's
+//! output. The program's output is kept in the view rather than written to the terminal so
+//! that the two don't fight over it.
+//!
+//! The program is stepped with the tape's step and time limits and interrupt flag as usual, and
+//! an error stops it with the error shown in the view.
+//!
+//! The keys are
+//! * `space` - Pause or resume the program.
+//! * `s`     - Step, execute one instruction while paused.
+//! * `+`     - Faster, double the number of instructions executed each frame.
+//! * `-`     - Slower, halve the number of instructions executed each frame.
+//! * `k` `j` - Scroll the output up or down.
+//! * `q`     - Quit.
+//! * Ctrl-C  - Interrupt the program, see INTERRUPT.
+
+use crate::{BfError, BfIo, BfTape, CellKind, StopReason};
+use ratatui::backend::Backend;
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Paragraph;
+use ratatui::Terminal;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Number of cells either side of the data pointer shown
+const WINDOW_RADIUS: usize = 8;
+
+/// Number of lines of the program's output shown
+const OUTPUT_LINES: usize = 10;
+
+/// Most instructions that can be executed each frame
+const MAX_STEPS_PER_FRAME: u64 = 1 << 20;
+
+/// Key sent for Ctrl-C, which a terminal in raw mode gives as a key rather than a signal. The
+/// sender of the keys is expected to set the tape's interrupt flag as well, and the key makes
+/// sure the flag is seen even while the program is paused. Once the program has stopped it
+/// quits the view, as q does.
+pub const INTERRUPT: u8 = 0x03;
+
+/// Style of the cell at the data pointer and the next instruction
+const HIGHLIGHT: Style = Style::new().reversed();
+
+/// Style of cells that aren't zero
+const NON_ZERO: Style = Style::new().fg(Color::Cyan);
+
+/// Style of the error that stopped the program
+const ERROR: Style = Style::new().fg(Color::Red);
+
+/// State of the view between frames
+///
+/// Example usage:
+/// ```
+///     let program = bft_types::BfProgram::new(&"tiny.bf", "+.").unwrap();
+///     let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTapeBuilder::new().build(&program);
+///     let mut io = bft_interp::VecIo::new(Vec::new());
+///     // The program finishes before any keys are pressed and the keys run out
+///     let (_, keys) = std::sync::mpsc::channel();
+///     let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(80, 20)).unwrap();
+///     let mut visualizer = bft_interp::visualizer::Visualizer::new();
+///     let reason = visualizer.run(&mut tape, &mut io, &keys, &mut terminal, std::time::Duration::ZERO).unwrap();
+///     assert_eq!(reason, bft_interp::StopReason::Halted);
+///     assert_eq!(visualizer.output(), &[1]);
+/// ```
+#[derive(Debug)]
+pub struct Visualizer {
+    /// Number of instructions executed each frame
+    steps_per_frame: u64,
+    /// Flag indicating if the program is paused
+    paused: bool,
+    /// Flag indicating if one instruction should be executed while paused
+    single_step: bool,
+    /// Number of lines the output is scrolled up by
+    scroll: usize,
+    /// Everything the program has output
+    output: Vec<u8>,
+    /// The error that stopped the program, if it failed
+    error: Option<String>,
+}
+
+impl Default for Visualizer {
+    /// Default instance of Visualizer as recommended by Clippy
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Visualizer {
+    /// Create a view that executes one instruction each frame
+    pub fn new() -> Self {
+        Self {
+            steps_per_frame: 1,
+            paused: false,
+            single_step: false,
+            scroll: 0,
+            output: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Number of instructions executed each frame
+    pub fn steps_per_frame(&self) -> u64 {
+        self.steps_per_frame
+    }
+
+    /// Flag indicating if the program is paused
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Everything the program has output
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Act on a key that was pressed, returning false if the key was to quit. Unknown keys
+    /// are ignored.
+    pub fn handle_key(&mut self, key: u8) -> bool {
+        match key {
+            b' ' => self.paused = !self.paused,
+            b's' => self.single_step = self.paused,
+            b'+' => self.steps_per_frame = (self.steps_per_frame * 2).min(MAX_STEPS_PER_FRAME),
+            b'-' => self.steps_per_frame = (self.steps_per_frame / 2).max(1),
+            b'k' => self.scroll += 1,
+            b'j' => self.scroll = self.scroll.saturating_sub(1),
+            b'q' => return false,
+            _ => {}
+        }
+        true
+    }
+
+    /// Run the program, drawing a frame on the terminal after each batch of instructions. Keys
+    /// are taken from keys between frames, and the frame time is waited between them.
+    ///
+    /// Once the program has stopped, whether it finished or failed, the view stays on the
+    /// terminal, so that the end can be looked at and the output scrolled, until q is pressed
+    /// or the keys run out. Returns the reason the program stopped, StopReason::Aborted if it was
+    /// quit before it did, or the error that stopped it.
+    pub fn run<T, I, B>(
+        &mut self,
+        tape: &mut BfTape<'_, T>,
+        io: &mut I,
+        keys: &mpsc::Receiver<u8>,
+        terminal: &mut Terminal<B>,
+        frame: Duration,
+    ) -> Result<StopReason<T>, BfError>
+    where
+        T: CellKind,
+        I: BfIo,
+        B: Backend,
+        B::Error: Send + Sync + 'static,
+    {
+        self.error = None;
+        loop {
+            let mut interrupted = false;
+            while let Ok(key) = keys.try_recv() {
+                if key == INTERRUPT {
+                    interrupted = true;
+                } else if !self.handle_key(key) {
+                    return Ok(StopReason::Aborted {
+                        program_pointer: tape.program_pointer(),
+                    });
+                }
+            }
+
+            // An interrupt is acted on by a step, which stops at the interrupt flag
+            let steps = if interrupted {
+                1
+            } else if self.paused {
+                u64::from(std::mem::take(&mut self.single_step))
+            } else {
+                self.steps_per_frame
+            };
+            let mut stopped = Ok(None);
+            for _ in 0..steps {
+                let mut view_io = ViewIo {
+                    io: &mut *io,
+                    output: &mut self.output,
+                };
+                stopped = tape.step_io(&mut view_io);
+                if !matches!(stopped, Ok(None)) {
+                    break;
+                }
+            }
+
+            let stopped = match stopped {
+                Ok(None) => {
+                    let state = if self.paused { "paused" } else { "running" };
+                    self.draw(tape, terminal, state)?;
+                    std::thread::sleep(frame);
+                    continue;
+                }
+                Ok(Some(reason)) => Ok(reason),
+                Err(error) => {
+                    self.error = Some(error.to_string());
+                    Err(error)
+                }
+            };
+
+            let state = if stopped.is_ok() {
+                "finished"
+            } else {
+                "failed"
+            };
+            self.draw(tape, terminal, state)?;
+            while let Ok(key) = keys.recv() {
+                if key == INTERRUPT || !self.handle_key(key) {
+                    break;
+                }
+                self.draw(tape, terminal, state)?;
+            }
+            return stopped;
+        }
+    }
+
+    /// The lines of a frame. The state is shown next to the step count.
+    pub fn render<T: CellKind>(&self, tape: &BfTape<'_, T>, state: &str) -> Vec<Line<'static>> {
+        let mut lines = vec![
+            Line::from(format!(
+                "Step {}  {} per frame  {}",
+                tape.steps(),
+                self.steps_per_frame,
+                state
+            )),
+            Line::default(),
+        ];
+
+        let (start, cells) = tape.tape_window(WINDOW_RADIUS);
+        let mut window = vec![Span::raw(format!("[{}]", start))];
+        for (index, cell) in (start..).zip(cells.iter()) {
+            let text = format!("{:>4?}", cell);
+            window.push(if index == tape.data_pointer() {
+                Span::styled(text, HIGHLIGHT)
+            } else if !cell.is_zero() {
+                Span::styled(text, NON_ZERO)
+            } else {
+                Span::raw(text)
+            });
+        }
+        lines.push(Line::from(window));
+
+        let source = tape.current_instruction().and_then(|inst| {
+            let location = inst.location();
+            let line = tape.program.source_line(location.line())?;
+            let column = location.offset() - 1;
+            let before: String = line.chars().take(column).collect();
+            let at: String = line.chars().skip(column).take(1).collect();
+            let after: String = line.chars().skip(column + 1).collect();
+            Some(Line::from(vec![
+                Span::raw(format!("{:>4} | {}", location.line(), before)),
+                Span::styled(at, HIGHLIGHT),
+                Span::raw(after),
+            ]))
+        });
+        lines.push(source.unwrap_or_else(|| Line::from("End of program")));
+        if let Some(error) = &self.error {
+            lines.extend(
+                error
+                    .lines()
+                    .map(|line| Line::styled(line.to_string(), ERROR)),
+            );
+        }
+
+        lines.push(Line::default());
+        lines.push(Line::from("Output:"));
+        let output = String::from_utf8_lossy(&self.output);
+        let output: Vec<&str> = output.lines().collect();
+        let end = output.len() - self.scroll.min(output.len());
+        let start = end.saturating_sub(OUTPUT_LINES);
+        lines.extend(
+            output[start..end]
+                .iter()
+                .map(|line| Line::from(line.to_string())),
+        );
+
+        lines.push(Line::default());
+        lines.push(Line::from(
+            "space pause  s step  + faster  - slower  k/j scroll  q quit",
+        ));
+        lines
+    }
+
+    /// Draw a frame on the terminal
+    fn draw<T, B>(
+        &self,
+        tape: &BfTape<'_, T>,
+        terminal: &mut Terminal<B>,
+        state: &str,
+    ) -> Result<(), BfError>
+    where
+        T: CellKind,
+        B: Backend,
+        B::Error: Send + Sync + 'static,
+    {
+        let lines = self.render(tape, state);
+        terminal
+            .draw(|frame| frame.render_widget(Paragraph::new(lines), frame.area()))
+            .map(|_| ())
+            .map_err(|e| tape.io_error(std::io::Error::other(e)))
+    }
+}
+
+/// Input and output of a program being viewed. The input is read as usual but the output is
+/// kept for the view.
+struct ViewIo<'v, I> {
+    /// Where the program's input comes from
+    io: &'v mut I,
+    /// Everything the program has output
+    output: &'v mut Vec<u8>,
+}
+
+impl<I: BfIo> BfIo for ViewIo<'_, I> {
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        self.io.read_byte()
+    }
+
+    fn write_byte(&mut self, byte: u8) -> std::io::Result<()> {
+        self.output.push(byte);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BfTapeBuilder, VecIo};
+    use bft_types::BfProgram;
+    use ratatui::backend::TestBackend;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    /// Run the program in the view with the keys given, which then run out, returning the
+    /// result and the last frame drawn
+    fn run_with(
+        visualizer: &mut Visualizer,
+        tape: &mut BfTape<u8>,
+        io: &mut VecIo,
+        keys: &[u8],
+    ) -> (Result<StopReason<u8>, BfError>, Vec<String>) {
+        let (sender, receiver) = mpsc::channel();
+        for key in keys {
+            sender.send(*key).unwrap();
+        }
+        drop(sender);
+        let mut terminal = Terminal::new(TestBackend::new(80, 16)).unwrap();
+        let result = visualizer.run(tape, io, &receiver, &mut terminal, Duration::ZERO);
+        let buffer = terminal.backend().buffer();
+        let screen = buffer
+            .content()
+            .chunks(usize::from(buffer.area.width))
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .map(|row| row.trim_end().to_string())
+            .collect();
+        (result, screen)
+    }
+
+    /// Test the keys that control the view
+    #[test]
+    fn keys() {
+        let mut visualizer = Visualizer::new();
+        assert!(visualizer.handle_key(b'-'));
+        assert_eq!(visualizer.steps_per_frame(), 1);
+        for _ in 0..30 {
+            visualizer.handle_key(b'+');
+        }
+        assert_eq!(visualizer.steps_per_frame(), MAX_STEPS_PER_FRAME);
+        visualizer.handle_key(b'-');
+        assert_eq!(visualizer.steps_per_frame(), MAX_STEPS_PER_FRAME / 2);
+
+        // Stepping only does anything while paused
+        visualizer.handle_key(b's');
+        assert!(!visualizer.single_step);
+        visualizer.handle_key(b' ');
+        visualizer.handle_key(b's');
+        assert!(visualizer.paused() && visualizer.single_step);
+
+        visualizer.handle_key(b'j');
+        assert_eq!(visualizer.scroll, 0);
+        visualizer.handle_key(b'k');
+        assert_eq!(visualizer.scroll, 1);
+        assert!(visualizer.handle_key(b'x'));
+        assert!(!visualizer.handle_key(b'q'));
+    }
+
+    /// Test drawing the tape, the source line and the output
+    #[test]
+    fn render() {
+        let program = BfProgram::new("view.bf", "++>+.\n<.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().tape_size(4).build(&program);
+        let mut visualizer = Visualizer::new();
+        let mut io = ViewIo {
+            io: &mut VecIo::default(),
+            output: &mut visualizer.output,
+        };
+        for _ in 0..5 {
+            tape.step_io(&mut io).unwrap();
+        }
+        assert_eq!(
+            visualizer.render(&tape, "paused"),
+            [
+                Line::from("Step 5  1 per frame  paused"),
+                Line::default(),
+                Line::from(vec![
+                    Span::raw("[0]"),
+                    Span::styled("   2", NON_ZERO),
+                    Span::styled("   1", HIGHLIGHT),
+                    Span::raw("   0"),
+                    Span::raw("   0"),
+                ]),
+                Line::from(vec![
+                    Span::raw("   2 | "),
+                    Span::styled("<", HIGHLIGHT),
+                    Span::raw("."),
+                ]),
+                Line::default(),
+                Line::from("Output:"),
+                Line::from("\x01"),
+                Line::default(),
+                Line::from("space pause  s step  + faster  - slower  k/j scroll  q quit"),
+            ]
+        );
+
+        // Scrolling the output up leaves nothing in view
+        visualizer.handle_key(b'k');
+        tape.step_io(&mut VecIo::default()).unwrap();
+        tape.step_io(&mut VecIo::default()).unwrap();
+        let lines = visualizer.render(&tape, "finished");
+        assert_eq!(lines[3], Line::from("End of program"));
+        assert_eq!(lines[5..7], [Line::from("Output:"), Line::default()]);
+    }
+
+    /// Test quitting, pausing and stepping through a program with keys
+    #[test]
+    fn run_with_keys() {
+        let program = BfProgram::new("view.bf", "+.+.+.").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        // Start from the cell before A so that what is output can be seen
+        tape.load_initial(b"@").unwrap();
+        let mut io = VecIo::default();
+        let mut visualizer = Visualizer::new();
+
+        // Pause, step and quit
+        let (reason, _) = run_with(&mut visualizer, &mut tape, &mut io, b" sq");
+        // The keys are all taken before the frame, so quitting stops the step being made
+        assert_eq!(reason.unwrap(), StopReason::Aborted { program_pointer: 0 });
+        assert_eq!(tape.steps(), 0);
+
+        // Resume and run to the end, where the view stays until the keys run out
+        let (reason, screen) = run_with(&mut visualizer, &mut tape, &mut io, b" +");
+        assert_eq!(reason.unwrap(), StopReason::Halted);
+        assert_eq!(visualizer.output(), b"ABC");
+        assert!(io.output().is_empty());
+        assert_eq!(
+            screen[..8],
+            [
+                "Step 6  2 per frame  finished",
+                "",
+                "[0]  67   0   0   0   0   0   0   0   0",
+                "End of program",
+                "",
+                "Output:",
+                "ABC",
+                "",
+            ]
+        );
+    }
+
+    /// Test that an error stops the program with the error shown in the view
+    #[test]
+    fn run_to_error() {
+        let program = BfProgram::new("view.bf", "+.<").unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        let mut visualizer = Visualizer::new();
+        let (result, screen) = run_with(&mut visualizer, &mut tape, &mut VecIo::default(), b"");
+        assert!(
+            matches!(result, Err(BfError::DataPtrMovedBeforeStart { .. })),
+            "{:?}",
+            result
+        );
+        assert_eq!(screen[0], "Step 3  1 per frame  failed");
+        assert!(screen[4].contains("[E_TAPE_UNDERRUN]"), "{:?}", screen);
+        assert_eq!(visualizer.output(), [1]);
+    }
+
+    /// Test that the step limit and interrupt flag stop a program in the view
+    #[test]
+    fn run_to_limits() {
+        let mut program = BfProgram::new("view.bf", "+[]").unwrap();
+        program.validate().unwrap();
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().step_limit(Some(100)).build(&program);
+        let mut visualizer = Visualizer::new();
+        for _ in 0..10 {
+            visualizer.handle_key(b'+');
+        }
+        let (result, _) = run_with(&mut visualizer, &mut tape, &mut VecIo::default(), b"");
+        assert!(matches!(
+            result,
+            Err(BfError::StepLimitExceeded { steps: 100, .. })
+        ));
+
+        // Interrupting a paused program stops it
+        let mut tape: BfTape<u8> = BfTapeBuilder::new().build(&program);
+        tape.set_interrupt_flag(Arc::new(AtomicBool::new(true)));
+        let mut visualizer = Visualizer::new();
+        visualizer.handle_key(b' ');
+        let keys = [INTERRUPT, INTERRUPT];
+        let (result, screen) = run_with(&mut visualizer, &mut tape, &mut VecIo::default(), &keys);
+        assert!(matches!(result, Err(BfError::Interrupted { .. })));
+        assert_eq!(screen[0], "Step 0  1 per frame  failed");
+        assert_eq!(tape.steps(), 0);
+    }
+}
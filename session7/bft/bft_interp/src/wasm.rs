@@ -0,0 +1,194 @@
+//! Bindings for running BF programs from JavaScript, such as in a browser playground.
+//!
+//! A program can be run in one go with `run`, or a few instructions at a time with `step_n`
+//! so that a front end can show the tape changing as the program runs. Errors are thrown as
+//! objects with the error's `code`, its `message` and the `line` and `column` in the source
+//! that it happened at, which are null if the error isn't tied to a location.
+
+use crate::{parse_source, BfError, BfTapeBuilder, OwnedBfTape, VecIo};
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+
+/// A program and its tape, with the input and output kept in memory.
+///
+#[wasm_bindgen]
+pub struct WasmInterpreter {
+    /// The tape, which owns the program
+    tape: OwnedBfTape<u8>,
+    /// Input waiting to be read and output that hasn't been taken yet
+    io: VecIo,
+    /// Flag indicating if the program has run to completion
+    finished: bool,
+}
+
+#[wasm_bindgen]
+impl WasmInterpreter {
+    /// Parse and validate a program and create a tape with a number of cells for it. Zero cells
+    /// gives a tape of the default size.
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, cells: usize) -> Result<WasmInterpreter, JsValue> {
+        Self::from_source(source, cells).map_err(|error| to_js_error(&error))
+    }
+
+    /// Run the program from the start with the input, returning everything that it outputs
+    pub fn run(&mut self, input: &[u8]) -> Result<Vec<u8>, JsValue> {
+        self.run_to_end(input).map_err(|error| to_js_error(&error))
+    }
+
+    /// Execute up to n instructions, returning true once the program has finished
+    pub fn step_n(&mut self, n: u32) -> Result<bool, JsValue> {
+        self.step_many(n).map_err(|error| to_js_error(&error))
+    }
+
+    /// Add input for the program to read when it's being stepped. Once the input runs out the
+    /// program sees the end of file.
+    pub fn push_input(&mut self, input: &[u8]) {
+        self.io.input.extend(input);
+    }
+
+    /// Take the output written since the output was last taken
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.io.output)
+    }
+
+    /// Flag indicating if the program has run to completion
+    #[wasm_bindgen(getter)]
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// The data pointer
+    #[wasm_bindgen(getter)]
+    pub fn data_pointer(&self) -> usize {
+        self.tape.data_pointer()
+    }
+
+    /// The program pointer
+    #[wasm_bindgen(getter)]
+    pub fn program_pointer(&self) -> usize {
+        self.tape.program_pointer()
+    }
+
+    /// Index of the first cell returned by tape_window for the same radius
+    pub fn window_start(&self, radius: usize) -> usize {
+        self.tape.tape_window(radius).0
+    }
+
+    /// The cells either side of the data pointer, up to radius cells in each direction
+    pub fn tape_window(&self, radius: usize) -> Vec<u8> {
+        self.tape.tape_window(radius).1.into_owned()
+    }
+}
+
+impl WasmInterpreter {
+    /// Parse and validate a program and create a tape for it
+    fn from_source(source: &str, cells: usize) -> Result<Self, BfError> {
+        let program = parse_source(source)?;
+        let tape = BfTapeBuilder::new()
+            .tape_size(cells)
+            .try_build(Arc::new(program))?;
+        Ok(Self {
+            tape,
+            io: VecIo::new(Vec::new()),
+            finished: false,
+        })
+    }
+
+    /// Run the program from the start with the input
+    fn run_to_end(&mut self, input: &[u8]) -> Result<Vec<u8>, BfError> {
+        self.tape.reset();
+        self.io = VecIo::new(input.to_vec());
+        self.finished = false;
+        let result = self.tape.interpreter_io(&mut self.io);
+        self.finished = true;
+        result?;
+        Ok(self.take_output())
+    }
+
+    /// Execute up to n instructions. Nothing can pause the program, so it only stops early
+    /// when it finishes.
+    fn step_many(&mut self, n: u32) -> Result<bool, BfError> {
+        for _ in 0..n {
+            if self.finished {
+                break;
+            }
+            self.finished = self.tape.step_io(&mut self.io)?.is_some();
+        }
+        Ok(self.finished)
+    }
+}
+
+/// Convert an error into an object for JavaScript
+fn to_js_error(error: &BfError) -> JsValue {
+    let location = error.location();
+    let fields = [
+        ("code", JsValue::from_str(error.code())),
+        ("message", JsValue::from_str(&error.to_string())),
+        (
+            "line",
+            location.map_or(JsValue::NULL, |location| location.line().into()),
+        ),
+        (
+            "column",
+            location.map_or(JsValue::NULL, |location| location.offset().into()),
+        ),
+    ];
+    let object = js_sys::Object::new();
+    for (name, value) in fields {
+        // Setting a property of a plain object can't fail
+        js_sys::Reflect::set(&object, &JsValue::from_str(name), &value).ok();
+    }
+    object.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test running a program in one go, and again with different input
+    #[test]
+    fn run() {
+        let mut interpreter = WasmInterpreter::from_source(",+.,+.", 10).unwrap();
+        assert_eq!(interpreter.run_to_end(b"AB").unwrap(), b"BC");
+        assert!(interpreter.finished());
+        assert_eq!(interpreter.run_to_end(b"ab").unwrap(), b"bc");
+    }
+
+    /// Test stepping through a program a few instructions at a time
+    #[test]
+    fn step_many() {
+        let mut interpreter = WasmInterpreter::from_source("++>+++[-].", 10).unwrap();
+        assert!(!interpreter.step_many(3).unwrap());
+        assert_eq!(interpreter.data_pointer(), 1);
+        assert_eq!(interpreter.program_pointer(), 3);
+        assert!(!interpreter.step_many(3).unwrap());
+        assert_eq!(interpreter.window_start(1), 0);
+        assert_eq!(interpreter.tape_window(1), [2, 3, 0]);
+        assert!(interpreter.step_many(100).unwrap());
+        assert_eq!(interpreter.tape_window(1), [2, 0, 0]);
+        assert_eq!(interpreter.take_output(), [0]);
+        assert!(interpreter.step_many(1).unwrap());
+    }
+
+    /// Test input given while stepping
+    #[test]
+    fn push_input() {
+        let mut interpreter = WasmInterpreter::from_source(",.", 10).unwrap();
+        interpreter.push_input(b"x");
+        assert!(interpreter.step_many(10).unwrap());
+        assert_eq!(interpreter.take_output(), b"x");
+    }
+
+    /// Test that errors have their code and where in the source they happened
+    #[test]
+    fn errors() {
+        let error = WasmInterpreter::from_source("[", 10).err().unwrap();
+        assert_eq!(error.code(), "E_INVALID_PROGRAM");
+        assert_eq!(error.location(), None);
+
+        let mut interpreter = WasmInterpreter::from_source("+\n>>", 2).unwrap();
+        let error = interpreter.run_to_end(b"").unwrap_err();
+        assert_eq!(error.code(), "E_TAPE_OVERRUN");
+        assert_eq!(error.location(), Some(bft_types::BfLocation::new(2, 2)));
+    }
+}
@@ -0,0 +1,63 @@
+//! Tests of the wasm module as JavaScript sees it, which only build for wasm32 with the wasm
+//! feature. They are run in node by wasm-bindgen-test-runner:
+//!
+//!     cargo test -p bft_interp --target wasm32-unknown-unknown --features wasm --test wasm
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use bft_interp::wasm::WasmInterpreter;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// Get a property of an object that was thrown
+fn field(error: &JsValue, name: &str) -> JsValue {
+    js_sys::Reflect::get(error, &JsValue::from_str(name)).unwrap()
+}
+
+/// Test running a program, then stepping through it with input given as it goes
+#[wasm_bindgen_test]
+fn run_and_step() {
+    let mut interpreter = WasmInterpreter::new(",+.,+.", 10).unwrap();
+    assert_eq!(interpreter.run(b"AB").unwrap(), b"BC");
+    assert!(interpreter.finished());
+
+    let mut interpreter = WasmInterpreter::new("++>+,.", 10).unwrap();
+    assert!(!interpreter.step_n(3).unwrap());
+    assert_eq!(interpreter.data_pointer(), 1);
+    assert_eq!(interpreter.program_pointer(), 3);
+    assert_eq!(interpreter.window_start(1), 0);
+    assert_eq!(interpreter.tape_window(1), [2, 0, 0]);
+    interpreter.push_input(b"x");
+    assert!(interpreter.step_n(10).unwrap());
+    assert_eq!(interpreter.take_output(), b"x");
+    assert!(interpreter.take_output().is_empty());
+}
+
+/// Test that errors are thrown as objects with the code, message and location
+#[wasm_bindgen_test]
+fn errors() {
+    let error = WasmInterpreter::new("[", 10).err().unwrap();
+    assert!(error.is_object());
+    assert_eq!(field(&error, "code"), "E_INVALID_PROGRAM");
+    assert!(field(&error, "message")
+        .as_string()
+        .unwrap()
+        .starts_with("[E_INVALID_PROGRAM]"));
+    assert!(field(&error, "line").is_null());
+    assert!(field(&error, "column").is_null());
+
+    let mut interpreter = WasmInterpreter::new("+\n>>", 2).unwrap();
+    let error = interpreter.run(b"").unwrap_err();
+    assert_eq!(field(&error, "code"), "E_TAPE_OVERRUN");
+    assert!(field(&error, "message")
+        .as_string()
+        .unwrap()
+        .contains("Data pointer moved after end of tape"));
+    assert_eq!(field(&error, "line").as_f64(), Some(2.0));
+    assert_eq!(field(&error, "column").as_f64(), Some(2.0));
+    assert!(interpreter.finished());
+
+    let mut interpreter = WasmInterpreter::new("<", 2).unwrap();
+    let error = interpreter.step_n(1).unwrap_err();
+    assert_eq!(field(&error, "code"), "E_TAPE_UNDERRUN");
+    assert_eq!(field(&error, "column").as_f64(), Some(1.0));
+}
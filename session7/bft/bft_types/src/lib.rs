@@ -206,6 +206,68 @@ impl fmt::Display for BfJumpLocation {
     }
 }
 
+/// Operations of a program compiled into a form that runs faster than one instruction at a
+/// time. Runs of the same command are combined and common loops are replaced by the
+/// operations that have the same effect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BfOp {
+    /// Add to the current cell, a negative amount subtracts. From a run of + or -.
+    Add(i64),
+    /// Move the data pointer, a negative amount moves it back. From a run of > or <.
+    Move(isize),
+    /// Set the current cell to zero. From [-] or [+].
+    ClearCell,
+    /// Add the current cell multiplied by a factor to the cell at an offset from it. From a
+    /// loop such as [->++<], which becomes a MulAdd for each cell the loop changes followed
+    /// by a ClearCell. A loop that moves further than the cells it changes also has a MulAdd
+    /// with a factor of zero for the furthest cell it moves to in that direction, so that
+    /// the loop is known to go off the end of the tape there.
+    MulAdd { offset: isize, factor: i64 },
+    /// Move the data pointer forward until it reaches a zero cell. From [>].
+    ScanRight,
+    /// Move the data pointer back until it reaches a zero cell. From [<].
+    ScanLeft,
+    /// Output the current cell. From a `.`.
+    Output,
+    /// Input a value into the current cell. From a `,`.
+    Input,
+    /// Start of a loop. If the current cell is zero, carry on after the LoopEnd at the index.
+    LoopStart(usize),
+    /// End of a loop. If the current cell isn't zero, carry on after the LoopStart at the index.
+    LoopEnd(usize),
+}
+
+/// A program compiled into BfOps, see BfProgram::compile.
+///
+/// The source map gives the index of the instruction in the program that each op was made
+/// from, so that errors can be reported at a place in the source. The ops of a loop that was
+/// replaced all map to its `[`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BfIr {
+    /// The operations in the order they are run
+    ops: Vec<BfOp>,
+    /// Index of the instruction each op was made from
+    source_map: Vec<usize>,
+}
+
+impl BfIr {
+    /// The operations
+    pub fn ops(&self) -> &[BfOp] {
+        &self.ops
+    }
+
+    /// Index of the instruction in the program that each op was made from
+    pub fn source_map(&self) -> &[usize] {
+        &self.source_map
+    }
+
+    /// Add an op made from the instruction at an index
+    fn push(&mut self, op: BfOp, source: usize) {
+        self.ops.push(op);
+        self.source_map.push(source);
+    }
+}
+
 /// Brain Fuck program
 ///
 /// To read a BF program from a file use the from_file method.
@@ -230,6 +292,10 @@ pub struct BfProgram {
     instructions: Vec<BfInstruction>,
     /// Locations of matching jumps
     location_map: BiMap<BfLocation, BfLocation>,
+    /// Index of the matching jump for each instruction
+    jump_table: Vec<usize>,
+    /// The source the program was parsed from, so that errors can show the code
+    source: Option<String>,
 }
 
 // Implementations for BfProgram
@@ -249,10 +315,31 @@ impl BfProgram {
         &self.location_map
     }
 
+    /// The program's jumps as indexes into the instructions. The entry for a jump forward
+    /// is the index of the matching jump backward and vice versa. Entries for other
+    /// instructions are their own index. The table is empty until the program is validated.
+    pub fn jump_table(&self) -> &[usize] {
+        &self.jump_table
+    }
+
     /// Size of program
     pub fn size(&self) -> usize {
         self.instructions.len()
     }
+
+    /// A line of the program's source, numbered from 1. None if the program wasn't parsed
+    /// from source or the line doesn't exist.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// let program = bft_types::BfProgram::new(&"lines.bf", "+\n-").unwrap();
+    /// assert_eq!(program.source_line(2), Some("-"));
+    /// assert_eq!(program.source_line(3), None);
+    /// ```
+    pub fn source_line(&self, line: usize) -> Option<&str> {
+        self.source.as_ref()?.lines().nth(line.checked_sub(1)?)
+    }
 }
 
 // Implementation details for Brain Fuck program
@@ -270,6 +357,7 @@ impl BfProgram {
     /// let content:String = std::fs::read_to_string::<&String>(&filename).unwrap();
     /// let program = bft_types::BfProgram::new(filename, &content).unwrap();
     /// ```
+    #[allow(clippy::explicit_counter_loop)]
     pub fn new(filename: impl AsRef<Path>, content: &str) -> std::io::Result<Self> {
         let mut instructions = Vec::new();
         let mut line_no = 1;
@@ -288,10 +376,38 @@ impl BfProgram {
             filename: filename.as_ref().to_path_buf(),
             instructions,
             location_map: BiMap::new(),
+            jump_table: Vec::new(),
+            source: Some(content.to_string()),
         };
         Ok(program)
     }
 
+    /// Create a new Brain Fuck program from instructions that have already been parsed, or
+    /// built by hand. The instructions can include comments, which do nothing when run.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use bft_types::{BfCommand, BfInstruction};
+    /// let program = bft_types::BfProgram::from_instructions(
+    ///     &"built.bf",
+    ///     vec![
+    ///         BfInstruction::new(BfCommand::IncValue, 1, 1),
+    ///         BfInstruction::new(BfCommand::Comment, 1, 2),
+    ///     ],
+    /// );
+    /// assert_eq!(program.size(), 2);
+    /// ```
+    pub fn from_instructions(filename: impl AsRef<Path>, instructions: Vec<BfInstruction>) -> Self {
+        Self {
+            filename: filename.as_ref().to_path_buf(),
+            instructions,
+            location_map: BiMap::new(),
+            jump_table: Vec::new(),
+            source: None,
+        }
+    }
+
     /// Read a BrainFuck program from a file. The program will be returned in a Result<>.
     /// If the file is not found or there are issues with reading it an error will be returned.
     ///
@@ -309,6 +425,24 @@ impl BfProgram {
         Ok(program)
     }
 
+    /// Read a BrainFuck program from a reader, such as stdin. The name is used in place of a
+    /// filename. It is an error for the program not to be UTF-8.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// let program = bft_types::BfProgram::from_reader("<stdin>", "+[-]".as_bytes()).unwrap();
+    /// assert_eq!(program.size(), 4);
+    /// ```
+    pub fn from_reader(
+        name: impl AsRef<Path>,
+        mut reader: impl std::io::Read,
+    ) -> std::io::Result<BfProgram> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        BfProgram::new(name, &content)
+    }
+
     /// Validate a BrainFuck program by finding matching jump forwards and backs
     ///
     /// Parse the instructions using a stack to keep track of jumps and when a pair
@@ -333,12 +467,13 @@ impl BfProgram {
         // Jump forwards (the [) are pushed on to the stack. When a jump backward is
         // found, the top item on the stack is removed which will be the matching jump
         // forward. The locations of both jumps are then saved for later use
-        let mut stack: Vec<BfInstruction> = Vec::new();
+        let mut stack: Vec<usize> = Vec::new();
+        let mut jump_table: Vec<usize> = (0..self.instructions.len()).collect();
 
         // Parse the BF program and find the jumps
-        for i in &self.instructions {
+        for (index, i) in self.instructions.iter().enumerate() {
             if i.command == (BfCommand::JumpForward) {
-                stack.push(*i);
+                stack.push(index);
             } else if i.command == (BfCommand::JumpBackward) {
                 // If stack is empty, then the jump forward for this jump back
                 // is missing. Or there is an extra jump back.
@@ -348,7 +483,10 @@ impl BfProgram {
 
                 // Make a note of the locations of the two jumps in the pair
                 let last_jump = stack.pop().unwrap();
-                self.location_map.insert(last_jump.location, i.location);
+                self.location_map
+                    .insert(self.instructions[last_jump].location, i.location);
+                jump_table[last_jump] = index;
+                jump_table[index] = last_jump;
             }
         }
 
@@ -356,12 +494,211 @@ impl BfProgram {
         // extra jump forward.
         if !stack.is_empty() {
             let last_bracket = stack.pop().unwrap();
-            return Err(anyhow!("Extra {}", last_bracket));
+            return Err(anyhow!("Extra {}", self.instructions[last_bracket]));
         }
 
         // Stack is empty, all jumps paired up, so pass their locations back
+        self.jump_table = jump_table;
         Ok(())
     }
+
+    /// Find all of the brackets that don't have a match, in the order they are in the source.
+    /// validate stops at the first of them, this is for reporting every one.
+    ///
+    /// Usage:
+    /// ```
+    ///   let program = bft_types::BfProgram::new(&"sample.bf", "]+[[-]").unwrap();
+    ///   let unmatched = program.unmatched_brackets();
+    ///   assert_eq!(unmatched.len(), 2);
+    ///   assert_eq!(unmatched[0].location().to_string(), "1:1");
+    ///   assert_eq!(unmatched[1].location().to_string(), "1:3");
+    /// ```
+    pub fn unmatched_brackets(&self) -> Vec<&BfInstruction> {
+        // The same stack of jump forwards as validate uses, but an extra jump back is noted
+        // rather than stopping, and what's left on the stack at the end are the extra jump
+        // forwards
+        let mut stack: Vec<usize> = Vec::new();
+        let mut unmatched: Vec<usize> = Vec::new();
+        for (index, i) in self.instructions.iter().enumerate() {
+            if i.command == BfCommand::JumpForward {
+                stack.push(index);
+            } else if i.command == BfCommand::JumpBackward && stack.pop().is_none() {
+                unmatched.push(index);
+            }
+        }
+        unmatched.extend(stack);
+        unmatched.sort_unstable();
+        unmatched
+            .into_iter()
+            .map(|index| &self.instructions[index])
+            .collect()
+    }
+
+    /// Compile the program into BfOps, which can be run faster than the instructions. The
+    /// program must have been validated first. Comments are left out.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use bft_types::BfOp;
+    /// let mut program = bft_types::BfProgram::new(&"mul.bf", "+++[->++<]>.").unwrap();
+    /// program.validate().unwrap();
+    /// let ir = program.compile().unwrap();
+    /// assert_eq!(
+    ///     ir.ops(),
+    ///     &[
+    ///         BfOp::Add(3),
+    ///         BfOp::MulAdd { offset: 1, factor: 2 },
+    ///         BfOp::ClearCell,
+    ///         BfOp::Move(1),
+    ///         BfOp::Output
+    ///     ]
+    /// );
+    /// assert_eq!(ir.source_map(), &[0, 3, 3, 10, 11]);
+    /// ```
+    pub fn compile(&self) -> Result<BfIr, anyhow::Error> {
+        if self.jump_table.len() != self.instructions.len() {
+            return Err(anyhow!(
+                "{} must be validated before it is compiled",
+                self.filename.display()
+            ));
+        }
+
+        let mut ir = BfIr::default();
+        // Index of the LoopStart op of each loop that hasn't been closed yet
+        let mut loops: Vec<usize> = Vec::new();
+        let mut index = 0;
+        while index < self.instructions.len() {
+            let start = index;
+            let command = self.instructions[start].command;
+            index += 1;
+            match command {
+                BfCommand::Comment => {}
+                BfCommand::IncValue | BfCommand::DecValue => {
+                    let count = self.run_length(start);
+                    index = start + count;
+                    let amount = count as i64;
+                    let amount = if command == BfCommand::IncValue {
+                        amount
+                    } else {
+                        -amount
+                    };
+                    ir.push(BfOp::Add(amount), start);
+                }
+                BfCommand::IncDataPointer | BfCommand::DecDataPointer => {
+                    let count = self.run_length(start);
+                    index = start + count;
+                    let amount = count as isize;
+                    let amount = if command == BfCommand::IncDataPointer {
+                        amount
+                    } else {
+                        -amount
+                    };
+                    ir.push(BfOp::Move(amount), start);
+                }
+                BfCommand::OutputValue => ir.push(BfOp::Output, start),
+                BfCommand::InputValue => ir.push(BfOp::Input, start),
+                BfCommand::JumpForward => {
+                    let end = self.jump_table[start];
+                    match self.fuse_loop(start, end) {
+                        Some(ops) => {
+                            for op in ops {
+                                ir.push(op, start);
+                            }
+                            index = end + 1;
+                        }
+                        None => {
+                            // Where the loop ends isn't known until its LoopEnd is reached
+                            loops.push(ir.ops.len());
+                            ir.push(BfOp::LoopStart(0), start);
+                        }
+                    }
+                }
+                BfCommand::JumpBackward => {
+                    let open = loops
+                        .pop()
+                        .ok_or_else(|| anyhow!("Extra {}", self.instructions[start]))?;
+                    ir.ops[open] = BfOp::LoopStart(ir.ops.len());
+                    ir.push(BfOp::LoopEnd(open), start);
+                }
+            }
+        }
+        Ok(ir)
+    }
+
+    /// Number of instructions in a row, starting at an index, that have the same command
+    fn run_length(&self, start: usize) -> usize {
+        let command = self.instructions[start].command;
+        self.instructions[start..]
+            .iter()
+            .take_while(|inst| inst.command == command)
+            .count()
+    }
+
+    /// The ops that have the same effect as the loop between the jumps at start and end, or
+    /// None if the loop has to be run as it is
+    fn fuse_loop(&self, start: usize, end: usize) -> Option<Vec<BfOp>> {
+        let body: Vec<BfCommand> = self.instructions[start + 1..end]
+            .iter()
+            .map(|inst| inst.command)
+            .filter(|&command| command != BfCommand::Comment)
+            .collect();
+        match body.as_slice() {
+            [BfCommand::DecValue] | [BfCommand::IncValue] => return Some(vec![BfOp::ClearCell]),
+            [BfCommand::IncDataPointer] => return Some(vec![BfOp::ScanRight]),
+            [BfCommand::DecDataPointer] => return Some(vec![BfOp::ScanLeft]),
+            _ => {}
+        }
+
+        // A loop that only moves and changes cells, ends up back where it started and takes one
+        // off the current cell each time round adds a multiple of the current cell to the
+        // other cells it changes
+        let mut offset: isize = 0;
+        let (mut lowest, mut highest) = (0, 0);
+        let mut changes: Vec<(isize, i64)> = Vec::new();
+        for command in body {
+            let change = match command {
+                BfCommand::IncDataPointer => {
+                    offset += 1;
+                    highest = highest.max(offset);
+                    continue;
+                }
+                BfCommand::DecDataPointer => {
+                    offset -= 1;
+                    lowest = lowest.min(offset);
+                    continue;
+                }
+                BfCommand::IncValue => 1,
+                BfCommand::DecValue => -1,
+                _ => return None,
+            };
+            match changes.iter_mut().find(|(cell, _)| *cell == offset) {
+                Some((_, factor)) => *factor += change,
+                None => changes.push((offset, change)),
+            }
+        }
+        if offset != 0 || !changes.contains(&(0, -1)) {
+            return None;
+        }
+        let mut ops: Vec<BfOp> = changes
+            .into_iter()
+            .filter(|&(offset, factor)| offset != 0 && factor != 0)
+            .map(|(offset, factor)| BfOp::MulAdd { offset, factor })
+            .collect();
+        for furthest in [lowest, highest] {
+            let reached = ops
+                .iter()
+                .any(|op| matches!(op, BfOp::MulAdd { offset, .. } if *offset == furthest));
+            if furthest != 0 && !reached {
+                ops.push(BfOp::MulAdd {
+                    offset: furthest,
+                    factor: 0,
+                });
+            }
+        }
+        ops.push(BfOp::ClearCell);
+        Some(ops)
+    }
 }
 
 #[cfg(test)]
@@ -375,6 +712,36 @@ mod tests {
         assert_eq!(program.size(), 5);
     }
 
+    // Test that a program can be built from instructions, including comments
+    #[test]
+    fn program_from_instructions() {
+        let mut program = BfProgram::from_instructions(
+            "built.bf",
+            vec![
+                BfInstruction::new(BfCommand::JumpForward, 1, 1),
+                BfInstruction::new(BfCommand::Comment, 1, 2),
+                BfInstruction::new(BfCommand::JumpBackward, 1, 3),
+            ],
+        );
+        assert_eq!(program.size(), 3);
+        program.validate().unwrap();
+        assert_eq!(program.jump_table(), &[2, 1, 0]);
+    }
+
+    // Test that the source is kept for the lines of the program
+    #[test]
+    fn source_lines() {
+        let program = BfProgram::new("sample.bf", "><+\n\n-.").unwrap();
+        assert_eq!(program.source_line(0), None);
+        assert_eq!(program.source_line(1), Some("><+"));
+        assert_eq!(program.source_line(2), Some(""));
+        assert_eq!(program.source_line(3), Some("-."));
+        assert_eq!(program.source_line(4), None);
+
+        let program = BfProgram::from_instructions("built.bf", Vec::new());
+        assert_eq!(program.source_line(1), None);
+    }
+
     // Test that commands in a BF program have been read correctly.
     #[test]
     fn check_commands_in_minimal_program() {
@@ -468,6 +835,20 @@ mod tests {
         assert_eq!(program.size(), 0);
     }
 
+    // Test that a program is read from a reader, standing in for stdin
+    #[test]
+    fn program_from_reader() {
+        let reader = std::io::Cursor::new(b"+[\n-]".to_vec());
+        let program = BfProgram::from_reader("<stdin>", reader).unwrap();
+        assert_eq!(program.filename(), Path::new("<stdin>"));
+        assert_eq!(program.size(), 4);
+        assert_eq!(program.source_line(2), Some("-]"));
+
+        let reader = std::io::Cursor::new(vec![0xff, b'+']);
+        let error = BfProgram::from_reader("<stdin>", reader).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
     // Check that an empty file is handled.
     #[test]
     fn read_empty_file() {
@@ -510,6 +891,35 @@ mod tests {
         assert!(program.validate().is_ok());
     }
 
+    // Find every unmatched bracket, not just the first
+    #[test]
+    fn unmatched_brackets() {
+        let program = BfProgram::new("good.bf", "><+-[.[]]").unwrap();
+        assert!(program.unmatched_brackets().is_empty());
+
+        let program = BfProgram::new("bad.bf", "[\n]]+[\n-]]").unwrap();
+        let unmatched: Vec<(BfCommand, BfLocation)> = program
+            .unmatched_brackets()
+            .iter()
+            .map(|inst| (inst.command(), inst.location()))
+            .collect();
+        assert_eq!(
+            unmatched,
+            vec![
+                (BfCommand::JumpBackward, BfLocation { line: 2, offset: 2 }),
+                (BfCommand::JumpBackward, BfLocation { line: 3, offset: 3 }),
+            ]
+        );
+
+        let program = BfProgram::new("bad.bf", "[[]]][[").unwrap();
+        let unmatched: Vec<usize> = program
+            .unmatched_brackets()
+            .iter()
+            .map(|inst| inst.location().offset())
+            .collect();
+        assert_eq!(unmatched, vec![5, 6, 7]);
+    }
+
     // Check that the locations of jumps are correct in a good BF program
     #[test]
     fn validate_good_jumps() {
@@ -524,4 +934,89 @@ mod tests {
             &BfLocation { line: 1, offset: 7 }
         );
     }
+
+    // Check that the jump table pairs up the indexes of the jumps
+    #[test]
+    fn validate_jump_table() {
+        let mut program = BfProgram::new("good.bf", "+[>[-]<]").unwrap();
+        assert!(program.jump_table().is_empty());
+        assert!(program.validate().is_ok());
+        assert_eq!(program.jump_table(), &[0, 7, 2, 5, 4, 3, 6, 1]);
+    }
+
+    // Check that runs are combined, common loops are replaced and other loops are kept
+    #[test]
+    fn compile_ops() {
+        let mut program = BfProgram::new("ops.bf", ">>+++--[-]<[>]\n[<][>+<-->]>[.,]").unwrap();
+        assert!(program.compile().is_err());
+        program.validate().unwrap();
+        let ir = program.compile().unwrap();
+        assert_eq!(
+            ir.ops(),
+            &[
+                BfOp::Move(2),
+                BfOp::Add(3),
+                BfOp::Add(-2),
+                BfOp::ClearCell,
+                BfOp::Move(-1),
+                BfOp::ScanRight,
+                BfOp::ScanLeft,
+                BfOp::LoopStart(13),
+                BfOp::Move(1),
+                BfOp::Add(1),
+                BfOp::Move(-1),
+                BfOp::Add(-2),
+                BfOp::Move(1),
+                BfOp::LoopEnd(7),
+                BfOp::Move(1),
+                BfOp::LoopStart(18),
+                BfOp::Output,
+                BfOp::Input,
+                BfOp::LoopEnd(15),
+            ]
+        );
+        assert_eq!(ir.ops().len(), ir.source_map().len());
+        assert_eq!(ir.source_map()[5], 11);
+        assert_eq!(ir.source_map()[14], 25);
+    }
+
+    // Check that a loop that moves further than the cells it changes keeps how far it moves
+    #[test]
+    fn compile_loop_reach() {
+        let cases = [
+            (
+                "[->+<]",
+                vec![BfOp::MulAdd {
+                    offset: 1,
+                    factor: 1,
+                }],
+            ),
+            (
+                "[-><]",
+                vec![BfOp::MulAdd {
+                    offset: 1,
+                    factor: 0,
+                }],
+            ),
+            (
+                "[-<<>+>]",
+                vec![
+                    BfOp::MulAdd {
+                        offset: -1,
+                        factor: 1,
+                    },
+                    BfOp::MulAdd {
+                        offset: -2,
+                        factor: 0,
+                    },
+                ],
+            ),
+        ];
+        for (source, mut ops) in cases {
+            let mut program = BfProgram::new("reach.bf", source).unwrap();
+            program.validate().unwrap();
+            ops.push(BfOp::ClearCell);
+            assert_eq!(program.compile().unwrap().ops(), ops, "{}", source);
+        }
+    }
 }
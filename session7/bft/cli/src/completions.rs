@@ -0,0 +1,126 @@
+//! Shell completion scripts for bft.
+//!
+//! The scripts are made by clap_complete from bft's clap command, see crate::command, so they
+//! have the same subcommands and options as the command line. An option's possible values,
+//! such as the tape modes, are completed, as are file names for the program and for other
+//! options that take a value.
+//!
+//! Install the script for your shell with, for example, `bft completions zsh > _bft` in a
+//! directory on $fpath, or `source <(bft completions bash)`.
+
+use clap::{Command, ValueEnum};
+use std::io::Write;
+
+/// The shells that completion scripts can be generated for.
+///
+/// * Bash
+/// * Zsh
+/// * Fish
+/// * PowerShell
+///
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum Shell {
+    /// The Bourne Again SHell
+    Bash,
+    /// The Z shell
+    Zsh,
+    /// The friendly interactive shell
+    Fish,
+    /// Microsoft's PowerShell
+    #[value(name = "powershell")]
+    PowerShell,
+}
+
+impl From<Shell> for clap_complete::Shell {
+    fn from(shell: Shell) -> Self {
+        match shell {
+            Shell::Bash => clap_complete::Shell::Bash,
+            Shell::Zsh => clap_complete::Shell::Zsh,
+            Shell::Fish => clap_complete::Shell::Fish,
+            Shell::PowerShell => clap_complete::Shell::PowerShell,
+        }
+    }
+}
+
+/// Write the completion script for a shell, which clap_complete makes from the command.
+///
+/// Example usage:
+/// ```
+///     let mut script = Vec::new();
+///     cli::completions::generate(cli::completions::Shell::Bash, cli::command(), &mut script).unwrap();
+///     let script = String::from_utf8(script).unwrap();
+///     assert!(script.contains("--tape-mode"));
+/// ```
+pub fn generate(
+    shell: Shell,
+    mut command: Command,
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    let name = command.get_name().to_string();
+    // clap_complete panics if it can't write, so the script is made in memory and then
+    // written, which lets an error such as a closed pipe be reported
+    let mut script = Vec::new();
+    clap_complete::generate(
+        clap_complete::Shell::from(shell),
+        &mut command,
+        name,
+        &mut script,
+    );
+    writer.write_all(&script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The script for a shell as a string
+    fn script(shell: Shell) -> String {
+        let mut script = Vec::new();
+        generate(shell, crate::command(), &mut script).unwrap();
+        String::from_utf8(script).unwrap()
+    }
+
+    /// Test that every shell's script has every subcommand and every long option of each,
+    /// so that an option added to the command line can't be missed out
+    #[test]
+    fn every_option() {
+        let command = crate::command();
+        for shell in Shell::value_variants() {
+            let script = script(*shell);
+            for subcommand in command.get_subcommands() {
+                assert!(
+                    script.contains(subcommand.get_name()),
+                    "{} isn't in the {:?} script",
+                    subcommand.get_name(),
+                    shell
+                );
+                for long in subcommand.get_arguments().filter_map(|arg| arg.get_long()) {
+                    assert!(
+                        script.contains(long),
+                        "--{} of {} isn't in the {:?} script",
+                        long,
+                        subcommand.get_name(),
+                        shell
+                    );
+                }
+            }
+        }
+    }
+
+    /// Test that the scripts complete the possible values of options
+    #[test]
+    fn option_values() {
+        let bash = script(Shell::Bash);
+        assert!(bash.contains("complete -F _bft -o bashdefault -o default bft"));
+        assert!(bash.contains("fixed grow grow-both-ways wrap"));
+        let zsh = script(Shell::Zsh);
+        assert!(zsh.starts_with("#compdef bft\n"));
+        assert!(zsh.contains("grow-both-ways\\:"));
+        let fish = script(Shell::Fish);
+        assert!(fish.contains("-l tape-mode -d"));
+        assert!(fish.contains("{fixed\t"));
+        assert!(fish.contains("How cell values are output"));
+        let powershell = script(Shell::PowerShell);
+        assert!(powershell.contains("'--tape-mode'"));
+    }
+}
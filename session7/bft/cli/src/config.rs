@@ -0,0 +1,391 @@
+//! Default options read from a configuration file.
+//!
+//! The file is TOML with a key for each option, named as the option's long name without the
+//! leading dashes. Flags take true or false, -d's count takes a number, and the other options
+//! take a string or a number, such as
+//!
+//! ```toml
+//! # Options I always use
+//! tape-mode = "grow"
+//! cells = 65536
+//! output-format = "ascii"
+//! ```
+//!
+//! The file is read with the toml crate, so it can be laid out in any way TOML allows, but the
+//! options have to be at the top level rather than in a table, and their values have to be strings,
+//! integers or booleans. Options given on the command line take precedence over environment
+//! variables such as BFT_CELLS, which take precedence over the file, which takes precedence over
+//! the built-in defaults. This is done by putting the options from the file in front of those on
+//! the command line, leaving out any that the command line or an environment variable already
+//! gives.
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked for in the current directory
+const LOCAL_CONFIG: &str = "bft.toml";
+
+/// A value in a config file
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ConfigValue {
+    /// A quoted string
+    String(String),
+    /// A whole number
+    Integer(i64),
+    /// true or false
+    Boolean(bool),
+}
+
+impl ConfigValue {
+    /// Name of the type of the value used in errors
+    fn type_name(&self) -> &'static str {
+        match self {
+            ConfigValue::String(_) => "a string",
+            ConfigValue::Integer(_) => "a number",
+            ConfigValue::Boolean(_) => "true or false",
+        }
+    }
+}
+
+/// Options read from a config file, with the path of the file and anything wrong with it that
+/// wasn't bad enough to stop it being used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The file the options were read from
+    pub path: PathBuf,
+    /// The options, to be put in front of those on the command line
+    pub args: Vec<OsString>,
+    /// Warnings about the file, such as keys that aren't options
+    pub warnings: Vec<String>,
+}
+
+/// The places a config file is looked for when --config isn't given, in order: bft.toml in the
+/// current directory then bft/config.toml in $XDG_CONFIG_HOME, or ~/.config if that isn't set.
+pub fn default_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(LOCAL_CONFIG)];
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join("bft").join("config.toml"));
+    }
+    paths
+}
+
+/// Read the config file for a command line, which is the file given by --config or the first
+/// of the default paths that exists, None if there isn't one. It is an error for a file given
+/// by --config not to exist.
+///
+/// The command line has already been parsed, ignoring errors, so that it is known which options
+/// it and the environment variables give.
+pub fn load(
+    command: &Command,
+    matches: &ArgMatches,
+    default_paths: &[PathBuf],
+) -> Result<Option<Config>, clap::Error> {
+    let path = match matches.get_one::<PathBuf>("config") {
+        Some(path) => path.clone(),
+        None => match default_paths.iter().find(|path| path.is_file()) {
+            Some(path) => path.clone(),
+            None => return Ok(None),
+        },
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| {
+        command.clone().error(
+            clap::error::ErrorKind::Io,
+            format!("can't read config file {}: {}", path.display(), e),
+        )
+    })?;
+    let entries = parse(&text).map_err(|e| {
+        command.clone().error(
+            clap::error::ErrorKind::InvalidValue,
+            format!("in config file {}, {}", path.display(), e),
+        )
+    })?;
+
+    let mut config = Config {
+        path,
+        args: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for (key, value) in entries {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()) && arg.get_id().as_str() != "config")
+        else {
+            config.warnings.push(format!(
+                "{} in config file {} isn't an option and is ignored",
+                key,
+                config.path.display()
+            ));
+            continue;
+        };
+        if given_in(
+            command,
+            matches,
+            arg,
+            &[ValueSource::CommandLine, ValueSource::EnvVariable],
+        ) {
+            continue;
+        }
+        let args = option_args(arg, &value).ok_or_else(|| {
+            command.clone().error(
+                clap::error::ErrorKind::InvalidValue,
+                format!(
+                    "in config file {}, {} should be {} rather than {}",
+                    config.path.display(),
+                    key,
+                    expected_type(arg.get_action()),
+                    value.type_name()
+                ),
+            )
+        })?;
+        check_value(
+            command,
+            &format!("in config file {}, {}", config.path.display(), key),
+            &args,
+        )?;
+        config.args.extend(args);
+    }
+    Ok(Some(config))
+}
+
+/// Whether the command line gives the option, or an option that it can't be used with, in
+/// which case the option's environment variable isn't used
+pub(crate) fn given_on_command_line(
+    command: &Command,
+    matches: &ArgMatches,
+    arg: &clap::Arg,
+) -> bool {
+    given_in(command, matches, arg, &[ValueSource::CommandLine])
+}
+
+/// Whether the option, or an option that it can't be used with, comes from one of the sources,
+/// in which case the option in the config file isn't used
+fn given_in(
+    command: &Command,
+    matches: &ArgMatches,
+    arg: &clap::Arg,
+    sources: &[ValueSource],
+) -> bool {
+    let given = |id: &str| {
+        matches
+            .value_source(id)
+            .is_some_and(|source| sources.contains(&source))
+    };
+    // Both -d and --debug-level set the debug level, so either replaces both
+    let id = arg.get_id().as_str();
+    // A conflict can be given on either of the options
+    let conflicts = |one: &clap::Arg, other: &clap::Arg| {
+        command
+            .get_arg_conflicts_with(one)
+            .iter()
+            .any(|conflict| conflict.get_id() == other.get_id())
+    };
+    given(id)
+        || (id == "debug" && given("debug_level"))
+        || (id == "debug_level" && given("debug"))
+        || command
+            .get_arguments()
+            .filter(|other| given(other.get_id().as_str()))
+            .any(|other| conflicts(arg, other) || conflicts(other, arg))
+}
+
+/// Description of the type of value an option takes, used in errors
+fn expected_type(action: &ArgAction) -> &'static str {
+    match action {
+        ArgAction::SetTrue | ArgAction::SetFalse => "true or false",
+        ArgAction::Count => "a number",
+        _ => "a string or a number",
+    }
+}
+
+/// The command line arguments for an option from the config file, None if the value is the
+/// wrong type for the option. A value is joined to the option so that one such as -1 isn't
+/// taken to be an option itself.
+fn option_args(arg: &clap::Arg, value: &ConfigValue) -> Option<Vec<OsString>> {
+    let flag = format!("--{}", arg.get_long()?);
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, ConfigValue::Boolean(set))
+        | (ArgAction::SetFalse, ConfigValue::Boolean(set)) => Some(if *set {
+            vec![OsString::from(flag)]
+        } else {
+            Vec::new()
+        }),
+        (ArgAction::Count, ConfigValue::Integer(count)) => {
+            Some(vec![OsString::from(flag); usize::try_from(*count).ok()?])
+        }
+        (ArgAction::Set | ArgAction::Append, ConfigValue::String(text)) => {
+            Some(vec![OsString::from(format!("{}={}", flag, text))])
+        }
+        (ArgAction::Set | ArgAction::Append, ConfigValue::Integer(number)) => {
+            Some(vec![OsString::from(format!("{}={}", flag, number))])
+        }
+        _ => None,
+    }
+}
+
+/// Check that the value of an option from the config file or an environment variable is one
+/// the option takes, so that the error can say where it came from, as given by source. Errors
+/// that come from using options together are left to when the whole command line is parsed.
+pub(crate) fn check_value(
+    command: &Command,
+    source: &str,
+    args: &[OsString],
+) -> Result<(), clap::Error> {
+    let check = std::iter::once(OsString::from("bft"))
+        .chain(args.iter().cloned())
+        .chain(std::iter::once(OsString::from("config.bf")));
+    // Only the option being checked, not those in other environment variables
+    let mut checker = command.clone();
+    for id in command.get_arguments().map(|arg| arg.get_id().clone()) {
+        checker = checker.mut_arg(id, |arg| arg.env(None));
+    }
+    match checker.try_get_matches_from(check) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                clap::error::ErrorKind::InvalidValue | clap::error::ErrorKind::ValueValidation
+            ) =>
+        {
+            // Just the reason, without clap's usage that follows it
+            let reason = e.to_string();
+            let reason = reason
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim_start_matches("error: ");
+            Err(command
+                .clone()
+                .error(e.kind(), format!("{}: {}", source, reason)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse the text of a config file into its keys and values, in the order they are in the
+/// file. An error in the TOML gives the line it is on, and a value that isn't a string, an
+/// integer or a boolean gives the key it is for.
+///
+/// Example usage:
+/// ```
+///     let entries = cli::config::parse("cells = 65536 # more than usual\nextensible = true\n").unwrap();
+///     assert_eq!(
+///         entries,
+///         vec![
+///             ("cells".to_string(), cli::config::ConfigValue::Integer(65536)),
+///             ("extensible".to_string(), cli::config::ConfigValue::Boolean(true)),
+///         ]
+///     );
+/// ```
+pub fn parse(text: &str) -> Result<Vec<(String, ConfigValue)>, String> {
+    let table = toml::from_str::<toml::Table>(text).map_err(|e| {
+        // toml's message can be over several lines, or empty when a value is missing
+        let message = match e.message() {
+            "" => "expected a value".to_string(),
+            message => message.replace('\n', ", "),
+        };
+        match e.span() {
+            Some(span) => format!(
+                "line {}: {}",
+                text[..span.start].matches('\n').count() + 1,
+                message
+            ),
+            None => message,
+        }
+    })?;
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            if value.is_table() {
+                return Err(format!(
+                    "[{}]: tables aren't supported, options go at the top level",
+                    key
+                ));
+            }
+            // A date or time would be taken to be a string
+            match ConfigValue::deserialize(value.clone()) {
+                Ok(config_value) if !value.is_datetime() => Ok((key, config_value)),
+                _ => Err(format!(
+                    "{}: {} values aren't supported, use a string, whole number, true or false",
+                    key,
+                    value.type_str()
+                )),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test the values and layout that are understood
+    #[test]
+    fn parse_values() {
+        let text = r#"
+# A comment
+cells = 65_536
+tape-mode = "grow"   # Trailing comment
+eval = '+[.# not a comment]'
+escaped = "a \"quoted\" \\ value # not a comment"
+unbuffered = false
+offset=-3
+"#;
+        assert_eq!(
+            parse(text).unwrap(),
+            vec![
+                ("cells".to_string(), ConfigValue::Integer(65536)),
+                (
+                    "tape-mode".to_string(),
+                    ConfigValue::String("grow".to_string())
+                ),
+                (
+                    "eval".to_string(),
+                    ConfigValue::String("+[.# not a comment]".to_string())
+                ),
+                (
+                    "escaped".to_string(),
+                    ConfigValue::String("a \"quoted\" \\ value # not a comment".to_string())
+                ),
+                ("unbuffered".to_string(), ConfigValue::Boolean(false)),
+                ("offset".to_string(), ConfigValue::Integer(-3)),
+            ]
+        );
+        assert_eq!(parse("").unwrap(), vec![]);
+    }
+
+    /// Test that what isn't understood is an error on the line it is on, or for the key it is
+    /// for
+    #[test]
+    fn parse_errors() {
+        for (text, error) in [
+            ("[options]\ncells = 1", "[options]: tables aren't supported"),
+            ("a.b = 1", "[a]: tables aren't supported"),
+            ("\ncells", "line 2: expected `.`, `=`"),
+            ("my cells = 1", "line 1: expected `.`, `=`"),
+            ("cells = 1\ncells = 2", "line 2: duplicate key `cells`"),
+            ("eval = \"+.", "line 1: invalid basic string"),
+            ("eval = '+.", "line 1: invalid literal string"),
+            (
+                "eval = \"\\q\"",
+                "line 1: invalid escape sequence, expected",
+            ),
+            ("cells = lots", "line 1: invalid string"),
+            ("cells = 1.5", "cells: float values aren't supported"),
+            ("cells = [1]", "cells: array values"),
+            ("cells = 1979-05-27", "cells: datetime values"),
+            ("cells = 99999999999999999999", "line 1: number too large"),
+            ("cells = 1 2", "line 1: expected newline"),
+            ("cells =", "line 1: expected a value"),
+        ] {
+            let message = parse(text).unwrap_err();
+            assert!(message.starts_with(error), "{}: {}", text, message);
+        }
+    }
+}
@@ -1,5 +1,11 @@
-use clap::{arg, Command};
+use clap::builder::PossibleValue;
+use clap::{arg, Command, ValueEnum};
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::time::Duration;
+
+pub mod completions;
+pub mod config;
 
 /// Allocation strategy for the tape that consists of the BF program's data.
 ///
@@ -8,13 +14,89 @@ use std::path::PathBuf;
 ///
 /// * TapeCanGrow allows for allocation of more memory when required.
 /// * TapeIsFixed doesn't allow the amount of memory used to store the tape to be reallocated.
+/// * TapeCanGrowBothWays also allocates more memory to the left of the first cell.
+/// * TapeCanGrowWithLimit(n) allows more memory to be allocated until the tape has n cells.
+/// * TapeWraps keeps the tape fixed but moving off one end comes back round at the other.
+///
+/// On the command line the strategy is given by --tape-mode, as fixed, grow, grow-both-ways or
+/// wrap. TapeCanGrowWithLimit is grow with --max-tape, so it has no name of its own.
 ///
+/// Example usage:
+/// ```
+///     let strategy: cli::AllocStrategy = "wrap".parse().unwrap();
+///     assert_eq!(strategy, cli::AllocStrategy::TapeWraps);
+///     assert!("sideways".parse::<cli::AllocStrategy>().is_err());
+/// ```
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum AllocStrategy {
     /// Allows more memory to be allocated when required.
     TapeCanGrow,
     /// The amount of memory used to store the tape cannot be changed.
     TapeIsFixed,
+    /// Allows more memory to be allocated when required, on the left of the first cell as well
+    /// as the right of the last, so that the tape has no start or end.
+    TapeCanGrowBothWays,
+    /// Allows more memory to be allocated when required, until the tape has this many cells.
+    /// Going beyond the last of them is an error rather than growing the tape further, so that
+    /// a runaway program doesn't use all the memory.
+    TapeCanGrowWithLimit(usize),
+    /// The tape is fixed in size but moving the data pointer off one end of it moves it to the
+    /// other end, so that the cells form a ring.
+    TapeWraps,
+}
+
+impl ValueEnum for AllocStrategy {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            AllocStrategy::TapeIsFixed,
+            AllocStrategy::TapeCanGrow,
+            AllocStrategy::TapeCanGrowBothWays,
+            AllocStrategy::TapeWraps,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            AllocStrategy::TapeIsFixed => Some(PossibleValue::new("fixed").help(
+                "The tape has the cells given by --cells, going past either end is an error",
+            )),
+            AllocStrategy::TapeCanGrow => Some(
+                PossibleValue::new("grow")
+                    .help("The tape grows past the last cell as needed, up to --max-tape if given"),
+            ),
+            AllocStrategy::TapeCanGrowBothWays => Some(
+                PossibleValue::new("grow-both-ways")
+                    .help("The tape grows before the first cell as well as past the last"),
+            ),
+            AllocStrategy::TapeWraps => Some(
+                PossibleValue::new("wrap")
+                    .help("Going past either end of the tape comes back round at the other end"),
+            ),
+            AllocStrategy::TapeCanGrowWithLimit(_) => None,
+        }
+    }
+}
+
+impl std::str::FromStr for AllocStrategy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(text, false)
+    }
+}
+
+impl std::fmt::Display for AllocStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AllocStrategy::TapeCanGrow => write!(f, "can grow"),
+            AllocStrategy::TapeIsFixed => write!(f, "fixed"),
+            AllocStrategy::TapeCanGrowBothWays => write!(f, "can grow both ways"),
+            AllocStrategy::TapeCanGrowWithLimit(limit) => {
+                write!(f, "can grow to {} cells", limit)
+            }
+            AllocStrategy::TapeWraps => write!(f, "wraps round"),
+        }
+    }
 }
 
 /// Output format for data cell values.
@@ -23,13 +105,153 @@ pub enum AllocStrategy {
 ///
 /// * AsciiOutput
 /// * BinaryOutput
+/// * HexOutput
+/// * Utf8Output
+///
+/// On the command line the format is given by --output-format, as ascii, numbers, hex or utf8,
+/// which are also how the formats are displayed.
 ///
+/// Example usage:
+/// ```
+///     let format: cli::OutputFormat = "hex".parse().unwrap();
+///     assert_eq!(format, cli::OutputFormat::HexOutput);
+///     assert_eq!(cli::OutputFormat::BinaryOutput.to_string(), "numbers");
+/// ```
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum OutputFormat {
     /// Values are converted into an ASCII character when a data cell's value is output
     AsciiOutput,
     /// Values are output as is with commas separating values
     BinaryOutput,
+    /// Values are output as lowercase hex with commas separating values. All of the cell is
+    /// output, using two digits for each byte in the cell (e.g. `0a` for u8, `000a` for u16)
+    HexOutput,
+    /// Values are treated as Unicode code points and output as UTF-8. Invalid code points are
+    /// output as the replacement character U+FFFD
+    Utf8Output,
+}
+
+impl ValueEnum for OutputFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            OutputFormat::AsciiOutput,
+            OutputFormat::BinaryOutput,
+            OutputFormat::HexOutput,
+            OutputFormat::Utf8Output,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            OutputFormat::AsciiOutput => {
+                PossibleValue::new("ascii").help("Each value is output as an ASCII character")
+            }
+            OutputFormat::BinaryOutput => PossibleValue::new("numbers")
+                .help("Values are output as decimal numbers separated by commas"),
+            OutputFormat::HexOutput => PossibleValue::new("hex")
+                .help("Values are output as hex numbers separated by commas"),
+            OutputFormat::Utf8Output => {
+                PossibleValue::new("utf8").help("Values are Unicode code points output as UTF-8")
+            }
+        })
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(text, false)
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Every format has a name on the command line
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+/// Size of the cells of the tape.
+///
+/// * Bits8
+/// * Bits16
+/// * Bits32
+///
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum CellSize {
+    /// Cells are 8 bits, the usual size, holding 0 to 255
+    #[value(name = "8")]
+    Bits8,
+    /// Cells are 16 bits, holding 0 to 65535
+    #[value(name = "16")]
+    Bits16,
+    /// Cells are 32 bits, holding 0 to 4294967295
+    #[value(name = "32")]
+    Bits32,
+}
+
+/// Input format for data cell values.
+///
+/// * RawInput
+/// * DecimalInput
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputFormat {
+    /// Each byte of input is stored in a data cell as is
+    RawInput,
+    /// Input is decimal numbers separated by commas or whitespace, each stored in a data cell
+    DecimalInput,
+}
+
+/// Behaviour when a cell is incremented past its maximum value or decremented past its minimum.
+///
+/// * Wrap
+/// * Saturate
+/// * Error
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverflowBehavior {
+    /// The value wraps around, so incrementing 255 in a u8 cell gives 0
+    Wrap,
+    /// The value stays at the limit, so incrementing 255 in a u8 cell gives 255
+    Saturate,
+    /// The program is stopped with an error
+    Error,
+}
+
+/// What is stored in the current cell when an input command reaches the end of the input.
+///
+/// * Zero
+/// * NegOne
+/// * Unchanged
+///
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum EofBehavior {
+    /// The cell is set to 0
+    Zero,
+    /// The cell is set to -1, which is 255 in a u8 cell
+    #[value(name = "neg1")]
+    NegOne,
+    /// The cell keeps whatever value it had before the input command
+    Unchanged,
+}
+
+/// Whether a newline is output when a program finishes, so that the shell prompt doesn't end
+/// up on the same line as the program's output.
+///
+/// * Always
+/// * Never
+/// * IfMissing
+///
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum TrailingNewline {
+    /// A newline is always output
+    Always,
+    /// A newline is never output
+    Never,
+    /// A newline is output if the program output something that didn't end with a newline
+    IfMissing,
 }
 
 /// Debug levels
@@ -41,134 +263,525 @@ pub enum OutputFormat {
 /// * Information
 /// * Verbose
 /// * Detailed
+/// * Trace
 ///
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+/// On the command line the level is given by --debug-level, as none, info, verbose, detailed
+/// or trace, which are also how the levels are displayed.
+///
+/// Example usage:
+/// ```
+///     let level: cli::DebugLevelType = "verbose".parse().unwrap();
+///     assert_eq!(level, cli::DebugLevelType::Verbose);
+///     assert_eq!(cli::DebugLevelType::Information.to_string(), "info");
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, ValueEnum)]
 pub enum DebugLevelType {
     /// No debug output
     None,
     /// Only output informational debug stuff
+    #[value(name = "info")]
     Information,
     /// More information and debug data
     Verbose,
     /// Very detailed and very verbose amount of debug information
     Detailed,
+    /// Everything in Detailed as well as the tape after every instruction
+    Trace,
 }
 
 impl std::convert::From<u8> for DebugLevelType {
-    /// Convert a u8 (typically from a CLI argument) into the appropriate
-    /// debug enum.
+    /// Convert a u8 (typically the count of -d arguments) into the appropriate
+    /// debug enum. Counts above 3 are Detailed, the Trace level has to be asked
+    /// for by name.
     fn from(orig: u8) -> Self {
         match orig {
             0 => DebugLevelType::None,
             1 => DebugLevelType::Information,
             2 => DebugLevelType::Verbose,
-            3 => DebugLevelType::Detailed,
-            _ => DebugLevelType::None,
+            _ => DebugLevelType::Detailed,
         }
     }
 }
 
-pub struct Args {
-    /// Name of the program
-    program: PathBuf,
+impl std::str::FromStr for DebugLevelType {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(text, false)
+    }
+}
+
+impl std::fmt::Display for DebugLevelType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Every level has a name on the command line
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+/// Name given to a program passed on the command line with --eval, which is shown in place
+/// of a filename
+pub const INLINE_PROGRAM_NAME: &str = "<eval>";
+
+/// Name given to a program read from stdin, which is shown in place of a filename
+pub const STDIN_PROGRAM_NAME: &str = "<stdin>";
+
+/// Where the BF program to run comes from.
+///
+/// * File(path) is a file of BF source code.
+/// * Inline(code) is BF source code given on the command line.
+/// * Stdin is BF source code read from stdin, which is asked for with a program of `-`.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgramSource {
+    /// The program is read from a file
+    File(PathBuf),
+    /// The program was given on the command line
+    Inline(String),
+    /// The program is read from stdin
+    Stdin,
+}
+
+impl std::fmt::Display for ProgramSource {
+    /// The path of the file, or the name used in place of one
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProgramSource::File(path) => write!(f, "{}", path.display()),
+            ProgramSource::Inline(_) => write!(f, "{}", INLINE_PROGRAM_NAME),
+            ProgramSource::Stdin => write!(f, "{}", STDIN_PROGRAM_NAME),
+        }
+    }
+}
+
+/// Options for bft run, which runs a program. This is what bft does when it isn't given a
+/// subcommand.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunArgs {
+    /// Where the program comes from
+    source: ProgramSource,
 
     /// Number of cells, must be non-zero. The default is 30,000 if not specified
     cells: usize,
 
+    /// Size of the cells
+    cell_size: CellSize,
+
     /// Output format
     output_format: OutputFormat,
 
-    /// Enable tape to auto-extend from the initial size
-    extensible: AllocStrategy,
+    /// Input format
+    input_format: InputFormat,
+
+    /// What an input command does at the end of the input
+    eof_behavior: EofBehavior,
+
+    /// What happens when the data pointer goes past the ends of the tape
+    tape_mode: AllocStrategy,
+
+    /// Most instructions the program can execute, None for no limit
+    max_steps: Option<u64>,
+
+    /// Longest the program can run for, None for no limit
+    timeout: Option<Duration>,
+
+    /// Amount of debug output
+    debug: DebugLevelType,
+
+    /// Report which instructions were executed
+    coverage: bool,
+
+    /// Report how often each command was executed and the loops with the most iterations
+    profile: bool,
+
+    /// Flush the output after every output command
+    unbuffered: bool,
+
+    /// File of bytes to initialise the start of the tape with
+    tape_init: Option<PathBuf>,
+
+    /// Run the program under the interactive debugger
+    debug_interactive: bool,
+
+    /// File the input read by the program is saved to
+    record_input: Option<PathBuf>,
+
+    /// File of input that is given to the program before any is read from stdin
+    replay_input: Option<PathBuf>,
+
+    /// File the program reads its input from instead of stdin
+    input: Option<PathBuf>,
+
+    /// Bytes given on the command line that the program reads as its input instead of stdin
+    input_text: Option<Vec<u8>>,
+
+    /// File the program's output is written to instead of stdout
+    output: Option<PathBuf>,
+
+    /// Wait for the output file to reach the disk before exiting
+    sync_output: bool,
+
+    /// Show the program running in a live view on the terminal
+    visualize: bool,
+
+    /// Number of cells of the tape to dump once the program has finished
+    dump_tape: Option<usize>,
+
+    /// File the tape is dumped to instead of stderr
+    dump_file: Option<PathBuf>,
+
+    /// File a JSON line for each executed instruction is written to, - for stderr
+    trace_file: Option<PathBuf>,
 
-    /// Debug
-    debug: u8,
+    /// Flag indicating that only the program's output is written to stdout, without any status
+    quiet: bool,
+
+    /// Whether a newline is output when the program finishes, None to decide from where the
+    /// output goes
+    trailing_newline: Option<TrailingNewline>,
+
+    /// Config file the default options were read from, if there was one
+    config_file: Option<PathBuf>,
+
+    /// Warnings about the config file, such as keys that aren't options
+    config_warnings: Vec<String>,
 }
 
-impl Default for Args {
-    /// Default instance of Args as recommended by Clippy
-    fn default() -> Self {
-        Self::new()
+impl RunArgs {
+    /// Parse the options of bft run from a list of arguments, the first of which is the name of
+    /// the program, with default options from the config file, see the config module. Unless
+    /// --config gives one, the config file is looked for in the places given by
+    /// config::default_paths. Options that aren't on the command line can also come from
+    /// environment variables, such as BFT_CELLS, which take precedence over the config file.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let args = cli::RunArgs::try_new(["bft", "-e", "hello-world.bf"]).unwrap();
+    ///     assert_eq!(args.tape_mode(), cli::AllocStrategy::TapeCanGrow);
+    ///     assert!(cli::RunArgs::try_new(["bft", "--cells", "0", "hello-world.bf"]).is_err());
+    /// ```
+    pub fn try_new(
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+    ) -> Result<Self, clap::Error> {
+        Self::try_new_with_config_paths(args, &config::default_paths())
     }
-}
 
-impl Args {
-    /// Create a new instance of the arguments to the program
-    pub fn new() -> Self {
-        let matches = Command::new("bft")
-            .version("1.0")
-            .author("J Axtell <jonaxtell@codethink.co.uk>")
-            .about("Runs a BF program")
-            .arg(arg!(<program> "Name of BF program").required(true))
-            .arg(
-                arg!(cells: -c --cells <count> "Number of initial cells in the tape")
-                    .default_value("30000")
-                    .required(false)
-                    .value_parser(clap::value_parser!(u32).range(1..)),
-            )
-            .arg(
-                arg!(extensible: -e --extensible "Tape can grow beyond the initial size")
-                    .default_value("false")
-                    .required(false),
-            )
-            .arg(
-                arg!(numbers: -n --numbers  "Output cell values as numbers rather than ASCII characters")
-                    .default_value("false")
-                    .required(false),
-            )
-            .arg(
-                arg!(-d --debug "Debug. Multiple occurrences will increase verbosity")
-                    .required(false)
-                    .action(clap::ArgAction::Count),
-            )
-            .get_matches();
+    /// Parse the options of bft run from a list of arguments, as try_new does, but look for the
+    /// config file in the paths given when --config isn't used.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let args = cli::RunArgs::try_new_with_config_paths(["bft", "hello-world.bf"], &[]).unwrap();
+    ///     assert_eq!(args.config_file(), None);
+    /// ```
+    pub fn try_new_with_config_paths(
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+        config_paths: &[PathBuf],
+    ) -> Result<Self, clap::Error> {
+        let mut command = run_command().bin_name("bft run");
+        // The options from the config file go in front of those on the command line, leaving
+        // out the ones that the command line or environment variables give, so that they take
+        // precedence
+        let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        let given = command
+            .clone()
+            .ignore_errors(true)
+            .try_get_matches_from(&args)?;
+        command = Self::apply_env(command, &given)?;
+        let config = config::load(&command, &given, config_paths)?;
+        let args = match &config {
+            Some(config) => args
+                .iter()
+                .take(1)
+                .chain(&config.args)
+                .chain(args.iter().skip(1))
+                .cloned()
+                .collect(),
+            None => args,
+        };
+        let matches = command.try_get_matches_from_mut(args)?;
 
         // Check debug arg first since it's used for outputting other arg statuses
-        let debug = matches.get_count("debug");
-        if <u8 as Into<DebugLevelType>>::into(debug) > DebugLevelType::Information {
-            println!("Debug is {:?}", debug);
+        // A level given by name wins over the count of -d
+        let debug = match matches.get_one::<DebugLevelType>("debug_level") {
+            Some(&level) => level,
+            None => matches.get_count("debug").into(),
+        };
+        if debug > DebugLevelType::Information {
+            eprintln!("Debug is {:?}", debug);
+        }
+
+        let (config_file, config_warnings) = match config {
+            Some(config) => (Some(config.path), config.warnings),
+            None => (None, Vec::new()),
+        };
+        if debug > DebugLevelType::Information {
+            eprintln!("Config file is {:?}", config_file);
         }
 
-        let program_name = matches.get_one::<String>("program").unwrap();
-        if <u8 as Into<DebugLevelType>>::into(debug) > DebugLevelType::Information {
-            println!("program is {:?}", program_name);
+        let source = program_source(&matches);
+        if debug > DebugLevelType::Information {
+            eprintln!("program is {:?}", source);
         }
 
-        let cells = matches.get_one::<u32>("cells").unwrap();
-        if <u8 as Into<DebugLevelType>>::into(debug) > DebugLevelType::Information {
-            println!("Cells is {:?}", cells);
+        let cells = *matches.get_one::<usize>("cells").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Cells is {:?}", cells);
         }
 
-        let extensible = if *matches.get_one::<bool>("extensible").unwrap() {
+        let cell_size = *matches.get_one::<CellSize>("cell_size").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Cell size is {:?}", cell_size);
+        }
+
+        // The old flags are the same as their tape modes, which they conflict with
+        let tape_mode = if *matches.get_one::<bool>("grow_both_ways").unwrap() {
+            AllocStrategy::TapeCanGrowBothWays
+        } else if *matches.get_one::<bool>("extensible").unwrap() {
             AllocStrategy::TapeCanGrow
         } else {
-            AllocStrategy::TapeIsFixed
+            *matches.get_one::<AllocStrategy>("tape_mode").unwrap()
+        };
+        // The ceiling is only for a tape that grows past its last cell
+        let tape_mode = match (tape_mode, matches.get_one::<usize>("max_tape")) {
+            (AllocStrategy::TapeCanGrow, Some(&limit)) => {
+                AllocStrategy::TapeCanGrowWithLimit(limit)
+            }
+            (AllocStrategy::TapeIsFixed, Some(_)) => {
+                return Err(command.error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "--max-tape needs a tape that can grow, use --tape-mode grow (or --extensible)",
+                ));
+            }
+            (tape_mode, Some(_)) => {
+                return Err(command.error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    format!(
+                        "--max-tape can only be used with --tape-mode grow, not {}",
+                        tape_mode.to_possible_value().unwrap().get_name()
+                    ),
+                ));
+            }
+            (tape_mode, None) => tape_mode,
         };
-        if <u8 as Into<DebugLevelType>>::into(debug) > DebugLevelType::Information {
-            println!("Extensible is {:?}", extensible);
+        if debug > DebugLevelType::Information {
+            eprintln!("Tape mode is {:?}", tape_mode);
         }
 
+        // As are the old output flags and their formats
         let output_format = if *matches.get_one::<bool>("numbers").unwrap() {
             OutputFormat::BinaryOutput
+        } else if *matches.get_one::<bool>("hex").unwrap() {
+            OutputFormat::HexOutput
         } else {
-            OutputFormat::AsciiOutput
+            *matches.get_one::<OutputFormat>("output_format").unwrap()
         };
-        if <u8 as Into<DebugLevelType>>::into(debug) > DebugLevelType::Information {
-            println!("Output format is {:?}", output_format);
+        if debug > DebugLevelType::Information {
+            eprintln!("Output format is {:?}", output_format);
         }
 
-        Args {
-            program: program_name.into(),
-            cells: *cells as usize,
-            extensible,
+        let input_format = if *matches.get_one::<bool>("decimal").unwrap() {
+            InputFormat::DecimalInput
+        } else {
+            InputFormat::RawInput
+        };
+        if debug > DebugLevelType::Information {
+            eprintln!("Input format is {:?}", input_format);
+        }
+
+        let eof_behavior = *matches.get_one::<EofBehavior>("eof_behavior").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("EOF behavior is {:?}", eof_behavior);
+        }
+
+        let max_steps =
+            Some(*matches.get_one::<u64>("max_steps").unwrap()).filter(|&steps| steps > 0);
+        if debug > DebugLevelType::Information {
+            eprintln!("Max steps is {:?}", max_steps);
+        }
+
+        let timeout = matches.get_one::<Duration>("timeout").copied();
+        if debug > DebugLevelType::Information {
+            eprintln!("Timeout is {:?}", timeout);
+        }
+
+        let coverage = *matches.get_one::<bool>("coverage").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Coverage is {:?}", coverage);
+        }
+
+        let profile = *matches.get_one::<bool>("profile").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Profile is {:?}", profile);
+        }
+
+        let unbuffered = *matches.get_one::<bool>("unbuffered").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Unbuffered is {:?}", unbuffered);
+        }
+
+        let tape_init = matches.get_one::<PathBuf>("tape_init").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Tape init is {:?}", tape_init);
+        }
+
+        let debug_interactive = *matches.get_one::<bool>("debug_interactive").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Debug interactive is {:?}", debug_interactive);
+        }
+
+        let record_input = matches.get_one::<PathBuf>("record_input").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Record input is {:?}", record_input);
+        }
+
+        let replay_input = matches.get_one::<PathBuf>("replay_input").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Replay input is {:?}", replay_input);
+        }
+
+        let input = matches.get_one::<PathBuf>("input").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Input file is {:?}", input);
+        }
+
+        let input_text = matches.get_one::<Vec<u8>>("input_text").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Input is {:?}", input_text);
+        }
+
+        let output = matches.get_one::<PathBuf>("output").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Output file is {:?}", output);
+        }
+
+        let sync_output = *matches.get_one::<bool>("sync_output").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Sync output is {:?}", sync_output);
+        }
+
+        // Stdin can't be both where the program comes from and its input
+        if source == ProgramSource::Stdin && input.is_none() && input_text.is_none() {
+            return Err(command.error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "the program is read from stdin so its input must be given with --input-file or --input",
+            ));
+        }
+
+        let visualize = *matches.get_one::<bool>("visualize").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Visualize is {:?}", visualize);
+        }
+
+        let dump_tape = matches.get_one::<usize>("dump_tape").copied();
+        if debug > DebugLevelType::Information {
+            eprintln!("Dump tape is {:?}", dump_tape);
+        }
+
+        let dump_file = matches.get_one::<PathBuf>("dump_file").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Dump file is {:?}", dump_file);
+        }
+
+        let trace_file = matches.get_one::<PathBuf>("trace_file").cloned();
+        if debug > DebugLevelType::Information {
+            eprintln!("Trace file is {:?}", trace_file);
+        }
+
+        let quiet = *matches.get_one::<bool>("quiet").unwrap();
+        if debug > DebugLevelType::Information {
+            eprintln!("Quiet is {:?}", quiet);
+        }
+
+        let trailing_newline = matches
+            .get_one::<TrailingNewline>("trailing_newline")
+            .copied();
+        if debug > DebugLevelType::Information {
+            eprintln!("Trailing newline is {:?}", trailing_newline);
+        }
+
+        Ok(RunArgs {
+            source,
+            cells,
+            cell_size,
+            tape_mode,
             output_format,
+            input_format,
+            eof_behavior,
+            max_steps,
+            timeout,
             debug,
+            coverage,
+            profile,
+            unbuffered,
+            tape_init,
+            debug_interactive,
+            record_input,
+            replay_input,
+            input,
+            input_text,
+            output,
+            sync_output,
+            visualize,
+            dump_tape,
+            dump_file,
+            trace_file,
+            quiet,
+            trailing_newline,
+            config_file,
+            config_warnings,
+        })
+    }
+
+    /// Start building the options of bft run in code rather than parsing them, for when bft
+    /// is embedded in another program. The options not given have the same defaults as on the
+    /// command line, and neither a config file nor environment variables are read.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let args = cli::RunArgs::builder(cli::ProgramSource::File("hello-world.bf".into()))
+    ///         .cells(1000)
+    ///         .tape_mode(cli::AllocStrategy::TapeCanGrow)
+    ///         .build();
+    ///     assert_eq!(args, cli::RunArgs::try_new_with_config_paths(
+    ///         ["bft", "--cells", "1000", "--tape-mode", "grow", "hello-world.bf"], &[]).unwrap());
+    /// ```
+    pub fn builder(source: ProgramSource) -> RunArgsBuilder {
+        RunArgsBuilder::new(source)
+    }
+
+    /// Check the environment variables of the options that the command line doesn't give,
+    /// so that a bad value names its variable, and stop the command using the variables of
+    /// options that are overridden by options on the command line they can't be used with.
+    fn apply_env(mut command: Command, given: &clap::ArgMatches) -> Result<Command, clap::Error> {
+        let overridden: Vec<clap::Id> = command
+            .get_arguments()
+            .filter(|arg| arg.get_env().is_some())
+            .filter(|arg| config::given_on_command_line(&command, given, arg))
+            .map(|arg| arg.get_id().clone())
+            .collect();
+        for id in overridden {
+            command = command.mut_arg(id, |arg| arg.env(None));
+        }
+        for arg in command.get_arguments() {
+            let (Some(variable), Some(long)) = (arg.get_env(), arg.get_long()) else {
+                continue;
+            };
+            // Joined to the option so that a value such as -1 isn't taken to be an option
+            if let Some(value) = std::env::var_os(variable) {
+                let mut option = OsString::from(format!("--{}=", long));
+                option.push(value);
+                config::check_value(
+                    &command,
+                    &format!("in environment variable {}", variable.to_string_lossy()),
+                    &[option],
+                )?;
+            }
         }
+        Ok(command)
     }
 
-    /// Name of BF source file
-    pub fn program(&self) -> &PathBuf {
-        &self.program
+    /// Where the BF program comes from, a file or the command line
+    pub fn source(&self) -> &ProgramSource {
+        &self.source
     }
 
     /// The number of cells in the BF program's tape. The number of cells cannot be zero.
@@ -176,11 +789,28 @@ impl Args {
         self.cells
     }
 
-    /// Flag indicating if the BF program's tape can grow or is fixed
+    /// Size of the cells of the BF program's tape
+    /// * Bits8
+    /// * Bits16
+    /// * Bits32
+    pub fn cell_size(&self) -> CellSize {
+        self.cell_size
+    }
+
+    /// What happens when the data pointer goes past the ends of the BF program's tape
     /// * TapeCanGrow allows for allocation of more memory when required.
     /// * TapeIsFixed doesn't allow the amount of memory used to store the tape to be reallocated.
+    /// * TapeCanGrowBothWays also allocates more memory to the left of the first cell.
+    /// * TapeCanGrowWithLimit(n) allows more memory to be allocated until the tape has n cells.
+    /// * TapeWraps moves the data pointer round to the other end of the tape.
+    pub fn tape_mode(&self) -> AllocStrategy {
+        self.tape_mode
+    }
+
+    /// The tape mode, from when the only choice was whether the tape could grow
+    #[deprecated(note = "use tape_mode, which this is the same as")]
     pub fn extensible(&self) -> AllocStrategy {
-        self.extensible
+        self.tape_mode
     }
 
     /// Flag indicating how values are output
@@ -190,12 +820,2156 @@ impl Args {
         self.output_format
     }
 
+    /// Flag indicating how values are input
+    /// * RawInput causes each byte of input to be stored as is
+    /// * DecimalInput causes decimal numbers to be read and stored
+    pub fn input_format(&self) -> InputFormat {
+        self.input_format
+    }
+
+    /// What an input command stores in the cell at the end of the input
+    /// * Zero
+    /// * NegOne
+    /// * Unchanged
+    pub fn eof_behavior(&self) -> EofBehavior {
+        self.eof_behavior
+    }
+
+    /// Most instructions the program can execute before it is stopped, None if there is no limit
+    pub fn max_steps(&self) -> Option<u64> {
+        self.max_steps
+    }
+
+    /// Longest the program can run for before it is stopped, None if there is no limit
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
     /// Flag indicating the amount of debug to output
     /// * None
     /// * Information
     /// * Verbose
     /// * Detailed
+    /// * Trace
     pub fn debug(&self) -> DebugLevelType {
-        self.debug.into()
+        self.debug
+    }
+
+    /// Flag indicating if a report of which instructions were executed should be output
+    pub fn coverage(&self) -> bool {
+        self.coverage
+    }
+
+    /// Flag indicating if a profile of the commands and loops executed should be output
+    pub fn profile(&self) -> bool {
+        self.profile
+    }
+
+    /// Flag indicating if the output should be flushed after every output command
+    pub fn unbuffered(&self) -> bool {
+        self.unbuffered
+    }
+
+    /// File of bytes to initialise the start of the tape with, if any
+    pub fn tape_init(&self) -> Option<&PathBuf> {
+        self.tape_init.as_ref()
+    }
+
+    /// Flag indicating if the program should be run under the interactive debugger
+    pub fn debug_interactive(&self) -> bool {
+        self.debug_interactive
+    }
+
+    /// File to save the input read by the program to, if any
+    pub fn record_input(&self) -> Option<&PathBuf> {
+        self.record_input.as_ref()
+    }
+
+    /// File of input to replay before reading from stdin, if any
+    pub fn replay_input(&self) -> Option<&PathBuf> {
+        self.replay_input.as_ref()
+    }
+
+    /// File the program reads its input from instead of stdin, None to read from stdin
+    pub fn input(&self) -> Option<&PathBuf> {
+        self.input.as_ref()
+    }
+
+    /// Bytes the program reads as its input instead of stdin, with the escapes given to
+    /// --input decoded, None to read from stdin
+    pub fn input_text(&self) -> Option<&[u8]> {
+        self.input_text.as_deref()
+    }
+
+    /// File the program's output is written to instead of stdout, None to write to stdout
+    pub fn output(&self) -> Option<&PathBuf> {
+        self.output.as_ref()
+    }
+
+    /// Flag indicating if the output file should be synced to the disk before exiting
+    pub fn sync_output(&self) -> bool {
+        self.sync_output
+    }
+
+    /// Flag indicating if the program should be shown running in a live view
+    pub fn visualize(&self) -> bool {
+        self.visualize
+    }
+
+    /// Number of cells at the start of the tape to dump once the program has finished, if any
+    pub fn dump_tape(&self) -> Option<usize> {
+        self.dump_tape
+    }
+
+    /// File to write the dump of the tape to rather than stderr
+    pub fn dump_file(&self) -> Option<&PathBuf> {
+        self.dump_file.as_ref()
+    }
+
+    /// File to write the trace of the executed instructions to, - meaning stderr so that the
+    /// trace doesn't get mixed up with the program's output
+    pub fn trace_file(&self) -> Option<&PathBuf> {
+        self.trace_file.as_ref()
+    }
+
+    /// Flag indicating that stdout only has the program's output on it, with errors on stderr
+    /// and no status messages or warnings
+    pub fn quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Whether a newline is output when the program finishes, None if it wasn't given so that
+    /// it is decided by where the output goes
+    pub fn trailing_newline(&self) -> Option<TrailingNewline> {
+        self.trailing_newline
+    }
+
+    /// Config file the default options were read from, None if there wasn't one
+    pub fn config_file(&self) -> Option<&PathBuf> {
+        self.config_file.as_ref()
+    }
+
+    /// Warnings about the config file that didn't stop it being used, such as keys that
+    /// aren't options
+    pub fn config_warnings(&self) -> &[String] {
+        &self.config_warnings
+    }
+}
+
+/// Builder for the options of bft run, see RunArgs::builder. Each setter is the same as the
+/// option of the same name on the command line, and the defaults are those of the command line.
+#[derive(Clone, Debug)]
+pub struct RunArgsBuilder {
+    args: RunArgs,
+}
+
+impl RunArgsBuilder {
+    /// Create a builder for running the program given, with the default options
+    pub fn new(source: ProgramSource) -> Self {
+        Self {
+            args: RunArgs {
+                source,
+                cells: 30000,
+                cell_size: CellSize::Bits8,
+                output_format: OutputFormat::AsciiOutput,
+                input_format: InputFormat::RawInput,
+                eof_behavior: EofBehavior::NegOne,
+                tape_mode: AllocStrategy::TapeIsFixed,
+                max_steps: None,
+                timeout: None,
+                debug: DebugLevelType::None,
+                coverage: false,
+                profile: false,
+                unbuffered: false,
+                tape_init: None,
+                debug_interactive: false,
+                record_input: None,
+                replay_input: None,
+                input: None,
+                input_text: None,
+                output: None,
+                sync_output: false,
+                visualize: false,
+                dump_tape: None,
+                dump_file: None,
+                trace_file: None,
+                quiet: false,
+                trailing_newline: None,
+                config_file: None,
+                config_warnings: Vec::new(),
+            },
+        }
+    }
+
+    /// Where the program comes from
+    pub fn source(mut self, source: ProgramSource) -> Self {
+        self.args.source = source;
+        self
+    }
+
+    /// Number of cells the tape starts with. Zero, which --cells doesn't allow, means the
+    /// default of 30,000.
+    pub fn cells(mut self, cells: usize) -> Self {
+        self.args.cells = if cells == 0 { 30000 } else { cells };
+        self
+    }
+
+    /// Size of the cells
+    pub fn cell_size(mut self, cell_size: CellSize) -> Self {
+        self.args.cell_size = cell_size;
+        self
+    }
+
+    /// What happens when the data pointer goes past the ends of the tape, which includes the
+    /// ceiling that --max-tape gives
+    pub fn tape_mode(mut self, tape_mode: AllocStrategy) -> Self {
+        self.args.tape_mode = tape_mode;
+        self
+    }
+
+    /// How values are output
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.args.output_format = output_format;
+        self
+    }
+
+    /// How the input is read
+    pub fn input_format(mut self, input_format: InputFormat) -> Self {
+        self.args.input_format = input_format;
+        self
+    }
+
+    /// What an input command does at the end of the input
+    pub fn eof_behavior(mut self, eof_behavior: EofBehavior) -> Self {
+        self.args.eof_behavior = eof_behavior;
+        self
+    }
+
+    /// Most instructions the program can execute, None for no limit
+    pub fn max_steps(mut self, max_steps: Option<u64>) -> Self {
+        self.args.max_steps = max_steps;
+        self
+    }
+
+    /// Longest the program can run for, None for no limit
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.args.timeout = timeout;
+        self
+    }
+
+    /// Amount of debug output
+    pub fn debug(mut self, debug: DebugLevelType) -> Self {
+        self.args.debug = debug;
+        self
+    }
+
+    /// Report which instructions were executed
+    pub fn coverage(mut self, coverage: bool) -> Self {
+        self.args.coverage = coverage;
+        self
+    }
+
+    /// Report how often each command was executed and the loops with the most iterations
+    pub fn profile(mut self, profile: bool) -> Self {
+        self.args.profile = profile;
+        self
+    }
+
+    /// Flush the output after every output command
+    pub fn unbuffered(mut self, unbuffered: bool) -> Self {
+        self.args.unbuffered = unbuffered;
+        self
+    }
+
+    /// File of bytes to initialise the start of the tape with
+    pub fn tape_init(mut self, tape_init: Option<PathBuf>) -> Self {
+        self.args.tape_init = tape_init;
+        self
+    }
+
+    /// Run the program under the interactive debugger
+    pub fn debug_interactive(mut self, debug_interactive: bool) -> Self {
+        self.args.debug_interactive = debug_interactive;
+        self
+    }
+
+    /// File the input read by the program is saved to
+    pub fn record_input(mut self, record_input: Option<PathBuf>) -> Self {
+        self.args.record_input = record_input;
+        self
+    }
+
+    /// File of input that is given to the program before any is read from stdin
+    pub fn replay_input(mut self, replay_input: Option<PathBuf>) -> Self {
+        self.args.replay_input = replay_input;
+        self
+    }
+
+    /// File the program reads its input from instead of stdin
+    pub fn input(mut self, input: Option<PathBuf>) -> Self {
+        self.args.input = input;
+        self
+    }
+
+    /// Bytes the program reads as its input instead of stdin, as they are without any escapes
+    pub fn input_text(mut self, input_text: Option<Vec<u8>>) -> Self {
+        self.args.input_text = input_text;
+        self
+    }
+
+    /// File the program's output is written to instead of stdout
+    pub fn output(mut self, output: Option<PathBuf>) -> Self {
+        self.args.output = output;
+        self
+    }
+
+    /// Wait for the output file to reach the disk before exiting
+    pub fn sync_output(mut self, sync_output: bool) -> Self {
+        self.args.sync_output = sync_output;
+        self
+    }
+
+    /// Show the program running in a live view on the terminal
+    pub fn visualize(mut self, visualize: bool) -> Self {
+        self.args.visualize = visualize;
+        self
+    }
+
+    /// Number of cells of the tape to dump once the program has finished
+    pub fn dump_tape(mut self, dump_tape: Option<usize>) -> Self {
+        self.args.dump_tape = dump_tape;
+        self
+    }
+
+    /// File the tape is dumped to instead of stderr
+    pub fn dump_file(mut self, dump_file: Option<PathBuf>) -> Self {
+        self.args.dump_file = dump_file;
+        self
+    }
+
+    /// File a JSON line for each executed instruction is written to, - for stderr
+    pub fn trace_file(mut self, trace_file: Option<PathBuf>) -> Self {
+        self.args.trace_file = trace_file;
+        self
+    }
+
+    /// Only write the program's output to stdout, without any status
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.args.quiet = quiet;
+        self
+    }
+
+    /// Whether a newline is output when the program finishes, None to decide from where the
+    /// output goes
+    pub fn trailing_newline(mut self, trailing_newline: Option<TrailingNewline>) -> Self {
+        self.args.trailing_newline = trailing_newline;
+        self
+    }
+
+    /// The options, which have no config file
+    pub fn build(self) -> RunArgs {
+        self.args
+    }
+}
+
+impl From<RunArgs> for Args {
+    /// bft run with the options given
+    fn from(args: RunArgs) -> Self {
+        Args::Run(Box::new(args))
+    }
+}
+
+/// The options for each of bft's subcommands. bft without a subcommand, such as bft file.bf,
+/// is the same as bft run file.bf as it was before there were subcommands.
+///
+/// * Run runs a program.
+/// * Check parses and validates a program without running it.
+/// * Fmt pretty-prints a program.
+/// * Dump lists a program's instructions with where its jumps go.
+/// * Stats shows statistics about a program.
+/// * Completions writes a shell completion script, see the completions module.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Args {
+    /// bft run, boxed as it has far more options than the others
+    Run(Box<RunArgs>),
+    /// bft check
+    Check(CheckArgs),
+    /// bft fmt
+    Fmt(FmtArgs),
+    /// bft dump
+    Dump(DumpArgs),
+    /// bft stats
+    Stats(StatsArgs),
+    /// bft completions, which is hidden from the help
+    Completions(CompletionsArgs),
+}
+
+impl Default for Args {
+    /// Default instance of Args as recommended by Clippy
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Args {
+    /// Create a new instance of the arguments to the program from the process's command line.
+    /// If the arguments are wrong, or help or the version is asked for, the message is shown
+    /// and the process exits.
+    pub fn new() -> Self {
+        Self::try_new(std::env::args_os()).unwrap_or_else(|error| error.exit())
+    }
+
+    /// Create a new instance of the arguments to the program from a list of arguments, the
+    /// first of which is the name of the program. Unlike new, an error is returned rather than
+    /// the process exiting, including when help or the version is asked for.
+    ///
+    /// When the first argument isn't a subcommand, or -h, -V or their long forms, the arguments
+    /// are those of bft run, which reads default options from the config file and environment
+    /// variables, see RunArgs::try_new.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let args = cli::Args::try_new(["bft", "check", "hello-world.bf"]).unwrap();
+    ///     assert!(matches!(args, cli::Args::Check(_)));
+    ///     let args = cli::Args::try_new(["bft", "-e", "hello-world.bf"]).unwrap();
+    ///     assert!(matches!(args, cli::Args::Run(_)));
+    /// ```
+    pub fn try_new(
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+    ) -> Result<Self, clap::Error> {
+        Self::try_new_with_config_paths(args, &config::default_paths())
+    }
+
+    /// Create a new instance of the arguments to the program from a list of arguments, as
+    /// try_new does, but look for bft run's config file in the paths given when --config isn't
+    /// used.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let args = cli::Args::try_new_with_config_paths(["bft", "run", "hello-world.bf"], &[]).unwrap();
+    ///     assert_eq!(args.source(), Some(&cli::ProgramSource::File("hello-world.bf".into())));
+    /// ```
+    pub fn try_new_with_config_paths(
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+        config_paths: &[PathBuf],
+    ) -> Result<Self, clap::Error> {
+        let mut command = command();
+        let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        // Without a subcommand the arguments are those of bft run
+        let top_level = |first: &OsString| {
+            command.find_subcommand(first).is_some()
+                || ["help", "-h", "--help", "-V", "--version"]
+                    .contains(&first.to_str().unwrap_or(""))
+        };
+        if args.get(1).is_some_and(|first| !top_level(first)) {
+            // bft --check is the same as bft check, wherever the --check is
+            let run = command.find_subcommand("run").unwrap();
+            match check_flag_position(run, &args) {
+                Some(index) => {
+                    args.remove(index);
+                    args.insert(1, OsString::from("check"));
+                }
+                None => args.insert(1, OsString::from("run")),
+            }
+        }
+        if args.get(1).is_some_and(|first| first == "run") {
+            // bft run is parsed on its own so that it can have its config file
+            return Ok(Args::Run(Box::new(RunArgs::try_new_with_config_paths(
+                args.into_iter().skip(1),
+                config_paths,
+            )?)));
+        }
+
+        let matches = command.try_get_matches_from_mut(args)?;
+        Ok(match matches.subcommand() {
+            Some(("check", matches)) => Args::Check(CheckArgs {
+                sources: match matches.get_one::<String>("eval") {
+                    Some(code) => vec![ProgramSource::Inline(code.clone())],
+                    None => matches
+                        .get_many::<String>("program")
+                        .unwrap()
+                        .map(|program| match program.as_str() {
+                            "-" => ProgramSource::Stdin,
+                            path => ProgramSource::File(path.into()),
+                        })
+                        .collect(),
+                },
+            }),
+            Some(("fmt", matches)) => Args::Fmt(FmtArgs {
+                source: program_source(matches),
+                indent: *matches.get_one::<usize>("indent").unwrap(),
+                width: *matches.get_one::<usize>("width").unwrap(),
+            }),
+            Some(("dump", matches)) => Args::Dump(DumpArgs {
+                source: program_source(matches),
+            }),
+            Some(("stats", matches)) => Args::Stats(StatsArgs {
+                source: program_source(matches),
+            }),
+            Some(("completions", matches)) => Args::Completions(CompletionsArgs {
+                shell: *matches.get_one::<completions::Shell>("shell").unwrap(),
+            }),
+            // A subcommand is required and run has already been handled
+            _ => unreachable!("bft parsed without a subcommand other than run"),
+        })
+    }
+
+    /// Start building the options of bft run in code, see RunArgs::builder. The options that
+    /// are built can be turned into Args with into.
+    ///
+    /// Example usage:
+    /// ```
+    ///     let args: cli::Args = cli::Args::builder(cli::ProgramSource::Inline("+.".to_string()))
+    ///         .debug(cli::DebugLevelType::Verbose)
+    ///         .build()
+    ///         .into();
+    ///     assert_eq!(args.source().unwrap().to_string(), cli::INLINE_PROGRAM_NAME);
+    /// ```
+    pub fn builder(source: ProgramSource) -> RunArgsBuilder {
+        RunArgs::builder(source)
+    }
+
+    /// Where the program comes from, whichever the subcommand, None for completions which
+    /// doesn't have a program and for check which can have more than one
+    pub fn source(&self) -> Option<&ProgramSource> {
+        match self {
+            Args::Run(args) => Some(args.source()),
+            Args::Check(_) => None,
+            Args::Fmt(args) => Some(args.source()),
+            Args::Dump(args) => Some(args.source()),
+            Args::Stats(args) => Some(args.source()),
+            Args::Completions(_) => None,
+        }
+    }
+}
+
+/// Options for bft check, which parses and validates programs without running them
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckArgs {
+    /// Where each of the programs comes from
+    sources: Vec<ProgramSource>,
+}
+
+impl CheckArgs {
+    /// Where each of the programs comes from, in the order they were given
+    pub fn sources(&self) -> &[ProgramSource] {
+        &self.sources
+    }
+}
+
+/// Options for bft fmt, which pretty-prints a program to stdout
+#[derive(Clone, Debug, PartialEq)]
+pub struct FmtArgs {
+    /// Where the program comes from
+    source: ProgramSource,
+
+    /// Number of spaces the body of a loop is indented by
+    indent: usize,
+
+    /// Most commands on a line, other than the indent
+    width: usize,
+}
+
+impl FmtArgs {
+    /// Where the program comes from
+    pub fn source(&self) -> &ProgramSource {
+        &self.source
+    }
+
+    /// Number of spaces the body of a loop is indented by
+    pub fn indent(&self) -> usize {
+        self.indent
+    }
+
+    /// Most commands on a line, other than the indent
+    pub fn width(&self) -> usize {
+        self.width
+    }
+}
+
+/// Options for bft dump, which lists a program's instructions with where its jumps go
+#[derive(Clone, Debug, PartialEq)]
+pub struct DumpArgs {
+    /// Where the program comes from
+    source: ProgramSource,
+}
+
+impl DumpArgs {
+    /// Where the program comes from
+    pub fn source(&self) -> &ProgramSource {
+        &self.source
+    }
+}
+
+/// Options for bft stats, which shows statistics about a program
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsArgs {
+    /// Where the program comes from
+    source: ProgramSource,
+}
+
+impl StatsArgs {
+    /// Where the program comes from
+    pub fn source(&self) -> &ProgramSource {
+        &self.source
+    }
+}
+
+/// Options for bft completions, which writes a shell completion script to stdout
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompletionsArgs {
+    /// The shell the script is for
+    shell: completions::Shell,
+}
+
+impl CompletionsArgs {
+    /// The shell the script is for
+    pub fn shell(&self) -> completions::Shell {
+        self.shell
+    }
+}
+
+/// Where --check is given in bft's arguments, as long as it isn't the value of one of bft run's
+/// options, such as --input --check, and doesn't come after --
+fn check_flag_position(run: &Command, args: &[OsString]) -> Option<usize> {
+    let takes_value = |arg: Option<&clap::Arg>| {
+        arg.is_some_and(|arg| {
+            matches!(
+                arg.get_action(),
+                clap::ArgAction::Set | clap::ArgAction::Append
+            )
+        })
+    };
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        let text = arg.to_string_lossy();
+        if text == "--" {
+            return None;
+        }
+        if text == "--check" {
+            return Some(index);
+        }
+        if let Some(long) = text.strip_prefix("--") {
+            // A value can be joined to the option with =, otherwise it's the next argument
+            let option = run.get_arguments().find(|arg| arg.get_long() == Some(long));
+            if takes_value(option) {
+                index += 1;
+            }
+        } else if let Some(shorts) = text.strip_prefix('-') {
+            // The first short option in a group that takes a value takes the rest of the
+            // group, or the next argument if it is the last in the group
+            for (position, short) in shorts.char_indices() {
+                let option = run
+                    .get_arguments()
+                    .find(|arg| arg.get_short() == Some(short));
+                if takes_value(option) {
+                    if position + short.len_utf8() == shorts.len() {
+                        index += 1;
+                    }
+                    break;
+                }
+            }
+        }
+        index += 1;
+    }
+    None
+}
+
+/// The command for bft, with a subcommand for each thing it can do with a program. Args parses
+/// the command line with it and the completion scripts are made from it.
+///
+/// Example usage:
+/// ```
+///     let command = cli::command();
+///     assert!(command.find_subcommand("check").is_some());
+///     command.debug_assert();
+/// ```
+pub fn command() -> Command {
+    Command::new("bft")
+        .version("1.0")
+        .author("J Axtell <jonaxtell@codethink.co.uk>")
+        .about("Runs a BF program, or checks or lists it")
+        .after_help("Without a command bft runs the program, bft hello-world.bf is the same as bft run hello-world.bf, \
+            and bft --check is the same as bft check")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(run_command())
+        .subcommand(
+            Command::new("check")
+                .about("Parses and validates BF programs without running them, reporting every problem")
+                .arg(
+                    arg!([program] ... "Names of BF programs, - to read one from stdin")
+                        .required_unless_present("eval")
+                        .conflicts_with("eval"),
+                )
+                .arg(
+                    arg!(eval: -x --eval <code> "Check the BF program given rather than ones in files")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            program_args(Command::new("fmt").about("Pretty-prints a BF program to stdout"))
+                .arg(
+                    arg!(indent: --indent <spaces> "Number of spaces the body of a loop is indented by")
+                        .default_value("2")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(width: --width <commands> "Most commands on a line, not counting the indent")
+                        .default_value("80")
+                        .required(false)
+                        .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+                ),
+        )
+        .subcommand(program_args(
+            Command::new("dump").about("Lists a BF program's instructions with where its jumps go"),
+        ))
+        .subcommand(program_args(
+            Command::new("stats").about("Shows statistics about a BF program"),
+        ))
+        .subcommand(
+            Command::new("completions")
+                .about("Writes a completion script for a shell to stdout, such as bft completions zsh > _bft")
+                .hide(true)
+                .arg(
+                    arg!(shell: <shell> "The shell to write the script for")
+                        .value_parser(clap::value_parser!(completions::Shell)),
+                ),
+        )
+}
+
+/// Add the arguments that give the program to a subcommand, a file or - for stdin, or the
+/// code itself with --eval
+fn program_args(command: Command) -> Command {
+    command
+        .arg(
+            arg!([program] "Name of BF program, - to read it from stdin")
+                .required_unless_present("eval")
+                .conflicts_with("eval"),
+        )
+        .arg(
+            arg!(eval: -x --eval <code> "Use the BF program given rather than one in a file")
+                .required(false),
+        )
+}
+
+/// Where the program comes from, given the matches of a subcommand that has program_args
+fn program_source(matches: &clap::ArgMatches) -> ProgramSource {
+    match matches.get_one::<String>("eval") {
+        Some(code) => ProgramSource::Inline(code.clone()),
+        None => match matches.get_one::<String>("program").unwrap().as_str() {
+            "-" => ProgramSource::Stdin,
+            path => ProgramSource::File(path.into()),
+        },
+    }
+}
+
+/// The command for bft run, which has all of the options for running a program
+fn run_command() -> Command {
+    program_args(Command::new("run").about("Runs a BF program"))
+    .arg(
+        arg!(cells: -c --cells <count> "Number of cells the tape starts with, such as 30000, 64k or 1M")
+            .env("BFT_CELLS")
+            .long_help("Number of cells the tape starts with, such as 30000, 64k or 1M (k is 1024 cells, m 1024 * 1024 \
+                and g 1024 * 1024 * 1024, in either case). \
+                With --tape-mode grow this is only the initial size, the tape grows past it as needed up to \
+                any ceiling set by --max-tape")
+            .default_value("30000")
+            .required(false)
+            .value_parser(parse_cell_count),
+    )
+    .arg(
+        arg!(cell_size: --"cell-size" <bits> "Size of the tape's cells in bits")
+            .env("BFT_CELL_SIZE")
+            .default_value("8")
+            .required(false)
+            .value_parser(clap::value_parser!(CellSize)),
+    )
+    .arg(
+        arg!(tape_mode: --"tape-mode" <mode> "What happens when the data pointer goes past the ends of the tape")
+            .env("BFT_TAPE_MODE")
+            .default_value("fixed")
+            .required(false)
+            .value_parser(clap::value_parser!(AllocStrategy))
+            .conflicts_with_all(["extensible", "grow_both_ways"]),
+    )
+    .arg(
+        arg!(extensible: -e --extensible "Deprecated, the same as --tape-mode grow")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(grow_both_ways: --"grow-both-ways" "Deprecated, the same as --tape-mode grow-both-ways")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(max_tape: --"max-tape" <cells> "Most cells a tape that grows can grow to, such as 100k or 2M")
+            .env("BFT_MAX_TAPE")
+            .long_help("Most cells a tape that grows can grow to, such as 100k or 2M (k is 1024 cells, m 1024 * 1024 \
+                and g 1024 * 1024 * 1024, in either case). \
+                Needs --tape-mode grow. The tape starts with the number of cells given by --cells and grows \
+                up to this ceiling, moving beyond it stops the program with an error")
+            .required(false)
+            .value_parser(parse_cell_count),
+    )
+    .arg(
+        arg!(output_format: --"output-format" <format> "How cell values are output")
+            .env("BFT_OUTPUT_FORMAT")
+            .default_value("ascii")
+            .required(false)
+            .value_parser(clap::value_parser!(OutputFormat))
+            .conflicts_with_all(["numbers", "hex"]),
+    )
+    .arg(
+        arg!(numbers: -n --numbers  "Deprecated, the same as --output-format numbers")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(hex: --hex "Deprecated, the same as --output-format hex")
+            .default_value("false")
+            .required(false)
+            .conflicts_with("numbers"),
+    )
+    .arg(
+        arg!(decimal: --decimal "Input cell values as decimal numbers separated by commas or whitespace")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(eof_behavior: --"eof-behavior" <mode> "What , stores in the cell at the end of the input")
+            .env("BFT_EOF_BEHAVIOR")
+            .default_value("neg1")
+            .required(false)
+            .value_parser(clap::value_parser!(EofBehavior)),
+    )
+    .arg(
+        arg!(max_steps: --"max-steps" <steps> "Most instructions the program can execute before it is stopped, 0 for no limit")
+            .env("BFT_MAX_STEPS")
+            .default_value("0")
+            .required(false)
+            .value_parser(clap::value_parser!(u64)),
+    )
+    .arg(
+        arg!(timeout: --timeout <duration> "Longest the program can run for before it is stopped, such as 500ms, 5s or 2m")
+            .env("BFT_TIMEOUT")
+            .required(false)
+            .value_parser(parse_duration),
+    )
+    .arg(
+        arg!(-d --debug "Debug. Multiple occurrences will increase verbosity")
+            .required(false)
+            .action(clap::ArgAction::Count),
+    )
+    .arg(
+        arg!(debug_level: --"debug-level" <level> "Amount of debug output, used in place of counting -d")
+            .env("BFT_DEBUG")
+            .required(false)
+            .value_parser(clap::value_parser!(DebugLevelType)),
+    )
+    .arg(
+        arg!(coverage: --coverage "Report which instructions were executed")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(profile: --profile "Report how often each command was executed and the loops with the most iterations to stderr")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(unbuffered: --unbuffered "Flush the output after every output command")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(tape_init: --"tape-init" <file> "File of bytes to initialise the start of the tape with")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+        arg!(debug_interactive: --"debug-interactive" "Run the program under the interactive debugger, which is driven from the terminal")
+            .default_value("false")
+            .required(false),
+    )
+    .arg(
+        arg!(record_input: --"record-input" <file> "Save the input read by the program to a file so that it can be replayed")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+        arg!(replay_input: --"replay-input" <file> "Give the program the input in a file before reading any from stdin")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+        arg!(input: -i --"input-file" <file> "Read the program's input from a file rather than stdin")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+        arg!(input_text: --input <text> "Give the program the text as its input rather than stdin, with escapes such as \\n and \\x41")
+            .long_help("Give the program the text as its input rather than stdin. The escapes \\n, \\r, \\t, \\0, \\\\ and \\x \
+                followed by two hex digits give other bytes, such as \\x41 for A or \\xff for 255. After the text the \
+                program gets the end of the input, as given by --eof-behavior")
+            .required(false)
+            .allow_hyphen_values(true)
+            .conflicts_with("input")
+            .value_parser(parse_input_text),
+    )
+    .arg(
+        arg!(output: -o --"output-file" <file> "Write the program's output to a file rather than stdout")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+        arg!(sync_output: --"sync-output" "Wait for the output file to reach the disk before exiting")
+            .default_value("false")
+            .required(false)
+            .requires("output"),
+    )
+    .arg(
+        arg!(visualize: --visualize "Show the tape and source in a live view on the terminal as the program runs")
+            .default_value("false")
+            .required(false)
+            .conflicts_with("debug_interactive"),
+    )
+    .arg(
+        arg!(dump_tape: --"dump-tape" <count> "Dump the first cells of the tape in hexdump style to stderr once the program has finished")
+            .required(false)
+            .value_parser(clap::value_parser!(usize)),
+    )
+    .arg(
+        arg!(dump_file: --"dump-file" <file> "Write the dump of the tape to a file rather than stderr")
+            .required(false)
+            .requires("dump_tape")
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+        arg!(trace_file: --"trace-file" <file> "Write a JSON line for each instruction executed to a file, use - for stderr")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+        arg!(quiet: -q --quiet "Only write the program's output to stdout, with no status messages or warnings and errors only on stderr")
+            .default_value("false")
+            .required(false)
+            .conflicts_with_all(["debug", "debug_level"]),
+    )
+    .arg(
+        arg!(trailing_newline: --"trailing-newline" <when> "Whether a newline is output after the program's output, by default one is added if missing on a terminal unless --quiet is given")
+            .required(false)
+            .value_parser(clap::value_parser!(TrailingNewline)),
+    )
+    .arg(
+        arg!(config: --config <file> "Read default options from a TOML file rather than ./bft.toml or $XDG_CONFIG_HOME/bft/config.toml")
+            .env("BFT_CONFIG")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf)),
+    )
+}
+
+/// Parse a duration of a whole number of milliseconds, seconds or minutes, such as 500ms, 5s
+/// or 2m
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let error = || {
+        format!(
+            "'{}' isn't a duration, use a number followed by ms, s or m, such as 500ms, 5s or 2m",
+            text
+        )
+    };
+    let split = text.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+    let amount: u64 = text[..split].parse().map_err(|_| error())?;
+    match &text[split..] {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" => Ok(Duration::from_secs(amount)),
+        "m" => amount
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(error),
+        _ => Err(error()),
+    }
+}
+
+/// Parse a non-zero number of cells, such as 30000, 64k or 1M. The number can be followed by
+/// k or K for 1024 cells, m or M for 1024 * 1024 cells, or g or G for 1024 * 1024 * 1024 cells.
+/// A number of cells that doesn't fit in a usize is an error rather than being cut short.
+fn parse_cell_count(text: &str) -> Result<usize, String> {
+    let error = || {
+        format!(
+            "'{}' isn't a number of cells, use a non-zero number optionally followed by k, m or g \
+             (powers of 1024) that fits in memory, such as 30000, 64k or 1M",
+            text
+        )
+    };
+    let (digits, multiplier) = match text.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&text[..index], 1 << 10),
+        Some((index, 'm' | 'M')) => (&text[..index], 1 << 20),
+        Some((index, 'g' | 'G')) => (&text[..index], 1 << 30),
+        _ => (text, 1),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(error());
+    }
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|count| count.checked_mul(multiplier))
+        .filter(|&count| count > 0)
+        .ok_or_else(error)
+}
+
+/// Turn the text given to --input into the bytes of the program's input, decoding the escapes
+/// \n, \r, \t, \0, \\ and \x followed by two hex digits. Other characters are their UTF-8.
+fn parse_input_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) if digits.len() == 2 && !digits.starts_with('+') => byte,
+                    _ => {
+                        return Err(format!(
+                        "'\\x{}' isn't an escape, \\x is followed by two hex digits such as \\x41",
+                        digits
+                    ))
+                    }
+                }
+            }
+            Some(other) => {
+                return Err(format!(
+                    "'\\{}' isn't an escape, use \\n, \\r, \\t, \\0, \\\\ or \\x41",
+                    other
+                ))
+            }
+            None => return Err("the text ends with a \\, use \\\\ for a backslash".to_string()),
+        });
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Held for reading while the arguments are parsed, and for writing by the tests in env
+    /// while they set environment variables, so that the variables don't leak into other tests
+    static ENV_LOCK: std::sync::RwLock<()> = std::sync::RwLock::new(());
+
+    /// Parse the options of bft run given after the name of the program, looking for a config
+    /// file in the paths given. The caller has to hold ENV_LOCK.
+    fn try_parse(args: &[&str], config_paths: &[PathBuf]) -> Result<RunArgs, clap::Error> {
+        RunArgs::try_new_with_config_paths(
+            std::iter::once("bft").chain(args.iter().copied()),
+            config_paths,
+        )
+    }
+
+    /// Parse the arguments given after the name of the program, looking for a config file in
+    /// the paths given
+    fn parse_with_config_paths(
+        args: &[&str],
+        config_paths: &[PathBuf],
+    ) -> Result<RunArgs, clap::Error> {
+        let _env = ENV_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        try_parse(args, config_paths)
+    }
+
+    /// Parse the arguments given after the name of the program, without looking for a config
+    /// file so that the tests don't depend on the machine they are run on
+    fn parse(args: &[&str]) -> Result<RunArgs, clap::Error> {
+        parse_with_config_paths(args, &[])
+    }
+
+    /// Test the arguments when only the program is given
+    #[test]
+    fn defaults() {
+        let args = parse(&["hello.bf"]).unwrap();
+        assert_eq!(
+            args.source(),
+            &ProgramSource::File(PathBuf::from("hello.bf"))
+        );
+        assert_eq!(args.source().to_string(), "hello.bf");
+        assert_eq!(args.cell_count(), 30000);
+        assert_eq!(args.cell_size(), CellSize::Bits8);
+        assert_eq!(args.tape_mode(), AllocStrategy::TapeIsFixed);
+        assert_eq!(args.output_format(), OutputFormat::AsciiOutput);
+        assert_eq!(args.input_format(), InputFormat::RawInput);
+        assert_eq!(args.eof_behavior(), EofBehavior::NegOne);
+        assert_eq!(args.max_steps(), None);
+        assert_eq!(args.timeout(), None);
+        assert_eq!(args.debug(), DebugLevelType::None);
+        assert!(!args.coverage());
+        assert!(!args.profile());
+        assert!(!args.unbuffered());
+        assert_eq!(args.tape_init(), None);
+        assert!(!args.debug_interactive());
+        assert_eq!(args.record_input(), None);
+        assert_eq!(args.replay_input(), None);
+        assert_eq!(args.input(), None);
+        assert_eq!(args.output(), None);
+        assert!(!args.sync_output());
+        assert!(!args.visualize());
+        assert_eq!(args.dump_tape(), None);
+        assert_eq!(args.dump_file(), None);
+        assert_eq!(args.trace_file(), None);
+        assert!(!args.quiet());
+        assert_eq!(args.trailing_newline(), None);
+        assert_eq!(args.config_file(), None);
+    }
+
+    /// Test each of the flags
+    #[test]
+    fn flags() {
+        assert_eq!(parse(&["-c", "10", "a.bf"]).unwrap().cell_count(), 10);
+        assert_eq!(parse(&["--cells", "20", "a.bf"]).unwrap().cell_count(), 20);
+        for (bits, cell_size) in [
+            ("8", CellSize::Bits8),
+            ("16", CellSize::Bits16),
+            ("32", CellSize::Bits32),
+        ] {
+            let args = parse(&["--cell-size", bits, "a.bf"]).unwrap();
+            assert_eq!(args.cell_size(), cell_size);
+        }
+        assert_eq!(
+            parse(&["-e", "a.bf"]).unwrap().tape_mode(),
+            AllocStrategy::TapeCanGrow
+        );
+        assert_eq!(
+            parse(&["--grow-both-ways", "a.bf"]).unwrap().tape_mode(),
+            AllocStrategy::TapeCanGrowBothWays
+        );
+        assert_eq!(
+            parse(&["-e", "--max-tape", "50", "a.bf"])
+                .unwrap()
+                .tape_mode(),
+            AllocStrategy::TapeCanGrowWithLimit(50)
+        );
+        assert_eq!(
+            parse(&["-n", "a.bf"]).unwrap().output_format(),
+            OutputFormat::BinaryOutput
+        );
+        assert_eq!(
+            parse(&["--hex", "a.bf"]).unwrap().output_format(),
+            OutputFormat::HexOutput
+        );
+        assert_eq!(
+            parse(&["--decimal", "a.bf"]).unwrap().input_format(),
+            InputFormat::DecimalInput
+        );
+        for (mode, eof_behavior) in [
+            ("zero", EofBehavior::Zero),
+            ("neg1", EofBehavior::NegOne),
+            ("unchanged", EofBehavior::Unchanged),
+        ] {
+            let args = parse(&["--eof-behavior", mode, "a.bf"]).unwrap();
+            assert_eq!(args.eof_behavior(), eof_behavior);
+        }
+        assert_eq!(
+            parse(&["--max-steps", "1000", "a.bf"]).unwrap().max_steps(),
+            Some(1000)
+        );
+        assert_eq!(
+            parse(&["--max-steps", "18446744073709551615", "a.bf"])
+                .unwrap()
+                .max_steps(),
+            Some(u64::MAX)
+        );
+        assert_eq!(
+            parse(&["--max-steps", "0", "a.bf"]).unwrap().max_steps(),
+            None
+        );
+        for (duration, timeout) in [
+            ("500ms", Duration::from_millis(500)),
+            ("5s", Duration::from_secs(5)),
+            ("2m", Duration::from_secs(120)),
+            ("0s", Duration::ZERO),
+        ] {
+            let args = parse(&["--timeout", duration, "a.bf"]).unwrap();
+            assert_eq!(args.timeout(), Some(timeout));
+        }
+        assert_eq!(
+            parse(&["-d", "a.bf"]).unwrap().debug(),
+            DebugLevelType::Information
+        );
+        assert!(parse(&["--coverage", "a.bf"]).unwrap().coverage());
+        assert!(parse(&["--profile", "a.bf"]).unwrap().profile());
+        assert!(parse(&["--unbuffered", "a.bf"]).unwrap().unbuffered());
+        assert_eq!(
+            parse(&["--tape-init", "init.bin", "a.bf"])
+                .unwrap()
+                .tape_init(),
+            Some(&PathBuf::from("init.bin"))
+        );
+        assert!(parse(&["--debug-interactive", "a.bf"])
+            .unwrap()
+            .debug_interactive());
+        assert_eq!(
+            parse(&["--record-input", "in.txt", "a.bf"])
+                .unwrap()
+                .record_input(),
+            Some(&PathBuf::from("in.txt"))
+        );
+        assert_eq!(
+            parse(&["--replay-input", "in.txt", "a.bf"])
+                .unwrap()
+                .replay_input(),
+            Some(&PathBuf::from("in.txt"))
+        );
+        assert_eq!(
+            parse(&["-i", "message.txt", "a.bf"]).unwrap().input(),
+            Some(&PathBuf::from("message.txt"))
+        );
+        assert_eq!(
+            parse(&["--input-file", "message.txt", "a.bf"])
+                .unwrap()
+                .input(),
+            Some(&PathBuf::from("message.txt"))
+        );
+        assert_eq!(
+            parse(&["--input", "hello", "a.bf"]).unwrap().input_text(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(
+            parse(&["-o", "out.txt", "a.bf"]).unwrap().output(),
+            Some(&PathBuf::from("out.txt"))
+        );
+        let args = parse(&["--output-file", "out.txt", "--sync-output", "a.bf"]).unwrap();
+        assert_eq!(args.output(), Some(&PathBuf::from("out.txt")));
+        assert!(args.sync_output());
+        assert!(parse(&["--visualize", "a.bf"]).unwrap().visualize());
+        let args = parse(&["--eval", "+++."]).unwrap();
+        assert_eq!(args.source(), &ProgramSource::Inline("+++.".to_string()));
+        assert_eq!(args.source().to_string(), "<eval>");
+        let args = parse(&["-x", "+."]).unwrap();
+        assert_eq!(args.source(), &ProgramSource::Inline("+.".to_string()));
+        let args = parse(&["-", "-i", "message.txt"]).unwrap();
+        assert_eq!(args.source(), &ProgramSource::Stdin);
+        assert_eq!(args.source().to_string(), "<stdin>");
+        assert_eq!(args.input(), Some(&PathBuf::from("message.txt")));
+        let args = parse(&["--dump-tape", "32", "a.bf"]).unwrap();
+        assert_eq!(args.dump_tape(), Some(32));
+        assert_eq!(args.dump_file(), None);
+        let args = parse(&["--dump-tape", "8", "--dump-file", "tape.txt", "a.bf"]).unwrap();
+        assert_eq!(args.dump_tape(), Some(8));
+        assert_eq!(args.dump_file(), Some(&PathBuf::from("tape.txt")));
+        assert_eq!(
+            parse(&["--trace-file", "trace.jsonl", "a.bf"])
+                .unwrap()
+                .trace_file(),
+            Some(&PathBuf::from("trace.jsonl"))
+        );
+        assert_eq!(
+            parse(&["--trace-file", "-", "a.bf"]).unwrap().trace_file(),
+            Some(&PathBuf::from("-"))
+        );
+    }
+
+    /// Test the number of cells given to --cells and --max-tape, with and without suffixes
+    #[test]
+    fn cell_counts() {
+        for (count, cells) in [
+            ("30000", 30000),
+            ("500", 500),
+            ("64k", 64 * 1024),
+            ("64K", 64 * 1024),
+            ("2m", 2 * 1024 * 1024),
+            ("2M", 2 * 1024 * 1024),
+            ("1g", 1024 * 1024 * 1024),
+            ("1G", 1024 * 1024 * 1024),
+        ] {
+            assert_eq!(
+                parse(&["--cells", count, "a.bf"]).unwrap().cell_count(),
+                cells
+            );
+            let args = parse(&["-e", "--cells", "1", "--max-tape", count, "a.bf"]).unwrap();
+            assert_eq!(args.tape_mode(), AllocStrategy::TapeCanGrowWithLimit(cells));
+        }
+        for count in [
+            "0",
+            "0k",
+            "k",
+            "M",
+            "1.5k",
+            "5T",
+            "5kk",
+            "+5",
+            "10kb",
+            "99999999999999999999M",
+        ] {
+            let error = parse(&["--cells", count, "a.bf"]).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+            assert!(error.to_string().contains("30000, 64k or 1M"), "{}", error);
+        }
+        // Whatever the size of a usize, the most cells fit but one more doesn't, either as a
+        // number or once multiplied by a suffix
+        let most = usize::MAX.to_string();
+        assert_eq!(
+            parse(&["--cells", &most, "a.bf"]).unwrap().cell_count(),
+            usize::MAX
+        );
+        let too_many = (usize::MAX as u128 + 1).to_string();
+        let too_many_g = format!("{}g", (usize::MAX >> 30) + 1);
+        for count in [too_many.as_str(), &too_many_g] {
+            let error = parse(&["--cells", count, "a.bf"]).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        }
+        // The ceiling only means something for a tape that can grow
+        let error = parse(&["--max-tape", "1M", "a.bf"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        assert!(error.to_string().contains("--extensible"), "{}", error);
+        let error = parse(&["-e", "--grow-both-ways", "--max-tape", "1M", "a.bf"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    /// Test default options read from a config file, and that the command line overrides them
+    #[test]
+    fn config_file() {
+        let dir = std::env::temp_dir().join(format!("bft-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let path_arg = path.to_string_lossy().to_string();
+        let missing = dir.join("missing.toml");
+        let with_config = |text: &str, args: &[&str]| {
+            std::fs::write(&path, text).unwrap();
+            parse_with_config_paths(args, &[missing.clone(), path.clone()])
+        };
+
+        // The first config file that exists is used
+        let config = "# My usual options\nextensible = true\ncells = 65536\noutput-format = \"numbers\"\ndebug = 0\n";
+        let args = with_config(config, &["a.bf"]).unwrap();
+        assert_eq!(args.config_file(), Some(&path));
+        assert!(args.config_warnings().is_empty());
+        assert_eq!(args.tape_mode(), AllocStrategy::TapeCanGrow);
+        assert_eq!(args.cell_count(), 65536);
+        assert_eq!(args.output_format(), OutputFormat::BinaryOutput);
+
+        // But the command line takes precedence, including over options it can't be used with
+        let args = with_config(
+            config,
+            &["--cells", "100", "--hex", "--tape-mode", "wrap", "a.bf"],
+        )
+        .unwrap();
+        assert_eq!(args.cell_count(), 100);
+        assert_eq!(args.output_format(), OutputFormat::HexOutput);
+        assert_eq!(args.tape_mode(), AllocStrategy::TapeWraps);
+        let args = with_config("debug-level = \"trace\"\n", &["-d", "a.bf"]).unwrap();
+        assert_eq!(args.debug(), DebugLevelType::Information);
+
+        // The program can come from the config file too
+        let args = with_config("eval = '+.'\n", &[]).unwrap();
+        assert_eq!(args.source(), &ProgramSource::Inline("+.".to_string()));
+        let args = with_config("eval = '-.'\n", &[]).unwrap();
+        assert_eq!(args.source(), &ProgramSource::Inline("-.".to_string()));
+        let args = with_config("eval = '+.'\n", &["a.bf"]).unwrap();
+        assert_eq!(args.source(), &ProgramSource::File(PathBuf::from("a.bf")));
+
+        // Keys that aren't options are warned about
+        let args = with_config(
+            "cells = 10\ncolour = true\nconfig = 'other.toml'\n",
+            &["a.bf"],
+        )
+        .unwrap();
+        assert_eq!(args.cell_count(), 10);
+        assert_eq!(args.config_warnings().len(), 2);
+        assert!(args.config_warnings()[0].contains("colour"));
+        assert!(args.config_warnings()[0].contains(&path_arg));
+
+        // Values of the wrong type, and bad values, name the file and key
+        for (config, kind) in [
+            ("cells = true", clap::error::ErrorKind::InvalidValue),
+            ("extensible = 1", clap::error::ErrorKind::InvalidValue),
+            ("debug = -1", clap::error::ErrorKind::InvalidValue),
+            ("cells = 0", clap::error::ErrorKind::ValueValidation),
+            ("max-steps = -1", clap::error::ErrorKind::ValueValidation),
+            (
+                "tape-mode = 'sideways'",
+                clap::error::ErrorKind::InvalidValue,
+            ),
+        ] {
+            let error = with_config(config, &["a.bf"]).err().unwrap();
+            assert_eq!(error.kind(), kind, "{}", config);
+            let message = error.to_string();
+            assert!(message.contains(&path_arg), "{}", message);
+            let key = config.split(' ').next().unwrap();
+            assert!(
+                message.contains(&format!(", {} ", key))
+                    || message.contains(&format!(", {}:", key)),
+                "{}",
+                message
+            );
+        }
+        let error = with_config("cells = 1 2", &["a.bf"]).err().unwrap();
+        assert!(error.to_string().contains("line 1"), "{}", error);
+        let error = with_config("cells = [1]", &["a.bf"]).err().unwrap();
+        assert!(error.to_string().contains(", cells: array"), "{}", error);
+
+        // --config gives the file to use, which has to exist
+        std::fs::write(&path, "cells = 10\n").unwrap();
+        let other = dir.join("other.toml");
+        std::fs::write(&other, "cells = 20\n").unwrap();
+        let args = parse(&["--config", &other.to_string_lossy(), "a.bf"]).unwrap();
+        assert_eq!(args.config_file(), Some(&other));
+        assert_eq!(args.cell_count(), 20);
+        let error = parse(&["--config", &missing.to_string_lossy(), "a.bf"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::Io);
+        assert!(error
+            .to_string()
+            .contains(&missing.to_string_lossy().to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Without a config file the built-in defaults are used
+        let args = parse_with_config_paths(&["a.bf"], &[missing]).unwrap();
+        assert_eq!(args.config_file(), None);
+        assert!(args.config_warnings().is_empty());
+        assert_eq!(args.cell_count(), 30000);
+    }
+
+    /// Test the debug level given by counting -d or by name
+    #[test]
+    fn debug_levels() {
+        // The count saturates rather than going back to no debug
+        for (count, level) in [
+            (0, DebugLevelType::None),
+            (1, DebugLevelType::Information),
+            (2, DebugLevelType::Verbose),
+            (3, DebugLevelType::Detailed),
+            (4, DebugLevelType::Detailed),
+            (u8::MAX, DebugLevelType::Detailed),
+        ] {
+            assert_eq!(DebugLevelType::from(count), level);
+        }
+        assert_eq!(
+            parse(&["-dddd", "a.bf"]).unwrap().debug(),
+            DebugLevelType::Detailed
+        );
+
+        for (name, level) in [
+            ("none", DebugLevelType::None),
+            ("info", DebugLevelType::Information),
+            ("verbose", DebugLevelType::Verbose),
+            ("detailed", DebugLevelType::Detailed),
+            ("trace", DebugLevelType::Trace),
+        ] {
+            assert_eq!(name.parse::<DebugLevelType>(), Ok(level));
+            assert_eq!(level.to_string(), name);
+            let args = parse(&["--debug-level", name, "a.bf"]).unwrap();
+            assert_eq!(args.debug(), level);
+        }
+        for name in ["", "information", "Verbose", "4", "loud"] {
+            assert!(name.parse::<DebugLevelType>().is_err(), "{}", name);
+        }
+        let error = parse(&["--debug-level", "loud", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::InvalidValue);
+
+        // The level given by name wins over the count of -d, whichever is given first
+        for args in [
+            &["-dd", "--debug-level", "none", "a.bf"][..],
+            &["--debug-level", "none", "-dd", "a.bf"],
+        ] {
+            assert_eq!(parse(args).unwrap().debug(), DebugLevelType::None);
+        }
+        assert_eq!(
+            parse(&["-d", "--debug-level", "trace", "a.bf"])
+                .unwrap()
+                .debug(),
+            DebugLevelType::Trace
+        );
+    }
+
+    /// Test the options that take one of an enum's values, and the old flags that are the same
+    /// as some of the values
+    #[test]
+    fn value_enums() {
+        for (mode, strategy) in [
+            ("fixed", AllocStrategy::TapeIsFixed),
+            ("grow", AllocStrategy::TapeCanGrow),
+            ("grow-both-ways", AllocStrategy::TapeCanGrowBothWays),
+            ("wrap", AllocStrategy::TapeWraps),
+        ] {
+            assert_eq!(mode.parse::<AllocStrategy>(), Ok(strategy));
+            let args = parse(&["--tape-mode", mode, "a.bf"]).unwrap();
+            assert_eq!(args.tape_mode(), strategy);
+        }
+        assert_eq!(
+            parse(&["--tape-mode", "grow", "--max-tape", "1k", "a.bf"])
+                .unwrap()
+                .tape_mode(),
+            AllocStrategy::TapeCanGrowWithLimit(1024)
+        );
+        assert!(AllocStrategy::TapeCanGrowWithLimit(1000)
+            .to_possible_value()
+            .is_none());
+        for (name, format) in [
+            ("ascii", OutputFormat::AsciiOutput),
+            ("numbers", OutputFormat::BinaryOutput),
+            ("hex", OutputFormat::HexOutput),
+            ("utf8", OutputFormat::Utf8Output),
+        ] {
+            assert_eq!(name.parse::<OutputFormat>(), Ok(format));
+            assert_eq!(format.to_string(), name);
+            let args = parse(&["--output-format", name, "a.bf"]).unwrap();
+            assert_eq!(args.output_format(), format);
+        }
+        for (bits, cell_size) in [
+            ("8", CellSize::Bits8),
+            ("16", CellSize::Bits16),
+            ("32", CellSize::Bits32),
+        ] {
+            let args = parse(&["--cell-size", bits, "a.bf"]).unwrap();
+            assert_eq!(args.cell_size(), cell_size);
+        }
+        for (mode, eof_behavior) in [
+            ("zero", EofBehavior::Zero),
+            ("neg1", EofBehavior::NegOne),
+            ("unchanged", EofBehavior::Unchanged),
+        ] {
+            let args = parse(&["--eof-behavior", mode, "a.bf"]).unwrap();
+            assert_eq!(args.eof_behavior(), eof_behavior);
+        }
+
+        // The old flags are the same as the values
+        for (flags, strategy) in [
+            (&["-e"][..], AllocStrategy::TapeCanGrow),
+            (&["--extensible"], AllocStrategy::TapeCanGrow),
+            (&["--grow-both-ways"], AllocStrategy::TapeCanGrowBothWays),
+            (
+                &["-e", "--grow-both-ways"],
+                AllocStrategy::TapeCanGrowBothWays,
+            ),
+            (
+                &["-e", "--max-tape", "2M"],
+                AllocStrategy::TapeCanGrowWithLimit(2 * 1024 * 1024),
+            ),
+        ] {
+            let args = parse(&[flags, &["a.bf"]].concat()).unwrap();
+            assert_eq!(args.tape_mode(), strategy);
+            #[allow(deprecated)]
+            let extensible = args.extensible();
+            assert_eq!(extensible, strategy);
+        }
+        for (flag, format) in [
+            ("-n", OutputFormat::BinaryOutput),
+            ("--numbers", OutputFormat::BinaryOutput),
+            ("--hex", OutputFormat::HexOutput),
+        ] {
+            let args = parse(&[flag, "a.bf"]).unwrap();
+            assert_eq!(args.output_format(), format);
+        }
+
+        // But can't be used with them
+        for args in [
+            &["--tape-mode", "grow", "-e", "a.bf"][..],
+            &["--tape-mode", "fixed", "--grow-both-ways", "a.bf"],
+            &["--output-format", "hex", "-n", "a.bf"],
+            &["--output-format", "numbers", "--hex", "a.bf"],
+            &["--tape-mode", "wrap", "--max-tape", "1k", "a.bf"],
+            &["--tape-mode", "grow-both-ways", "--max-tape", "1k", "a.bf"],
+        ] {
+            let error = parse(args).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+        let error = parse(&["--tape-mode", "fixed", "--max-tape", "1k", "a.bf"])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        for (option, value) in [
+            ("--tape-mode", "sideways"),
+            ("--output-format", "binary"),
+            ("--cell-size", "64"),
+            ("--eof-behavior", "minus1"),
+        ] {
+            let error = parse(&[option, value, "a.bf"]).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::InvalidValue);
+        }
+    }
+
+    /// Test that bad arguments are returned as errors rather than exiting the process
+    #[test]
+    fn invalid() {
+        let error = parse(&["--cells", "0", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        let error = parse(&["--cell-size", "7", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::InvalidValue);
+        let error = parse(&["--eof-behavior", "minus1", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::InvalidValue);
+        for steps in ["18446744073709551616", "lots"] {
+            let error = parse(&["--max-steps", steps, "a.bf"]).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        }
+        assert!(parse(&["--max-steps", "-1", "a.bf"]).is_err());
+        for duration in [
+            "soon",
+            "5",
+            "ms",
+            "1.5s",
+            "5 s",
+            "2h",
+            "99999999999999999999s",
+        ] {
+            let error = parse(&["--timeout", duration, "a.bf"]).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+            assert!(error.to_string().contains("500ms, 5s or 2m"), "{}", error);
+        }
+        let error = parse(&[]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        let error = parse(&["--eval", "+.", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        let error = parse(&["-n", "--hex", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        let error = parse(&["--max-tape", "50", "a.bf"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        let error = parse(&["--dump-file", "tape.txt", "a.bf"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        let error = parse(&["--dump-tape", "lots", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        let error = parse(&["--sync-output", "a.bf"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        // Stdin can't be the program and its input
+        let error = parse(&["-"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        assert!(error.to_string().contains("--input-file"));
+        let error = parse(&["--help"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::DisplayHelp);
+    }
+
+    /// Test --quiet and the trailing newline it turns off
+    #[test]
+    fn quiet() {
+        for flag in ["-q", "--quiet"] {
+            assert!(parse(&[flag, "a.bf"]).unwrap().quiet());
+        }
+
+        // There's no debug output when quiet
+        for args in [
+            &["-q", "-d", "a.bf"][..],
+            &["--debug-level", "info", "--quiet", "a.bf"],
+        ] {
+            let error = parse(args).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+
+        for (name, trailing_newline) in [
+            ("always", TrailingNewline::Always),
+            ("never", TrailingNewline::Never),
+            ("if-missing", TrailingNewline::IfMissing),
+        ] {
+            let args = parse(&["-q", "--trailing-newline", name, "a.bf"]).unwrap();
+            assert_eq!(args.trailing_newline(), Some(trailing_newline));
+        }
+        let error = parse(&["--trailing-newline", "sometimes", "a.bf"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::InvalidValue);
+    }
+
+    /// Test that each subcommand is parsed into its options, and that without one the
+    /// arguments are those of bft run
+    #[test]
+    fn subcommands() {
+        let parse_all = |args: &[&str]| {
+            let _env = ENV_LOCK.read().unwrap_or_else(|e| e.into_inner());
+            Args::try_new_with_config_paths(std::iter::once("bft").chain(args.iter().copied()), &[])
+        };
+        let file = ProgramSource::File(PathBuf::from("a.bf"));
+
+        // The shim for bft without a subcommand, with the options before or after the program
+        for args in [
+            &["a.bf", "-c", "100"][..],
+            &["-c", "100", "a.bf"],
+            &["run", "-c", "100", "a.bf"],
+        ] {
+            match parse_all(args).unwrap() {
+                Args::Run(run) => {
+                    assert_eq!(run.source(), &file);
+                    assert_eq!(run.cell_count(), 100);
+                }
+                args => panic!("{:?} isn't run", args),
+            }
+        }
+        let args = parse_all(&["--eval", "+."]).unwrap();
+        assert_eq!(
+            args.source(),
+            Some(&ProgramSource::Inline("+.".to_string()))
+        );
+        assert!(matches!(args, Args::Run(_)));
+        // A file with the name of a subcommand has to be given with run, or as a path
+        assert!(matches!(parse_all(&["./check"]).unwrap(), Args::Run(_)));
+
+        assert_eq!(
+            parse_all(&["check", "a.bf"]).unwrap(),
+            Args::Check(CheckArgs {
+                sources: vec![file.clone()]
+            })
+        );
+        // check takes any number of programs, and --check anywhere is the same as check
+        let many = Args::Check(CheckArgs {
+            sources: vec![
+                file.clone(),
+                ProgramSource::Stdin,
+                ProgramSource::File(PathBuf::from("b.bf")),
+            ],
+        });
+        assert_eq!(parse_all(&["check", "a.bf", "-", "b.bf"]).unwrap(), many);
+        assert_eq!(parse_all(&["--check", "a.bf", "-", "b.bf"]).unwrap(), many);
+        assert_eq!(parse_all(&["a.bf", "-", "--check", "b.bf"]).unwrap(), many);
+        let args = parse_all(&["--check", "--eval", "+["]).unwrap();
+        assert_eq!(args.source(), None);
+        assert_eq!(
+            args,
+            Args::Check(CheckArgs {
+                sources: vec![ProgramSource::Inline("+[".to_string())]
+            })
+        );
+        assert_eq!(parse_all(&["check", "-x", "+["]).unwrap(), args);
+        // Unless it's the value of an option, or after --
+        let args = parse_all(&["--input", "--check", "--eval", "+."]).unwrap();
+        assert!(matches!(args, Args::Run(_)));
+        assert_eq!(
+            args.source(),
+            Some(&ProgramSource::Inline("+.".to_string()))
+        );
+        assert_eq!(
+            parse_all(&["--eval", "+[", "--check"]).unwrap(),
+            Args::Check(CheckArgs {
+                sources: vec![ProgramSource::Inline("+[".to_string())]
+            })
+        );
+        let error = parse_all(&["a.bf", "--", "--check"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::UnknownArgument);
+        let error = parse_all(&["--check"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        let error = parse_all(&["--check", "--eval", "+", "a.bf"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert_eq!(
+            parse_all(&["dump", "-"]).unwrap(),
+            Args::Dump(DumpArgs {
+                source: ProgramSource::Stdin
+            })
+        );
+        assert_eq!(
+            parse_all(&["stats", "--eval", "+"]).unwrap(),
+            Args::Stats(StatsArgs {
+                source: ProgramSource::Inline("+".to_string())
+            })
+        );
+        match parse_all(&["fmt", "a.bf"]).unwrap() {
+            Args::Fmt(fmt) => {
+                assert_eq!(fmt.source(), &file);
+                assert_eq!(fmt.indent(), 2);
+                assert_eq!(fmt.width(), 80);
+            }
+            args => panic!("{:?} isn't fmt", args),
+        }
+        match parse_all(&["fmt", "--indent", "4", "--width", "40", "a.bf"]).unwrap() {
+            Args::Fmt(fmt) => assert_eq!((fmt.indent(), fmt.width()), (4, 40)),
+            args => panic!("{:?} isn't fmt", args),
+        }
+
+        // The options of run are only for run
+        let error = parse_all(&["check", "-c", "10", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::UnknownArgument);
+        let error = parse_all(&["fmt", "--width", "0", "a.bf"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        let error = parse_all(&["stats"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+
+        // Help and the version are for bft as a whole, which is also what is shown without any
+        // arguments
+        let error = parse_all(&["--help"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::DisplayHelp);
+        assert!(error.to_string().contains("check"));
+        let error = parse_all(&["-V"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::DisplayVersion);
+        let error = parse_all(&[]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+        );
+        let error = parse_all(&["run", "--help"]).err().unwrap();
+        assert!(error.to_string().contains("--tape-mode"));
+    }
+
+    /// Test the escapes in the text given to --input, which can't be used with --input-file
+    #[test]
+    fn input_text() {
+        for (text, bytes) in [
+            ("hello", &b"hello"[..]),
+            ("", b""),
+            ("two\\nlines\\n", b"two\nlines\n"),
+            ("\\r\\t\\0\\\\", b"\r\t\0\\"),
+            ("\\x41\\x62\\xFF\\x00", b"Ab\xff\0"),
+            ("£", "£".as_bytes()),
+            ("-n", b"-n"),
+        ] {
+            let args = parse(&["--input", text, "a.bf"]).unwrap();
+            assert_eq!(args.input_text(), Some(bytes), "{}", text);
+        }
+        for text in ["\\q", "end\\", "\\x4", "\\x4g", "\\x+1", "\\xé1"] {
+            let error = parse(&["--input", text, "a.bf"]).err().unwrap();
+            assert_eq!(
+                error.kind(),
+                clap::error::ErrorKind::ValueValidation,
+                "{}",
+                text
+            );
+        }
+
+        // The input can be given one way or the other
+        let error = parse(&["--input", "hello", "-i", "in.txt", "a.bf"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        // And with --input the program can come from stdin
+        let args = parse(&["-", "--input", "hello"]).unwrap();
+        assert_eq!(args.source(), &ProgramSource::Stdin);
+        assert_eq!(parse(&["a.bf"]).unwrap().input_text(), None);
+    }
+
+    /// Test that options built in code are the same as those parsed from the command line
+    #[test]
+    fn builder() {
+        let same = |parsed: RunArgs, built: RunArgs| {
+            assert_eq!(built.source(), parsed.source());
+            assert_eq!(built.cell_count(), parsed.cell_count());
+            assert_eq!(built.cell_size(), parsed.cell_size());
+            assert_eq!(built.tape_mode(), parsed.tape_mode());
+            assert_eq!(built.output_format(), parsed.output_format());
+            assert_eq!(built.input_format(), parsed.input_format());
+            assert_eq!(built.eof_behavior(), parsed.eof_behavior());
+            assert_eq!(built.max_steps(), parsed.max_steps());
+            assert_eq!(built.timeout(), parsed.timeout());
+            assert_eq!(built.debug(), parsed.debug());
+            assert_eq!(built.coverage(), parsed.coverage());
+            assert_eq!(built.profile(), parsed.profile());
+            assert_eq!(built.unbuffered(), parsed.unbuffered());
+            assert_eq!(built.tape_init(), parsed.tape_init());
+            assert_eq!(built.debug_interactive(), parsed.debug_interactive());
+            assert_eq!(built.record_input(), parsed.record_input());
+            assert_eq!(built.replay_input(), parsed.replay_input());
+            assert_eq!(built.input(), parsed.input());
+            assert_eq!(built.output(), parsed.output());
+            assert_eq!(built.sync_output(), parsed.sync_output());
+            assert_eq!(built.visualize(), parsed.visualize());
+            assert_eq!(built.dump_tape(), parsed.dump_tape());
+            assert_eq!(built.dump_file(), parsed.dump_file());
+            assert_eq!(built.trace_file(), parsed.trace_file());
+            assert_eq!(built.quiet(), parsed.quiet());
+            assert_eq!(built.trailing_newline(), parsed.trailing_newline());
+            assert_eq!(built.config_file(), parsed.config_file());
+            assert_eq!(built.config_warnings(), parsed.config_warnings());
+            assert_eq!(built, parsed);
+            assert_eq!(Args::from(built), Args::Run(Box::new(parsed)));
+        };
+
+        same(
+            parse(&["hello.bf"]).unwrap(),
+            RunArgs::builder(ProgramSource::File(PathBuf::from("hello.bf"))).build(),
+        );
+        same(
+            parse(&["--eval", "+."]).unwrap(),
+            Args::builder(ProgramSource::Inline("+.".to_string()))
+                .cells(0)
+                .build(),
+        );
+
+        same(
+            parse(&[
+                "--cells",
+                "64k",
+                "--cell-size",
+                "16",
+                "--tape-mode",
+                "grow",
+                "--max-tape",
+                "1M",
+                "--output-format",
+                "hex",
+                "--decimal",
+                "--eof-behavior",
+                "unchanged",
+                "--max-steps",
+                "1000",
+                "--timeout",
+                "5s",
+                "--coverage",
+                "--profile",
+                "--unbuffered",
+                "--tape-init",
+                "tape.bin",
+                "--record-input",
+                "in.rec",
+                "--replay-input",
+                "in.old",
+                "-i",
+                "in.txt",
+                "-o",
+                "out.txt",
+                "--sync-output",
+                "--dump-tape",
+                "16",
+                "--dump-file",
+                "tape.txt",
+                "--trace-file",
+                "-",
+                "--quiet",
+                "--trailing-newline",
+                "always",
+                "-",
+            ])
+            .unwrap(),
+            RunArgs::builder(ProgramSource::File(PathBuf::from("other.bf")))
+                .source(ProgramSource::Stdin)
+                .cells(64 * 1024)
+                .cell_size(CellSize::Bits16)
+                .tape_mode(AllocStrategy::TapeCanGrowWithLimit(1024 * 1024))
+                .output_format(OutputFormat::HexOutput)
+                .input_format(InputFormat::DecimalInput)
+                .eof_behavior(EofBehavior::Unchanged)
+                .max_steps(Some(1000))
+                .timeout(Some(Duration::from_secs(5)))
+                .coverage(true)
+                .profile(true)
+                .unbuffered(true)
+                .tape_init(Some(PathBuf::from("tape.bin")))
+                .record_input(Some(PathBuf::from("in.rec")))
+                .replay_input(Some(PathBuf::from("in.old")))
+                .input(Some(PathBuf::from("in.txt")))
+                .output(Some(PathBuf::from("out.txt")))
+                .sync_output(true)
+                .dump_tape(Some(16))
+                .dump_file(Some(PathBuf::from("tape.txt")))
+                .trace_file(Some(PathBuf::from("-")))
+                .quiet(true)
+                .trailing_newline(Some(TrailingNewline::Always))
+                .build(),
+        );
+
+        same(
+            parse(&["--debug-level", "detailed", "--debug-interactive", "a.bf"]).unwrap(),
+            RunArgs::builder(ProgramSource::File(PathBuf::from("a.bf")))
+                .debug(DebugLevelType::Detailed)
+                .debug_interactive(true)
+                .build(),
+        );
+        same(
+            parse(&["--input", "a\\x00b", "a.bf"]).unwrap(),
+            RunArgs::builder(ProgramSource::File(PathBuf::from("a.bf")))
+                .input_text(Some(b"a\0b".to_vec()))
+                .build(),
+        );
+        same(
+            parse(&["--visualize", "a.bf"]).unwrap(),
+            RunArgs::builder(ProgramSource::File(PathBuf::from("a.bf")))
+                .visualize(true)
+                .build(),
+        );
+    }
+
+    /// Test that clap finds nothing wrong with the command, and the hidden completions
+    /// subcommand
+    #[test]
+    fn command_is_valid() {
+        command().debug_assert();
+        run_command().debug_assert();
+
+        let _env = ENV_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        for (name, shell) in [
+            ("bash", completions::Shell::Bash),
+            ("zsh", completions::Shell::Zsh),
+            ("fish", completions::Shell::Fish),
+            ("powershell", completions::Shell::PowerShell),
+        ] {
+            let args = Args::try_new_with_config_paths(["bft", "completions", name], &[]).unwrap();
+            assert_eq!(args, Args::Completions(CompletionsArgs { shell }));
+            assert_eq!(args.source(), None);
+        }
+        let error = Args::try_new_with_config_paths(["bft", "completions", "csh"], &[])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::InvalidValue);
+        let error = Args::try_new_with_config_paths(["bft", "--help"], &[])
+            .err()
+            .unwrap();
+        assert!(!error.to_string().contains("completions"));
+    }
+
+    /// Tests that set environment variables, which are run one at a time
+    mod env {
+        use super::*;
+
+        /// Environment variables set for a test, which are removed when it is dropped
+        struct EnvVars<'a> {
+            vars: Vec<&'a str>,
+            _lock: std::sync::RwLockWriteGuard<'static, ()>,
+        }
+
+        impl<'a> EnvVars<'a> {
+            fn set(vars: &[(&'a str, &str)]) -> Self {
+                let lock = ENV_LOCK.write().unwrap_or_else(|e| e.into_inner());
+                for (name, value) in vars {
+                    std::env::set_var(name, value);
+                }
+                EnvVars {
+                    vars: vars.iter().map(|(name, _)| *name).collect(),
+                    _lock: lock,
+                }
+            }
+        }
+
+        impl Drop for EnvVars<'_> {
+            fn drop(&mut self) {
+                for name in &self.vars {
+                    std::env::remove_var(name);
+                }
+            }
+        }
+
+        /// Test that the environment variables give the options
+        #[test]
+        fn env_values() {
+            let _env = EnvVars::set(&[
+                ("BFT_CELLS", "100000"),
+                ("BFT_CELL_SIZE", "16"),
+                ("BFT_TAPE_MODE", "grow"),
+                ("BFT_MAX_TAPE", "1M"),
+                ("BFT_OUTPUT_FORMAT", "hex"),
+                ("BFT_EOF_BEHAVIOR", "zero"),
+                ("BFT_MAX_STEPS", "1000000"),
+                ("BFT_TIMEOUT", "5s"),
+                ("BFT_DEBUG", "verbose"),
+            ]);
+            let args = try_parse(&["a.bf"], &[]).unwrap();
+            assert_eq!(args.cell_count(), 100000);
+            assert_eq!(args.cell_size(), CellSize::Bits16);
+            assert_eq!(
+                args.tape_mode(),
+                AllocStrategy::TapeCanGrowWithLimit(1024 * 1024)
+            );
+            assert_eq!(args.output_format(), OutputFormat::HexOutput);
+            assert_eq!(args.eof_behavior(), EofBehavior::Zero);
+            assert_eq!(args.max_steps(), Some(1000000));
+            assert_eq!(args.timeout(), Some(Duration::from_secs(5)));
+            assert_eq!(args.debug(), DebugLevelType::Verbose);
+        }
+
+        /// Test that the command line takes precedence over the environment variables, which
+        /// take precedence over the config file
+        #[test]
+        fn env_precedence() {
+            let dir = std::env::temp_dir().join(format!("bft-env-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("config.toml");
+            std::fs::write(&path, "cells = 10\nhex = true\nmax-steps = 50\n").unwrap();
+            let _env = EnvVars::set(&[
+                ("BFT_CELLS", "20"),
+                ("BFT_OUTPUT_FORMAT", "numbers"),
+                ("BFT_DEBUG", "trace"),
+                ("BFT_CONFIG", &path.to_string_lossy()),
+            ]);
+
+            // The config file is used for what the variables don't give
+            let args = try_parse(&["a.bf"], &[]).unwrap();
+            assert_eq!(args.config_file(), Some(&path));
+            assert_eq!(args.cell_count(), 20);
+            assert_eq!(args.output_format(), OutputFormat::BinaryOutput);
+            assert_eq!(args.max_steps(), Some(50));
+            assert_eq!(args.debug(), DebugLevelType::Trace);
+
+            // Including over options they can't be used with
+            let args = try_parse(
+                &["-c", "30", "--hex", "-d", "--max-steps", "0", "a.bf"],
+                &[],
+            )
+            .unwrap();
+            assert_eq!(args.cell_count(), 30);
+            assert_eq!(args.output_format(), OutputFormat::HexOutput);
+            assert_eq!(args.max_steps(), None);
+            assert_eq!(args.debug(), DebugLevelType::Information);
+            let error = try_parse(&["--config", "missing.toml", "a.bf"], &[])
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::Io);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        /// Test that bad values in environment variables name the variable, unless the command
+        /// line overrides them
+        #[test]
+        fn env_errors() {
+            for (name, value, kind) in [
+                ("BFT_CELLS", "lots", clap::error::ErrorKind::ValueValidation),
+                ("BFT_CELLS", "0", clap::error::ErrorKind::ValueValidation),
+                (
+                    "BFT_TAPE_MODE",
+                    "sideways",
+                    clap::error::ErrorKind::InvalidValue,
+                ),
+                (
+                    "BFT_MAX_STEPS",
+                    "-1",
+                    clap::error::ErrorKind::ValueValidation,
+                ),
+                ("BFT_TIMEOUT", "", clap::error::ErrorKind::ValueValidation),
+                ("BFT_DEBUG", "loud", clap::error::ErrorKind::InvalidValue),
+            ] {
+                let _env = EnvVars::set(&[(name, value)]);
+                let error = try_parse(&["a.bf"], &[]).err().unwrap();
+                assert_eq!(error.kind(), kind, "{}={}", name, value);
+                assert!(error.to_string().contains(name), "{}", error);
+            }
+
+            let _env = EnvVars::set(&[("BFT_CELLS", "lots"), ("BFT_DEBUG", "loud")]);
+            let args = try_parse(&["--cells", "40", "-d", "a.bf"], &[]).unwrap();
+            assert_eq!(args.cell_count(), 40);
+            assert_eq!(args.debug(), DebugLevelType::Information);
+        }
     }
 }
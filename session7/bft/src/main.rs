@@ -1,14 +1,44 @@
-use std::io::{stdin, stdout};
+use bft_interp::BfIo;
+use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::{cursor, execute, terminal};
+use std::io::{stderr, stdin, stdout, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Exit code used when the BF program was interrupted by Ctrl-C (128 + SIGINT as shells do)
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Exit code used when there isn't enough memory for the tape (EX_OSERR from sysexits.h)
+const EXIT_OUT_OF_MEMORY: i32 = 71;
+
+/// Exit code used when the program's input or output fails (EX_IOERR from sysexits.h)
+const EXIT_IO_ERROR: i32 = 74;
+
+/// Exit code used when the program runs for more steps or longer than allowed (128 + SIGXCPU
+/// as shells report for a process that goes over its CPU time limit)
+const EXIT_LIMIT_EXCEEDED: i32 = 152;
+
+/// Exit code used when the program fails while running, such as by moving off the tape
+const EXIT_RUNTIME_ERROR: i32 = 1;
+
+/// Number of cells either side of the data pointer shown when the program is interrupted
+const INTERRUPT_WINDOW: usize = 8;
+
+/// Number of loops with the most iterations shown by --profile
+const PROFILE_TOP_LOOPS: usize = 10;
+
+/// Time between the frames of the live view
+const VISUALIZE_FRAME: std::time::Duration = std::time::Duration::from_millis(50);
 
 /// Program to read a Brain Fuck program and run it
 ///
-fn run_bft(args: &cli::Args) -> Result<(), Box<dyn std::error::Error>> {
-    let mut program = bft_types::BfProgram::from_file(args.program())?;
+fn run_bft(args: &cli::RunArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut program = bft_interp::load_program(args.source())?;
 
     // Debug code to dump BF program.
     if args.debug() > cli::DebugLevelType::Verbose {
         for inst in program.instructions() {
-            println!(
+            eprintln!(
                 "[{}]: {} {}",
                 program.filename().to_string_lossy(),
                 inst.location(),
@@ -19,7 +49,7 @@ fn run_bft(args: &cli::Args) -> Result<(), Box<dyn std::error::Error>> {
 
     // Do some validation before running the BF program
     if args.debug() >= cli::DebugLevelType::Information {
-        println!("Validating...");
+        eprintln!("Validating...");
     }
     match program.validate() {
         Err(e) => {
@@ -27,58 +57,510 @@ fn run_bft(args: &cli::Args) -> Result<(), Box<dyn std::error::Error>> {
         }
         Ok(()) => {
             if args.debug() >= cli::DebugLevelType::Verbose {
-                println!("Jumps");
+                eprintln!("Jumps");
                 for l in program.location_map() {
-                    println!("{:?}", l);
+                    eprintln!("{:?}", l);
                 }
             }
             if args.debug() >= cli::DebugLevelType::Information {
-                println!("Valid BF program, will now run it....");
+                eprintln!("Valid BF program, will now run it....");
             }
         }
     }
 
+    // The tape's cells are as wide as asked for
+    match args.cell_size() {
+        cli::CellSize::Bits8 => run_tape::<u8>(args, &program),
+        cli::CellSize::Bits16 => run_tape::<u16>(args, &program),
+        cli::CellSize::Bits32 => run_tape::<u32>(args, &program),
+    }
+}
+
+/// Run a validated program on a tape of cells of type T
+fn run_tape<T: bft_interp::CellKind>(
+    args: &cli::RunArgs,
+    program: &bft_types::BfProgram,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Create a tape for the program to be used by the interpreter
-    let mut tape: bft_interp::BfTape<u8> = bft_interp::BfTape::new(
-        &program,
-        args.cell_count(),
-        args.extensible(),
-        args.output_format(),
-    );
-    tape.set_debug(args.debug());
+    // Keep the shell prompt off the end of the output on a terminal but leave piped output, and
+    // quiet output, as is unless asked otherwise
+    let trailing_newline = match args.trailing_newline() {
+        Some(trailing_newline) => trailing_newline,
+        None if !args.quiet() && stdout().is_terminal() => cli::TrailingNewline::IfMissing,
+        None => cli::TrailingNewline::Never,
+    };
+    let mut tape: bft_interp::BfTape<T> = bft_interp::BfTapeBuilder::new()
+        .tape_size(args.cell_count())
+        .alloc_strategy(args.tape_mode())
+        .output_format(args.output_format())
+        .input_format(args.input_format())
+        .eof_behavior(args.eof_behavior())
+        .step_limit(args.max_steps())
+        .time_limit(args.timeout())
+        .debug(args.debug())
+        .auto_flush(args.unbuffered())
+        .trailing_newline(trailing_newline)
+        .try_build(program)
+        .or_else(|e| match e {
+            bft_interp::BfError::TapeAllocationFailed { requested_cells } => {
+                out_of_memory(args, requested_cells)
+            }
+            e => Err(e),
+        })?;
+    let interrupt = bft_interp::install_interrupt_flag()?;
+    tape.set_interrupt_flag(Arc::clone(&interrupt));
+    if args.coverage() {
+        tape.enable_coverage();
+    }
+    if args.profile() {
+        tape.enable_profiler();
+    }
+    // The trace goes to stderr for - rather than stdout where it would be mixed up with the
+    // program's output
+    match args.trace_file() {
+        Some(path) if path.as_os_str() == "-" => {
+            tape.set_trace_writer(Box::new(std::io::BufWriter::new(std::io::stderr())))
+        }
+        Some(path) => tape.set_trace_writer(Box::new(bft_interp::OutputFile::create(path)?)),
+        None => {}
+    }
+    if let Some(path) = args.tape_init() {
+        tape.load_initial(&std::fs::read(path)?)?;
+    }
+    if let Some(path) = args.replay_input() {
+        tape.set_replay_input(std::fs::read(path)?);
+    }
+    if args.record_input().is_some() {
+        tape.enable_input_recording();
+    }
 
-    // And run the interpreter
-    match tape.interpreter(&mut stdin(), &mut stdout()) {
-        Ok(_) => {}
-        Err(e) => println!("Error {}", e),
+    // The program's input comes from a file or the text given on the command line if there
+    // was one, otherwise from stdin
+    let mut input: Box<dyn Read> = match (args.input(), args.input_text()) {
+        (Some(path), _) => Box::new(bft_interp::open_input(path)?),
+        (None, Some(text)) => Box::new(std::io::Cursor::new(text.to_vec())),
+        (None, None) => Box::new(stdin().lock()),
+    };
+    // And its output goes to a file if one was given, otherwise to stdout. Debug output still
+    // goes to stderr.
+    let mut output_file = args
+        .output()
+        .map(bft_interp::OutputFile::create)
+        .transpose()?;
+    let mut stdout = stdout();
+    let mut output: &mut dyn Write = match &mut output_file {
+        Some(file) => file,
+        None => &mut stdout,
+    };
+
+    // And run the interpreter, or the debugger which is driven from the terminal so that the
+    // program can still use stdin and stdout
+    let result = if args.debug_interactive() {
+        let tty = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?;
+        let commands = std::io::BufReader::new(tty.try_clone()?);
+        let mut io = bft_interp::StdIo::new(input, &mut output);
+        let result = bft_interp::debugger::run(&mut tape, &mut io, commands, &mut &tty);
+        io.flush()?;
+        result
+    } else if args.visualize() {
+        visualize(&mut tape, interrupt, input, output)?
+    } else {
+        tape.interpreter_buffered(&mut input, &mut output)
+    };
+    // The input is saved even if the program failed or was interrupted, as that is when it is
+    // most likely to be wanted
+    if let (Some(path), Some(input)) = (args.record_input(), tape.recorded_input()) {
+        std::fs::write(path, input)?;
+    }
+    // As is the trace, which the interpreter only flushes when the program halts
+    tape.finish_trace()?;
+    // As is the dump of the tape, so that what the program left in memory can be seen however
+    // it stopped
+    if let Some(count) = args.dump_tape() {
+        match args.dump_file() {
+            Some(path) => {
+                let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                tape.dump(&mut file, Some(0..count))?;
+                file.flush()?;
+            }
+            None => tape.dump(&mut std::io::stderr(), Some(0..count))?,
+        }
+    }
+    // The exit code of a program that didn't finish, which bft exits with once the output has
+    // been finished and the reports written
+    let exit_code = match result {
+        Ok(_) => None,
+        Err(bft_interp::BfError::Interrupted { instruction, .. }) => {
+            // Show where the program had got to so that a runaway program can be diagnosed
+            eprintln!();
+            eprintln!(
+                "bft: Interrupted at {}:{} {}",
+                program.filename().to_string_lossy(),
+                instruction.location(),
+                instruction.command()
+            );
+            eprintln!("bft: Data pointer is {}", tape.data_pointer());
+            let (start, cells) = tape.tape_window(INTERRUPT_WINDOW);
+            eprintln!("bft: Cells from {}: {:?}", start, cells);
+            Some(EXIT_INTERRUPTED)
+        }
+        // The output being closed, such as when piped into head, isn't a failure of the program
+        Err(e) if e.io_kind() == Some(std::io::ErrorKind::BrokenPipe) => None,
+        Err(bft_interp::BfError::TapeAllocationFailed { requested_cells }) => {
+            out_of_memory(args, requested_cells)
+        }
+        Err(e) if e.io_kind().is_some() => {
+            eprintln!("Error {}", e);
+            Some(EXIT_IO_ERROR)
+        }
+        Err(
+            e @ (bft_interp::BfError::StepLimitExceeded { .. }
+            | bft_interp::BfError::TimeLimitExceeded { .. }),
+        ) => {
+            eprintln!("Error {}", e);
+            Some(EXIT_LIMIT_EXCEEDED)
+        }
+        Err(e) => {
+            eprintln!("Error {}", e);
+            // Show what the memory looked like when the program failed, unless it has already
+            // been dumped as asked for
+            if args.dump_tape().is_none() {
+                tape.dump(&mut std::io::stderr(), None)?;
+            }
+            Some(EXIT_RUNTIME_ERROR)
+        }
+    };
+    if let Some(file) = output_file {
+        file.finish(args.sync_output())?;
+    }
+
+    if let Some(hits) = tape.coverage() {
+        report_coverage(program, hits);
+    }
+    if let Some(profile) = tape.profile() {
+        eprint!("{}", profile.report(PROFILE_TOP_LOOPS));
+    }
+
+    if args.debug() >= cli::DebugLevelType::Information {
+        let stats = tape.run_stats();
+        eprintln!(
+            "bft: {} steps, tape of {} cells, data pointer reached cell {}",
+            stats.steps, stats.data_length, stats.memory.high_water
+        );
+        eprintln!(
+            "bft: {} cells ({} bytes) allocated, tape grew {} times",
+            stats.memory.allocated_cells, stats.memory.allocated_bytes, stats.memory.growth_events
+        );
+    }
+
+    // The program's output and the reports above are kept when it fails or is stopped, but
+    // whatever ran it needs to know that it didn't finish
+    if let Some(code) = exit_code {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Run the program in a live view on the terminal, see bft_interp::visualizer. The view is drawn
+/// on stderr with the terminal in raw mode so that keys are read as they are pressed, which
+/// turns Ctrl-C into a key, so it sets the interrupt flag here. The program's output is written
+/// once the view is quit.
+fn visualize<T: bft_interp::CellKind>(
+    tape: &mut bft_interp::BfTape<T>,
+    interrupt: Arc<AtomicBool>,
+    input: Box<dyn Read>,
+    output: &mut dyn Write,
+) -> Result<Result<bft_interp::StopReason<T>, bft_interp::BfError>, Box<dyn std::error::Error>> {
+    let (sender, keys) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = ratatui::crossterm::event::read() {
+            let key = match event {
+                Event::Key(key) if key.kind != KeyEventKind::Press => continue,
+                Event::Key(key)
+                    if key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    interrupt.store(true, Ordering::Relaxed);
+                    bft_interp::visualizer::INTERRUPT
+                }
+                Event::Key(key) => match key.code {
+                    KeyCode::Char(c) if c.is_ascii() => c as u8,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if sender.send(key).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut view = ViewTerminal::new()?;
+    let mut visualizer = bft_interp::visualizer::Visualizer::new();
+    let mut io = bft_interp::StdIo::new(input, std::io::sink());
+    let result = visualizer.run(tape, &mut io, &keys, &mut view.0, VISUALIZE_FRAME);
+    drop(view);
+    output.write_all(visualizer.output())?;
+    Ok(result)
+}
+
+/// The terminal the live view is drawn on. It is put back as it was when this is dropped, so
+/// the terminal isn't left in raw mode however the view ends.
+struct ViewTerminal(ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stderr>>);
+
+impl ViewTerminal {
+    /// Put the terminal into raw mode and switch to its alternate screen for the view
+    fn new() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stderr(), terminal::EnterAlternateScreen)
+            .and_then(|_| ratatui::Terminal::new(ratatui::backend::CrosstermBackend::new(stderr())))
+            .map(Self)
+            .inspect_err(|_| restore_terminal())
+    }
+}
+
+impl Drop for ViewTerminal {
+    fn drop(&mut self) {
+        restore_terminal();
     }
+}
+
+/// Take the terminal out of raw mode and back to its main screen. Nothing more can be done if
+/// this fails, so errors are ignored.
+fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(stderr(), terminal::LeaveAlternateScreen, cursor::Show);
+}
+
+/// Load a program and validate it, for the subcommands that don't run it
+fn load_validated(
+    source: &cli::ProgramSource,
+) -> Result<bft_types::BfProgram, Box<dyn std::error::Error>> {
+    let mut program = bft_interp::load_program(source)?;
+    program.validate()?;
+    Ok(program)
+}
+
+/// Check that programs are valid without running them, for bft check. Every problem with each
+/// of the programs is reported, one per line, rather than stopping at the first.
+fn check_bft(args: &cli::CheckArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut invalid = 0;
+    for source in args.sources() {
+        let problems: Vec<String> = match bft_interp::load_program(source) {
+            Ok(program) => program
+                .unmatched_brackets()
+                .iter()
+                .map(|inst| format!("{}:{}: Extra {}", source, inst.location(), inst.command()))
+                .collect(),
+            Err(e) => vec![format!("{}: {}", source, e)],
+        };
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if !problems.is_empty() {
+            invalid += 1;
+        }
+    }
+    match invalid {
+        0 => Ok(()),
+        _ => Err(format!(
+            "{} of {} programs checked are not valid",
+            invalid,
+            args.sources().len()
+        )
+        .into()),
+    }
+}
+
+/// Pretty-print a valid program to stdout, for bft fmt
+fn fmt_bft(args: &cli::FmtArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let program = load_validated(args.source())?;
+    let text = bft_interp::listing::format(&program, args.indent(), args.width());
+    stdout().write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// List a valid program's instructions to stdout, for bft dump
+fn dump_bft(args: &cli::DumpArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let program = load_validated(args.source())?;
+    let mut output = std::io::BufWriter::new(stdout().lock());
+    bft_interp::listing::dump(&program, &mut output)?;
+    output.flush()?;
+    Ok(())
+}
 
+/// Show statistics about a program, for bft stats. The program doesn't have to be valid, so
+/// that a program with unmatched brackets can be looked into.
+fn stats_bft(args: &cli::StatsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let program = bft_interp::load_program(args.source())?;
+    print!(
+        "{}",
+        bft_interp::listing::ProgramStats::new(&program).report()
+    );
     Ok(())
 }
 
+/// Report that there isn't enough memory for the tape and exit
+fn out_of_memory(args: &cli::RunArgs, requested_cells: usize) -> ! {
+    eprintln!(
+        "bft: Not enough memory for a tape of {} cells to run {}, try fewer cells with -c",
+        requested_cells,
+        args.source()
+    );
+    std::process::exit(EXIT_OUT_OF_MEMORY);
+}
+
+/// Output how many of the program's instructions were executed and where the ones that
+/// weren't executed are in the source
+fn report_coverage(program: &bft_types::BfProgram, hits: &[u32]) {
+    let executed = hits.iter().filter(|&&h| h > 0).count();
+    let percent = if hits.is_empty() {
+        100.0
+    } else {
+        executed as f64 * 100.0 / hits.len() as f64
+    };
+    eprintln!(
+        "bft: {} of {} instructions executed ({:.1}%)",
+        executed,
+        hits.len(),
+        percent
+    );
+    for (inst, _) in program
+        .instructions()
+        .iter()
+        .zip(hits)
+        .filter(|(_, &h)| h == 0)
+    {
+        eprintln!(
+            "bft: Not executed {}:{} {}",
+            program.filename().to_string_lossy(),
+            inst.location(),
+            inst.command()
+        );
+    }
+}
+
 /// Main
 ///
 /// Will terminate with an exit code of 1 if there was an error in the BF
 /// program. An exit code of zero is used if the BF program was run with no
-/// issues.
+/// issues. If the BF program is interrupted with Ctrl-C, the location it had reached is
+/// output and the exit code is 130. If there isn't enough memory for the tape, the exit
+/// code is 71. If the input or output fails, such as the output file not being writable,
+/// the exit code is 74. If the program runs for more steps than --max-steps allows, or for
+/// longer than --timeout allows, the exit code is 152. Errors are output to stderr so that
+/// stdout only has the program's output on it.
+///
+/// bft has subcommands, which are
+/// * run         - Run the program, which is what bft does without a subcommand.
+/// * check       - Parse and validate any number of programs without running them, such as bft check src/*.bf. Each
+///   problem, such as every unmatched bracket, is output to stderr as file:line:col: message, and the exit code is
+///   1 unless all of the programs are valid. bft --check is the same as bft check.
+/// * fmt         - Pretty-print the program to stdout, with the body of each loop indented by --indent spaces (2 by
+///   default) and at most --width commands (80 by default) on a line.
+/// * dump        - List the program's instructions to stdout with their locations and where the jumps go.
+/// * stats       - Show the number of each command, the number of loops and how deeply they are nested.
+/// * completions \<shell\> - Write a completion script for bash, zsh, fish or powershell to stdout, such as
+///   bft completions zsh > _bft. This is hidden from the help.
+///
+/// A program that is also the name of a subcommand has to be given with run, as in bft run check, or as a path such
+/// as ./check. Each subcommand other than completions takes the program as a file, - for stdin or --eval \<code\>.
 ///
-/// Various options can be specified. They are
-/// * -c \<cells\>  - Specify the number of cells in the BF program's tape. The default is 30,000.
-/// * -e          - Allows the tape to grow as necessary. If not specified the tape is fixed in size.
-/// * -a          - ASCII output. Use for hello-world.bf. Without this option, values are output as numbers.
-/// * -d          - Debug output. Multiple occurences of this option increase the amount of debug information that is output.
+/// Various options can be specified for run. They are
+/// * -c \<cells\>  - Specify the number of cells in the BF program's tape, such as 30000, 64k or 1M, where k, m and g are powers of 1024. The default is 30,000.
+/// * --tape-mode \<mode\> - What happens at the ends of the tape. fixed (the default) makes going past them an error, grow
+///   allows the tape to grow as necessary, grow-both-ways allows it to grow to the left of the first cell as well and wrap
+///   comes back round at the other end.
+/// * -e          - Deprecated, the same as --tape-mode grow.
+/// * --grow-both-ways - Deprecated, the same as --tape-mode grow-both-ways.
+/// * --max-tape \<cells\> - With --tape-mode grow, the most cells the tape can grow to, such as 100k or 2M. Going beyond them is an error.
+/// * --output-format \<format\> - How values are output: ascii (the default, use for hello-world.bf), numbers, hex or utf8.
+/// * -n          - Deprecated, the same as --output-format numbers.
+/// * --hex       - Deprecated, the same as --output-format hex.
+/// * --decimal   - Input values as decimal numbers separated by commas or whitespace.
+/// * --eof-behavior \<mode\> - What , stores at the end of the input: zero, neg1 (-1, the default) or unchanged.
+/// * --max-steps \<steps\> - Stop the program after this many instructions. The default of 0 is no limit.
+/// * --timeout \<duration\> - Stop the program after it has run for this long, such as 500ms, 5s or 2m.
+/// * --tape-init \<file\> - Initialise the start of the tape with the bytes in the file.
+/// * --coverage  - Report which instructions were executed and which were not.
+/// * --profile   - Report how often each command was executed and the 10 loops with the most iterations.
+/// * --unbuffered - Flush the output after every output command, for interactive programs.
+/// * --debug-interactive - Run the program under a debugger driven from the terminal, see bft_interp::debugger.
+/// * --record-input \<file\> - Save the input read by the program to the file.
+/// * --replay-input \<file\> - Give the program the input in the file before reading from stdin.
+/// * -i \<file\>   - Read the program's input from the file rather than stdin.
+/// * --input \<text\> - Give the program the text as its input rather than stdin, such as --input 'hello\n'. The escapes
+///   \n, \r, \t, \0, \\ and \x41 give other bytes. After the text the program gets the end of the input, as
+///   --eof-behavior says. It can't be used with -i.
+/// * -o \<file\>   - Write the program's output to the file rather than stdout. It is created, or truncated if it exists.
+/// * --sync-output - With -o, wait for the output file to reach the disk before exiting.
+/// * --dump-tape \<count\> - Dump the first cells of the tape in hexdump style to stderr once the program has finished, however it stopped.
+/// * --trace-file \<file\> - Write a JSON line for each instruction executed to the file, or to stderr for -.
+/// * --dump-file \<file\> - With --dump-tape, write the dump to the file rather than stderr.
+/// * --visualize - Show the tape and source in a live view as the program runs, see bft_interp::visualizer.
+/// * --cell-size \<bits\> - The size of the tape's cells, 8, 16 or 32 bits. The default is 8.
+/// * -x, --eval \<code\> - Run the BF program given rather than one in a file. Errors in it are shown as being in <eval>.
+/// * -d          - Debug output. Multiple occurences of this option increase the amount of debug information that is output, up to -ddd.
+/// * --debug-level \<level\> - The amount of debug output by name, none, info, verbose, detailed or trace, in place of counting -d.
+///   trace also shows the tape after every instruction.
+/// * -q          - Quiet. Only the program's output is written to stdout, with no status messages or config file warnings.
+///   It can't be used with -d or --debug-level.
+/// * --trailing-newline \<when\> - Whether a newline is output after the program's output: always, never or if-missing.
+///   By default one is added if it is missing when stdout is a terminal and -q isn't given.
+/// * --config \<file\> - Read default options from the TOML file, see cli::config. Without this option ./bft.toml is used
+///   if it exists, then $XDG_CONFIG_HOME/bft/config.toml. Options on the command line take precedence over the file.
+///   This can also be given by BFT_CONFIG.
 /// * -h          - Help
 /// * -V          - Version
 ///
+/// The main options can be given by environment variables instead, which are shown by --help. They are BFT_CELLS,
+/// BFT_CELL_SIZE, BFT_TAPE_MODE, BFT_MAX_TAPE, BFT_OUTPUT_FORMAT, BFT_EOF_BEHAVIOR, BFT_MAX_STEPS, BFT_TIMEOUT and
+/// BFT_DEBUG, which takes a level by name as --debug-level does. Options on the command line take precedence over
+/// the environment variables, which take precedence over the config file.
+///
 /// Usage:
 ///     bft <filename.bf> \[options\]
+///     bft --eval <code> \[options\]
+///     bft -x <code> \[options\]
+///     bft - --input-file <file> \[options\]
+///     bft - --input <text> \[options\]
+///     bft \<subcommand\> <filename.bf> \[options\]
+///
+/// A program of `-` is read from stdin, so its input has to come from a file or --input.
 fn main() {
     let args = &cli::Args::new();
-    match run_bft(args) {
+    let result = match args {
+        cli::Args::Run(args) => {
+            if !args.quiet() {
+                for warning in args.config_warnings() {
+                    eprintln!("bft: Warning, {}", warning);
+                }
+            }
+            run_bft(args)
+        }
+        cli::Args::Check(args) => check_bft(args),
+        cli::Args::Fmt(args) => fmt_bft(args),
+        cli::Args::Dump(args) => dump_bft(args),
+        cli::Args::Stats(args) => stats_bft(args),
+        cli::Args::Completions(args) => {
+            cli::completions::generate(args.shell(), cli::command(), &mut stdout().lock())
+                .map_err(Into::into)
+        }
+    };
+    match result {
         Ok(_) => {}
         Err(e) => {
-            println!("bft: Error in {}, {}", args.program().to_string_lossy(), e);
-            std::process::exit(1)
+            match args.source() {
+                Some(source) => eprintln!("bft: Error in {}, {}", source, e),
+                None => eprintln!("bft: Error, {}", e),
+            }
+            match e.downcast_ref::<bft_interp::BfError>() {
+                Some(e) if e.io_kind().is_some() => std::process::exit(EXIT_IO_ERROR),
+                _ => std::process::exit(1),
+            }
         }
     }
     std::process::exit(0)
@@ -0,0 +1,59 @@
+//! Tests of the bft binary with --cell-size, which gives the tape cells that hold more than a
+//! byte.
+
+mod common;
+
+use common::run_bft;
+
+/// Test that a 16 bit cell holds 256 and that numbers are output and input whole
+#[test]
+fn wide_cells() {
+    let large = "++++++++++++++++[>++++++++++++++++<-]>.";
+    let output = run_bft(&["-q", "--cell-size", "16", "-n", "--eval", large]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"256");
+
+    // With 8 bit cells it wraps around to 0
+    let output = run_bft(&["-q", "-n", "--eval", large]);
+    assert_eq!(output.stdout, b"0");
+
+    // A number read in is output the same in decimal and hex
+    let output = run_bft(&[
+        "-q",
+        "--cell-size",
+        "16",
+        "--decimal",
+        "-n",
+        "--input",
+        "300",
+        "--eval",
+        ",.",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"300");
+    let output = run_bft(&[
+        "-q",
+        "--cell-size",
+        "32",
+        "--decimal",
+        "--hex",
+        "--input",
+        "300",
+        "--eval",
+        ",.",
+    ]);
+    assert_eq!(output.stdout, b"0000012c");
+
+    // ASCII output is the low byte of the cell, 0x141 is A
+    let output = run_bft(&[
+        "-q",
+        "--cell-size",
+        "16",
+        "--decimal",
+        "--input",
+        "321",
+        "--eval",
+        ",.",
+    ]);
+    assert_eq!(output.stdout, b"A");
+}
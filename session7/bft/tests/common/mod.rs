@@ -0,0 +1,53 @@
+//! Running the bft binary for the tests, shared by each of them with `mod common`.
+//!
+//! Not every test uses every function here.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+
+/// An empty directory in cargo's temporary directory for the tests, which is the current
+/// directory, config directory and home directory of bft so that it doesn't find a config file
+fn empty_dir() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bft-empty");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    })
+}
+
+/// The bft binary with the arguments, without any config file or environment variables getting
+/// in the way
+pub fn bft(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_bft"));
+    command
+        .args(args)
+        .env("XDG_CONFIG_HOME", empty_dir())
+        .env("HOME", empty_dir())
+        .current_dir(empty_dir());
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with("BFT_") {
+            command.env_remove(name);
+        }
+    }
+    command
+}
+
+/// Run bft with the arguments, returning what it output
+pub fn run_bft(args: &[&str]) -> Output {
+    bft(args).output().unwrap()
+}
+
+/// Run bft with the arguments and stdin given, returning what it output
+pub fn run_bft_with_input(args: &[&str], input: &[u8]) -> Output {
+    let mut child = bft(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), input).unwrap();
+    child.wait_with_output().unwrap()
+}
@@ -0,0 +1,52 @@
+//! Tests of the bft binary's exit status when the BF program fails while running, which
+//! scripts rely on to tell that the program didn't work.
+
+mod common;
+
+use common::run_bft;
+
+/// Test that moving off either end of the tape exits with 1 after reporting the error, and
+/// that the output written before the error is kept
+#[test]
+fn runtime_error() {
+    let output = run_bft(&["--eval", "<"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[E_TAPE_UNDERRUN]"), "{}", stderr);
+
+    let output = run_bft(&["-q", "-c", "2", "--eval", "+++.>>"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"\x03");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[E_TAPE_OVERRUN]"), "{}", stderr);
+}
+
+/// Test that the tape is only dumped once when a program fails with --dump-tape, as many cells
+/// as were asked for and to the dump file if there is one
+#[test]
+fn runtime_error_dump_tape() {
+    let output = run_bft(&["--eval", "<", "--dump-tape", "16"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let dump_starts = stderr.lines().filter(|line| line.starts_with("00000000 "));
+    assert_eq!(dump_starts.count(), 1, "{}", stderr);
+    assert!(stderr.contains("\n00000010\n"), "{}", stderr);
+    assert!(!stderr.contains("00007530"), "{}", stderr);
+
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("error-dump.txt");
+    let path = path.to_str().unwrap();
+    let output = run_bft(&["--eval", "<", "--dump-tape", "16", "--dump-file", path]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("00000000 "), "{}", stderr);
+    let dump = std::fs::read_to_string(path).unwrap();
+    assert_eq!(dump.matches("00000000 ").count(), 1, "{}", dump);
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Test that a program that runs without error still exits with 0
+#[test]
+fn no_error() {
+    let output = run_bft(&["-q", "--eval", "+"]);
+    assert_eq!(output.status.code(), Some(0));
+}
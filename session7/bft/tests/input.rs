@@ -0,0 +1,62 @@
+//! Tests of the bft binary with --input, which gives the BF program its input on the command
+//! line rather than on stdin.
+
+mod common;
+
+use common::run_bft_with_input;
+
+/// Test that a cat program echoes the text given to --input, not what is on stdin
+#[test]
+fn cat_input_text() {
+    let output = run_bft_with_input(
+        &[
+            "-q",
+            "--eval",
+            ",[.,]",
+            "--eof-behavior",
+            "zero",
+            "--input",
+            "hello\\n\\x41\\x42",
+        ],
+        b"not this",
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"hello\nAB");
+    assert!(output.stderr.is_empty());
+
+    // With the default of -1 at the end of the input, which the program stops at instead
+    let output = run_bft_with_input(&["-q", "--eval", ",+[-.,+]", "--input", "bye"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"bye");
+
+    // The end of the input leaves the cell as it was when asked to
+    let output = run_bft_with_input(
+        &[
+            "-q",
+            "-n",
+            "--eval",
+            ",.,.,.",
+            "--eof-behavior",
+            "unchanged",
+            "--input",
+            "\\x07",
+        ],
+        b"",
+    );
+    assert_eq!(output.stdout, b"7,7,7");
+}
+
+/// Test that --input can't be used with --input-file, and that a bad escape is an error
+#[test]
+fn input_text_errors() {
+    let output = run_bft_with_input(
+        &["--eval", ",.", "--input", "a", "--input-file", "in.txt"],
+        b"",
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--input-file"));
+
+    let output = run_bft_with_input(&["--eval", ",.", "--input", "\\x4"], b"");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("two hex digits"));
+}
@@ -0,0 +1,25 @@
+//! Tests of the bft binary stopping a program that goes over the limits it was given.
+
+mod common;
+
+use common::run_bft;
+
+/// Test that a program that never halts is stopped by --max-steps, exiting with 152 and
+/// saying where it had got to
+#[test]
+fn step_limit() {
+    let output = run_bft(&["--eval", "+[]", "--max-steps", "1000"]);
+    assert_eq!(output.status.code(), Some(152));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("[E_STEP_LIMIT] Step limit of 1000 exceeded"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("@1:3"), "{}", stderr);
+
+    // A program that halts within the limit isn't affected by it
+    let output = run_bft(&["-q", "--eval", "+++.", "--max-steps", "1000"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x03");
+}
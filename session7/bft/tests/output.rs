@@ -0,0 +1,56 @@
+//! Tests of the bft binary with --output-file, which writes the BF program's output to a file
+//! rather than to stdout.
+
+mod common;
+
+use common::run_bft;
+
+/// Test that the output goes to the file, replacing what was in it, and not to stdout
+#[test]
+fn output_file() {
+    let path = std::env::temp_dir().join(format!("bft-output-file-{}.txt", std::process::id()));
+    std::fs::write(&path, "something longer that is replaced").unwrap();
+    let output = run_bft(&[
+        "-q",
+        "--eval",
+        "++++++++[>++++++++<-]>+.+.",
+        "--output-file",
+        &path.to_string_lossy(),
+    ]);
+    let contents = std::fs::read(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty());
+    assert_eq!(contents.unwrap(), b"AB");
+}
+
+/// Test that an error writing the file exits with 74 and says which file it was
+#[cfg(target_os = "linux")]
+#[test]
+fn output_file_error() {
+    let output = run_bft(&["--eval", "+.", "-o", "/dev/full"]);
+    assert_eq!(output.status.code(), Some(74));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("writing /dev/full: "), "{}", stderr);
+}
+
+/// Test that the output file is finished, and synced with --sync-output, when the program is
+/// stopped early, and that bft still exits with the code for why it stopped
+#[test]
+fn output_file_stopped_early() {
+    let path = std::env::temp_dir().join(format!("bft-output-stopped-{}.txt", std::process::id()));
+    let output = run_bft(&[
+        "-q",
+        "--eval",
+        "++++++++[>++++++++<-]>+.+[]",
+        "--max-steps",
+        "1000",
+        "--output-file",
+        &path.to_string_lossy(),
+        "--sync-output",
+    ]);
+    let contents = std::fs::read(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(152), "{:?}", output);
+    assert_eq!(contents.unwrap(), b"A");
+}
@@ -0,0 +1,60 @@
+//! Tests of the bft binary's output with --quiet, which has to leave only the BF program's
+//! output on stdout so that bft can be used as a filter.
+
+mod common;
+
+use common::run_bft_with_input;
+
+/// Test that stdout is exactly the program's output when it runs without error
+#[test]
+fn quiet_output() {
+    // Copies its input to its output, with no newline added
+    let output = run_bft_with_input(&["-q", "--eval", ",[.,]", "--eof-behavior", "zero"], b"abc");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"abc");
+    assert!(output.stderr.is_empty());
+
+    // Unless one is asked for
+    let output = run_bft_with_input(
+        &[
+            "-q",
+            "--trailing-newline",
+            "always",
+            "--eval",
+            ",[.,]",
+            "--eof-behavior",
+            "zero",
+        ],
+        b"abc",
+    );
+    assert_eq!(output.stdout, b"abc\n");
+}
+
+/// Test that errors go to stderr, leaving what the program output before them on stdout
+#[test]
+fn quiet_errors() {
+    // Moving off the start of the tape is an error once something has been output
+    let output = run_bft_with_input(&["-q", "--eval", "+++.<"], b"");
+    assert_eq!(output.stdout, [3]);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Error"),
+        "{:?}",
+        output
+    );
+
+    // As are errors in the program, before it runs
+    let output = run_bft_with_input(&["-q", "--eval", "+[."], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("<eval>"),
+        "{:?}",
+        output
+    );
+
+    // And the limits
+    let output = run_bft_with_input(&["-q", "--max-steps", "5", "--eval", "+[]"], b"");
+    assert_eq!(output.status.code(), Some(152));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+}
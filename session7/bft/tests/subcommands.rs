@@ -0,0 +1,142 @@
+//! Tests of the bft binary's subcommands, and that bft without one still runs the program as
+//! it did before there were subcommands.
+
+mod common;
+
+use common::run_bft;
+
+use std::path::PathBuf;
+
+/// Path of one of the example programs
+fn example(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+/// Test that bft file.bf, with options before or after the file, is the same as bft run
+#[test]
+fn run_without_subcommand() {
+    let hello = example("hello-world.bf");
+    let hello = hello.to_str().unwrap();
+    let run = run_bft(&["run", hello]);
+    assert!(run.status.success());
+    assert_eq!(run.stdout, b"Hello World!\n");
+    for args in [&[hello][..], &["-c", "100", hello], &[hello, "-c", "100"]] {
+        let bare = run_bft(args);
+        assert!(bare.status.success(), "{:?}", args);
+        assert_eq!(bare.stdout, run.stdout, "{:?}", args);
+    }
+    let output = run_bft(&["--eval", "+++.", "-n"]);
+    assert_eq!(output.stdout, b"3");
+}
+
+/// Test that check succeeds for a valid program and fails for one with unmatched brackets,
+/// without running either
+#[test]
+fn check() {
+    let output = run_bft(&["check", example("hello-world.bf").to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let output = run_bft(&["check", example("extra-bracket.bf").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Extra"));
+    let output = run_bft(&["check", "--eval", ",."]);
+    assert!(output.status.success());
+}
+
+/// Test that --check reports every unmatched bracket in a program as file:line:col: message
+#[test]
+fn check_reports_every_problem() {
+    let output = run_bft(&["--check", example("hello-world.bf").to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let output = run_bft(&["--check", "--eval", "+]\n[.]]"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "<eval>:1:2: Extra Jump backward if nonzero",
+            "<eval>:2:4: Extra Jump backward if nonzero",
+            "bft: Error, 1 of 1 programs checked are not valid",
+        ]
+    );
+}
+
+/// Test that --check with several programs fails if any of them isn't valid, after reporting
+/// the problems with all of them
+#[test]
+fn check_many() {
+    let good = example("hello-world.bf");
+    let extra = example("extra-bracket.bf");
+    let open = example("open-bracket.bf");
+    let missing = std::env::temp_dir().join("bft-no-such-program.bf");
+    let [good, extra, open, missing] =
+        [&good, &extra, &open, &missing].map(|path| path.to_str().unwrap());
+
+    let output = run_bft(&["--check", good, good]);
+    assert!(output.status.success());
+
+    let output = run_bft(&["--check", good, extra, good, open, missing]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("hello-world.bf"), "{}", stderr);
+    let problems: Vec<&str> = stderr.lines().collect();
+    assert_eq!(problems.len(), 4, "{}", stderr);
+    assert!(
+        problems[0].starts_with(&format!("{}:", extra)),
+        "{}",
+        stderr
+    );
+    assert!(problems[1].starts_with(&format!("{}:", open)), "{}", stderr);
+    assert!(
+        problems[2].starts_with(&format!("{}: ", missing)),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        problems[3],
+        "bft: Error, 3 of 5 programs checked are not valid"
+    );
+}
+
+/// Test that fmt indents loops and keeps the commands
+#[test]
+fn fmt() {
+    let output = run_bft(&["fmt", "--indent", "4", "--eval", "+[-[>]] comment"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "+\n[\n    -\n    [\n        >\n    ]\n]\n"
+    );
+    let output = run_bft(&["fmt", "--eval", "+]"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// Test that dump lists each command with where its jumps go
+#[test]
+fn dump() {
+    let output = run_bft(&["dump", "--eval", "+[-]"]);
+    assert!(output.status.success());
+    let listing = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(listing.lines().count(), 4);
+    assert!(listing.contains("<eval>:1:2 [ "));
+    assert!(listing.contains("jumps to 3 at 1:4"));
+}
+
+/// Test that stats counts the commands and loops, even of a program that isn't valid
+#[test]
+fn stats() {
+    let output = run_bft(&["stats", "--eval", "++[>[<]"]);
+    assert!(output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        report.starts_with("<eval>: 7 commands on 1 lines, 2 loops nested up to 2 deep\n"),
+        "{}",
+        report
+    );
+    assert!(report.contains("+ IncValue: 2"));
+}